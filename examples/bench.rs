@@ -0,0 +1,213 @@
+//! Throughput and latency benchmarks for the runtime.
+//!
+//! ```text
+//! cargo run --release --example bench -- echo [connections] [requests] [size]
+//! cargo run --release --example bench -- copy [megabytes] [chunk_size]
+//! cargo run --release --example bench -- timer [timers] [max_millis]
+//! ```
+//!
+//! Each run ends with the counters of the runtime's loop. Built with `--features
+//! metrics`, it also reports the driver's submissions, completions, parks and the
+//! latency of its operations by opcode.
+use std::env;
+use std::io;
+use std::process;
+use std::time::{Duration, Instant};
+
+use futures_util::future::join_all;
+use slings::fs::{self, File};
+use slings::net::{TcpListener, TcpStream};
+use slings::time::delay_until;
+use slings::{AsyncReadExt, AsyncWriteExt, Runtime};
+
+fn main() -> io::Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let arg = |i: usize, default: usize| -> usize {
+        args.get(i).and_then(|v| v.parse().ok()).unwrap_or(default)
+    };
+
+    let runtime = Runtime::new()?;
+    match args.first().map(|s| s.as_str()) {
+        Some("echo") | None => runtime.block_on(echo(arg(1, 64), arg(2, 10_000), arg(3, 64)))?,
+        Some("copy") => runtime.block_on(copy(arg(1, 64), arg(2, 64 * 1024)))?,
+        Some("timer") => runtime.block_on(timer(arg(1, 100_000), arg(2, 100)))?,
+        Some(other) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown benchmark: {}", other),
+            ))
+        }
+    }
+    report_runtime(&runtime);
+    Ok(())
+}
+
+async fn echo(connections: usize, requests: usize, size: usize) -> io::Result<()> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    slings::spawn_local(async move {
+        loop {
+            let (mut stream, _) = match listener.accept().await {
+                Ok(conn) => conn,
+                Err(_) => return,
+            };
+            slings::spawn_local(async move {
+                let mut buf = vec![0; 4096];
+                loop {
+                    match stream.read(&mut buf).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => {
+                            if stream.write_all(&buf[..n]).await.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    let start = Instant::now();
+    let clients = (0..connections).map(|_| async move {
+        let mut stream = TcpStream::connect(addr).await?;
+        let msg = vec![b'x'; size];
+        let mut buf = vec![0; size];
+        let mut latencies = Vec::with_capacity(requests);
+        for _ in 0..requests {
+            let sent = Instant::now();
+            stream.write_all(&msg).await?;
+            stream.read_exact(&mut buf).await?;
+            latencies.push(sent.elapsed());
+        }
+        Ok::<_, io::Error>(latencies)
+    });
+
+    let mut latencies = Vec::with_capacity(connections * requests);
+    for result in join_all(clients).await {
+        latencies.extend(result?);
+    }
+    report("echo", start.elapsed(), latencies);
+    Ok(())
+}
+
+/// Copies a file of `megabytes` through userspace in `chunk_size` pieces, each read and
+/// written through the ring, timing every piece.
+async fn copy(megabytes: usize, chunk_size: usize) -> io::Result<()> {
+    let dir = env::temp_dir();
+    let src = dir.join(format!("slings-bench-{}.src", process::id()));
+    let dst = dir.join(format!("slings-bench-{}.dst", process::id()));
+    let len = (megabytes * 1024 * 1024) as u64;
+    let chunk_size = chunk_size.max(1);
+
+    let file = File::create(&src).await?;
+    let chunk = vec![b'x'; chunk_size];
+    let mut pos = 0;
+    while pos < len {
+        let n = chunk_size.min((len - pos) as usize);
+        pos += file.write_at(&chunk[..n], pos).await? as u64;
+    }
+    file.close().await?;
+
+    let start = Instant::now();
+    let from = File::open(&src).await?;
+    let to = File::create(&dst).await?;
+    let mut buf = vec![0; chunk_size];
+    let mut latencies = Vec::with_capacity((len / chunk_size as u64) as usize + 1);
+    let mut pos = 0;
+    loop {
+        let copied = Instant::now();
+        let n = from.read_at(&mut buf, pos).await?;
+        if n == 0 {
+            break;
+        }
+        let mut written = 0;
+        while written < n {
+            written += to.write_at(&buf[written..n], pos + written as u64).await?;
+        }
+        pos += n as u64;
+        latencies.push(copied.elapsed());
+    }
+    to.sync_all().await?;
+    let elapsed = start.elapsed();
+
+    fs::remove_file(&src).await?;
+    fs::remove_file(&dst).await?;
+    report("copy", elapsed, latencies);
+    println!(
+        "  {:.1} MiB/s",
+        pos as f64 / (1024.0 * 1024.0) / elapsed.as_secs_f64()
+    );
+    Ok(())
+}
+
+async fn timer(timers: usize, max_millis: usize) -> io::Result<()> {
+    let start = Instant::now();
+    let delays = (0..timers).map(|i| async move {
+        let deadline = Instant::now() + Duration::from_millis((i % max_millis.max(1)) as u64);
+        delay_until(deadline).await;
+        Instant::now().saturating_duration_since(deadline)
+    });
+
+    let lateness = join_all(delays).await;
+    report("timer", start.elapsed(), lateness);
+    Ok(())
+}
+
+fn report(name: &str, elapsed: Duration, mut latencies: Vec<Duration>) {
+    if latencies.is_empty() {
+        println!("{}: no samples", name);
+        return;
+    }
+    latencies.sort();
+    let percentile = |p: f64| latencies[((latencies.len() - 1) as f64 * p) as usize];
+    println!(
+        "{}: {} ops in {:?} ({:.0} ops/sec)",
+        name,
+        latencies.len(),
+        elapsed,
+        latencies.len() as f64 / elapsed.as_secs_f64()
+    );
+    println!(
+        "  p50 {:?}  p90 {:?}  p99 {:?}  max {:?}",
+        percentile(0.5),
+        percentile(0.9),
+        percentile(0.99),
+        percentile(1.0)
+    );
+}
+
+/// The counters the runtime kept over the whole run.
+fn report_runtime(runtime: &Runtime) {
+    let stats = runtime.loop_metrics();
+    println!(
+        "loop: {} iterations, {} submits, {} waits, {} completions ({:.3} syscalls per completion), busy {:?}",
+        stats.iterations,
+        stats.submits,
+        stats.waits,
+        stats.completions,
+        stats.syscalls_per_completion,
+        stats.busy
+    );
+
+    #[cfg(feature = "metrics")]
+    {
+        let metrics = runtime.metrics();
+        println!(
+            "driver: {} sqes, {} cqes, {} parks, {} unparks, {:.0}% of ring buffers in use",
+            metrics.sqes_submitted,
+            metrics.cqes_processed,
+            metrics.parks,
+            metrics.unparks,
+            metrics.buffer_utilization() * 100.0
+        );
+        for (opcode, latency) in &metrics.latencies {
+            println!(
+                "  opcode {:>2}: mean {:?}  p50 {:?}  p99 {:?}",
+                opcode,
+                latency.mean(),
+                latency.quantile(0.5),
+                latency.quantile(0.99)
+            );
+        }
+    }
+}
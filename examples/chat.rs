@@ -0,0 +1,139 @@
+//! A chat server: every line a client sends goes out to all the other clients.
+//!
+//! ```text
+//! cargo run --example chat [--features metrics] -- [addr]
+//! ```
+//!
+//! Connect with `nc 127.0.0.1 8080`. Clients quiet for 5 minutes are dropped. Ctrl-c
+//! tells the clients the server is going away, lets their last lines go out, and
+//! prints the runtime's metrics, with the latencies by opcode if built with the
+//! `metrics` feature.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::io;
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::time::Duration;
+
+use futures_util::future::{join_all, select, Either};
+use futures_util::stream::StreamExt;
+use slings::io::BufReader;
+use slings::net::{TcpListener, TcpStream};
+use slings::sync::mpsc::{unbounded_channel, UnboundedSender};
+use slings::{signal, time, AsyncBufReadExt, AsyncWriteExt, JoinHandle, Runtime};
+
+/// How long a client may stay quiet before it is dropped.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// How long the clients' last lines may take to go out once the server stops.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(1);
+
+/// The lines queued for each client and the task writing them out, by address.
+type Clients = Rc<RefCell<HashMap<SocketAddr, (UnboundedSender<Rc<str>>, JoinHandle<()>)>>>;
+
+fn main() -> io::Result<()> {
+    let addr = env::args()
+        .nth(1)
+        .unwrap_or_else(|| "127.0.0.1:8080".into());
+    let rt = Runtime::new()?;
+    let res = rt.block_on(async {
+        let listener = TcpListener::bind(addr.as_str()).await?;
+        println!("chat server listening on {}", listener.local_addr()?);
+        let clients = Clients::default();
+        let serve = Box::pin(serve(listener, clients.clone()));
+        let res = match select(serve, Box::pin(signal::ctrl_c())).await {
+            Either::Left((res, _)) | Either::Right((res, _)) => res,
+        };
+        let writers = farewell(&clients);
+        let _ = time::timeout(SHUTDOWN_GRACE, join_all(writers)).await;
+        res
+    });
+    print_metrics(&rt);
+    rt.shutdown(Duration::from_secs(5))?;
+    res
+}
+
+async fn serve(listener: TcpListener, clients: Clients) -> io::Result<()> {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            // the client left before it was accepted.
+            Err(e) if e.kind() == io::ErrorKind::ConnectionAborted => continue,
+            Err(e) => return Err(e),
+        };
+        slings::spawn_local(client(stream, peer, clients.clone()));
+    }
+}
+
+async fn client(stream: TcpStream, peer: SocketAddr, clients: Clients) {
+    let (read, mut write) = stream.into_split();
+    let (lines, mut queued) = unbounded_channel::<Rc<str>>();
+    let writer = slings::spawn_local(async move {
+        while let Some(line) = queued.recv().await {
+            if write.write_all(line.as_bytes()).await.is_err() {
+                return;
+            }
+        }
+    });
+    clients.borrow_mut().insert(peer, (lines, writer));
+    broadcast(&clients, peer, format!("{} joined\n", peer));
+
+    let mut lines = BufReader::new(read).lines();
+    // a quiet client, one that left and a failed read all end the session.
+    while let Ok(Some(Ok(line))) = time::timeout(IDLE_TIMEOUT, lines.next()).await {
+        broadcast(&clients, peer, format!("{}: {}\n", peer, line));
+    }
+    // gone already if the server is shutting down.
+    if clients.borrow_mut().remove(&peer).is_some() {
+        broadcast(&clients, peer, format!("{} left\n", peer));
+    }
+}
+
+/// Queues `line` for every client but `from`.
+fn broadcast(clients: &Clients, from: SocketAddr, line: String) {
+    let line: Rc<str> = line.into();
+    for (peer, (lines, _)) in clients.borrow().iter() {
+        if *peer != from {
+            let _ = lines.send(line.clone());
+        }
+    }
+}
+
+/// Tells every client the server is going away, and hands back the tasks writing
+/// their last lines.
+fn farewell(clients: &Clients) -> Vec<JoinHandle<()>> {
+    let mut clients = clients.borrow_mut();
+    let writers = clients.drain().map(|(_, (lines, writer))| {
+        let _ = lines.send("server shutting down\n".into());
+        writer
+    });
+    writers.collect()
+}
+
+fn print_metrics(rt: &Runtime) {
+    let stats = rt.loop_metrics();
+    println!(
+        "{} turns of the loop, {} completions, {:.2} syscalls per completion",
+        stats.iterations, stats.completions, stats.syscalls_per_completion
+    );
+    #[cfg(feature = "metrics")]
+    {
+        let metrics = rt.metrics();
+        println!(
+            "{} entries submitted, {} parks, {:.0}% of the ring buffers in use",
+            metrics.sqes_submitted,
+            metrics.parks,
+            metrics.buffer_utilization() * 100.0
+        );
+        for (opcode, latencies) in &metrics.latencies {
+            println!(
+                "opcode {:>2}: {} operations, mean {:?}, p99 {:?}",
+                opcode,
+                latencies.count,
+                latencies.mean(),
+                latencies.quantile(0.99)
+            );
+        }
+    }
+}
@@ -28,8 +28,7 @@ fn main() -> io::Result<()> {
                     }
                     delay_for(Duration::from_secs(1)).await;
                 }
-            })
-            .detach();
+            });
         }
     })
 }
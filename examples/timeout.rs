@@ -11,8 +11,8 @@ fn main() -> io::Result<()> {
             Ok(stream) => match stream {
                 Ok(mut stream) => {
                     let mut buf = vec![0; 10];
-                    let n = stream.read_exact(&mut buf).await?;
-                    println!("read {:?} bytes", n);
+                    stream.read_exact(&mut buf).await?;
+                    println!("read bytes: {:?}", buf);
                 }
                 Err(e) => {
                     println!("connect err: {:?}", e);
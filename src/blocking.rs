@@ -0,0 +1,240 @@
+//! A pool of threads for work that would block the thread of a runtime.
+
+use std::collections::VecDeque;
+use std::fs;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::Duration;
+
+use crate::driver::remote::{RemoteWaker, Wake};
+
+/// Threads the pool runs at once per CPU the process may use, its jobs mostly wait.
+const THREADS_PER_CPU: usize = 4;
+
+/// Most threads the pool runs at once by default, however many CPUs there are.
+const MAX_THREADS: usize = 64;
+
+/// How long an idle thread waits for a job before it exits.
+const KEEP_ALIVE: Duration = Duration::from_secs(10);
+
+type Job = Box<dyn FnOnce() + Send>;
+
+struct Pool {
+    state: Mutex<State>,
+    condvar: Condvar,
+}
+
+struct State {
+    queue: VecDeque<Job>,
+    threads: usize,
+    /// Threads waiting for a job.
+    idle: usize,
+    /// Most threads running at once, further jobs wait for one of them.
+    max_threads: usize,
+}
+
+fn pool() -> &'static Pool {
+    static POOL: OnceLock<Pool> = OnceLock::new();
+    POOL.get_or_init(|| Pool {
+        state: Mutex::new(State {
+            queue: VecDeque::new(),
+            threads: 0,
+            idle: 0,
+            max_threads: (available_cpus() * THREADS_PER_CPU).min(MAX_THREADS),
+        }),
+        condvar: Condvar::new(),
+    })
+}
+
+impl Pool {
+    fn execute(&'static self, job: Job) {
+        let mut state = self.state.lock().unwrap();
+        state.queue.push_back(job);
+        // every idle thread takes one job, a thread is started for the jobs left over.
+        if state.queue.len() > state.idle && state.threads < state.max_threads {
+            self.start(&mut state);
+        }
+        drop(state);
+        self.condvar.notify_one();
+    }
+
+    fn start(&'static self, state: &mut State) {
+        state.threads += 1;
+        thread::Builder::new()
+            .name("slings-blocking".into())
+            .spawn(move || self.work())
+            .expect("failed to spawn a blocking thread");
+    }
+
+    fn set_max_threads(&'static self, max: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.max_threads = max.max(1);
+        // jobs waiting for a thread get one right away if the limit went up, threads
+        // beyond a lower limit exit once they run out of work.
+        let waiting = state.queue.len().saturating_sub(state.idle);
+        let room = state.max_threads.saturating_sub(state.threads);
+        for _ in 0..waiting.min(room) {
+            self.start(&mut state);
+        }
+    }
+
+    fn work(&self) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(job) = state.queue.pop_front() {
+                drop(state);
+                job();
+                state = self.state.lock().unwrap();
+                continue;
+            }
+            if state.threads > state.max_threads {
+                state.threads -= 1;
+                return;
+            }
+            state.idle += 1;
+            let (guard, timeout) = self.condvar.wait_timeout(state, KEEP_ALIVE).unwrap();
+            state = guard;
+            state.idle -= 1;
+            if timeout.timed_out() && state.queue.is_empty() {
+                state.threads -= 1;
+                return;
+            }
+        }
+    }
+}
+
+/// Limits the pool to `max` threads running at once, see
+/// [`Runtime::set_blocking_threads`](crate::Runtime::set_blocking_threads).
+pub(crate) fn set_max_threads(max: usize) {
+    pool().set_max_threads(max);
+}
+
+/// CPUs the process may use: those it may run on, fewer if the CPU quota of its cgroup
+/// allows less time than that.
+fn available_cpus() -> usize {
+    let cpus = thread::available_parallelism().map_or(1, |n| n.get());
+    cgroup_cpus().map_or(cpus, |quota| quota.min(cpus))
+}
+
+/// The CPUs the tightest cgroup v2 quota among the process's cgroup and its ancestors
+/// amounts to, rounded up, `None` without any quota.
+fn cgroup_cpus() -> Option<usize> {
+    let cgroups = fs::read_to_string("/proc/self/cgroup").ok()?;
+    // the unified hierarchy is the one listed as `0::/path`.
+    let path = cgroups.lines().find_map(|line| line.strip_prefix("0::"))?;
+    let root = Path::new("/sys/fs/cgroup");
+    let dir = root.join(path.trim_start_matches('/'));
+    let quotas = dir.ancestors().take_while(|dir| dir.starts_with(root));
+    quotas.filter_map(cpu_max).min()
+}
+
+/// The quota in `cpu.max` of the cgroup at `dir`, which holds the microseconds its
+/// processes may run per period and the period, `max` for no limit.
+fn cpu_max(dir: &Path) -> Option<usize> {
+    let max = fs::read_to_string(dir.join("cpu.max")).ok()?;
+    let mut fields = max.split_whitespace();
+    let quota: u64 = fields.next()?.parse().ok()?;
+    let period: u64 = fields.next()?.parse().ok()?;
+    if period == 0 {
+        return None;
+    }
+    Some((quota.div_ceil(period) as usize).max(1))
+}
+
+/// Runs `f` on the blocking pool, resolving to its output or the value it panicked
+/// with.
+pub(crate) fn run<F, T>(f: F) -> Blocking<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let (done, output) = oneshot();
+    pool().execute(Box::new(move || {
+        done.complete(panic::catch_unwind(AssertUnwindSafe(f)));
+    }));
+    output
+}
+
+/// A slot for the output of work done on another thread, and the future a task awaits
+/// it with.
+pub(crate) fn oneshot<T>() -> (Complete<T>, Blocking<T>) {
+    let slot = Arc::new(Mutex::new(Slot {
+        output: None,
+        waiter: None,
+    }));
+    let complete = Complete {
+        slot: Some(slot.clone()),
+    };
+    let blocking = Blocking {
+        slot,
+        waiter: RemoteWaker::new(),
+    };
+    (complete, blocking)
+}
+
+/// Completes a [`Blocking`] from any thread. Dropped without completing, it fails the
+/// `Blocking` as if the work panicked, so its task is not left waiting forever.
+pub(crate) struct Complete<T> {
+    slot: Option<Arc<Mutex<Slot<T>>>>,
+}
+
+impl<T> Complete<T> {
+    pub(crate) fn complete(mut self, output: thread::Result<T>) {
+        if let Some(slot) = self.slot.take() {
+            fill(&slot, output);
+        }
+    }
+}
+
+impl<T> Drop for Complete<T> {
+    fn drop(&mut self) {
+        if let Some(slot) = self.slot.take() {
+            fill(
+                &slot,
+                Err(Box::new("the work was dropped before it finished")),
+            );
+        }
+    }
+}
+
+fn fill<T>(slot: &Mutex<Slot<T>>, output: thread::Result<T>) {
+    let mut slot = slot.lock().unwrap();
+    slot.output = Some(output);
+    let waiter = slot.waiter.take();
+    drop(slot);
+    if let Some(waiter) = waiter {
+        waiter.wake();
+    }
+}
+
+struct Slot<T> {
+    output: Option<thread::Result<T>>,
+    waiter: Option<Wake>,
+}
+
+/// The output of a job on the blocking pool or another thread, see [`run`] and
+/// [`oneshot`].
+pub(crate) struct Blocking<T> {
+    slot: Arc<Mutex<Slot<T>>>,
+    waiter: RemoteWaker,
+}
+
+impl<T> Future for Blocking<T> {
+    type Output = thread::Result<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let me = &mut *self;
+        let mut slot = me.slot.lock().unwrap();
+        if let Some(output) = slot.output.take() {
+            return Poll::Ready(output);
+        }
+        me.waiter.register(cx.waker());
+        slot.waiter = me.waiter.handle();
+        Poll::Pending
+    }
+}
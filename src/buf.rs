@@ -0,0 +1,5 @@
+//! Buffers handed to the kernel, registered ahead of time or owned by an operation.
+
+pub use crate::driver::fixed::{FixedBuf, FixedBufRegistry};
+pub use crate::driver::iobuf::{IoBuf, IoBufMut};
+pub use crate::driver::vectored::IoSliceOwned;
@@ -0,0 +1,286 @@
+//! Connection setup for clients.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::future::{self, Future};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use crate::net::tcp::stream::ATTEMPT_DELAY;
+use crate::net::TcpStream;
+
+/// Resolves an authority, connects to it and keeps released connections around for
+/// reuse.
+///
+/// Resolved addresses are tried Happy Eyeballs style (RFC 8305): IPv6 and IPv4
+/// addresses alternate, and each attempt that has not connected after 250ms is joined
+/// by one to the next address. The first to connect wins.
+///
+/// A new connection goes through the handshake set with
+/// [`with_handshake`](Connector::with_handshake) before it is handed out, which is where
+/// TLS goes. Connections are pooled as `S`, the handshake done.
+///
+/// Pooled connections are keyed by the authority they were connected for. A pooled
+/// connection that was closed by the peer, has bytes left to read or idled past the
+/// idle timeout is discarded instead of handed out.
+pub struct Connector<S = TcpStream> {
+    pool: RefCell<HashMap<String, VecDeque<Idle<S>>>>,
+    handshake: Handshake<S>,
+    max_idle: usize,
+    idle_timeout: Duration,
+    attempt_delay: Duration,
+}
+
+type Handshake<S> = Rc<dyn Fn(&str, TcpStream) -> Pin<Box<dyn Future<Output = io::Result<S>>>>>;
+
+struct Idle<S> {
+    stream: S,
+    since: Instant,
+}
+
+/// A connection a [`Connector`] can pool.
+pub trait Poolable {
+    /// Whether the connection can be handed out again: the peer did not close it and
+    /// there is nothing left to read on it, neither in the kernel nor buffered.
+    fn is_reusable(&self) -> bool;
+}
+
+impl Poolable for TcpStream {
+    fn is_reusable(&self) -> bool {
+        self.is_read_idle() && is_open(self)
+    }
+}
+
+impl Default for Connector {
+    fn default() -> Connector {
+        Connector::new()
+    }
+}
+
+impl Connector {
+    pub fn new() -> Connector {
+        Connector::with_handshake(|_, stream| future::ready(Ok(stream)))
+    }
+}
+
+impl<S: Poolable + 'static> Connector<S> {
+    /// Runs `handshake` with the authority on every new connection, such as a TLS
+    /// client handshake, and hands out what it returns.
+    pub fn with_handshake<F, T>(handshake: F) -> Connector<S>
+    where
+        F: Fn(&str, TcpStream) -> T + 'static,
+        T: Future<Output = io::Result<S>> + 'static,
+    {
+        Connector {
+            pool: RefCell::new(HashMap::new()),
+            handshake: Rc::new(move |authority, stream| Box::pin(handshake(authority, stream))),
+            max_idle: 8,
+            idle_timeout: Duration::from_secs(90),
+            attempt_delay: ATTEMPT_DELAY,
+        }
+    }
+
+    /// Sets how many idle connections are kept per authority, 8 by default.
+    pub fn set_max_idle(&mut self, max_idle: usize) {
+        self.max_idle = max_idle;
+    }
+
+    /// Sets how long a connection may sit in the pool, 90 seconds by default.
+    pub fn set_idle_timeout(&mut self, timeout: Duration) {
+        self.idle_timeout = timeout;
+    }
+
+    /// Sets how long an attempt runs before the next address is tried too.
+    pub fn set_attempt_delay(&mut self, delay: Duration) {
+        self.attempt_delay = delay;
+    }
+
+    /// Returns a pooled connection to `authority` (`host:port`), or connects a new one
+    /// and runs the handshake on it.
+    ///
+    /// Host names are resolved on the blocking pool, see
+    /// [`lookup_host`](crate::net::lookup_host).
+    pub async fn connect(&self, authority: &str) -> io::Result<S> {
+        if let Some(stream) = self.take_idle(authority) {
+            return Ok(stream);
+        }
+        let stream = TcpStream::connect_racing(authority, self.attempt_delay).await?;
+        (self.handshake)(authority, stream).await
+    }
+
+    /// Hands a connection back for reuse by a later `connect` to `authority`. The
+    /// connection has to be at a request boundary, one with bytes left to read is not
+    /// handed out again. The oldest pooled connection is closed to make room.
+    pub fn release(&self, authority: &str, stream: S) {
+        if self.max_idle == 0 {
+            return;
+        }
+        let mut pool = self.pool.borrow_mut();
+        let idle = pool.entry(authority.to_owned()).or_default();
+        while idle.len() >= self.max_idle {
+            idle.pop_front();
+        }
+        idle.push_back(Idle {
+            stream,
+            since: Instant::now(),
+        });
+    }
+
+    /// Number of pooled connections to `authority`.
+    pub fn idle(&self, authority: &str) -> usize {
+        self.pool
+            .borrow()
+            .get(authority)
+            .map_or(0, |idle| idle.len())
+    }
+
+    fn take_idle(&self, authority: &str) -> Option<S> {
+        let mut pool = self.pool.borrow_mut();
+        let idle = pool.get_mut(authority)?;
+        // the most recently released connection is the least likely to be closed.
+        while let Some(Idle { stream, since }) = idle.pop_back() {
+            if since.elapsed() < self.idle_timeout && stream.is_reusable() {
+                return Some(stream);
+            }
+        }
+        pool.remove(authority);
+        None
+    }
+}
+
+/// Whether the peer has not closed `stream`, and sent nothing the stream did not read.
+fn is_open(stream: &TcpStream) -> bool {
+    let mut byte = 0u8;
+    let res = syscall!(recv(
+        stream.as_raw_fd(),
+        &mut byte as *mut u8 as *mut libc::c_void,
+        1,
+        libc::MSG_PEEK | libc::MSG_DONTWAIT
+    ));
+    matches!(res, Err(e) if e.kind() == io::ErrorKind::WouldBlock)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    use futures_util::io::{AsyncReadExt, AsyncWriteExt};
+
+    use crate::net::TcpListener;
+    use crate::time::delay_for;
+    use crate::Runtime;
+
+    #[test]
+    fn a_released_connection_is_handed_out_again() {
+        Runtime::new().unwrap().block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let authority = listener.local_addr().unwrap().to_string();
+            let connector = Connector::new();
+            let stream = connector.connect(&authority).await.unwrap();
+            let (_peer, _) = listener.accept().await.unwrap();
+            let local = stream.local_addr().unwrap();
+
+            connector.release(&authority, stream);
+            assert_eq!(connector.idle(&authority), 1);
+            let stream = connector.connect(&authority).await.unwrap();
+            assert_eq!(stream.local_addr().unwrap(), local);
+            assert_eq!(connector.idle(&authority), 0);
+        });
+    }
+
+    #[test]
+    fn no_connection_is_pooled_without_room() {
+        Runtime::new().unwrap().block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let authority = listener.local_addr().unwrap().to_string();
+            let mut connector = Connector::new();
+            connector.set_max_idle(0);
+            let stream = connector.connect(&authority).await.unwrap();
+            connector.release(&authority, stream);
+            assert_eq!(connector.idle(&authority), 0);
+
+            // lowering the limit makes the next release close the oldest down to it.
+            connector.set_max_idle(2);
+            for _ in 0..2 {
+                let stream = TcpStream::connect(&*authority).await.unwrap();
+                connector.release(&authority, stream);
+            }
+            connector.set_max_idle(1);
+            let stream = TcpStream::connect(&*authority).await.unwrap();
+            connector.release(&authority, stream);
+            assert_eq!(connector.idle(&authority), 1);
+        });
+    }
+
+    #[test]
+    fn a_connection_with_bytes_left_to_read_is_not_reused() {
+        Runtime::new().unwrap().block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let authority = listener.local_addr().unwrap().to_string();
+            let connector = Connector::new();
+            let mut stream = connector.connect(&authority).await.unwrap();
+            let (mut peer, _) = listener.accept().await.unwrap();
+            peer.write_all(b"ab").await.unwrap();
+            // one byte is read, the other stays buffered in the stream.
+            let mut byte = [0; 1];
+            stream.read_exact(&mut byte).await.unwrap();
+            assert!(!stream.is_reusable());
+            connector.release(&authority, stream);
+
+            let stream = connector.connect(&authority).await.unwrap();
+            assert!(stream.is_reusable());
+            assert_eq!(connector.idle(&authority), 0);
+
+            // the peer closing a pooled connection makes it unusable too.
+            let local = stream.local_addr().unwrap();
+            connector.release(&authority, stream);
+            let (peer, _) = listener.accept().await.unwrap();
+            drop(peer);
+            delay_for(Duration::from_millis(20)).await;
+            let stream = connector.connect(&authority).await.unwrap();
+            assert_ne!(stream.local_addr().unwrap(), local);
+        });
+    }
+
+    /// A connection that went through a handshake, standing in for a TLS stream.
+    struct Handshaken(TcpStream);
+
+    impl Poolable for Handshaken {
+        fn is_reusable(&self) -> bool {
+            self.0.is_reusable()
+        }
+    }
+
+    #[test]
+    fn only_new_connections_go_through_the_handshake() {
+        Runtime::new().unwrap().block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let authority = listener.local_addr().unwrap().to_string();
+            let handshakes = Rc::new(Cell::new(0));
+            let count = handshakes.clone();
+            let connector = Connector::with_handshake(move |_, stream| {
+                count.set(count.get() + 1);
+                future::ready(Ok(Handshaken(stream)))
+            });
+            let stream = connector.connect(&authority).await.unwrap();
+            let (_peer, _) = listener.accept().await.unwrap();
+            assert_eq!(handshakes.get(), 1);
+
+            connector.release(&authority, stream);
+            let _stream = connector.connect(&authority).await.unwrap();
+            assert_eq!(handshakes.get(), 1);
+
+            // a failed handshake fails the connect.
+            let connector = Connector::<Handshaken>::with_handshake(|authority, _| {
+                let err = io::Error::new(io::ErrorKind::InvalidData, authority.to_owned());
+                future::ready(Err(err))
+            });
+            let err = connector.connect(&authority).await.err().unwrap();
+            assert_eq!(err.to_string(), authority);
+        });
+    }
+}
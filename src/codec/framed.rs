@@ -0,0 +1,407 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_util::future::poll_fn;
+use futures_util::io::{AsyncBufRead, AsyncWrite};
+use futures_util::stream::Stream;
+use pin_project_lite::pin_project;
+
+use super::{Decoder, Encoder};
+
+pin_project! {
+    /// The frames a [`Decoder`] cuts out of the bytes of a reader, as a stream.
+    ///
+    /// The reader's own buffer is decoded from, which for TCP and Unix streams is the
+    /// runtime's buffer ring, so only frames spanning several reads are copied. Other
+    /// readers are wrapped in a [`BufReader`](crate::io::BufReader) first.
+    pub struct FramedRead<R, D> {
+        #[pin]
+        inner: R,
+        decoder: D,
+        state: ReadState,
+    }
+}
+
+/// Encodes frames with an [`Encoder`] and writes them to a writer.
+pub struct FramedWrite<W, E> {
+    inner: W,
+    encoder: E,
+    buf: Vec<u8>,
+}
+
+pin_project! {
+    /// A [`FramedRead`] and a [`FramedWrite`] on the same stream, with a single codec
+    /// for both directions.
+    pub struct Framed<T, C> {
+        #[pin]
+        inner: T,
+        codec: C,
+        state: ReadState,
+        buf: Vec<u8>,
+    }
+}
+
+/// The bytes of a frame that spans reads, kept until the rest of it arrives.
+#[derive(Default)]
+struct ReadState {
+    buf: Vec<u8>,
+    /// Bytes of `buf` already decoded.
+    pos: usize,
+    eof: bool,
+}
+
+impl<R, D> FramedRead<R, D> {
+    pub fn new(inner: R, decoder: D) -> FramedRead<R, D> {
+        FramedRead {
+            inner,
+            decoder,
+            state: ReadState::default(),
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    pub fn decoder(&self) -> &D {
+        &self.decoder
+    }
+
+    pub fn decoder_mut(&mut self) -> &mut D {
+        &mut self.decoder
+    }
+
+    /// The bytes read and not decoded yet.
+    pub fn read_buffer(&self) -> &[u8] {
+        &self.state.buf[self.state.pos..]
+    }
+
+    /// Unwraps the reader, the bytes of a partly read frame are lost.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: AsyncBufRead, D: Decoder> Stream for FramedRead<R, D> {
+    type Item = io::Result<D::Item>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let me = self.project();
+        poll_frame(me.inner, me.decoder, me.state, cx)
+    }
+}
+
+impl<W, E> FramedWrite<W, E> {
+    pub fn new(inner: W, encoder: E) -> FramedWrite<W, E> {
+        FramedWrite {
+            inner,
+            encoder,
+            buf: Vec::new(),
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    pub fn encoder(&self) -> &E {
+        &self.encoder
+    }
+
+    pub fn encoder_mut(&mut self) -> &mut E {
+        &mut self.encoder
+    }
+
+    /// Encodes `item` without writing it, it goes out with the next
+    /// [`flush`](FramedWrite::flush) or [`send`](FramedWrite::send). Feeding several
+    /// frames before flushing writes them together.
+    pub fn feed<I>(&mut self, item: I) -> io::Result<()>
+    where
+        E: Encoder<I>,
+    {
+        self.encoder.encode(item, &mut self.buf)
+    }
+
+    /// Unwraps the writer, the frames fed and not flushed yet are lost.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: AsyncWrite + Unpin, E> FramedWrite<W, E> {
+    /// Writes the frames fed so far.
+    pub async fn flush(&mut self) -> io::Result<()> {
+        let (inner, buf) = (&mut self.inner, &mut self.buf);
+        poll_fn(|cx| poll_flush(Pin::new(&mut *inner), buf, cx)).await
+    }
+
+    /// Encodes `item` and writes it along with the frames fed before.
+    pub async fn send<I>(&mut self, item: I) -> io::Result<()>
+    where
+        E: Encoder<I>,
+    {
+        self.feed(item)?;
+        self.flush().await
+    }
+}
+
+impl<T, C> Framed<T, C> {
+    pub fn new(inner: T, codec: C) -> Framed<T, C> {
+        Framed {
+            inner,
+            codec,
+            state: ReadState::default(),
+            buf: Vec::new(),
+        }
+    }
+
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    pub fn codec(&self) -> &C {
+        &self.codec
+    }
+
+    pub fn codec_mut(&mut self) -> &mut C {
+        &mut self.codec
+    }
+
+    /// The bytes read and not decoded yet.
+    pub fn read_buffer(&self) -> &[u8] {
+        &self.state.buf[self.state.pos..]
+    }
+
+    /// Encodes `item` without writing it, see [`FramedWrite::feed`].
+    pub fn feed<I>(&mut self, item: I) -> io::Result<()>
+    where
+        C: Encoder<I>,
+    {
+        self.codec.encode(item, &mut self.buf)
+    }
+
+    /// Unwraps the stream, the bytes of a partly read frame and the frames fed and not
+    /// flushed yet are lost.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: AsyncWrite + Unpin, C> Framed<T, C> {
+    /// Writes the frames fed so far.
+    pub async fn flush(&mut self) -> io::Result<()> {
+        let (inner, buf) = (&mut self.inner, &mut self.buf);
+        poll_fn(|cx| poll_flush(Pin::new(&mut *inner), buf, cx)).await
+    }
+
+    /// Encodes `item` and writes it along with the frames fed before.
+    pub async fn send<I>(&mut self, item: I) -> io::Result<()>
+    where
+        C: Encoder<I>,
+    {
+        self.feed(item)?;
+        self.flush().await
+    }
+}
+
+impl<T: AsyncBufRead, C: Decoder> Stream for Framed<T, C> {
+    type Item = io::Result<C::Item>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let me = self.project();
+        poll_frame(me.inner, me.codec, me.state, cx)
+    }
+}
+
+/// Decodes the next frame, reading until a whole one arrived. `None` once the reader
+/// ended between frames.
+fn poll_frame<R: AsyncBufRead, D: Decoder>(
+    mut inner: Pin<&mut R>,
+    decoder: &mut D,
+    state: &mut ReadState,
+    cx: &mut Context<'_>,
+) -> Poll<Option<io::Result<D::Item>>> {
+    loop {
+        if state.pos < state.buf.len() {
+            if let Some((item, n)) = decoder.decode(&state.buf[state.pos..])? {
+                state.consume(n);
+                return Poll::Ready(Some(Ok(item)));
+            }
+        }
+        if state.eof {
+            return Poll::Ready(None);
+        }
+        let chunk = ready!(inner.as_mut().poll_fill_buf(cx))?;
+        if chunk.is_empty() {
+            state.eof = true;
+            if state.pos == state.buf.len() {
+                return Poll::Ready(None);
+            }
+            return Poll::Ready(Some(Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "stream ended within a frame",
+            ))));
+        }
+        // nothing is kept from earlier reads, a frame that arrived whole is decoded from
+        // the reader's buffer.
+        if state.pos == state.buf.len() {
+            if let Some((item, n)) = decoder.decode(chunk)? {
+                inner.as_mut().consume(n);
+                return Poll::Ready(Some(Ok(item)));
+            }
+        }
+        state.buf.extend_from_slice(chunk);
+        let n = chunk.len();
+        inner.as_mut().consume(n);
+    }
+}
+
+/// Writes out `buf`, dropping the bytes written as it goes, then flushes the writer.
+fn poll_flush<W: AsyncWrite>(
+    mut inner: Pin<&mut W>,
+    buf: &mut Vec<u8>,
+    cx: &mut Context<'_>,
+) -> Poll<io::Result<()>> {
+    while !buf.is_empty() {
+        match ready!(inner.as_mut().poll_write(cx, buf))? {
+            0 => return Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+            n => drop(buf.drain(..n)),
+        }
+    }
+    inner.poll_flush(cx)
+}
+
+impl ReadState {
+    /// Marks `n` more bytes decoded, dropping the decoded ones once they make up the
+    /// larger part of the buffer.
+    fn consume(&mut self, n: usize) {
+        self.pos += n;
+        if self.pos == self.buf.len() {
+            self.buf.clear();
+            self.pos = 0;
+        } else if self.pos > self.buf.len() / 2 {
+            self.buf.drain(..self.pos);
+            self.pos = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    use futures_util::future::FutureExt;
+    use futures_util::io::AsyncRead;
+    use futures_util::stream::StreamExt;
+
+    use crate::codec::LengthDelimitedCodec;
+
+    /// A reader handing out its chunks one read at a time, then the end of the stream.
+    struct Chunks(VecDeque<Vec<u8>>);
+
+    impl Chunks {
+        fn new(chunks: &[&[u8]]) -> Chunks {
+            Chunks(chunks.iter().map(|chunk| chunk.to_vec()).collect())
+        }
+    }
+
+    impl AsyncRead for Chunks {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut [u8],
+        ) -> Poll<io::Result<usize>> {
+            let chunk = ready!(self.as_mut().poll_fill_buf(cx))?;
+            let n = chunk.len().min(buf.len());
+            buf[..n].copy_from_slice(&chunk[..n]);
+            self.consume(n);
+            Poll::Ready(Ok(n))
+        }
+    }
+
+    impl AsyncBufRead for Chunks {
+        fn poll_fill_buf(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+            Poll::Ready(Ok(self.get_mut().0.front().map_or(&[], Vec::as_slice)))
+        }
+
+        fn consume(mut self: Pin<&mut Self>, n: usize) {
+            let chunk = self.0.front_mut().unwrap();
+            chunk.drain(..n);
+            if chunk.is_empty() {
+                self.0.pop_front();
+            }
+        }
+    }
+
+    fn next<S: Stream + Unpin>(stream: &mut S) -> Option<S::Item> {
+        stream
+            .next()
+            .now_or_never()
+            .expect("the reader never waits")
+    }
+
+    #[test]
+    fn a_frame_spanning_reads_is_put_together() {
+        let chunks = Chunks::new(&[b"\0\0\0\x05h", b"el", b"lo\0\0\0\0\0\0", b"\0\x01x"]);
+        let mut framed = FramedRead::new(chunks, LengthDelimitedCodec::new());
+        assert_eq!(next(&mut framed).unwrap().unwrap(), b"hello");
+        assert_eq!(next(&mut framed).unwrap().unwrap(), b"");
+        assert_eq!(next(&mut framed).unwrap().unwrap(), b"x");
+        assert!(next(&mut framed).is_none());
+    }
+
+    #[test]
+    fn frames_arriving_in_one_read_are_decoded_from_the_readers_buffer() {
+        let chunks = Chunks::new(&[b"\0\0\0\x03one\0\0\0\0\0\0\0\x05three\0\0"]);
+        let mut framed = FramedRead::new(chunks, LengthDelimitedCodec::new());
+        assert_eq!(next(&mut framed).unwrap().unwrap(), b"one");
+        assert!(framed.read_buffer().is_empty());
+        assert_eq!(next(&mut framed).unwrap().unwrap(), b"");
+        assert_eq!(next(&mut framed).unwrap().unwrap(), b"three");
+        assert!(framed.read_buffer().is_empty());
+        // the start of a frame the stream ends in.
+        let err = next(&mut framed).unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(next(&mut framed).is_none());
+    }
+
+    #[test]
+    fn a_frame_over_the_maximum_fails_the_read() {
+        let mut codec = LengthDelimitedCodec::new();
+        codec.set_max_frame_length(4);
+        let chunks = Chunks::new(&[b"\0\0\0\x04four\0\0", b"\0\x05fives"]);
+        let mut framed = Framed::new(chunks, codec);
+        assert_eq!(next(&mut framed).unwrap().unwrap(), b"four");
+        let err = next(&mut framed).unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn fed_frames_are_written_together() {
+        let mut codec = LengthDelimitedCodec::new();
+        codec.set_max_frame_length(4);
+        let mut framed = FramedWrite::new(Vec::new(), codec);
+        framed.feed(b"one").unwrap();
+        framed.feed(b"").unwrap();
+        assert!(framed.get_ref().is_empty());
+        // a frame over the maximum is not encoded, the frames fed before stay.
+        let err = framed.feed(b"fives").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        framed.send(b"four").now_or_never().unwrap().unwrap();
+        assert_eq!(framed.get_ref(), b"\0\0\0\x03one\0\0\0\0\0\0\0\x04four");
+    }
+}
@@ -0,0 +1,144 @@
+use std::convert::TryFrom;
+use std::io;
+
+use super::{Decoder, Encoder};
+
+/// Longest frame accepted by default.
+const DEFAULT_MAX_FRAME_LENGTH: usize = 8 * 1024 * 1024;
+
+/// Bytes of the length prefix.
+const HEADER_LEN: usize = 4;
+
+/// Frames made of their length as a 32-bit big-endian integer followed by that many
+/// bytes, decoded into a `Vec<u8>` each.
+#[derive(Debug, Clone, Copy)]
+pub struct LengthDelimitedCodec {
+    max_frame_length: usize,
+}
+
+impl LengthDelimitedCodec {
+    pub fn new() -> LengthDelimitedCodec {
+        LengthDelimitedCodec {
+            max_frame_length: DEFAULT_MAX_FRAME_LENGTH,
+        }
+    }
+
+    /// Fails decoding and encoding frames longer than `max` bytes with `InvalidData`,
+    /// so a peer can not make the reader buffer without bound. 8 MiB by default.
+    pub fn set_max_frame_length(&mut self, max: usize) {
+        self.max_frame_length = max;
+    }
+
+    pub fn max_frame_length(&self) -> usize {
+        self.max_frame_length
+    }
+}
+
+impl Default for LengthDelimitedCodec {
+    fn default() -> LengthDelimitedCodec {
+        LengthDelimitedCodec::new()
+    }
+}
+
+impl Decoder for LengthDelimitedCodec {
+    type Item = Vec<u8>;
+
+    fn decode(&mut self, src: &[u8]) -> io::Result<Option<(Vec<u8>, usize)>> {
+        if src.len() < HEADER_LEN {
+            return Ok(None);
+        }
+        let len = u32::from_be_bytes([src[0], src[1], src[2], src[3]]) as usize;
+        if len > self.max_frame_length {
+            return Err(too_long());
+        }
+        let end = HEADER_LEN + len;
+        if src.len() < end {
+            return Ok(None);
+        }
+        Ok(Some((src[HEADER_LEN..end].to_vec(), end)))
+    }
+}
+
+impl<T: AsRef<[u8]>> Encoder<T> for LengthDelimitedCodec {
+    fn encode(&mut self, item: T, dst: &mut Vec<u8>) -> io::Result<()> {
+        let frame = item.as_ref();
+        if frame.len() > self.max_frame_length {
+            return Err(too_long());
+        }
+        let len = u32::try_from(frame.len()).map_err(|_| too_long())?;
+        dst.reserve(HEADER_LEN + frame.len());
+        dst.extend_from_slice(&len.to_be_bytes());
+        dst.extend_from_slice(frame);
+        Ok(())
+    }
+}
+
+fn too_long() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "frame longer than the maximum")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(codec: &mut LengthDelimitedCodec, frames: &[&[u8]]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for frame in frames {
+            codec.encode(frame, &mut buf).unwrap();
+        }
+        buf
+    }
+
+    #[test]
+    fn a_partial_frame_decodes_to_nothing() {
+        let mut codec = LengthDelimitedCodec::new();
+        let buf = encode(&mut codec, &[b"hello"]);
+        assert_eq!(buf, b"\0\0\0\x05hello");
+        for end in 0..buf.len() {
+            assert!(codec.decode(&buf[..end]).unwrap().is_none());
+        }
+        assert_eq!(codec.decode(&buf).unwrap(), Some((b"hello".to_vec(), 9)));
+    }
+
+    #[test]
+    fn a_zero_length_frame_is_just_its_header() {
+        let mut codec = LengthDelimitedCodec::new();
+        let buf = encode(&mut codec, &[b""]);
+        assert_eq!(buf, [0; 4]);
+        assert_eq!(codec.decode(&buf).unwrap(), Some((Vec::new(), 4)));
+    }
+
+    #[test]
+    fn frames_in_one_buffer_decode_one_at_a_time() {
+        let mut codec = LengthDelimitedCodec::new();
+        let mut buf = encode(&mut codec, &[b"one", b"", b"three"]);
+        // the start of a fourth frame.
+        buf.extend_from_slice(&[0, 0]);
+        let mut frames = Vec::new();
+        let mut pos = 0;
+        while let Some((frame, n)) = codec.decode(&buf[pos..]).unwrap() {
+            frames.push(frame);
+            pos += n;
+        }
+        assert_eq!(frames, [&b"one"[..], b"", b"three"]);
+        assert_eq!(&buf[pos..], [0, 0]);
+    }
+
+    #[test]
+    fn a_frame_over_the_maximum_fails() {
+        let mut codec = LengthDelimitedCodec::new();
+        codec.set_max_frame_length(4);
+        assert_eq!(codec.max_frame_length(), 4);
+
+        let buf = encode(&mut codec, &[b"four"]);
+        assert_eq!(codec.decode(&buf).unwrap(), Some((b"four".to_vec(), 8)));
+
+        let mut buf = Vec::new();
+        let err = codec.encode(b"fives", &mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(buf.is_empty());
+        // the header is enough to tell, before the frame arrived.
+        let err = codec.decode(&[0, 0, 0, 5, b'f']).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,31 @@
+//! Turning byte streams into streams of messages and back, for message-oriented
+//! protocols over TCP or Unix streams.
+//!
+//! A [`Decoder`] cuts frames out of the bytes read, an [`Encoder`] writes them, and
+//! [`FramedRead`], [`FramedWrite`] and [`Framed`] put them on top of a stream.
+
+mod framed;
+mod length_delimited;
+
+use std::io;
+
+pub use framed::{Framed, FramedRead, FramedWrite};
+pub use length_delimited::LengthDelimitedCodec;
+
+/// Decodes frames from the bytes read from a stream.
+pub trait Decoder {
+    type Item;
+
+    /// Decodes the frame at the start of `src`, returning it along with how many bytes
+    /// of `src` it took up, or `None` if `src` ends before the frame does.
+    ///
+    /// `src` is the reader's own buffer where a whole frame arrived in one read, so
+    /// frames are only copied when they span reads.
+    fn decode(&mut self, src: &[u8]) -> io::Result<Option<(Self::Item, usize)>>;
+}
+
+/// Encodes frames into the bytes written to a stream.
+pub trait Encoder<Item> {
+    /// Appends the encoded `item` to `dst`.
+    fn encode(&mut self, item: Item, dst: &mut Vec<u8>) -> io::Result<()>;
+}
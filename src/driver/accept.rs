@@ -1,18 +1,87 @@
 use std::io;
-use std::os::unix::io::RawFd;
-use std::ptr;
+use std::mem::{size_of, MaybeUninit};
+use std::net::SocketAddr;
+use std::os::unix::io::{FromRawFd, OwnedFd, RawFd};
+use std::task::{Context, Poll};
+use std::time::Duration;
 
 use io_uring::{opcode, types};
 
-use crate::driver::Action;
+use crate::driver::{to_socket_addr, Action, Completable};
+use crate::net::unix;
+use crate::waker_fn::waker_fn;
 
-pub struct Accept;
+pub struct Accept {
+    storage: Box<(MaybeUninit<libc::sockaddr_storage>, libc::socklen_t)>,
+}
+
+impl Completable for Accept {
+    type Output = OwnedFd;
+
+    fn complete(result: u32) -> OwnedFd {
+        unsafe { OwnedFd::from_raw_fd(result as RawFd) }
+    }
+}
 
 impl Action<Accept> {
-    pub(crate) fn accept(fd: RawFd) -> io::Result<Action<Accept>> {
-        let entry = opcode::Accept::new(types::Fd(fd), ptr::null_mut(), ptr::null_mut())
+    pub fn accept(fd: RawFd) -> io::Result<Action<Accept>> {
+        Action::accept_with(fd, None)
+    }
+
+    /// Like `accept`, cancelled by the kernel once `timeout` elapsed.
+    pub fn accept_timeout(fd: RawFd, timeout: Duration) -> io::Result<Action<Accept>> {
+        Action::accept_with(fd, Some(timeout))
+    }
+
+    fn accept_with(fd: RawFd, timeout: Option<Duration>) -> io::Result<Action<Accept>> {
+        let mut storage = Box::new((
+            MaybeUninit::<libc::sockaddr_storage>::zeroed(),
+            size_of::<libc::sockaddr_storage>() as libc::socklen_t,
+        ));
+        let entry = opcode::Accept::new(
+            types::Fd(fd),
+            storage.0.as_mut_ptr() as *mut _,
+            &mut storage.1,
+        )
+        .flags(libc::SOCK_CLOEXEC)
+        .build();
+        Action::submit_maybe_timeout(Accept { storage }, entry, timeout)
+    }
+}
+
+impl Accept {
+    /// The peer address written by the kernel when the accept completed.
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        unsafe { to_socket_addr(self.storage.0.as_ptr()) }
+    }
+
+    /// The peer address of an accepted Unix socket.
+    pub fn unix_peer_addr(&self) -> io::Result<unix::SocketAddr> {
+        unsafe { unix::SocketAddr::from_storage(self.storage.0.as_ptr(), self.storage.1) }
+    }
+}
+
+/// An accept that stays armed and posts one completion per connection. Needs Linux 5.19,
+/// older kernels reject it with `EINVAL`.
+pub struct AcceptMulti;
+
+impl Action<AcceptMulti> {
+    pub(crate) fn accept_multi(fd: RawFd) -> io::Result<Action<AcceptMulti>> {
+        let entry = opcode::AcceptMulti::new(types::Fd(fd))
             .flags(libc::SOCK_CLOEXEC)
             .build();
-        Action::submit(Accept, entry)
+        Action::submit(AcceptMulti, entry)
+    }
+
+    /// Closes the connections the kernel accepted that were not taken yet, so they do
+    /// not leak when the accept is dropped.
+    pub(crate) fn close_accepted(&mut self) {
+        let waker = waker_fn(|| ());
+        let cx = &mut Context::from_waker(&waker);
+        while let Poll::Ready(Some(shot)) = self.poll_next(cx) {
+            if let Ok(fd) = shot.result {
+                unsafe { libc::close(fd) };
+            }
+        }
     }
 }
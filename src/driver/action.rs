@@ -3,28 +3,125 @@ use std::io;
 use std::mem;
 use std::pin::Pin;
 use std::task::{Context, Poll};
+use std::time::Duration;
 
 use io_uring::squeue::Entry;
 
-use crate::driver::{self, Driver, State};
+use crate::driver::timeout::LinkTimeout;
+use crate::driver::{self, slot, Cqe, Driver, ProvidedBuf, State};
+use crate::error::Error;
 
-pub struct Action<T> {
-    pub driver: Driver,
-    pub action: Option<T>,
-    pub key: u64,
+/// An in-flight io_uring operation.
+///
+/// Dropping an `Action` before it completes asks the kernel to cancel the operation,
+/// any buffers owned by `T` are kept alive until the kernel reports the completion.
+pub struct Action<T: 'static> {
+    pub(crate) driver: Driver,
+    pub(crate) action: Option<T>,
+    pub(crate) key: u64,
+    detached: bool,
+    /// Whether a linked timeout may cancel the operation.
+    timed: bool,
+    /// Whether the operation cancelled itself, polled by a cancelled future.
+    cancelled: bool,
 }
 
 impl<T> Action<T> {
     pub fn submit(action: T, entry: Entry) -> io::Result<Action<T>> {
-        driver::CURRENT.with(|driver| {
-            let key = driver.submit(entry)?;
-            Ok(Action {
+        Action::try_submit(action, entry).map_err(|(e, _)| e)
+    }
+
+    /// Like `submit`, handing `action` back if the entry could not be submitted.
+    pub fn try_submit(action: T, entry: Entry) -> Result<Action<T>, (io::Error, T)> {
+        driver::CURRENT.with(|driver| match driver.submit(entry) {
+            Ok(key) => Ok(Action {
                 driver: driver.clone(),
                 action: Some(action),
                 key,
-            })
+                detached: false,
+                timed: false,
+                cancelled: false,
+            }),
+            Err(e) => Err((e, action)),
+        })
+    }
+
+    /// Submits two operations as a link chain, the second only starts once the first
+    /// completed in full and is cancelled otherwise.
+    pub fn submit_link<U>(
+        first: T,
+        first_entry: Entry,
+        second: U,
+        second_entry: Entry,
+    ) -> io::Result<(Action<T>, Action<U>)> {
+        driver::CURRENT.with(|driver| {
+            let (first_key, second_key) = driver.submit_link(first_entry, second_entry)?;
+            let first = Action {
+                driver: driver.clone(),
+                action: Some(first),
+                key: first_key,
+                detached: false,
+                timed: false,
+                cancelled: false,
+            };
+            let second = Action {
+                driver: driver.clone(),
+                action: Some(second),
+                key: second_key,
+                detached: false,
+                timed: false,
+                cancelled: false,
+            };
+            Ok((first, second))
         })
     }
+
+    /// Submits `entry` linked to a timeout. Once `timeout` elapsed the kernel cancels
+    /// the operation itself, which then completes with `ErrorKind::TimedOut`.
+    pub fn submit_with_timeout(
+        action: T,
+        entry: Entry,
+        timeout: Duration,
+    ) -> io::Result<Action<T>> {
+        let (timer, timer_entry) = LinkTimeout::new(timeout);
+        let (mut action, timer) = Action::submit_link(action, entry, timer, timer_entry)?;
+        // the timer completes on its own, cancelled once the operation completed first.
+        drop(timer.detach());
+        action.timed = true;
+        Ok(action)
+    }
+
+    /// Submits `entry`, linked to a timeout if there is one.
+    pub(crate) fn submit_maybe_timeout(
+        action: T,
+        entry: Entry,
+        timeout: Option<Duration>,
+    ) -> io::Result<Action<T>> {
+        match timeout {
+            Some(timeout) => Action::submit_with_timeout(action, entry, timeout),
+            None => Action::submit(action, entry),
+        }
+    }
+
+    /// Lets the operation run to completion even if the returned handle is dropped.
+    pub fn detach(mut self) -> Detached<T> {
+        self.detached = true;
+        Detached(self)
+    }
+
+    /// Asks the kernel to cancel the operation. It still completes, with
+    /// `Error::Cancelled` or with what it did before the cancellation reached it.
+    pub fn cancel(&self) {
+        if self.action.is_some() {
+            match self.driver.inner.try_borrow_mut() {
+                Ok(mut inner) => inner.cancel(self.key),
+                // cancelled by code the driver runs while borrowed, it takes care of it
+                // once released.
+                Err(_) => self.driver.deferred.cancel(self.key),
+            }
+            self.driver.flush();
+        }
+    }
 }
 
 impl<T> Future for Action<T>
@@ -36,43 +133,239 @@ where
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
         let me = &mut *self;
         let mut inner = me.driver.inner.borrow_mut();
-        let key = me.key as usize;
+        let key = slot(me.key);
         let state = mem::replace(&mut inner.actions[key], State::Submitted);
 
         match state {
             State::Submitted => {
                 inner.actions[key] = State::Waiting(cx.waker().clone());
+                cancel_once(&mut me.cancelled, me.key, &mut inner);
                 Poll::Pending
             }
             State::Waiting(waker) => {
+                cancel_once(&mut me.cancelled, me.key, &mut inner);
                 if !waker.will_wake(cx.waker()) {
                     inner.actions[key] = State::Waiting(cx.waker().clone());
+                    inner.deferred.discard(State::Waiting(waker));
                 } else {
                     inner.actions[key] = State::Waiting(waker);
                 }
                 Poll::Pending
             }
-            State::Completed(cqe) => {
+            State::Completed(cqe, buf) => {
                 inner.actions.remove(key);
-                let result = if cqe.result() >= 0 {
-                    Ok(cqe.result())
-                } else {
-                    Err(io::Error::from_raw_os_error(-cqe.result()))
-                };
-                let flags = cqe.flags();
                 let action = me.action.take().expect("action can not be None");
                 Poll::Ready(Completion {
                     action,
-                    result,
-                    _flags: flags,
+                    result: result(&cqe, me.timed),
+                    cqe,
+                    buf,
                 })
             }
+            State::Multi(..) | State::Ignored(_) => unreachable!("invalid operation state"),
+        }
+    }
+}
+
+impl<T> Action<T> {
+    /// Takes the next completion of a multishot operation, `None` once the completion
+    /// that ended it was taken.
+    pub fn poll_next(&mut self, cx: &mut Context) -> Poll<Option<Shot>> {
+        self.poll_next_owned(cx)
+            .map(|shot| shot.map(|(shot, _)| shot))
+    }
+
+    /// Like `poll_next`, also handing back the operation along with the completion that
+    /// ended it, for the buffers it holds.
+    pub fn poll_next_owned(&mut self, cx: &mut Context) -> Poll<Option<(Shot, Option<T>)>> {
+        if self.action.is_none() {
+            return Poll::Ready(None);
+        }
+        let mut inner = self.driver.inner.borrow_mut();
+        let key = slot(self.key);
+        let (cqe, buf) = match mem::replace(&mut inner.actions[key], State::Submitted) {
+            State::Submitted | State::Waiting(_) => {
+                inner.actions[key] = State::Waiting(cx.waker().clone());
+                cancel_once(&mut self.cancelled, self.key, &mut inner);
+                return Poll::Pending;
+            }
+            State::Completed(cqe, buf) => (cqe, buf),
+            State::Multi(mut queue, _) => match queue.pop_front() {
+                Some((cqe, buf)) => {
+                    inner.actions[key] = State::Multi(queue, None);
+                    (cqe, buf)
+                }
+                None => {
+                    inner.actions[key] = State::Multi(queue, Some(cx.waker().clone()));
+                    cancel_once(&mut self.cancelled, self.key, &mut inner);
+                    return Poll::Pending;
+                }
+            },
+            State::Ignored(_) => unreachable!("invalid operation state"),
+        };
+        let action = match cqe.more() {
+            true => None,
+            false => {
+                if let State::Multi(queue, _) = inner.actions.remove(key) {
+                    inner.memory().recycle(queue);
+                }
+                self.action.take()
+            }
+        };
+        let shot = Shot {
+            result: result(&cqe, self.timed),
+            cqe,
+            buf,
+        };
+        Poll::Ready(Some((shot, action)))
+    }
+
+    /// Whether the completion that ended the operation was taken.
+    pub fn is_finished(&self) -> bool {
+        self.action.is_none()
+    }
+}
+
+impl<T: Completable> Action<T> {
+    /// Like `poll_next`, decoding each completion with [`Completable::update`].
+    pub fn poll_update(&mut self, cx: &mut Context) -> Poll<Option<io::Result<T::Output>>> {
+        self.poll_next(cx)
+            .map(|shot| shot.map(|Shot { result, cqe, .. }| result.map(|_| T::update(&cqe))))
+    }
+}
+
+/// Cancels an operation that waits for the kernel, once, if a cancelled future polls
+/// it.
+fn cancel_once(cancelled: &mut bool, key: u64, inner: &mut driver::Inner) {
+    if inner.cancelling && !*cancelled {
+        *cancelled = true;
+        inner.cancel(key);
+    }
+}
+
+fn result(cqe: &Cqe, timed: bool) -> io::Result<i32> {
+    match cqe.result {
+        n if n >= 0 => Ok(n),
+        // the linked timeout fired and cancelled the operation.
+        n if -n == libc::ECANCELED && timed => Err(io::ErrorKind::TimedOut.into()),
+        n if -n == libc::ECANCELED => Err(Error::Cancelled.into()),
+        n => Err(io::Error::from_raw_os_error(-n)),
+    }
+}
+
+impl<T> Drop for Action<T> {
+    fn drop(&mut self) {
+        // `None` means the completion was already taken and the slot released.
+        let action = match self.action.take() {
+            Some(action) => action,
+            None => return,
+        };
+        let action = Box::new(action);
+        match self.driver.inner.try_borrow_mut() {
+            Ok(mut inner) => {
+                // the owner may close the fd once this returns, an operation that did not
+                // reach the kernel yet has to by then. The cancel of one that did goes out
+                // with the next turn of the loop.
+                let queued = inner.backend.sq_len() > 0;
+                inner.abandon(self.key, action, !self.detached);
+                if queued {
+                    let _ = inner.submit_queued();
+                }
+            }
+            // dropped by code the driver runs while borrowed, it takes care of it once
+            // released.
+            Err(_) => self
+                .driver
+                .deferred
+                .orphan(self.key, action, !self.detached),
         }
+        self.driver.flush();
+    }
+}
+
+/// An operation that keeps running in the background when dropped.
+pub struct Detached<T: 'static>(Action<T>);
+
+impl<T> Future for Detached<T>
+where
+    T: Unpin,
+{
+    type Output = Completion<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        Pin::new(&mut self.0).poll(cx)
     }
 }
 
+/// A finished operation along with its result.
+///
+/// `cqe` carries the raw result and flags, operations posting more than one completion
+/// or selecting a provided buffer read them from there.
 pub struct Completion<T> {
     pub(crate) action: T,
     pub(crate) result: io::Result<i32>,
-    pub(crate) _flags: u32,
+    pub(crate) cqe: Cqe,
+    /// The buffer the kernel picked for an operation submitted with
+    /// `IOSQE_BUFFER_SELECT`.
+    pub(crate) buf: Option<ProvidedBuf>,
+}
+
+impl<T> Completion<T> {
+    /// The raw result and flags the kernel posted.
+    pub fn cqe(&self) -> Cqe {
+        self.cqe
+    }
+}
+
+impl<T: Completable> Completion<T> {
+    /// The result decoded into the output of the operation.
+    pub fn output(self) -> io::Result<T::Output> {
+        let cqe = self.cqe;
+        self.result.map(|_| T::update(&cqe))
+    }
+
+    /// Like `output`, also handing back the operation for what else it holds.
+    pub fn into_parts(self) -> (io::Result<T::Output>, T) {
+        let cqe = self.cqe;
+        (self.result.map(|_| T::update(&cqe)), self.action)
+    }
+}
+
+/// An operation whose result stands for a typed output, such as the fd an accept
+/// returns or how many bytes a read transferred.
+///
+/// The kernel posts every result as an `i32` that is negative on failure. Failures turn
+/// into errors before `complete` sees the result, which is decoded there once instead of
+/// cast by every consumer.
+pub trait Completable {
+    type Output;
+
+    fn complete(result: u32) -> Self::Output;
+
+    /// Decodes a completion along with its flags, for operations that need more than
+    /// the result: whether more completions follow, which provided buffer the kernel
+    /// picked, or whether it is the notification of a zero copy send.
+    ///
+    /// Every completion the operation posts goes through here, a failure excepted. A
+    /// single shot operation posts one. A multishot operation posts one per event
+    /// flagged with [`Cqe::more`], taken with [`Action::poll_update`], followed by the
+    /// one that ended it, which is not flagged and hands back the operation's buffers.
+    /// Defaults to `complete` on the result.
+    fn update(cqe: &Cqe) -> Self::Output {
+        Self::complete(cqe.result as u32)
+    }
+}
+
+/// One completion of a multishot operation.
+pub struct Shot {
+    pub(crate) result: io::Result<i32>,
+    pub(crate) cqe: Cqe,
+    pub(crate) buf: Option<ProvidedBuf>,
+}
+
+impl Shot {
+    /// The raw result and flags the kernel posted.
+    pub fn cqe(&self) -> Cqe {
+        self.cqe
+    }
 }
@@ -0,0 +1,66 @@
+use std::io;
+use std::mem;
+use std::os::unix::io::RawFd;
+
+use io_uring::opcode;
+use io_uring::squeue::{Entry, Flags};
+
+use crate::driver::files::fixed_slot;
+use crate::driver::{Action, Completable};
+
+/// `IORING_OP_FTRUNCATE`, Linux 6.9. io-uring 0.5 has no builder for it.
+pub(crate) const OP_FTRUNCATE: u8 = 55;
+
+// `build_ftruncate` edits the entry as the bytes of an `io_uring_sqe`.
+const _: () = assert!(mem::size_of::<Entry>() == 64);
+
+/// An operation on the space of a file: preallocating it, truncating the file or
+/// advising the kernel how it will be accessed.
+pub struct Allocate;
+
+impl Completable for Allocate {
+    type Output = ();
+
+    fn complete(_: u32) {}
+}
+
+impl Action<Allocate> {
+    /// Allocates `len` bytes of the file open as `fd` from `offset`, `mode` takes the
+    /// `FALLOC_FL_*` flags of `fallocate(2)`.
+    pub fn fallocate(fd: RawFd, offset: u64, len: u64, mode: i32) -> io::Result<Action<Allocate>> {
+        let entry = target!(fd, |fd| opcode::Fallocate64::new(fd, len as _)
+            .offset64(offset as _)
+            .mode(mode)
+            .build());
+        Action::submit(Allocate, entry)
+    }
+
+    /// Advises the kernel how `len` bytes from `offset` will be accessed, a `len` of 0
+    /// covers the rest of the file. `advice` is one of the `POSIX_FADV_*` values.
+    pub fn fadvise(fd: RawFd, offset: u64, len: u32, advice: i32) -> io::Result<Action<Allocate>> {
+        let entry = target!(fd, |fd| opcode::Fadvise::new(fd, len as _, advice)
+            .offset64(offset as _)
+            .build());
+        Action::submit(Allocate, entry)
+    }
+
+    /// Truncates or extends the file open as `fd` to `len` bytes. Needs Linux 6.9, see
+    /// [`Features::ftruncate`](crate::driver::Features::ftruncate).
+    pub fn ftruncate(fd: RawFd, len: u64) -> io::Result<Action<Allocate>> {
+        let entry = match fixed_slot(fd) {
+            Some(slot) => build_ftruncate(slot as i32, len).flags(Flags::FIXED_FILE),
+            None => build_ftruncate(fd, len),
+        };
+        Action::submit(Allocate, entry)
+    }
+}
+
+fn build_ftruncate(fd: i32, len: u64) -> Entry {
+    // `Entry` is a `repr(C)` wrapper of `io_uring_sqe`, which holds the fd at offset 4
+    // and the length at offset 8.
+    let mut sqe: [u8; 64] = unsafe { mem::transmute(opcode::Nop::new().build()) };
+    sqe[0] = OP_FTRUNCATE;
+    sqe[4..8].copy_from_slice(&fd.to_ne_bytes());
+    sqe[8..16].copy_from_slice(&len.to_ne_bytes());
+    unsafe { mem::transmute(sqe) }
+}
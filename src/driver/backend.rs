@@ -0,0 +1,116 @@
+use std::io;
+use std::os::unix::io::RawFd;
+use std::time::Duration;
+
+use io_uring::squeue::Entry;
+
+use crate::driver::Cqe;
+
+/// Setup flags of the ring, see
+/// [`Runtime::with_ring_flags`](crate::Runtime::with_ring_flags).
+///
+/// By default the ring is set up with `COOP_TASKRUN` and `TASKRUN_FLAG`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RingFlags {
+    /// `IORING_SETUP_COOP_TASKRUN`: completions are posted when the thread enters the
+    /// kernel next rather than by interrupting it. Needs Linux 5.19.
+    pub coop_taskrun: bool,
+    /// `IORING_SETUP_TASKRUN_FLAG`: the kernel flags pending completions in the ring,
+    /// so the driver only enters it when there is work. Needs Linux 5.19.
+    pub taskrun_flag: bool,
+    /// `IORING_SETUP_DEFER_TASKRUN`: completions are only posted when the driver asks
+    /// for them, saving the interrupts of the other modes. Implies `single_issuer` and
+    /// `taskrun_flag`. Needs Linux 6.1.
+    pub defer_taskrun: bool,
+    /// `IORING_SETUP_SINGLE_ISSUER`: only the thread that created the runtime submits,
+    /// which lets the kernel skip locking. Needs Linux 6.0.
+    pub single_issuer: bool,
+}
+
+impl Default for RingFlags {
+    fn default() -> RingFlags {
+        RingFlags {
+            coop_taskrun: true,
+            taskrun_flag: true,
+            defer_taskrun: false,
+            single_issuer: false,
+        }
+    }
+}
+
+impl RingFlags {
+    /// No setup flags, what every kernel supports.
+    pub const NONE: RingFlags = RingFlags {
+        coop_taskrun: false,
+        taskrun_flag: false,
+        defer_taskrun: false,
+        single_issuer: false,
+    };
+}
+
+/// The queue pair a [`Driver`](crate::driver::Driver) submits operations to.
+///
+/// Entries are always io_uring submission entries, a backend decides how they are
+/// executed and reports one completion per entry through [`Backend::reap`].
+pub trait Backend {
+    /// Queues `sqe`, returns false if the submission queue is full.
+    ///
+    /// # Safety
+    ///
+    /// Every pointer in `sqe` must stay valid until its completion is reaped.
+    unsafe fn push(&mut self, sqe: &Entry) -> bool;
+
+    /// How many more entries `push` accepts before the submission queue is full.
+    fn sq_space(&mut self) -> usize;
+
+    /// How many entries are queued and not handed over yet.
+    fn sq_len(&mut self) -> usize;
+
+    /// The setup flags in effect, those the kernel did not support are left out.
+    fn flags(&self) -> RingFlags;
+
+    /// Whether operations with `opcode` can be submitted.
+    fn supports(&mut self, opcode: u8) -> bool;
+
+    /// Hands queued entries over for execution.
+    fn submit(&mut self) -> io::Result<usize>;
+
+    /// Like `submit`, then blocks until at least `want` completions are available.
+    fn submit_and_wait(&mut self, want: usize) -> io::Result<usize>;
+
+    /// Like `submit_and_wait`, gives up once `timeout` elapsed, failing with `ETIME`.
+    /// Fails with [`Error::KernelFeatureMissing`](crate::Error::KernelFeatureMissing)
+    /// if the wait can not be bounded, whatever `want` is.
+    fn submit_and_wait_timeout(&mut self, want: usize, timeout: Duration) -> io::Result<usize>;
+
+    /// Whether completions are pending that need a `submit` to be posted.
+    fn taskrun(&mut self) -> bool;
+
+    /// Whether posted completions are waiting to be reaped.
+    fn has_completions(&mut self) -> bool;
+
+    /// Posts the completions the kernel still holds back, those that overflowed the
+    /// completion queue or wait for task work to run, without waiting for more.
+    fn post_completions(&mut self) -> io::Result<()>;
+
+    /// Registers the provided buffer ring at `ring_addr` as group `bgid`.
+    fn register_buf_ring(&mut self, ring_addr: u64, entries: u16, bgid: u16) -> io::Result<()>;
+
+    /// Unregisters the provided buffer ring of group `bgid`.
+    fn unregister_buf_ring(&mut self, bgid: u16) -> io::Result<()>;
+
+    /// Registers `bufs` as the fixed buffers `ReadFixed` and `WriteFixed` index into.
+    fn register_buffers(&mut self, bufs: &[libc::iovec]) -> io::Result<()>;
+
+    /// Unregisters the fixed buffers.
+    fn unregister_buffers(&mut self) -> io::Result<()>;
+
+    /// Registers an empty table of `nr` fixed files.
+    fn register_files_sparse(&mut self, nr: u32) -> io::Result<()>;
+
+    /// Replaces the fixed files starting at `offset` with `fds`, -1 empties a slot.
+    fn update_files(&mut self, offset: u32, fds: &[RawFd]) -> io::Result<usize>;
+
+    /// Passes every posted completion with its user data to `f`.
+    fn reap(&mut self, f: &mut dyn FnMut(u64, Cqe));
+}
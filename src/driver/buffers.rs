@@ -1,81 +1,203 @@
-use std::mem::ManuallyDrop;
+use std::alloc::{self, Layout};
+use std::cell::{Cell, RefCell};
+use std::io;
+use std::mem;
 use std::ops;
+use std::ptr;
+use std::rc::Rc;
+use std::slice;
+use std::sync::atomic::{AtomicU16, Ordering};
 
-use io_uring::opcode;
-
-use crate::driver::Driver;
-
+/// The buffer group reads select their buffer from.
 pub const GROUP_ID: u16 = 1337;
 
-#[derive(Debug)]
+/// `struct io_uring_buf`, one slot of the ring.
+#[repr(C)]
+struct RingEntry {
+    addr: u64,
+    len: u32,
+    bid: u16,
+    resv: u16,
+}
+
+/// A ring of equally sized buffers registered with `IORING_REGISTER_PBUF_RING`, the
+/// kernel picks one when a read with `IOSQE_BUFFER_SELECT` has data.
+///
+/// Before Linux 5.19 the buffers are handed to the kernel with
+/// `IORING_OP_PROVIDE_BUFFERS` instead, see [`Buffers::provided`].
 pub struct Buffers {
-    pub size: usize,
-    pub num: usize,
-    pub mem: *mut u8,
+    ring: *mut RingEntry,
+    entries: u16,
+    size: usize,
+    mem: *mut u8,
+    tail: Cell<u16>,
+    /// Buffers selected by reads and not returned yet.
+    in_use: Cell<u16>,
+    /// Cleared once the ring is unregistered, buffers returned after that are not
+    /// handed back to the kernel.
+    registered: Cell<bool>,
+    /// Buffers returned and not provided to the kernel again yet, `None` for a ring.
+    returned: Option<RefCell<Vec<u16>>>,
 }
 
 impl Buffers {
-    pub fn new(num: usize, size: usize) -> Buffers {
-        let total = num * size;
-        let mut mem = ManuallyDrop::new(Vec::<u8>::with_capacity(total as usize));
-        Buffers {
-            mem: mem.as_mut_ptr(),
-            num,
+    pub fn new(entries: u16, size: usize) -> io::Result<Rc<Buffers>> {
+        let buffers = Buffers::alloc(entries, size, None)?;
+        for bid in 0..entries {
+            buffers.push(bid);
+        }
+        Ok(Rc::new(buffers))
+    }
+
+    /// Buffers the driver provides to the kernel with `IORING_OP_PROVIDE_BUFFERS`, all
+    /// of them at first and then each one as it is returned, see
+    /// [`take_returned`](Buffers::take_returned).
+    pub fn provided(entries: u16, size: usize) -> io::Result<Rc<Buffers>> {
+        let returned = RefCell::new(Vec::with_capacity(entries as usize));
+        Buffers::alloc(entries, size, Some(returned)).map(Rc::new)
+    }
+
+    fn alloc(
+        entries: u16,
+        size: usize,
+        returned: Option<RefCell<Vec<u16>>>,
+    ) -> io::Result<Buffers> {
+        if !entries.is_power_of_two() || entries > 1 << 15 || size == 0 || size > u32::MAX as usize
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "buffer ring entries must be a power of two up to 32768",
+            ));
+        }
+        let (ring_layout, mem_layout) = layouts(entries, size)?;
+        let ring = unsafe { alloc::alloc_zeroed(ring_layout) as *mut RingEntry };
+        let mem = unsafe { alloc::alloc(mem_layout) };
+        if ring.is_null() {
+            alloc::handle_alloc_error(ring_layout);
+        }
+        if mem.is_null() {
+            alloc::handle_alloc_error(mem_layout);
+        }
+        Ok(Buffers {
+            ring,
+            entries,
             size,
+            mem,
+            tail: Cell::new(0),
+            in_use: Cell::new(0),
+            registered: Cell::new(false),
+            returned,
+        })
+    }
+
+    pub fn ring_addr(&self) -> u64 {
+        self.ring as u64
+    }
+
+    pub fn entries(&self) -> u16 {
+        self.entries
+    }
+
+    /// Buffers the kernel filled that are still held by their readers.
+    #[cfg(feature = "metrics")]
+    pub fn in_use(&self) -> u16 {
+        self.in_use.get()
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn set_registered(&self, registered: bool) {
+        self.registered.set(registered);
+    }
+
+    /// Whether the buffers are provided with `IORING_OP_PROVIDE_BUFFERS` rather than
+    /// through the ring.
+    pub fn is_provided(&self) -> bool {
+        self.returned.is_some()
+    }
+
+    /// The buffers returned since the last call, to be provided to the kernel again.
+    pub fn take_returned(&self, bids: &mut Vec<u16>) {
+        if let Some(returned) = &self.returned {
+            bids.append(&mut returned.borrow_mut());
         }
     }
 
-    pub unsafe fn select(&mut self, bid: u16, driver: Driver) -> ProvidedBuf {
-        let ptr = self.mem.add(self.size * bid as usize);
-        let buf = ManuallyDrop::new(Vec::from_raw_parts(ptr, 0, self.size));
+    /// Takes buffer `bid`, which the kernel filled with `len` bytes.
+    pub fn select(self: &Rc<Self>, bid: u16, len: usize) -> ProvidedBuf {
+        self.in_use.set(self.in_use.get() + 1);
         ProvidedBuf {
-            buf,
-            driver: Some(driver),
+            buffers: self.clone(),
             bid,
+            len: len.min(self.size),
         }
     }
-}
 
-pub struct ProvidedBuf {
-    buf: ManuallyDrop<Vec<u8>>,
-    driver: Option<Driver>,
-    bid: u16,
+    /// Keeps `bids` to be provided to the kernel on a later turn, the submission queue
+    /// being full.
+    pub fn requeue(&self, bids: &[u16]) {
+        if let Some(returned) = &self.returned {
+            returned.borrow_mut().extend_from_slice(bids);
+        }
+    }
+
+    /// Makes buffer `bid` available to the kernel again.
+    fn push(&self, bid: u16) {
+        if let Some(returned) = &self.returned {
+            returned.borrow_mut().push(bid);
+            return;
+        }
+        let tail = self.tail.get();
+        unsafe {
+            let entry = self.ring.add((tail & (self.entries - 1)) as usize);
+            ptr::addr_of_mut!((*entry).addr).write(self.buf_ptr(bid) as u64);
+            ptr::addr_of_mut!((*entry).len).write(self.size as u32);
+            ptr::addr_of_mut!((*entry).bid).write(bid);
+            // the ring tail shares its slot with `resv` of the first entry.
+            let shared_tail = &*(ptr::addr_of!((*self.ring).resv) as *const AtomicU16);
+            shared_tail.store(tail.wrapping_add(1), Ordering::Release);
+        }
+        self.tail.set(tail.wrapping_add(1));
+    }
+
+    pub fn buf_ptr(&self, bid: u16) -> *mut u8 {
+        unsafe { self.mem.add(self.size * bid as usize) }
+    }
 }
 
-impl Drop for ProvidedBuf {
+impl Drop for Buffers {
     fn drop(&mut self) {
-        if let Some(driver) = self.driver.take() {
-            let driver = &mut *driver.inner.borrow_mut();
-            let buffers = &mut driver.buffers;
-            let entry = opcode::ProvideBuffers::new(
-                self.buf.as_mut_ptr(),
-                buffers.size as _,
-                1,
-                GROUP_ID,
-                self.bid,
-            )
-            .build()
-            .user_data(u64::MAX);
-
-            let ring = &mut driver.ring;
-            if ring.submission().is_full() {
-                ring.submit().expect("submit fail");
-                ring.submission().sync();
-            }
-            unsafe {
-                ring.submission().push(&entry).expect("push entry fail");
-            }
-            ring.submit().expect("submit fail");
+        let (ring_layout, mem_layout) = layouts(self.entries, self.size).expect("valid layout");
+        unsafe {
+            alloc::dealloc(self.ring as *mut u8, ring_layout);
+            alloc::dealloc(self.mem, mem_layout);
         }
     }
 }
 
-impl Default for ProvidedBuf {
-    fn default() -> Self {
-        ProvidedBuf {
-            buf: ManuallyDrop::new(Vec::new()),
-            driver: None,
-            bid: 0,
+fn layouts(entries: u16, size: usize) -> io::Result<(Layout, Layout)> {
+    let invalid = |_| io::Error::new(io::ErrorKind::InvalidInput, "buffer ring too large");
+    // the kernel wants the ring page aligned.
+    let ring = Layout::from_size_align(entries as usize * 16, 4096).map_err(invalid)?;
+    let total = size.saturating_mul(entries as usize);
+    let mem = Layout::from_size_align(total, 64).map_err(invalid)?;
+    Ok((ring, mem))
+}
+
+/// A buffer picked by the kernel, it goes back to the ring when dropped.
+pub struct ProvidedBuf {
+    buffers: Rc<Buffers>,
+    bid: u16,
+    len: usize,
+}
+
+impl Drop for ProvidedBuf {
+    fn drop(&mut self) {
+        self.buffers.in_use.set(self.buffers.in_use.get() - 1);
+        if self.buffers.registered.get() {
+            self.buffers.push(self.bid);
         }
     }
 }
@@ -84,12 +206,91 @@ impl ops::Deref for ProvidedBuf {
     type Target = [u8];
 
     fn deref(&self) -> &[u8] {
-        &self.buf[..]
+        unsafe { slice::from_raw_parts(self.buffers.buf_ptr(self.bid), self.len) }
     }
 }
 
-impl ops::DerefMut for ProvidedBuf {
-    fn deref_mut(&mut self) -> &mut [u8] {
-        &mut self.buf[..]
+/// Smallest and largest buffer size the ring is resized to.
+const MIN_SIZE: usize = 512;
+const MAX_SIZE: usize = 64 * 1024;
+
+/// Reads observed before the buffer size is reconsidered.
+const WINDOW: u64 = 256;
+
+/// Most buffers the ring grows to when reads find every buffer in use.
+const MAX_ENTRIES: u16 = 1024;
+
+/// Tracks how full the selected buffers come back and picks the next buffer size.
+///
+/// The size doubles when more than one in eight reads fills its buffer, a sign that
+/// messages are being split, and halves when no read in the window used more than a
+/// quarter of its buffer. The number of buffers doubles once a read found every buffer
+/// in use.
+#[derive(Debug, Default)]
+pub struct Sizing {
+    /// Cleared once the size was set explicitly.
+    pub adaptive: bool,
+    pub reads: u64,
+    pub filled: u64,
+    pub resizes: u64,
+    /// Reads that failed with `ENOBUFS`, every buffer being in use.
+    pub exhausted: u64,
+    /// Times the ring grew for lack of buffers.
+    pub grows: u64,
+    /// Whether a read found every buffer in use since the ring last grew.
+    starved: bool,
+    window_reads: u64,
+    window_filled: u64,
+    window_largest: usize,
+}
+
+impl Sizing {
+    pub fn adaptive() -> Sizing {
+        Sizing {
+            adaptive: true,
+            ..Sizing::default()
+        }
+    }
+
+    pub fn record(&mut self, len: usize, size: usize) {
+        self.reads += 1;
+        self.window_reads += 1;
+        if len >= size {
+            self.filled += 1;
+            self.window_filled += 1;
+        }
+        self.window_largest = self.window_largest.max(len);
+    }
+
+    pub fn record_exhausted(&mut self) {
+        self.exhausted += 1;
+        self.starved = true;
+    }
+
+    /// The number of buffers the ring should have once a read found every buffer in
+    /// use, `None` if none did or the ring is as large as it gets.
+    pub fn next_entries(&mut self, entries: u16) -> Option<u16> {
+        if !mem::take(&mut self.starved) || !self.adaptive || entries >= MAX_ENTRIES {
+            return None;
+        }
+        Some(entries * 2)
+    }
+
+    /// The size the ring should have once a full window was observed.
+    pub fn next_size(&mut self, size: usize) -> Option<usize> {
+        if !self.adaptive || self.window_reads < WINDOW {
+            return None;
+        }
+        let next = if self.window_filled * 8 > self.window_reads {
+            (size * 2).min(MAX_SIZE)
+        } else if self.window_largest <= size / 4 {
+            (size / 2).max(MIN_SIZE)
+        } else {
+            size
+        };
+        self.window_reads = 0;
+        self.window_filled = 0;
+        self.window_largest = 0;
+        Some(next).filter(|&next| next != size)
     }
 }
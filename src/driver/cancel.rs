@@ -0,0 +1,46 @@
+use std::io;
+use std::mem;
+use std::os::unix::io::RawFd;
+
+use io_uring::opcode;
+use io_uring::squeue::Entry;
+
+use crate::driver::{files, Action, Completable};
+
+/// `IORING_ASYNC_CANCEL_*`, io-uring 0.5 has no builder taking them.
+const CANCEL_ALL: u32 = 1 << 0;
+const CANCEL_FD: u32 = 1 << 1;
+const CANCEL_FD_FIXED: u32 = 1 << 3;
+
+/// Cancels every operation on an fd, completing with how many it cancelled. Needs
+/// Linux 5.19.
+pub struct CancelFd;
+
+impl Completable for CancelFd {
+    type Output = usize;
+
+    fn complete(result: u32) -> usize {
+        result as usize
+    }
+}
+
+impl Action<CancelFd> {
+    pub fn cancel_fd(fd: RawFd) -> io::Result<Action<CancelFd>> {
+        let (fd, flags) = match files::fixed_slot(fd) {
+            Some(slot) => (slot as RawFd, CANCEL_ALL | CANCEL_FD | CANCEL_FD_FIXED),
+            None => (fd, CANCEL_ALL | CANCEL_FD),
+        };
+        let entry = opcode::AsyncCancel::new(0).build();
+        Action::submit(CancelFd, by_fd(entry, fd, flags))
+    }
+}
+
+/// Turns a cancel by key into one matching the operations on `fd`.
+fn by_fd(entry: Entry, fd: RawFd, flags: u32) -> Entry {
+    // `Entry` is a `repr(C)` wrapper of `io_uring_sqe`, which holds the fd at offset 4
+    // and the cancel flags at offset 28.
+    let mut sqe: [u8; 64] = unsafe { mem::transmute(entry) };
+    sqe[4..8].copy_from_slice(&fd.to_ne_bytes());
+    sqe[28..32].copy_from_slice(&flags.to_ne_bytes());
+    unsafe { mem::transmute(sqe) }
+}
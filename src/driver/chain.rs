@@ -0,0 +1,120 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::ops::Deref;
+
+use crate::driver::ProvidedBuf;
+
+/// Received bytes, in a buffer of the driver's ring when one was available.
+pub enum Buf {
+    Owned(Vec<u8>),
+    Provided(ProvidedBuf),
+}
+
+impl Deref for Buf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Buf::Owned(buf) => buf,
+            Buf::Provided(buf) => buf,
+        }
+    }
+}
+
+/// A received buffer and the offset its unconsumed bytes start at.
+pub struct Segment {
+    buf: Buf,
+    pos: usize,
+}
+
+impl Segment {
+    pub fn as_slice(&self) -> &[u8] {
+        &self.buf[self.pos..]
+    }
+}
+
+/// A sequence of received buffers, kept as they came back from the kernel.
+///
+/// Filled by `read_chain` and drained by `write_chain` on streams, which lets a proxy
+/// look at the bytes it forwards without copying or coalescing them. Ring buffers held
+/// by a chain are not available to reads until the chain drops them.
+#[derive(Default)]
+pub struct BufChain {
+    segments: VecDeque<Segment>,
+    len: usize,
+}
+
+impl BufChain {
+    pub fn new() -> BufChain {
+        BufChain::default()
+    }
+
+    /// Total number of bytes in the chain.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The bytes of every segment, in order.
+    pub fn segments(&self) -> impl Iterator<Item = &[u8]> {
+        self.segments.iter().map(Segment::as_slice)
+    }
+
+    /// Drops every segment, returning ring buffers to the kernel.
+    pub fn clear(&mut self) {
+        self.segments.clear();
+        self.len = 0;
+    }
+
+    pub(crate) fn push(&mut self, buf: Buf, pos: usize) {
+        self.len += buf.len() - pos;
+        self.segments.push_back(Segment { buf, pos });
+    }
+
+    /// Removes up to `max` segments from the front.
+    pub(crate) fn take(&mut self, max: usize) -> Vec<Segment> {
+        let n = max.min(self.segments.len());
+        let segments: Vec<Segment> = self.segments.drain(..n).collect();
+        self.len -= segments.iter().map(|s| s.as_slice().len()).sum::<usize>();
+        segments
+    }
+
+    /// Puts segments taken with `take` back in front.
+    pub(crate) fn put_back(&mut self, segments: Vec<Segment>) {
+        for segment in segments.into_iter().rev() {
+            self.len += segment.as_slice().len();
+            self.segments.push_front(segment);
+        }
+    }
+
+    /// Drops the first `n` bytes.
+    pub(crate) fn advance(&mut self, mut n: usize) {
+        while n > 0 {
+            let front = match self.segments.front_mut() {
+                Some(front) => front,
+                None => return,
+            };
+            let available = front.as_slice().len();
+            if n < available {
+                front.pos += n;
+                self.len -= n;
+                return;
+            }
+            n -= available;
+            self.len -= available;
+            self.segments.pop_front();
+        }
+    }
+}
+
+impl fmt::Debug for BufChain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BufChain")
+            .field("len", &self.len)
+            .field("segments", &self.segments.len())
+            .finish()
+    }
+}
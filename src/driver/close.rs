@@ -0,0 +1,22 @@
+use std::io;
+use std::os::unix::io::RawFd;
+
+use io_uring::{opcode, types};
+
+use crate::driver::{Action, Completable};
+
+pub struct Close;
+
+impl Completable for Close {
+    type Output = ();
+
+    fn complete(_: u32) {}
+}
+
+impl Action<Close> {
+    /// Closes `fd`, which must not be used again once this is submitted.
+    pub fn close(fd: RawFd) -> io::Result<Action<Close>> {
+        let entry = opcode::Close::new(types::Fd(fd)).build();
+        Action::submit(Close, entry)
+    }
+}
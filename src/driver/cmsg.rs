@@ -0,0 +1,211 @@
+use std::io;
+use std::mem::{size_of, size_of_val};
+use std::net::SocketAddr;
+use std::os::unix::io::RawFd;
+use std::ptr;
+use std::slice;
+
+use io_uring::opcode;
+
+use crate::driver::{Action, Completable, MsgHdr};
+
+/// Control messages of a `msghdr`, kept in a buffer aligned for `cmsghdr`.
+pub struct Cmsgs {
+    buf: Vec<u64>,
+    len: usize,
+    /// Whether the kernel dropped received messages that did not fit.
+    truncated: bool,
+}
+
+impl Cmsgs {
+    /// An empty buffer with room for `capacity` bytes of control messages.
+    pub fn with_capacity(capacity: usize) -> Cmsgs {
+        Cmsgs {
+            buf: vec![0; capacity.div_ceil(size_of::<u64>())],
+            len: 0,
+            truncated: false,
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        self.buf.len() * size_of::<u64>()
+    }
+
+    fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.buf.as_mut_ptr().cast()
+    }
+
+    fn bytes(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.buf.as_ptr().cast(), self.len) }
+    }
+
+    /// Appends a message carrying `data`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the buffer has no room for it.
+    pub fn push<T: Copy>(&mut self, level: libc::c_int, ty: libc::c_int, data: T) {
+        self.push_slice(level, ty, slice::from_ref(&data))
+    }
+
+    /// Appends a message carrying the elements of `data`, like `push`.
+    pub fn push_slice<T: Copy>(&mut self, level: libc::c_int, ty: libc::c_int, data: &[T]) {
+        let size = size_of_val(data) as u32;
+        let space = unsafe { libc::CMSG_SPACE(size) } as usize;
+        assert!(
+            self.len + space <= self.capacity(),
+            "control buffer too small"
+        );
+        let header = libc::cmsghdr {
+            cmsg_len: unsafe { libc::CMSG_LEN(size) } as _,
+            cmsg_level: level,
+            cmsg_type: ty,
+        };
+        unsafe {
+            let at = self.as_mut_ptr().add(self.len);
+            ptr::write_bytes(at, 0, space);
+            ptr::write_unaligned(at.cast(), header);
+            let at = at.add(libc::CMSG_LEN(0) as usize);
+            ptr::copy_nonoverlapping(data.as_ptr().cast::<u8>(), at, size as usize);
+        }
+        self.len += space;
+    }
+
+    /// Whether received messages were dropped for lack of room, file descriptors passed
+    /// in them are closed.
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// The level, type and data of each message.
+    pub fn iter(&self) -> impl Iterator<Item = (libc::c_int, libc::c_int, &[u8])> {
+        let bytes = self.bytes();
+        let data_offset = unsafe { libc::CMSG_LEN(0) } as usize;
+        let mut offset = 0;
+        std::iter::from_fn(move || {
+            if offset + size_of::<libc::cmsghdr>() > bytes.len() {
+                return None;
+            }
+            let header: libc::cmsghdr =
+                unsafe { ptr::read_unaligned(bytes[offset..].as_ptr().cast()) };
+            let len = header.cmsg_len as usize;
+            if len < data_offset || offset + len > bytes.len() {
+                return None;
+            }
+            let data = &bytes[offset + data_offset..offset + len];
+            offset += unsafe { libc::CMSG_SPACE((len - data_offset) as u32) } as usize;
+            Some((header.cmsg_level, header.cmsg_type, data))
+        })
+    }
+}
+
+/// A `recvmsg` that also receives control messages.
+pub struct RecvMsgControl {
+    msg: Box<MsgHdr>,
+    buf: Vec<u8>,
+    control: Cmsgs,
+}
+impl Completable for RecvMsgControl {
+    type Output = usize;
+
+    fn complete(result: u32) -> usize {
+        result as usize
+    }
+}
+
+impl Action<RecvMsgControl> {
+    pub fn recvmsg_control(
+        fd: RawFd,
+        len: usize,
+        mut control: Cmsgs,
+    ) -> io::Result<Action<RecvMsgControl>> {
+        let mut buf = Vec::with_capacity(len);
+        let mut msg = MsgHdr::new(&mut buf, len);
+        msg.msghdr.msg_control = control.as_mut_ptr().cast();
+        msg.msghdr.msg_controllen = control.capacity() as _;
+        // passed fds must not leak into processes spawned later.
+        let entry = target!(fd, |fd| opcode::RecvMsg::new(fd, &mut msg.msghdr as *mut _)
+            .flags(libc::MSG_CMSG_CLOEXEC as u32)
+            .build());
+        Action::submit(RecvMsgControl { msg, buf, control }, entry)
+    }
+}
+
+impl RecvMsgControl {
+    /// The `n` bytes received, the sender and the control messages.
+    pub fn received(self, n: usize) -> io::Result<(Vec<u8>, SocketAddr, Cmsgs)> {
+        let addr = self.msg.addr()?;
+        let (buf, control) = self.received_from_peer(n);
+        Ok((buf, addr, control))
+    }
+
+    /// Like `received`, for a connected socket, which reports no sender.
+    pub fn received_from_peer(mut self, n: usize) -> (Vec<u8>, Cmsgs) {
+        unsafe { self.buf.set_len(n) };
+        // the kernel shrinks `msg_controllen` to the length of the messages it wrote.
+        let len: usize = self.msg.msghdr.msg_controllen as _;
+        self.control.len = len.min(self.control.capacity());
+        self.control.truncated = self.msg.msghdr.msg_flags & libc::MSG_CTRUNC != 0;
+        (self.buf, self.control)
+    }
+}
+
+/// A `sendmsg` to an address with control messages.
+pub struct SendMsgControl {
+    _msg: Box<MsgHdr>,
+    _buf: Vec<u8>,
+    _control: Cmsgs,
+}
+impl Completable for SendMsgControl {
+    type Output = usize;
+
+    fn complete(result: u32) -> usize {
+        result as usize
+    }
+}
+
+impl Action<SendMsgControl> {
+    pub fn sendmsg_control(
+        fd: RawFd,
+        mut buf: Vec<u8>,
+        addr: &SocketAddr,
+        control: Cmsgs,
+    ) -> io::Result<Action<SendMsgControl>> {
+        let len = buf.len();
+        let msg = MsgHdr::with_addr(&mut buf, len, addr);
+        Action::submit_control(fd, buf, msg, control)
+    }
+
+    /// Like `sendmsg_control`, on a connected socket.
+    pub fn send_control(
+        fd: RawFd,
+        mut buf: Vec<u8>,
+        control: Cmsgs,
+    ) -> io::Result<Action<SendMsgControl>> {
+        let len = buf.len();
+        let mut msg = MsgHdr::new(&mut buf, len);
+        // a connected socket refuses a destination, even an empty one.
+        msg.msghdr.msg_name = ptr::null_mut();
+        msg.msghdr.msg_namelen = 0;
+        Action::submit_control(fd, buf, msg, control)
+    }
+
+    fn submit_control(
+        fd: RawFd,
+        buf: Vec<u8>,
+        mut msg: Box<MsgHdr>,
+        mut control: Cmsgs,
+    ) -> io::Result<Action<SendMsgControl>> {
+        if control.len > 0 {
+            msg.msghdr.msg_control = control.as_mut_ptr().cast();
+            msg.msghdr.msg_controllen = control.len as _;
+        }
+        let entry = target!(fd, |fd| opcode::SendMsg::new(fd, &msg.msghdr).build());
+        let send = SendMsgControl {
+            _msg: msg,
+            _buf: buf,
+            _control: control,
+        };
+        Action::submit(send, entry)
+    }
+}
@@ -1,37 +1,168 @@
+use std::cell::Cell;
 use std::io;
 use std::net::SocketAddr;
 use std::os::unix::io::RawFd;
+use std::time::Duration;
 
 use io_uring::{opcode, types};
 
-use crate::driver::{socket_addr, Action};
+use crate::driver::{socket_addr, Action, SockAddrIn};
+use crate::net::unix;
+
+/// `SO_BINDTOIFINDEX`, libc only exports it for Android.
+const SO_BINDTOIFINDEX: libc::c_int = 62;
 
 pub struct Connect {
-    fd: RawFd,
+    fd: Socket,
+    addr: SocketAddr,
+    /// Read by the kernel once the entry is submitted, after the action moved.
+    _sockaddr: Box<SockAddrIn>,
 }
 
 impl Action<Connect> {
     pub fn connect(addr: SocketAddr) -> io::Result<Action<Connect>> {
+        Action::connect_with(addr, None, None, false)
+    }
+
+    /// Like `connect`, cancelled by the kernel once `timeout` elapsed.
+    pub fn connect_timeout(addr: SocketAddr, timeout: Duration) -> io::Result<Action<Connect>> {
+        Action::connect_with(addr, None, Some(timeout), false)
+    }
+
+    /// Like `connect`, with the socket bound to the interface or VRF device with index
+    /// `ifindex` first.
+    pub fn connect_bound(addr: SocketAddr, ifindex: u32) -> io::Result<Action<Connect>> {
+        Action::connect_with(addr, Some(ifindex), None, false)
+    }
+
+    /// Like `connect`, with TCP Fast Open. Once the peer handed out a cookie on an
+    /// earlier connection the connect completes right away, and the first write goes
+    /// out with the SYN. A kernel without Fast Open connects the usual way.
+    pub fn connect_fast_open(addr: SocketAddr) -> io::Result<Action<Connect>> {
+        Action::connect_with(addr, None, None, true)
+    }
+
+    fn connect_with(
+        addr: SocketAddr,
+        ifindex: Option<u32>,
+        timeout: Option<Duration>,
+        fast_open: bool,
+    ) -> io::Result<Action<Connect>> {
         let (sockaddr, socklen) = socket_addr(&addr);
-        let fd = match addr {
+        let sockaddr = Box::new(sockaddr);
+        let fd = Socket::new(match addr {
             SocketAddr::V4(_) => new_v4_socket(),
             SocketAddr::V6(_) => new_v6_socket(),
-        }?;
+        }?);
+        if ifindex.is_some() {
+            bind_device(fd.raw(), ifindex)?;
+        }
+        if fast_open {
+            match set_option(fd.raw(), libc::IPPROTO_TCP, libc::TCP_FASTOPEN_CONNECT, 1) {
+                // a kernel without it connects the usual way.
+                Err(e) if e.raw_os_error() != Some(libc::ENOPROTOOPT) => return Err(e),
+                _ => {}
+            }
+        }
         let entry =
-            opcode::Connect::new(types::Fd(fd), sockaddr.as_ptr() as *mut _, socklen).build();
-        Action::submit(Connect { fd }, entry)
+            opcode::Connect::new(types::Fd(fd.raw()), sockaddr.as_ptr() as *mut _, socklen).build();
+        let connect = Connect {
+            fd,
+            addr,
+            _sockaddr: sockaddr,
+        };
+        Action::submit_maybe_timeout(connect, entry, timeout)
     }
 }
 
 impl Connect {
     pub fn get_socket(&self, result: io::Result<i32>) -> io::Result<RawFd> {
+        self.fd.get(result)
+    }
+
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+pub struct ConnectUnix {
+    fd: Socket,
+    _addr: Box<unix::SocketAddr>,
+}
+
+impl Action<ConnectUnix> {
+    pub fn connect_unix(addr: unix::SocketAddr) -> io::Result<Action<ConnectUnix>> {
+        Action::connect_unix_with(addr, None)
+    }
+
+    /// Like `connect_unix`, cancelled by the kernel once `timeout` elapsed.
+    pub fn connect_unix_timeout(
+        addr: unix::SocketAddr,
+        timeout: Duration,
+    ) -> io::Result<Action<ConnectUnix>> {
+        Action::connect_unix_with(addr, Some(timeout))
+    }
+
+    fn connect_unix_with(
+        addr: unix::SocketAddr,
+        timeout: Option<Duration>,
+    ) -> io::Result<Action<ConnectUnix>> {
+        let fd = new_socket(libc::AF_UNIX, libc::SOCK_STREAM)?;
+        let addr = Box::new(addr);
+        let entry = opcode::Connect::new(types::Fd(fd), addr.as_ptr(), addr.len()).build();
+        Action::submit_maybe_timeout(
+            ConnectUnix {
+                fd: Socket::new(fd),
+                _addr: addr,
+            },
+            entry,
+            timeout,
+        )
+    }
+}
+
+impl ConnectUnix {
+    pub fn get_socket(&self, result: io::Result<i32>) -> io::Result<RawFd> {
+        self.fd.get(result)
+    }
+}
+
+/// The socket being connected, closed on drop unless it was handed out. A connect
+/// future that is dropped would leak it otherwise.
+struct Socket(Cell<RawFd>);
+
+impl Socket {
+    fn new(fd: RawFd) -> Socket {
+        Socket(Cell::new(fd))
+    }
+
+    fn raw(&self) -> RawFd {
+        self.0.get()
+    }
+
+    /// Hands out the socket if the connect succeeded.
+    fn get(&self, result: io::Result<i32>) -> io::Result<RawFd> {
         match result {
             Err(err) if err.raw_os_error() != Some(libc::EINPROGRESS) => Err(err),
-            _ => Ok(self.fd),
+            _ => Ok(self.0.replace(-1)),
+        }
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        let fd = self.0.get();
+        if fd >= 0 {
+            unsafe { libc::close(fd) };
         }
     }
 }
 
+fn close_socket(fd: RawFd, err: io::Error) -> io::Error {
+    unsafe { libc::close(fd) };
+    err
+}
+
 pub fn new_v4_socket() -> io::Result<i32> {
     new_socket(libc::AF_INET, libc::SOCK_STREAM)
 }
@@ -44,3 +175,82 @@ pub fn new_socket(domain: libc::c_int, socket_type: libc::c_int) -> io::Result<l
     let socket_type = socket_type | libc::SOCK_CLOEXEC;
     syscall!(socket(domain, socket_type, 0))
 }
+
+/// Creates a listening socket bound to `addr`. With `reuse_port`, `SO_REUSEPORT` lets
+/// other listeners that set it too bind the same address while this one is open. A
+/// `fast_open` queue length other than 0 accepts data sent with the SYN.
+pub fn listen(
+    addr: SocketAddr,
+    backlog: i32,
+    reuse_port: bool,
+    fast_open: u32,
+) -> io::Result<RawFd> {
+    let domain = match addr {
+        SocketAddr::V4(_) => libc::AF_INET,
+        SocketAddr::V6(_) => libc::AF_INET6,
+    };
+    let fd = new_socket(domain, libc::SOCK_STREAM)?;
+    let (sockaddr, socklen) = socket_addr(&addr);
+    let on: libc::c_int = 1;
+    let res = set_option(fd, libc::SOL_SOCKET, libc::SO_REUSEADDR, on)
+        .and_then(|_| match reuse_port {
+            true => set_option(fd, libc::SOL_SOCKET, libc::SO_REUSEPORT, on),
+            false => Ok(0),
+        })
+        .and_then(|_| match fast_open {
+            0 => Ok(0),
+            qlen => set_option(
+                fd,
+                libc::IPPROTO_TCP,
+                libc::TCP_FASTOPEN,
+                qlen as libc::c_int,
+            ),
+        })
+        .and_then(|_| syscall!(bind(fd, sockaddr.as_ptr(), socklen)))
+        .and_then(|_| syscall!(listen(fd, backlog)));
+    match res {
+        Ok(_) => Ok(fd),
+        Err(e) => Err(close_socket(fd, e)),
+    }
+}
+
+fn set_option(
+    fd: RawFd,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: libc::c_int,
+) -> io::Result<libc::c_int> {
+    syscall!(setsockopt(
+        fd,
+        level,
+        name,
+        &value as *const _ as *const libc::c_void,
+        std::mem::size_of::<libc::c_int>() as libc::socklen_t
+    ))
+}
+
+/// Binds `fd` to the interface or VRF device with index `ifindex`, so it only sends and
+/// receives through that device. `None` removes the binding.
+pub fn bind_device(fd: RawFd, ifindex: Option<u32>) -> io::Result<()> {
+    set_option(
+        fd,
+        libc::SOL_SOCKET,
+        SO_BINDTOIFINDEX,
+        ifindex.unwrap_or(0) as libc::c_int,
+    )?;
+    Ok(())
+}
+
+/// The index of the device `fd` is bound to, `None` if it is not bound.
+pub fn device_index(fd: RawFd) -> io::Result<Option<u32>> {
+    let mut value: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    syscall!(getsockopt(
+        fd,
+        libc::SOL_SOCKET,
+        SO_BINDTOIFINDEX,
+        &mut value as *mut _ as *mut libc::c_void,
+        &mut len
+    ))?;
+    Ok(if value > 0 { Some(value as u32) } else { None })
+}
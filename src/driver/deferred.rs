@@ -0,0 +1,253 @@
+use std::any::Any;
+use std::cell::{Cell, RefCell, RefMut};
+use std::collections::VecDeque;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::task::Waker;
+
+use crate::driver::{slot, Driver, Inner, State};
+
+/// Work that may run user code, held back until the driver is no longer borrowed.
+///
+/// Waking a task, dropping a finished operation's data or dropping a waker can all run
+/// code that uses the driver again, such as a waker that polls right away or a buffer
+/// whose drop drops an operation. Doing so while the driver is borrowed would panic, so
+/// it is queued here instead and run by [`Driver::flush`].
+#[derive(Default)]
+pub struct Deferred {
+    wakers: RefCell<Vec<Waker>>,
+    /// Tasks woken by multishot completions during the current reap pass, see
+    /// [`Deferred::wake_multishot`].
+    multishot: RefCell<Vec<Waker>>,
+    /// Where the next reap pass starts waking `multishot` tasks.
+    rotation: Cell<usize>,
+    /// Tasks of expired timers not woken yet, see [`Deferred::wake_timer`].
+    timers: RefCell<VecDeque<Waker>>,
+    dropped: RefCell<Vec<State>>,
+    /// Operations dropped while the driver was borrowed.
+    orphans: RefCell<Vec<Orphan>>,
+    /// Keys of the operations cancelled while the driver was borrowed.
+    cancels: RefCell<Vec<u64>>,
+    /// Run before the driver parks, see [`Driver::on_idle`].
+    idle: RefCell<Vec<IdleHook>>,
+}
+
+type IdleHook = Box<dyn FnMut(&Deferred) -> bool>;
+
+struct Orphan {
+    key: u64,
+    action: Box<dyn Any>,
+    cancel: bool,
+}
+
+impl Deferred {
+    pub fn wake(&self, waker: Waker) {
+        self.wakers.borrow_mut().push(waker);
+    }
+
+    /// Wakes a task for a multishot completion once the reap pass is over, see
+    /// [`Deferred::rotate`].
+    pub fn wake_multishot(&self, waker: Waker) {
+        self.multishot.borrow_mut().push(waker);
+    }
+
+    /// Queues the tasks woken by multishot completions during the reap pass just ended.
+    ///
+    /// The batch starts at a different task on every pass. A connection whose completions
+    /// are always reaped first, such as a busy one, would otherwise always run first as
+    /// well, and the others would wait behind it on every pass.
+    pub fn rotate(&self) {
+        let mut multishot = self.multishot.borrow_mut();
+        if multishot.is_empty() {
+            return;
+        }
+        let rotation = self.rotation.get();
+        self.rotation.set(rotation.wrapping_add(1));
+        let len = multishot.len();
+        multishot.rotate_left(rotation % len);
+        self.wakers.borrow_mut().append(&mut multishot);
+    }
+
+    /// Wakes a task for an expired timer, once [`Deferred::release_timers`] lets it.
+    pub fn wake_timer(&self, waker: Waker) {
+        self.timers.borrow_mut().push_back(waker);
+    }
+
+    /// Queues up to `max` of the tasks of expired timers, oldest first, and returns how
+    /// many are left for later.
+    ///
+    /// Called once per turn of the loop. When thousands of timers expire at once, such
+    /// as after the machine resumed from suspend, their tasks run over several turns and
+    /// the tasks woken by I/O completions run in between, instead of waiting behind all
+    /// of them.
+    pub fn release_timers(&self, max: usize) -> usize {
+        let mut timers = self.timers.borrow_mut();
+        let n = timers.len().min(max);
+        self.wakers.borrow_mut().extend(timers.drain(..n));
+        timers.len()
+    }
+
+    pub fn discard(&self, state: State) {
+        self.dropped.borrow_mut().push(state);
+    }
+
+    /// Queues an operation whose handle was dropped, see [`Inner::abandon`].
+    pub fn orphan(&self, key: u64, action: Box<dyn Any>, cancel: bool) {
+        let orphan = Orphan {
+            key,
+            action,
+            cancel,
+        };
+        self.orphans.borrow_mut().push(orphan);
+    }
+
+    /// Queues the cancel of an operation, see [`Action::cancel`](super::Action::cancel).
+    pub fn cancel(&self, key: u64) {
+        self.cancels.borrow_mut().push(key);
+    }
+
+    /// Makes room for waking and dropping `operations` per pass without growing.
+    pub fn reserve(&self, operations: usize) {
+        reserve(&self.wakers, operations);
+        reserve(&self.multishot, operations);
+        reserve(&self.dropped, operations);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.wakers.borrow().is_empty()
+            && self.dropped.borrow().is_empty()
+            && self.orphans.borrow().is_empty()
+            && self.cancels.borrow().is_empty()
+    }
+}
+
+/// The borrowed driver, runs the work deferred meanwhile once released.
+pub struct Locked<'a> {
+    inner: Option<RefMut<'a, Inner>>,
+    driver: &'a Driver,
+}
+
+impl Deref for Locked<'_> {
+    type Target = Inner;
+
+    fn deref(&self) -> &Inner {
+        self.inner.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for Locked<'_> {
+    fn deref_mut(&mut self) -> &mut Inner {
+        self.inner.as_mut().unwrap()
+    }
+}
+
+impl Drop for Locked<'_> {
+    fn drop(&mut self) {
+        self.inner.take();
+        self.driver.flush();
+    }
+}
+
+impl Driver {
+    /// Borrows the driver, the work deferred while it is borrowed runs on release.
+    pub fn lock(&self) -> Locked<'_> {
+        Locked {
+            inner: Some(self.inner.borrow_mut()),
+            driver: self,
+        }
+    }
+
+    /// Runs `hook` right before the driver parks, and again before every later park for
+    /// as long as it returns true. Work batched up while tasks were running goes out
+    /// there, so batching adds no latency once there is nothing left to run. Tasks are
+    /// woken through the `Deferred` passed to `hook`, which keeps the driver from parking.
+    pub fn on_idle(&self, hook: impl FnMut(&Deferred) -> bool + 'static) {
+        self.deferred.idle.borrow_mut().push(Box::new(hook));
+    }
+
+    /// Runs the idle hooks, see [`Driver::on_idle`].
+    pub(crate) fn idle(&self) {
+        let deferred = &*self.deferred;
+        let mut hooks = mem::take(&mut *deferred.idle.borrow_mut());
+        hooks.retain_mut(|hook| hook(deferred));
+        // hooks added by the hooks themselves run before the next park.
+        let mut idle = deferred.idle.borrow_mut();
+        hooks.append(&mut idle);
+        *idle = hooks;
+    }
+
+    /// Runs the deferred work, returns whether any task was woken.
+    pub fn flush(&self) -> bool {
+        let deferred = &*self.deferred;
+        let mut woken = false;
+        while !deferred.is_empty() {
+            let orphans = mem::take(&mut *deferred.orphans.borrow_mut());
+            let cancels = mem::take(&mut *deferred.cancels.borrow_mut());
+            if !orphans.is_empty() || !cancels.is_empty() {
+                // the holder of the borrow flushes them once it is done.
+                let mut inner = match self.inner.try_borrow_mut() {
+                    Ok(inner) => inner,
+                    Err(_) => {
+                        deferred.orphans.borrow_mut().extend(orphans);
+                        deferred.cancels.borrow_mut().extend(cancels);
+                        return woken;
+                    }
+                };
+                for key in cancels {
+                    inner.cancel(key);
+                }
+                for orphan in orphans {
+                    inner.abandon(orphan.key, orphan.action, orphan.cancel);
+                }
+                let _ = inner.submit_queued();
+            }
+            let mut dropped = mem::take(&mut *deferred.dropped.borrow_mut());
+            dropped.clear();
+            reuse(&deferred.dropped, dropped);
+            let mut wakers = mem::take(&mut *deferred.wakers.borrow_mut());
+            woken |= !wakers.is_empty();
+            wakers.drain(..).for_each(Waker::wake);
+            reuse(&deferred.wakers, wakers);
+        }
+        woken
+    }
+}
+
+fn reserve<T>(list: &RefCell<Vec<T>>, capacity: usize) {
+    let mut list = list.borrow_mut();
+    let additional = capacity.saturating_sub(list.len());
+    list.reserve(additional);
+}
+
+/// Puts the memory of a list taken to be run back in place, unless the work it ran
+/// queued more meanwhile.
+fn reuse<T>(list: &RefCell<Vec<T>>, spare: Vec<T>) {
+    let mut list = list.borrow_mut();
+    if list.is_empty() && list.capacity() < spare.capacity() {
+        *list = spare;
+    }
+}
+
+impl Inner {
+    /// Releases the slot of an operation whose handle was dropped, or keeps `action`
+    /// alive until the kernel posts the final completion, cancelling it if `cancel`.
+    pub fn abandon(&mut self, key: u64, action: Box<dyn Any>, cancel: bool) {
+        let slot = slot(key);
+        self.memory.abandoned();
+        match mem::replace(&mut self.actions[slot], State::Submitted) {
+            // the final completion is already queued, nothing is left to cancel.
+            state @ (State::Completed(..) | State::Multi(..)) if !state.in_kernel() => {
+                self.actions.remove(slot);
+                self.deferred.discard(state);
+                self.deferred.discard(State::Ignored(action));
+            }
+            state => {
+                self.actions[slot] = State::Ignored(action);
+                self.deferred.discard(state);
+                if cancel {
+                    self.cancel(key);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::mem;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use io_uring::opcode;
+use io_uring::squeue::Entry;
+
+use crate::driver::{remote, slot};
+
+/// Something the driver did, see
+/// [`Runtime::set_event_log`](crate::Runtime::set_event_log).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    /// When it happened, since the runtime started.
+    pub at: Duration,
+    pub kind: EventKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    /// An operation was queued for the kernel. `key` identifies it until its final
+    /// completion, the driver's own operations use `u64::MAX`.
+    Submit { key: u64, opcode: u8 },
+    /// The kernel posted a completion, `result` is a negated errno on failure.
+    Complete { key: u64, result: i32, flags: u32 },
+    /// The kernel was asked to cancel an operation.
+    Cancel { key: u64 },
+    /// The driver parked until a completion arrives, for at most `max`.
+    Park { max: Option<Duration> },
+    /// The driver woke up from parking.
+    Unpark,
+    /// The buffer ring of group `group` was replaced with one of `entries` buffers of
+    /// `size` bytes, to follow the observed read sizes or as asked.
+    BuffersResized {
+        group: u16,
+        entries: u16,
+        size: usize,
+    },
+    /// An operation found no buffer left in the ring it selects from and failed with
+    /// `ENOBUFS`.
+    BuffersExhausted { key: u64 },
+    /// A multishot operation ended while more was expected from it, and is armed again.
+    /// `result` is its last result, a negated errno if it ended with an error.
+    Rearm { key: u64, result: i32 },
+}
+
+impl EventKind {
+    /// Whether the event tells how the runtime adapts to the load, as passed to the
+    /// hook set with [`Runtime::set_adaptation_hook`](crate::Runtime::set_adaptation_hook).
+    fn is_adaptation(&self) -> bool {
+        matches!(
+            self,
+            EventKind::BuffersResized { .. }
+                | EventKind::BuffersExhausted { .. }
+                | EventKind::Rearm { .. }
+        )
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "{:>12.6}s ", self.at.as_secs_f64())?;
+        match self.kind {
+            EventKind::Submit { key, opcode } => {
+                write!(fmt, "submit   {} opcode {}", Key(key), opcode)
+            }
+            EventKind::Complete { key, result, flags } => {
+                write!(
+                    fmt,
+                    "complete {} result {} flags {:#x}",
+                    Key(key),
+                    result,
+                    flags
+                )
+            }
+            EventKind::Cancel { key } => write!(fmt, "cancel   {}", Key(key)),
+            EventKind::Park { max: Some(max) } => write!(fmt, "park     for at most {:?}", max),
+            EventKind::Park { max: None } => write!(fmt, "park"),
+            EventKind::Unpark => write!(fmt, "unpark"),
+            EventKind::BuffersResized {
+                group,
+                entries,
+                size,
+            } => write!(
+                fmt,
+                "resize   group {} to {} buffers of {} bytes",
+                group, entries, size
+            ),
+            EventKind::BuffersExhausted { key } => {
+                write!(fmt, "nobufs   {}", Key(key))
+            }
+            EventKind::Rearm { key, result } => {
+                write!(fmt, "rearm    {} result {}", Key(key), result)
+            }
+        }
+    }
+}
+
+/// Shows the key of an operation as its slot and the generation of the slot.
+struct Key(u64);
+
+impl fmt::Display for Key {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            u64::MAX => fmt.write_str("driver"),
+            remote::WAKE_KEY => fmt.write_str("wake"),
+            key => write!(fmt, "{}.{}", slot(key), key >> 32),
+        }
+    }
+}
+
+pub(crate) type AdaptationHook = Rc<dyn Fn(&Event)>;
+
+/// The most recent driver events, oldest first. Keeps nothing until it is given a
+/// capacity.
+pub(crate) struct EventLog {
+    events: VecDeque<Event>,
+    capacity: usize,
+    started: Instant,
+    hook: Option<AdaptationHook>,
+    /// Adaptation events not passed to the hook yet.
+    pending: Vec<Event>,
+}
+
+impl EventLog {
+    pub(crate) fn new(started: Instant) -> EventLog {
+        EventLog {
+            events: VecDeque::new(),
+            capacity: 0,
+            started,
+            hook: None,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Keeps the last `capacity` events from now on, 0 turns the log off.
+    pub(crate) fn set_capacity(&mut self, capacity: usize) {
+        let excess = self.events.len().saturating_sub(capacity);
+        self.events.drain(..excess);
+        self.events.shrink_to(capacity);
+        self.capacity = capacity;
+    }
+
+    pub(crate) fn record(&mut self, kind: EventKind) {
+        let hooked = self.hook.is_some() && kind.is_adaptation();
+        if self.capacity == 0 && !hooked {
+            return;
+        }
+        let event = Event {
+            at: self.started.elapsed(),
+            kind,
+        };
+        if hooked {
+            self.pending.push(event);
+        }
+        if self.capacity == 0 {
+            return;
+        }
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    pub(crate) fn set_hook(&mut self, hook: AdaptationHook) {
+        self.hook = Some(hook);
+    }
+
+    /// The hook along with the adaptation events it was not passed yet, `None` if there
+    /// are none.
+    pub(crate) fn take_pending(&mut self) -> Option<(AdaptationHook, Vec<Event>)> {
+        if self.pending.is_empty() {
+            return None;
+        }
+        let hook = self.hook.clone()?;
+        Some((hook, mem::take(&mut self.pending)))
+    }
+
+    /// Records a submission, or the cancellation it asks for.
+    pub(crate) fn record_sqe(&mut self, sqe: &Entry) {
+        if self.capacity == 0 {
+            return;
+        }
+        // `Entry` is a `repr(C)` wrapper of `io_uring_sqe`, which holds the opcode at
+        // offset 0, the key a cancel targets at 16 and the entry's own key at 32.
+        let raw = unsafe { &*(sqe as *const Entry as *const [u8; 64]) };
+        let u64_at = |offset: usize| {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&raw[offset..offset + 8]);
+            u64::from_ne_bytes(bytes)
+        };
+        self.record(match raw[0] {
+            opcode::AsyncCancel::CODE => EventKind::Cancel { key: u64_at(16) },
+            opcode => EventKind::Submit {
+                key: u64_at(32),
+                opcode,
+            },
+        });
+    }
+
+    pub(crate) fn events(&self) -> Vec<Event> {
+        self.events.iter().copied().collect()
+    }
+}
@@ -0,0 +1,74 @@
+use io_uring::opcode;
+
+use crate::driver::allocate::OP_FTRUNCATE;
+use crate::driver::waitid::OP_WAITID;
+use crate::driver::Backend;
+
+/// `IORING_OP_SOCKET`, added along with multishot accept in Linux 5.19.
+const OP_SOCKET: u8 = 45;
+
+/// `IORING_OP_SEND_ZC`, added along with multishot recv in Linux 6.0.
+const OP_SEND_ZC: u8 = 47;
+
+/// `IORING_OP_SENDMSG_ZC`, Linux 6.1.
+const OP_SENDMSG_ZC: u8 = 48;
+
+/// What the kernel supports of the features the driver has a fallback for, probed once
+/// when the driver starts, see [`Runtime::features`](crate::Runtime::features).
+///
+/// Operation flags can not be probed for, so multishot accept and recv are told apart
+/// by the opcodes added in the same release.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Features {
+    /// Provided buffer rings, Linux 5.19. Reads select their buffer from a ring the
+    /// driver registers.
+    pub buf_ring: bool,
+    /// `IORING_OP_PROVIDE_BUFFERS`, Linux 5.7. Without buffer rings, reads select from
+    /// buffers handed to the kernel one operation at a time.
+    pub provide_buffers: bool,
+    /// Multishot accept, Linux 5.19. Listeners accept with one operation per connection
+    /// otherwise.
+    pub multishot_accept: bool,
+    /// Multishot recv, Linux 6.0. Streams submit a recv per read otherwise.
+    pub multishot_recv: bool,
+    /// Zero copy send, Linux 6.0. Stream sends copy their buffer otherwise.
+    pub send_zc: bool,
+    /// Zero copy sendmsg, Linux 6.1. Datagram sends copy their buffer otherwise.
+    pub sendmsg_zc: bool,
+    /// `IORING_OP_SHUTDOWN`, Linux 5.11. Sockets are shut down with a system call
+    /// otherwise.
+    pub shutdown: bool,
+    /// `IORING_OP_WAITID`, Linux 6.7. Child processes are waited for by polling a
+    /// pidfd otherwise.
+    pub waitid: bool,
+    /// `IORING_OP_UNLINKAT` and `IORING_OP_RENAMEAT`, Linux 5.11. Files are removed and
+    /// renamed on the blocking pool otherwise.
+    pub unlink: bool,
+    /// `IORING_OP_MKDIRAT`, `IORING_OP_SYMLINKAT` and `IORING_OP_LINKAT`, Linux 5.15.
+    /// Directories and links are created on the blocking pool otherwise.
+    pub mkdir: bool,
+    /// `IORING_OP_FTRUNCATE`, Linux 6.9. Files are resized on the blocking pool
+    /// otherwise.
+    pub ftruncate: bool,
+}
+
+impl Features {
+    /// Asks the kernel which opcodes it supports, buffer rings are told by registering
+    /// one.
+    pub fn probe(backend: &mut dyn Backend) -> Features {
+        let mut supports = |opcode| backend.supports(opcode);
+        Features {
+            buf_ring: false,
+            provide_buffers: supports(opcode::ProvideBuffers::CODE),
+            multishot_accept: supports(OP_SOCKET),
+            multishot_recv: supports(OP_SEND_ZC),
+            send_zc: supports(OP_SEND_ZC),
+            sendmsg_zc: supports(OP_SENDMSG_ZC),
+            shutdown: supports(opcode::Shutdown::CODE),
+            waitid: supports(OP_WAITID),
+            unlink: supports(opcode::UnlinkAt::CODE),
+            mkdir: supports(opcode::MkDirAt::CODE),
+            ftruncate: supports(OP_FTRUNCATE),
+        }
+    }
+}
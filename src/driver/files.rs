@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+use std::io;
+use std::os::unix::io::RawFd;
+
+use crate::driver::{Driver, Inner, CURRENT};
+
+/// Slots of the sparse file table registered with the kernel.
+const SLOTS: u32 = 1024;
+
+/// Which fds have a slot in the registered file table.
+pub struct FileTable {
+    slots: HashMap<RawFd, u32>,
+    free: Vec<u32>,
+    next: u32,
+}
+
+impl FileTable {
+    fn slot(&self, fd: RawFd) -> Option<u32> {
+        self.slots.get(&fd).copied()
+    }
+}
+
+impl Inner {
+    /// Puts `fd` in a free slot of the file table, registering the table first if needed.
+    fn register_file(&mut self, fd: RawFd) -> io::Result<u32> {
+        if self.files.is_none() {
+            self.backend.register_files_sparse(SLOTS)?;
+            self.files = Some(FileTable {
+                slots: HashMap::new(),
+                free: Vec::new(),
+                next: 0,
+            });
+        }
+        let files = self.files.as_mut().unwrap();
+        if let Some(slot) = files.slot(fd) {
+            return Ok(slot);
+        }
+        let slot = match files.free.pop() {
+            Some(slot) => slot,
+            None if files.next < SLOTS => {
+                files.next += 1;
+                files.next - 1
+            }
+            None => return Err(io::Error::from_raw_os_error(libc::ENFILE)),
+        };
+        if let Err(e) = self.backend.update_files(slot, &[fd]) {
+            files.free.push(slot);
+            return Err(e);
+        }
+        files.slots.insert(fd, slot);
+        Ok(slot)
+    }
+
+    fn unregister_file(&mut self, fd: RawFd) {
+        if let Some(files) = self.files.as_mut() {
+            if let Some(slot) = files.slots.remove(&fd) {
+                let _ = self.backend.update_files(slot, &[-1]);
+                files.free.push(slot);
+            }
+        }
+    }
+}
+
+/// The slot `fd` is registered in, operations on it then use the slot instead.
+pub fn fixed_slot(fd: RawFd) -> Option<u32> {
+    CURRENT.with(|driver| driver.inner.borrow().files.as_ref()?.slot(fd))
+}
+
+/// Keeps an fd registered in the file table of the current runtime, its owner has to
+/// drop this before closing the fd.
+pub struct FixedFile {
+    fd: RawFd,
+}
+
+impl FixedFile {
+    pub fn register(fd: RawFd) -> io::Result<FixedFile> {
+        CURRENT.with(|driver| driver.inner.borrow_mut().register_file(fd))?;
+        Ok(FixedFile { fd })
+    }
+}
+
+impl Drop for FixedFile {
+    fn drop(&mut self) {
+        Driver::try_current(|driver| driver.inner.borrow_mut().unregister_file(self.fd));
+    }
+}
@@ -0,0 +1,239 @@
+use std::cell::RefCell;
+use std::io;
+use std::ops;
+use std::os::unix::io::RawFd;
+use std::rc::Rc;
+
+use io_uring::opcode;
+
+use crate::driver::iobuf::{IoBuf, IoBufMut};
+use crate::driver::{Action, Completable, Driver, CURRENT};
+
+/// Buffers registered with the kernel once, so reads and writes through them skip
+/// pinning their pages on every operation.
+///
+/// Only one registry can be registered with a runtime at a time. The buffers are
+/// unregistered once the registry and every buffer checked out of it are dropped.
+pub struct FixedBufRegistry {
+    shared: Rc<Shared>,
+}
+
+struct Shared {
+    /// `None` while the buffer is checked out.
+    bufs: RefCell<Vec<Option<Vec<u8>>>>,
+}
+
+impl FixedBufRegistry {
+    /// Registers `bufs` with the runtime of the current thread. The whole capacity of
+    /// each buffer is registered, its length is ignored.
+    pub fn new<I>(bufs: I) -> io::Result<FixedBufRegistry>
+    where
+        I: IntoIterator<Item = Vec<u8>>,
+    {
+        let mut bufs: Vec<Vec<u8>> = bufs.into_iter().collect();
+        if bufs.len() > u16::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "too many fixed buffers",
+            ));
+        }
+        let iovecs: Vec<libc::iovec> = bufs
+            .iter_mut()
+            .map(|buf| {
+                buf.clear();
+                libc::iovec {
+                    iov_base: buf.as_mut_ptr().cast(),
+                    iov_len: buf.capacity(),
+                }
+            })
+            .collect();
+        CURRENT.with(|driver| driver.inner.borrow_mut().backend.register_buffers(&iovecs))?;
+        Ok(FixedBufRegistry {
+            shared: Rc::new(Shared {
+                bufs: RefCell::new(bufs.into_iter().map(Some).collect()),
+            }),
+        })
+    }
+
+    /// Number of registered buffers.
+    pub fn len(&self) -> usize {
+        self.shared.bufs.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Takes buffer `index` out of the registry, `None` if it is already checked out or
+    /// out of range. It goes back when dropped.
+    pub fn check_out(&self, index: usize) -> Option<FixedBuf> {
+        let buf = self.shared.bufs.borrow_mut().get_mut(index)?.take()?;
+        Some(FixedBuf {
+            shared: self.shared.clone(),
+            index: index as u16,
+            buf,
+        })
+    }
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        Driver::try_current(|driver| driver.inner.borrow_mut().backend.unregister_buffers());
+    }
+}
+
+/// A buffer checked out of a [`FixedBufRegistry`].
+///
+/// It holds up to its capacity, which never changes since the kernel knows the buffer
+/// by its address.
+pub struct FixedBuf {
+    shared: Rc<Shared>,
+    index: u16,
+    buf: Vec<u8>,
+}
+
+impl FixedBuf {
+    /// The index of this buffer in its registry.
+    pub fn index(&self) -> usize {
+        self.index as usize
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buf.capacity()
+    }
+
+    pub fn clear(&mut self) {
+        self.buf.clear();
+    }
+
+    /// Appends `src`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the buffer has no room for `src`.
+    pub fn extend_from_slice(&mut self, src: &[u8]) {
+        assert!(
+            src.len() <= self.buf.capacity() - self.buf.len(),
+            "fixed buffer overflow"
+        );
+        self.buf.extend_from_slice(src);
+    }
+}
+
+impl ops::Deref for FixedBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
+impl ops::DerefMut for FixedBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.buf
+    }
+}
+
+unsafe impl IoBuf for FixedBuf {
+    fn stable_ptr(&self) -> *const u8 {
+        self.buf.stable_ptr()
+    }
+
+    fn bytes_init(&self) -> usize {
+        self.buf.bytes_init()
+    }
+
+    fn bytes_total(&self) -> usize {
+        self.buf.bytes_total()
+    }
+}
+
+unsafe impl IoBufMut for FixedBuf {
+    fn stable_mut_ptr(&mut self) -> *mut u8 {
+        self.buf.stable_mut_ptr()
+    }
+
+    unsafe fn set_init(&mut self, len: usize) {
+        self.buf.set_init(len)
+    }
+}
+
+impl Drop for FixedBuf {
+    fn drop(&mut self) {
+        let buf = std::mem::take(&mut self.buf);
+        self.shared.bufs.borrow_mut()[self.index as usize] = Some(buf);
+    }
+}
+
+pub struct ReadFixed {
+    buf: FixedBuf,
+}
+
+impl Completable for ReadFixed {
+    type Output = usize;
+
+    fn complete(result: u32) -> usize {
+        result as usize
+    }
+}
+
+impl Action<ReadFixed> {
+    /// Reads into `buf` starting at `offset`, replacing its contents.
+    pub fn read_fixed_at(
+        fd: RawFd,
+        mut buf: FixedBuf,
+        offset: u64,
+    ) -> io::Result<Action<ReadFixed>> {
+        buf.clear();
+        let ptr = buf.buf.as_mut_ptr();
+        let len = buf.capacity() as u32;
+        let entry = target!(fd, |fd| opcode::ReadFixed::new(fd, ptr, len, buf.index)
+            .offset64(offset as _)
+            .build());
+        Action::submit(ReadFixed { buf }, entry)
+    }
+}
+
+impl ReadFixed {
+    /// Takes the buffer back once the kernel filled `n` bytes of it.
+    pub fn filled(mut self, n: usize) -> FixedBuf {
+        unsafe { self.buf.buf.set_len(n) };
+        self.buf
+    }
+}
+
+pub struct WriteFixed {
+    buf: FixedBuf,
+}
+
+impl Completable for WriteFixed {
+    type Output = usize;
+
+    fn complete(result: u32) -> usize {
+        result as usize
+    }
+}
+
+impl Action<WriteFixed> {
+    /// Writes the contents of `buf`, at `offset` for seekable files.
+    pub fn write_fixed(
+        fd: RawFd,
+        buf: FixedBuf,
+        offset: Option<u64>,
+    ) -> io::Result<Action<WriteFixed>> {
+        let entry = target!(fd, |fd| {
+            let mut entry = opcode::WriteFixed::new(fd, buf.as_ptr(), buf.len() as u32, buf.index);
+            if let Some(offset) = offset {
+                entry = entry.offset64(offset as _);
+            }
+            entry.build()
+        });
+        Action::submit(WriteFixed { buf }, entry)
+    }
+}
+
+impl WriteFixed {
+    pub fn into_buf(self) -> FixedBuf {
+        self.buf
+    }
+}
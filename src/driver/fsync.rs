@@ -0,0 +1,27 @@
+use std::io;
+use std::os::unix::io::RawFd;
+
+use io_uring::{opcode, types};
+
+use crate::driver::{Action, Completable};
+
+pub struct Fsync;
+
+impl Completable for Fsync {
+    type Output = ();
+
+    fn complete(_: u32) {}
+}
+
+impl Action<Fsync> {
+    /// Flushes file data and, unless `data_only` is set, metadata to the device.
+    pub fn fsync(fd: RawFd, data_only: bool) -> io::Result<Action<Fsync>> {
+        let flags = if data_only {
+            types::FsyncFlags::DATASYNC
+        } else {
+            types::FsyncFlags::empty()
+        };
+        let entry = target!(fd, |fd| opcode::Fsync::new(fd).flags(flags).build());
+        Action::submit(Fsync, entry)
+    }
+}
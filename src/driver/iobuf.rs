@@ -0,0 +1,203 @@
+use std::io;
+use std::os::unix::io::RawFd;
+
+use io_uring::opcode;
+
+use crate::driver::{Action, Completable};
+
+/// A buffer an operation takes ownership of while the kernel reads out of it.
+///
+/// Operations taking an `IoBuf` hand it back together with their result, so it stays
+/// alive until the kernel is done with it even if the future is dropped early.
+///
+/// # Safety
+///
+/// `stable_ptr` has to point at `bytes_init` initialized bytes out of `bytes_total`, and
+/// must not change when the buffer is moved.
+pub unsafe trait IoBuf: Unpin + 'static {
+    fn stable_ptr(&self) -> *const u8;
+
+    /// Bytes holding data, the ones a write sends.
+    fn bytes_init(&self) -> usize;
+
+    /// Bytes the buffer has room for, the most a read fills in.
+    fn bytes_total(&self) -> usize;
+}
+
+/// A buffer an operation takes ownership of while the kernel writes into it.
+///
+/// # Safety
+///
+/// `stable_mut_ptr` has to point at the same `bytes_total` bytes as `stable_ptr`.
+pub unsafe trait IoBufMut: IoBuf {
+    fn stable_mut_ptr(&mut self) -> *mut u8;
+
+    /// Replaces the contents with the first `len` bytes, after a read filled them in.
+    ///
+    /// # Safety
+    ///
+    /// The first `len` bytes have to be initialized.
+    unsafe fn set_init(&mut self, len: usize);
+}
+
+unsafe impl IoBuf for Vec<u8> {
+    fn stable_ptr(&self) -> *const u8 {
+        self.as_ptr()
+    }
+
+    fn bytes_init(&self) -> usize {
+        self.len()
+    }
+
+    fn bytes_total(&self) -> usize {
+        self.capacity()
+    }
+}
+
+unsafe impl IoBufMut for Vec<u8> {
+    fn stable_mut_ptr(&mut self) -> *mut u8 {
+        self.as_mut_ptr()
+    }
+
+    unsafe fn set_init(&mut self, len: usize) {
+        self.set_len(len);
+    }
+}
+
+unsafe impl IoBuf for Box<[u8]> {
+    fn stable_ptr(&self) -> *const u8 {
+        self.as_ptr()
+    }
+
+    fn bytes_init(&self) -> usize {
+        self.len()
+    }
+
+    fn bytes_total(&self) -> usize {
+        self.len()
+    }
+}
+
+/// A boxed slice is initialized in full, a read overwrites the front of it.
+unsafe impl IoBufMut for Box<[u8]> {
+    fn stable_mut_ptr(&mut self) -> *mut u8 {
+        self.as_mut_ptr()
+    }
+
+    unsafe fn set_init(&mut self, _: usize) {}
+}
+
+unsafe impl IoBuf for &'static [u8] {
+    fn stable_ptr(&self) -> *const u8 {
+        self.as_ptr()
+    }
+
+    fn bytes_init(&self) -> usize {
+        self.len()
+    }
+
+    fn bytes_total(&self) -> usize {
+        self.len()
+    }
+}
+
+unsafe impl IoBuf for &'static str {
+    fn stable_ptr(&self) -> *const u8 {
+        self.as_ptr()
+    }
+
+    fn bytes_init(&self) -> usize {
+        self.len()
+    }
+
+    fn bytes_total(&self) -> usize {
+        self.len()
+    }
+}
+
+/// Copies as much of `src` into `buf` as it has room for, replacing its contents, and
+/// returns how many bytes were copied.
+pub(crate) fn fill<B: IoBufMut>(buf: &mut B, src: &[u8]) -> usize {
+    let n = src.len().min(buf.bytes_total());
+    unsafe {
+        src.as_ptr().copy_to_nonoverlapping(buf.stable_mut_ptr(), n);
+        buf.set_init(n);
+    }
+    n
+}
+
+pub struct ReadBuf<B> {
+    buf: B,
+}
+
+impl<B> Completable for ReadBuf<B> {
+    type Output = usize;
+
+    fn complete(result: u32) -> usize {
+        result as usize
+    }
+}
+
+impl<B: IoBufMut> Action<ReadBuf<B>> {
+    /// Reads into `buf` up to its capacity, at `offset` for seekable files. Hands `buf`
+    /// back if the read could not be submitted.
+    pub fn read_buf(
+        fd: RawFd,
+        mut buf: B,
+        offset: Option<u64>,
+    ) -> Result<Action<ReadBuf<B>>, (io::Error, B)> {
+        let ptr = buf.stable_mut_ptr();
+        let len = buf.bytes_total().min(u32::MAX as usize) as u32;
+        let entry = target!(fd, |fd| opcode::Read::new(fd, ptr, len)
+            .offset(offset.unwrap_or(0) as _)
+            .build());
+        Action::try_submit(ReadBuf { buf }, entry).map_err(|(e, read)| (e, read.buf))
+    }
+}
+
+impl<B: IoBufMut> ReadBuf<B> {
+    /// Takes the buffer back once the kernel filled `n` bytes of it.
+    pub fn filled(mut self, n: usize) -> B {
+        unsafe { self.buf.set_init(n) };
+        self.buf
+    }
+
+    pub fn into_buf(self) -> B {
+        self.buf
+    }
+}
+
+pub struct WriteBuf<B> {
+    buf: B,
+}
+
+impl<B> Completable for WriteBuf<B> {
+    type Output = usize;
+
+    fn complete(result: u32) -> usize {
+        result as usize
+    }
+}
+
+impl<B: IoBuf> Action<WriteBuf<B>> {
+    /// Writes the contents of `buf`, at `offset` for seekable files. Hands `buf` back if
+    /// the write could not be submitted.
+    pub fn write_buf(
+        fd: RawFd,
+        buf: B,
+        offset: Option<u64>,
+    ) -> Result<Action<WriteBuf<B>>, (io::Error, B)> {
+        let ptr = buf.stable_ptr();
+        let len = buf.bytes_init().min(u32::MAX as usize) as u32;
+        let entry = target!(fd, |fd| opcode::Write::new(fd, ptr, len)
+            .offset(offset.unwrap_or(0) as _)
+            .build());
+        Action::try_submit(WriteBuf { buf }, entry).map_err(|(e, write)| (e, write.buf))
+    }
+}
+
+impl<B> WriteBuf<B> {
+    pub fn into_buf(self) -> B {
+        self.buf
+    }
+}
@@ -0,0 +1,88 @@
+use std::time::{Duration, Instant};
+
+/// Weight of the latest loop iteration in the moving averages.
+const EWMA_WEIGHT: f64 = 0.125;
+
+/// Counts the system calls, completions and time of the driver's loop iterations.
+///
+/// An iteration ends every time the runtime hands control to the driver, after the
+/// tasks it woke ran. Both `io_uring_enter` calls that only submit and ones that park
+/// count as system calls.
+#[derive(Debug)]
+pub struct LoopStats {
+    pub iterations: u64,
+    /// `io_uring_enter` calls that only submitted.
+    pub submits: u64,
+    /// `io_uring_enter` calls that waited for a completion.
+    pub waits: u64,
+    /// Completions reaped.
+    pub completions: u64,
+    /// Turns of the loop that left tasks of expired timers to wake on a later one.
+    pub timers_held: u64,
+    /// Completions ignored, the operation they belonged to being gone.
+    pub stale: u64,
+    /// Time spent running rather than parked.
+    pub busy: Duration,
+    /// Length of the iteration that ended last, parked time included.
+    pub last_iteration: Duration,
+    syscalls_avg: f64,
+    completions_avg: f64,
+    /// When the current iteration started, and the counts before it.
+    started: Instant,
+    syscalls_before: u64,
+    completions_before: u64,
+    parked: Duration,
+}
+
+impl LoopStats {
+    pub fn new() -> LoopStats {
+        LoopStats {
+            iterations: 0,
+            submits: 0,
+            waits: 0,
+            completions: 0,
+            timers_held: 0,
+            stale: 0,
+            busy: Duration::ZERO,
+            last_iteration: Duration::ZERO,
+            syscalls_avg: 0.0,
+            completions_avg: 0.0,
+            started: Instant::now(),
+            syscalls_before: 0,
+            completions_before: 0,
+            parked: Duration::ZERO,
+        }
+    }
+
+    /// Records time spent parked in the current iteration.
+    pub fn parked(&mut self, parked: Duration) {
+        self.parked += parked;
+    }
+
+    /// Ends the current iteration and starts the next one.
+    pub fn iteration(&mut self) {
+        let now = Instant::now();
+        let elapsed = now - self.started;
+        let syscalls = self.submits + self.waits;
+        self.iterations += 1;
+        self.last_iteration = elapsed;
+        self.busy += elapsed.saturating_sub(self.parked);
+        let iteration_syscalls = (syscalls - self.syscalls_before) as f64;
+        let iteration_completions = (self.completions - self.completions_before) as f64;
+        self.syscalls_avg += (iteration_syscalls - self.syscalls_avg) * EWMA_WEIGHT;
+        self.completions_avg += (iteration_completions - self.completions_avg) * EWMA_WEIGHT;
+        self.started = now;
+        self.syscalls_before = syscalls;
+        self.completions_before = self.completions;
+        self.parked = Duration::ZERO;
+    }
+
+    /// Moving average of system calls per completion over the recent iterations, 0
+    /// before anything completed.
+    pub fn syscalls_per_completion(&self) -> f64 {
+        if self.completions_avg == 0.0 {
+            return 0.0;
+        }
+        self.syscalls_avg / self.completions_avg
+    }
+}
@@ -0,0 +1,87 @@
+use std::collections::VecDeque;
+
+use crate::driver::{Cqe, ProvidedBuf};
+
+/// The completions a multishot operation posted and its owner did not take yet.
+pub type Completions = VecDeque<(Cqe, Option<ProvidedBuf>)>;
+
+/// Completion queues kept for reuse by default.
+const DEFAULT_POOLED: usize = 16;
+
+/// Room for completions a pooled queue starts with.
+const QUEUE_CAPACITY: usize = 8;
+
+/// The memory the driver uses for its own bookkeeping, and counters of when it had to
+/// allocate more.
+///
+/// The table of in-flight operations and the lists of deferred work keep their memory
+/// once grown, and the completion queues of finished multishot operations are pooled.
+/// Reserving room up front with [`Inner::reserve`](crate::driver::Inner::reserve)
+/// leaves a driver that stays within it allocating nothing for itself.
+pub struct Memory {
+    queues: Vec<Completions>,
+    /// Most completion queues kept for reuse.
+    pooled: usize,
+    stats: MemoryStats,
+}
+
+/// Counts the allocations the driver made for its own bookkeeping, as opposed to the
+/// ones made by operations for their buffers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    /// Times the table of in-flight operations grew.
+    pub table_grows: u64,
+    /// Completion queues allocated for multishot operations, the pool being empty.
+    pub queues: u64,
+    /// Operations boxed to be kept alive for the kernel after their owner dropped them.
+    pub abandoned: u64,
+}
+
+impl Memory {
+    pub fn new() -> Memory {
+        Memory {
+            queues: Vec::new(),
+            pooled: DEFAULT_POOLED,
+            stats: MemoryStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> MemoryStats {
+        self.stats
+    }
+
+    /// Fills the pool with `queues` completion queues, and keeps up to as many from then
+    /// on.
+    pub fn reserve_queues(&mut self, queues: usize) {
+        self.pooled = self.pooled.max(queues);
+        while self.queues.len() < queues {
+            self.queues.push(VecDeque::with_capacity(QUEUE_CAPACITY));
+        }
+    }
+
+    /// A queue holding `first`, taken from the pool if it has one.
+    pub fn queue(&mut self, first: (Cqe, Option<ProvidedBuf>)) -> Completions {
+        let mut queue = self.queues.pop().unwrap_or_else(|| {
+            self.stats.queues += 1;
+            VecDeque::with_capacity(QUEUE_CAPACITY)
+        });
+        queue.push_back(first);
+        queue
+    }
+
+    /// Hands a drained queue back to the pool, queues still holding completions are
+    /// dropped with them.
+    pub fn recycle(&mut self, queue: Completions) {
+        if queue.is_empty() && self.queues.len() < self.pooled {
+            self.queues.push(queue);
+        }
+    }
+
+    pub(crate) fn table_grew(&mut self) {
+        self.stats.table_grows += 1;
+    }
+
+    pub(crate) fn abandoned(&mut self) {
+        self.stats.abandoned += 1;
+    }
+}
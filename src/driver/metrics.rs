@@ -0,0 +1,114 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use io_uring::squeue::Entry;
+
+use crate::driver::{remote, slot, Cqe};
+
+/// Buckets of a latency histogram, the last one counting every latency above half a
+/// second.
+pub const BUCKETS: usize = 21;
+
+/// The latencies of the operations of an opcode, from their submission to their final
+/// completion, see [`Runtime::metrics`](crate::Runtime::metrics).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    /// Operations completed.
+    pub count: u64,
+    /// Sum of their latencies.
+    pub total: Duration,
+    pub max: Duration,
+    /// Operations by latency, bucket `i` counting those that completed within `2^i`
+    /// microseconds and not within the bound of the bucket before.
+    pub buckets: [u64; BUCKETS],
+}
+
+impl LatencyHistogram {
+    fn record(&mut self, latency: Duration) {
+        let micros = latency.as_micros().max(1) as u64;
+        let bucket = (64 - (micros - 1).leading_zeros()) as usize;
+        self.buckets[bucket.min(BUCKETS - 1)] += 1;
+        self.count += 1;
+        self.total += latency;
+        self.max = self.max.max(latency);
+    }
+
+    pub fn mean(&self) -> Duration {
+        match self.count {
+            0 => Duration::ZERO,
+            count => Duration::from_nanos((self.total.as_nanos() / count as u128) as u64),
+        }
+    }
+
+    /// The latency `q` of the operations completed within, as the upper bound of the
+    /// bucket it falls in, `max` for the last one.
+    pub fn quantile(&self, q: f64) -> Duration {
+        let rank = (q.clamp(0.0, 1.0) * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (i, n) in self.buckets.iter().enumerate().take(BUCKETS - 1) {
+            seen += n;
+            if seen >= rank {
+                return Duration::from_micros(1 << i).min(self.max);
+            }
+        }
+        self.max
+    }
+}
+
+/// What the driver counts with the `metrics` feature on top of its loop statistics.
+#[derive(Default)]
+pub struct Metrics {
+    /// Entries queued for the kernel, the driver's own included.
+    pub sqes: u64,
+    pub parks: u64,
+    /// Parks ended by a task woken from another thread.
+    pub unparks: u64,
+    /// When the operation in each slot of the table was submitted, and its opcode.
+    submitted: Vec<Option<(Instant, u8)>>,
+    latencies: BTreeMap<u8, LatencyHistogram>,
+}
+
+impl Metrics {
+    pub fn submitted(&mut self, sqe: &Entry) {
+        self.sqes += 1;
+        // `Entry` is a `repr(C)` wrapper of `io_uring_sqe`, which holds the opcode at
+        // offset 0 and the entry's key at 32.
+        let raw = unsafe { &*(sqe as *const Entry as *const [u8; 64]) };
+        let mut key = [0; 8];
+        key.copy_from_slice(&raw[32..40]);
+        let (key, opcode) = (u64::from_ne_bytes(key), raw[0]);
+        tracing::trace!(target: "slings::driver", key, opcode, "submit");
+        if key >= remote::WAKE_KEY {
+            return;
+        }
+        let slot = slot(key);
+        if slot >= self.submitted.len() {
+            self.submitted.resize(slot + 1, None);
+        }
+        self.submitted[slot] = Some((Instant::now(), opcode));
+    }
+
+    /// Records the latency of the operation of `key` once `cqe` is its final completion.
+    pub fn completed(&mut self, key: u64, cqe: &Cqe) {
+        tracing::trace!(
+            target: "slings::driver",
+            key,
+            result = cqe.result,
+            flags = cqe.flags,
+            "complete"
+        );
+        if cqe.more() {
+            return;
+        }
+        let submitted = self.submitted.get_mut(slot(key)).and_then(Option::take);
+        if let Some((at, opcode)) = submitted {
+            let latency = at.elapsed();
+            self.latencies.entry(opcode).or_default().record(latency);
+        }
+    }
+
+    /// The latency histograms of the opcodes that completed operations, by opcode.
+    pub fn latencies(&self) -> Vec<(u8, LatencyHistogram)> {
+        self.latencies.iter().map(|(&op, &h)| (op, h)).collect()
+    }
+}
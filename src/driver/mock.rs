@@ -0,0 +1,205 @@
+//! A backend that completes submissions in userspace, used under Miri where the io_uring
+//! syscalls are unavailable.
+//!
+//! Submitted entries stay in flight until the next wait, so cancellation and dropped
+//! actions can be exercised. On completion a timeout reports `ETIME`, a cancelled entry
+//! reports `ECANCELED`, and every other operation succeeds with a result of 0.
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io;
+use std::mem;
+use std::os::unix::io::RawFd;
+use std::rc::Rc;
+use std::time::Duration;
+
+use io_uring::opcode;
+use io_uring::squeue::Entry;
+
+use crate::driver::{Backend, Cqe, RingFlags};
+
+#[derive(Debug, Clone, Copy)]
+struct Sqe {
+    opcode: u8,
+    fd: i32,
+    addr: u64,
+    /// The opcode specific flags, such as a cancel's.
+    op_flags: u32,
+    user_data: u64,
+}
+
+impl Sqe {
+    fn decode(entry: &Entry) -> Sqe {
+        // `Entry` is a `repr(C)` wrapper around the 64 byte `io_uring_sqe`, which is
+        // zero initialized by every opcode builder.
+        let raw = unsafe { &*(entry as *const Entry as *const [u8; 64]) };
+        let u64_at = |offset: usize| {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&raw[offset..offset + 8]);
+            u64::from_ne_bytes(bytes)
+        };
+        let u32_at = |offset: usize| {
+            let mut bytes = [0; 4];
+            bytes.copy_from_slice(&raw[offset..offset + 4]);
+            u32::from_ne_bytes(bytes)
+        };
+        Sqe {
+            opcode: raw[0],
+            fd: u32_at(4) as i32,
+            addr: u64_at(16),
+            op_flags: u32_at(28),
+            user_data: u64_at(32),
+        }
+    }
+}
+
+/// The completions posted and not reaped yet, with the user data of their entries.
+pub type Completions = Rc<RefCell<VecDeque<(u64, Cqe)>>>;
+
+pub struct Mock {
+    capacity: usize,
+    sq: Vec<Sqe>,
+    in_flight: VecDeque<Sqe>,
+    /// Shared so that tests can post completions of their own.
+    cq: Completions,
+}
+
+impl Mock {
+    pub fn new(entries: u32) -> Mock {
+        Mock {
+            capacity: entries as usize,
+            sq: Vec::new(),
+            in_flight: VecDeque::new(),
+            cq: Rc::default(),
+        }
+    }
+
+    /// The completion queue, completions pushed to it are reaped on the next turn of
+    /// the loop.
+    #[cfg(test)]
+    pub fn completions(&self) -> Completions {
+        self.cq.clone()
+    }
+}
+
+fn post(cq: &RefCell<VecDeque<(u64, Cqe)>>, user_data: u64, result: i32) {
+    cq.borrow_mut()
+        .push_back((user_data, Cqe { result, flags: 0 }));
+}
+
+impl Backend for Mock {
+    unsafe fn push(&mut self, sqe: &Entry) -> bool {
+        if self.sq.len() == self.capacity {
+            return false;
+        }
+        self.sq.push(Sqe::decode(sqe));
+        true
+    }
+
+    fn sq_space(&mut self) -> usize {
+        self.capacity - self.sq.len()
+    }
+
+    fn sq_len(&mut self) -> usize {
+        self.sq.len()
+    }
+
+    fn supports(&mut self, _: u8) -> bool {
+        true
+    }
+
+    fn flags(&self) -> RingFlags {
+        RingFlags::NONE
+    }
+
+    fn submit(&mut self) -> io::Result<usize> {
+        let n = self.sq.len();
+        for sqe in self.sq.drain(..) {
+            if sqe.opcode != opcode::AsyncCancel::CODE {
+                self.in_flight.push_back(sqe);
+                continue;
+            }
+            // `IORING_ASYNC_CANCEL_FD` matches every operation on the fd.
+            let by_fd = sqe.op_flags & (1 << 1) != 0;
+            let (cancelled, left): (VecDeque<Sqe>, _) =
+                self.in_flight.drain(..).partition(|s| match by_fd {
+                    true => s.fd == sqe.fd && s.opcode != opcode::Timeout::CODE,
+                    false => s.user_data == sqe.addr,
+                });
+            self.in_flight = left;
+            for target in &cancelled {
+                post(&self.cq, target.user_data, -libc::ECANCELED);
+            }
+            let result = match cancelled.len() {
+                0 => -libc::ENOENT,
+                n if by_fd => n as i32,
+                _ => 0,
+            };
+            post(&self.cq, sqe.user_data, result);
+        }
+        Ok(n)
+    }
+
+    fn submit_and_wait(&mut self, want: usize) -> io::Result<usize> {
+        let n = self.submit()?;
+        if want > 0 {
+            for sqe in self.in_flight.drain(..) {
+                let result = if sqe.opcode == opcode::Timeout::CODE {
+                    -libc::ETIME
+                } else {
+                    0
+                };
+                post(&self.cq, sqe.user_data, result);
+            }
+        }
+        Ok(n)
+    }
+
+    fn submit_and_wait_timeout(&mut self, want: usize, _: Duration) -> io::Result<usize> {
+        self.submit_and_wait(want)
+    }
+
+    fn taskrun(&mut self) -> bool {
+        false
+    }
+
+    fn has_completions(&mut self) -> bool {
+        !self.cq.borrow().is_empty()
+    }
+
+    fn post_completions(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn register_buf_ring(&mut self, _: u64, _: u16, _: u16) -> io::Result<()> {
+        // reads never select a buffer here, they fall back to their own.
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    fn unregister_buf_ring(&mut self, _: u16) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    fn register_buffers(&mut self, _: &[libc::iovec]) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    fn unregister_buffers(&mut self) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    fn register_files_sparse(&mut self, _: u32) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    fn update_files(&mut self, _: u32, _: &[RawFd]) -> io::Result<usize> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    fn reap(&mut self, f: &mut dyn FnMut(u64, Cqe)) {
+        // taken first, `f` may drop actions that post completions of their own.
+        let cq = mem::take(&mut *self.cq.borrow_mut());
+        for (user_data, cqe) in cq {
+            f(user_data, cqe);
+        }
+    }
+}
@@ -1,158 +1,1078 @@
+use std::any::Any;
 use std::cell::RefCell;
+use std::collections::HashMap;
 use std::io;
 use std::mem::{self, size_of, MaybeUninit};
 use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
 use std::ops::{Deref, DerefMut};
-use std::panic;
+use std::ptr;
 use std::rc::Rc;
 use std::slice;
 use std::task::Waker;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use io_uring::squeue::Entry;
-use io_uring::{cqueue, IoUring};
+use io_uring::squeue::{self, Entry};
+use io_uring::{cqueue, opcode, types};
 use scoped_tls::scoped_thread_local;
 use slab::Slab;
 
+use crate::error::Error;
+use event_log::EventLog;
+
+/// Builds an entry for `$fd` with `$target` bound to its fixed file slot if it has one,
+/// and to the plain fd otherwise.
+macro_rules! target {
+    ($fd:expr, |$target:ident| $build:expr) => {{
+        let fd = $fd;
+        match crate::driver::files::fixed_slot(fd) {
+            Some(slot) => {
+                let $target = io_uring::types::Fixed(slot);
+                $build
+            }
+            None => {
+                let $target = io_uring::types::Fd(fd);
+                $build
+            }
+        }
+    }};
+}
+
 pub mod accept;
 pub mod action;
+pub mod allocate;
+pub mod backend;
+pub mod buffers;
+pub mod cancel;
+pub mod chain;
+pub mod close;
+pub mod cmsg;
 pub mod connect;
+pub mod deferred;
+pub mod event_log;
+pub mod features;
+pub mod files;
+pub mod fixed;
+pub mod fsync;
+pub mod iobuf;
+pub mod loop_stats;
+pub mod memory;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod open;
 pub mod packet;
+pub mod path;
+pub mod poll;
 pub mod read;
 pub mod recv;
+pub mod recv_send;
 pub mod recvmsg;
+pub mod remote;
 pub mod send;
+pub mod send_zc;
 pub mod sendmsg;
+pub mod shared_fd;
+pub mod shutdown;
+pub mod splice;
+pub mod statx;
 pub mod stream;
 pub mod timeout;
+#[cfg(not(miri))]
+pub mod uring;
+pub mod vectored;
+pub mod waitid;
 pub mod write;
+pub mod writev;
 
-pub use action::Action;
+#[cfg(any(test, miri))]
+mod mock;
+
+pub use action::{Action, Completable, Completion, Detached, Shot};
+pub use backend::{Backend, RingFlags};
+pub use buffers::{Buffers, ProvidedBuf, Sizing};
+pub use deferred::Deferred;
+pub use event_log::{Event, EventKind};
+pub use features::Features;
+pub use loop_stats::LoopStats;
+pub use memory::Memory;
+#[cfg(feature = "metrics")]
+pub use metrics::{LatencyHistogram, Metrics};
 pub use packet::Packet;
-pub use read::Read;
-pub use recv::Recv;
+pub use poll::PollMulti;
+pub use read::{Read, ReadProvided};
+pub use recv::{Recv, RecvMulti};
 pub use recvmsg::RecvMsg;
 pub use send::Send;
+pub use send_zc::ZcWrite;
 pub use sendmsg::SendMsg;
-pub use stream::Stream;
+pub use shared_fd::{SharedFd, WeakFd};
+pub use statx::Statx;
+pub(crate) use stream::Ticket;
+pub use stream::{Stream, StreamParts, StreamStats};
 pub use timeout::Timeout;
 pub use write::Write;
 
 pub const DEFAULT_BUFFER_SIZE: usize = 4096;
 
+/// Number of buffers in the ring registered for every driver.
+pub const DEFAULT_BUFFER_ENTRIES: u16 = 64;
+
+/// Most tasks of expired timers woken per turn of the loop by default.
+pub const DEFAULT_TIMER_BATCH: usize = 256;
+
+/// How many times a full submission queue is flushed before giving up on an entry.
+const PUSH_ATTEMPTS: u32 = 8;
+
 scoped_thread_local!(static CURRENT: Driver);
 
 pub struct Driver {
     pub inner: Rc<RefCell<Inner>>,
+    deferred: Rc<Deferred>,
 }
 
 impl Clone for Driver {
     fn clone(&self) -> Self {
         Driver {
             inner: self.inner.clone(),
+            deferred: self.deferred.clone(),
         }
     }
 }
 
 pub struct Inner {
-    ring: IoUring,
+    backend: Box<dyn Backend>,
     actions: Slab<State>,
-    // buffers: Buffers,
+    /// How many times each slot of `actions` was taken, the upper half of the keys
+    /// handed out for it. A completion for an operation whose slot was released and
+    /// taken by another one since is told apart from the new operation's by it.
+    generations: Vec<u32>,
+    /// The ring reads select a buffer from, `None` if the kernel has no buffer rings.
+    buffers: Option<Rc<Buffers>>,
+    sizing: Sizing,
+    /// Rings registered under a group id of their own, reads name the one they select
+    /// from.
+    groups: HashMap<u16, Rc<Buffers>>,
+    /// The group of `groups` each read in flight selects from, by key.
+    selecting: HashMap<u64, u16>,
+    /// Cleared once the kernel rejected a multishot recv (before Linux 6.0).
+    recv_multi: bool,
+    features: Features,
+    /// Buffers returned to be provided to the kernel again, kept for its capacity.
+    returned: Vec<u16>,
+    /// Registered on first use, `None` until then.
+    files: Option<files::FileTable>,
+    remote: remote::Remote,
+    deferred: Rc<Deferred>,
+    started: Instant,
+    /// Milliseconds from `started` to when the driver last woke up.
+    ticks: u64,
+    /// Longest the driver parks without a completion, `None` for no limit.
+    max_park: Option<Duration>,
+    loop_stats: LoopStats,
+    /// Set while a cancelled future is polled, the operations it polls cancel themselves,
+    /// see [`crate::io::cancellable`].
+    cancelling: bool,
+    events: EventLog,
+    memory: Memory,
+    /// Most tasks of expired timers woken per turn of the loop.
+    timer_batch: usize,
+    #[cfg(feature = "metrics")]
+    metrics: Metrics,
 }
 
 impl Driver {
-    pub fn new() -> io::Result<Driver> {
-        let ring = IoUring::new(256)?;
-        // check if IORING_FEAT_FAST_POLL is supported
-        if !ring.params().is_feature_fast_poll() {
-            panic!("IORING_FEAT_FAST_POLL not supported");
-        }
-
-        let driver = Driver {
-            inner: Rc::new(RefCell::new(Inner {
-                ring,
-                actions: Slab::new(),
-            })),
+    /// A driver whose ring is set up with `flags`, those the kernel does not support
+    /// are left out.
+    pub fn with_flags(flags: RingFlags) -> io::Result<Driver> {
+        Driver::with_backend(default_backend(flags)?)
+    }
+
+    pub fn with_backend(mut backend: Box<dyn Backend>) -> io::Result<Driver> {
+        let features = Features::probe(&mut *backend);
+        let deferred = Rc::new(Deferred::default());
+        let started = Instant::now();
+        let mut inner = Inner {
+            backend,
+            actions: Slab::new(),
+            generations: Vec::new(),
+            buffers: None,
+            sizing: Sizing::adaptive(),
+            groups: HashMap::new(),
+            selecting: HashMap::new(),
+            recv_multi: features.multishot_recv,
+            features,
+            returned: Vec::new(),
+            files: None,
+            remote: remote::Remote::new()?,
+            deferred: deferred.clone(),
+            started,
+            ticks: 0,
+            max_park: None,
+            loop_stats: LoopStats::new(),
+            cancelling: false,
+            events: EventLog::new(started),
+            memory: Memory::new(),
+            timer_batch: DEFAULT_TIMER_BATCH,
+            #[cfg(feature = "metrics")]
+            metrics: Metrics::default(),
         };
-        Ok(driver)
+        // buffer rings need Linux 5.19, older kernels are provided the buffers one
+        // operation at a time, and reads bring their own buffer if that fails too.
+        let ring = inner.reconfigure_buffers(DEFAULT_BUFFER_ENTRIES, DEFAULT_BUFFER_SIZE);
+        inner.features.buf_ring = ring.is_ok();
+        if ring.is_err() && inner.features.provide_buffers {
+            let _ = inner.provide_buffers(DEFAULT_BUFFER_ENTRIES, DEFAULT_BUFFER_SIZE);
+        }
+        Ok(Driver {
+            inner: Rc::new(RefCell::new(inner)),
+            deferred,
+        })
     }
 
+    /// Ends a turn of the loop: hands the entries queued during the turn to the kernel,
+    /// and parks until a completion arrives unless tasks are ready to run already.
     pub fn wait(&self) -> io::Result<()> {
-        let inner = &mut *self.inner.borrow_mut();
-        let ring = &mut inner.ring;
+        self.dispatch_events();
+        {
+            let mut inner = self.inner.borrow_mut();
+            inner.loop_stats.iteration();
+            inner.tick();
+            inner.release_timers();
+            inner.replenish();
+        }
+        // tasks woken by completions reaped elsewhere are ready to run without parking.
+        if self.flush() {
+            return self.submit_queued();
+        }
+        self.idle();
+        if self.flush() {
+            return self.submit_queued();
+        }
+        let inner = &mut *self.lock();
 
-        if let Err(e) = ring.submit_and_wait(1) {
-            if e.raw_os_error() == Some(libc::EBUSY) {
-                return Ok(());
+        // tasks woken from other threads are ready to run without parking.
+        if inner.remote.wake(&inner.deferred) {
+            return inner.submit_queued();
+        }
+
+        // completions already posted can be reaped without entering the kernel.
+        if inner.backend.has_completions() {
+            inner.reap();
+            inner.adapt_buffers();
+            return inner.submit_queued();
+        }
+
+        if let Some(sqe) = inner.remote.arm() {
+            inner.push(&[sqe])?;
+        }
+        let parked = Instant::now();
+        inner.events.record(EventKind::Park {
+            max: inner.max_park,
+        });
+        #[cfg(feature = "metrics")]
+        let park = {
+            inner.metrics.parks += 1;
+            tracing::trace_span!(target: "slings::driver", "park").entered()
+        };
+        let res = match inner.max_park {
+            Some(max) => inner.backend.submit_and_wait_timeout(1, max),
+            None => inner.backend.submit_and_wait(1),
+        };
+        #[cfg(feature = "metrics")]
+        drop(park);
+        inner.events.record(EventKind::Unpark);
+        inner.loop_stats.waits += 1;
+        inner.loop_stats.parked(parked.elapsed());
+        match res {
+            Err(e) if !is_transient(&e) && e.raw_os_error() != Some(libc::ETIME) => return Err(e),
+            // a busy ring still needs its completions reaped to make progress.
+            _ => inner.reap(),
+        }
+        inner.tick();
+        inner.remote.wake(&inner.deferred);
+        inner.adapt_buffers();
+        Ok(())
+    }
+
+    /// Hands the queued entries to the kernel and reaps whatever completions are ready
+    /// without blocking. The kernel is only entered when entries are queued or it flagged
+    /// pending task work through `IORING_SQ_TASKRUN`.
+    pub fn poll(&self) -> io::Result<()> {
+        self.dispatch_events();
+        let inner = &mut *self.lock();
+        inner.loop_stats.iteration();
+        inner.replenish();
+        if inner.backend.sq_len() > 0 || inner.backend.taskrun() {
+            match inner.submit() {
+                Err(e) if !is_transient(&e) => return Err(e),
+                _ => {}
             }
-            if e.kind() == io::ErrorKind::Interrupted {
-                return Ok(());
+        }
+        inner.tick();
+        inner.reap();
+        inner.release_timers();
+        inner.remote.wake(&inner.deferred);
+        inner.adapt_buffers();
+        Ok(())
+    }
+
+    /// Passes the adaptation events recorded since the last turn to the hook, with the
+    /// driver released so the hook may use the runtime.
+    fn dispatch_events(&self) {
+        let pending = self.inner.borrow_mut().events.take_pending();
+        if let Some((hook, events)) = pending {
+            events.iter().for_each(|event| hook(event));
+        }
+    }
+
+    /// Bounds how long [`wait`](Driver::wait) parks without a completion, `None` parks
+    /// until one arrives. Fails if the kernel can not bound the wait, which needs Linux
+    /// 5.11.
+    pub fn set_max_park(&self, max: Option<Duration>) -> io::Result<()> {
+        let inner = &mut *self.lock();
+        if max.is_some() {
+            // waiting for nothing returns right away, or fails if unsupported.
+            inner.backend.submit_and_wait_timeout(0, Duration::ZERO)?;
+        }
+        inner.max_park = max;
+        Ok(())
+    }
+
+    /// Cancels every operation the kernel still works on and waits up to `timeout` for
+    /// their completions. Returns whether all of them completed.
+    pub fn drain(&self, timeout: Duration) -> io::Result<bool> {
+        let deadline = Instant::now() + timeout;
+        let inner = &mut *self.lock();
+        for key in inner.in_kernel() {
+            inner.cancel(key);
+        }
+        if inner.remote.armed() {
+            inner.cancel(remote::WAKE_KEY);
+        }
+        loop {
+            inner.reap();
+            if inner.in_kernel().is_empty() && !inner.remote.armed() {
+                return Ok(true);
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left == Duration::ZERO {
+                return Ok(false);
+            }
+            // the timer bounds the wait, its completion is ignored like a cancel's.
+            let spec = types::Timespec::new()
+                .sec(left.as_secs())
+                .nsec(left.subsec_nanos());
+            let sqe = opcode::Timeout::new(&spec).build().user_data(u64::MAX);
+            inner.push(&[sqe])?;
+            match inner.backend.submit_and_wait(1) {
+                Err(e) if !is_transient(&e) => return Err(e),
+                _ => {}
             }
-            return Err(e);
         }
+    }
 
-        let mut cq = ring.completion();
-        cq.sync();
-        for cqe in cq {
-            let key = cqe.user_data();
-            if key == u64::MAX {
-                continue;
+    /// Leaks the data of operations the kernel may still access and unregisters the
+    /// buffer ring, so nothing is freed under the kernel once the ring is torn down.
+    pub fn release(&self) {
+        let inner = &mut *self.lock();
+        inner.reap();
+        let pending = inner.in_kernel();
+        for &key in &pending {
+            if let State::Ignored(action) = inner.actions.remove(slot(key)) {
+                mem::forget(action);
             }
-            let action = &mut inner.actions[key as usize];
-            action.complete(cqe);
         }
+        inner.remote.release();
+        let groups = inner.groups.drain().collect::<Vec<_>>();
+        let default = inner
+            .buffers
+            .take()
+            .map(|buffers| (buffers::GROUP_ID, buffers));
+        for (bgid, buffers) in groups.into_iter().chain(default) {
+            if pending.is_empty() {
+                let _ = inner.backend.unregister_buf_ring(bgid);
+                buffers.set_registered(false);
+            } else {
+                // a pending read may still select one of its buffers.
+                mem::forget(buffers);
+            }
+        }
+    }
 
-        Ok(())
+    /// Makes the operations polled from now on cancel themselves if `cancelling`,
+    /// returning the previous setting to restore.
+    pub(crate) fn set_cancelling(&self, cancelling: bool) -> bool {
+        mem::replace(&mut self.inner.borrow_mut().cancelling, cancelling)
     }
 
     pub fn with<T>(&self, f: impl FnOnce() -> T) -> T {
         CURRENT.set(self, f)
     }
 
+    /// Runs `f` with the driver of the current runtime, `None` outside of one.
+    pub fn try_current<T>(f: impl FnOnce(&Driver) -> T) -> Option<T> {
+        if CURRENT.is_set() {
+            Some(CURRENT.with(f))
+        } else {
+            None
+        }
+    }
+
+    /// Hands the entries queued so far to the kernel right away, rather than at the end
+    /// of the turn of the loop.
+    pub fn submit_queued(&self) -> io::Result<()> {
+        self.lock().submit_queued()
+    }
+
     pub fn submit(&self, sqe: Entry) -> io::Result<u64> {
-        let mut inner = self.inner.borrow_mut();
-        let inner = &mut *inner;
-        let key = inner.actions.insert(State::Submitted) as u64;
+        let mut inner = self.lock();
+        let key = inner.insert();
+        if let Err(e) = inner.push(&[sqe.user_data(key)]) {
+            inner.actions.remove(slot(key));
+            return Err(e);
+        }
+        Ok(key)
+    }
+
+    /// Submits `first` linked to `second`, which only starts once `first` completed in
+    /// full and is cancelled otherwise.
+    pub fn submit_link(&self, first: Entry, second: Entry) -> io::Result<(u64, u64)> {
+        let mut inner = self.lock();
+        let first_key = inner.insert();
+        let second_key = inner.insert();
+        let sqes = [
+            first.flags(squeue::Flags::IO_LINK).user_data(first_key),
+            second.user_data(second_key),
+        ];
+        if let Err(e) = inner.push(&sqes) {
+            inner.actions.remove(slot(first_key));
+            inner.actions.remove(slot(second_key));
+            return Err(e);
+        }
+        Ok((first_key, second_key))
+    }
+}
 
-        let ring = &mut inner.ring;
-        if ring.submission().is_full() {
-            ring.submit()?;
-            ring.submission().sync();
+impl Inner {
+    /// Takes a slot for an operation about to be submitted, returning its key.
+    fn insert(&mut self) -> u64 {
+        if self.actions.len() == self.actions.capacity() {
+            self.memory.table_grew();
+        }
+        let slot = self.actions.insert(State::Submitted);
+        if slot >= self.generations.len() {
+            self.generations.resize(slot + 1, 0);
         }
+        let generation = &mut self.generations[slot];
+        *generation = generation.wrapping_add(1);
+        (*generation as u64) << 32 | slot as u64
+    }
 
-        let sqe = sqe.user_data(key);
-        unsafe {
-            ring.submission().push(&sqe).expect("push entry fail");
+    /// Queues `sqes` back to back, so a link chain is never split across submissions.
+    ///
+    /// The entries go to the kernel together with the others queued during the turn of
+    /// the loop, when the driver waits or polls, or once the submission queue is full.
+    /// Submitting once per turn instead of once per operation saves a system call for
+    /// every operation but one of those started while the tasks run.
+    fn push(&mut self, sqes: &[Entry]) -> io::Result<()> {
+        for attempt in 0..PUSH_ATTEMPTS {
+            if self.backend.sq_space() >= sqes.len() {
+                for sqe in sqes {
+                    let pushed = unsafe { self.backend.push(sqe) };
+                    debug_assert!(pushed);
+                    self.events.record_sqe(sqe);
+                    #[cfg(feature = "metrics")]
+                    self.metrics.submitted(sqe);
+                }
+                return Ok(());
+            }
+
+            // the submission queue is full, hand it to the kernel and drain the
+            // completion queue so the kernel has room to accept more entries.
+            match self.submit() {
+                Err(e) if !is_transient(&e) => return Err(e),
+                _ => {}
+            }
+            self.reap();
+            if attempt > 0 {
+                thread::sleep(Duration::from_micros(1 << attempt));
+            }
         }
-        ring.submit()?;
-        Ok(key)
+        Err(Error::RingFull.into())
+    }
+
+    /// Hands the queued entries to the kernel if there are any, a busy kernel picks them
+    /// up on a later submit.
+    fn submit_queued(&mut self) -> io::Result<()> {
+        if self.backend.sq_len() == 0 {
+            return Ok(());
+        }
+        match self.submit() {
+            Err(e) if !is_transient(&e) => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Hands the queued entries to the kernel.
+    fn submit(&mut self) -> io::Result<usize> {
+        self.loop_stats.submits += 1;
+        self.backend.submit()
+    }
+
+    fn reap(&mut self) {
+        let mut reaped = 0;
+        let mut stale = 0;
+        let actions = &mut self.actions;
+        let generations = &self.generations;
+        let buffers = &self.buffers;
+        let sizing = &mut self.sizing;
+        let groups = &self.groups;
+        let selecting = &mut self.selecting;
+        let remote = &mut self.remote;
+        let deferred = &self.deferred;
+        let events = &mut self.events;
+        let memory = &mut self.memory;
+        #[cfg(feature = "metrics")]
+        let metrics = &mut self.metrics;
+        self.backend.reap(&mut |key, cqe| {
+            reaped += 1;
+            events.record(EventKind::Complete {
+                key,
+                result: cqe.result,
+                flags: cqe.flags,
+            });
+            // claim the selected buffer right away, it goes back to the ring when the
+            // operation was dropped in the meantime.
+            let group = match selecting.is_empty() {
+                true => None,
+                false => selecting.remove(&key),
+            };
+            if cqe.result == -libc::ENOBUFS {
+                events.record(EventKind::BuffersExhausted { key });
+                if group.is_none() {
+                    sizing.record_exhausted();
+                }
+            }
+            let buf = match (cqe.buffer_id(), group, buffers) {
+                (Some(bid), Some(group), _) => groups
+                    .get(&group)
+                    .map(|buffers| buffers.select(bid, cqe.result.max(0) as usize)),
+                (Some(bid), None, Some(buffers)) => {
+                    let len = cqe.result.max(0) as usize;
+                    sizing.record(len, buffers.size());
+                    Some(buffers.select(bid, len))
+                }
+                _ => None,
+            };
+            if key == u64::MAX {
+                return;
+            }
+            if key == remote::WAKE_KEY {
+                #[cfg(feature = "metrics")]
+                {
+                    metrics.unparks += 1;
+                }
+                remote.completed();
+                return;
+            }
+            let slot = slot(key);
+            // the operation is gone and its slot possibly taken by another one, the
+            // selected buffer goes back to the ring.
+            if !actions.contains(slot) || generations[slot] != (key >> 32) as u32 {
+                stale += 1;
+                return;
+            }
+            #[cfg(feature = "metrics")]
+            metrics.completed(key, &cqe);
+            if actions[slot].complete(cqe, buf, deferred, memory) {
+                deferred.discard(actions.remove(slot));
+            }
+        });
+        deferred.rotate();
+        self.loop_stats.completions += reaped;
+        self.loop_stats.stale += stale;
+    }
+
+    /// Reaps the completions the kernel still holds back along with the posted ones, so
+    /// none of the operations completed until now is left to be reaped later.
+    fn reap_all(&mut self) {
+        while self.backend.post_completions().is_ok() && self.backend.has_completions() {
+            self.reap();
+        }
+        // a failed enter still leaves the completions posted until then.
+        self.reap();
+    }
+
+    /// Wakes the next batch of tasks of expired timers, see [`Deferred::release_timers`].
+    fn release_timers(&mut self) {
+        let left = self.deferred.release_timers(self.timer_batch);
+        if left > 0 {
+            self.loop_stats.timers_held += 1;
+        }
+    }
+
+    /// Wakes at most `max` tasks of expired timers per turn of the loop, the others on
+    /// the turns after.
+    pub fn set_timer_batch(&mut self, max: usize) {
+        self.timer_batch = max.max(1);
+    }
+
+    /// Resizes the buffer ring once the observed read sizes call for it, and grows it
+    /// once reads found every buffer in use.
+    fn adapt_buffers(&mut self) {
+        let (entries, size) = match &self.buffers {
+            Some(buffers) => (buffers.entries(), buffers.size()),
+            None => return,
+        };
+        let grown = self.sizing.next_entries(entries);
+        let resized = self.sizing.next_size(size);
+        if grown.is_none() && resized.is_none() {
+            return;
+        }
+        let res = self.reconfigure_buffers(grown.unwrap_or(entries), resized.unwrap_or(size));
+        if res.is_ok() {
+            self.sizing.grows += grown.is_some() as u64;
+            self.sizing.resizes += resized.is_some() as u64;
+        }
+    }
+
+    /// Sets the buffer ring to a fixed size, turning adaptive sizing off.
+    pub fn set_buffers(&mut self, entries: u16, size: usize) -> io::Result<()> {
+        self.sizing.adaptive = false;
+        self.reconfigure_buffers(entries, size)
+    }
+
+    /// Counters of the buffer ring, `None` without one.
+    pub fn buffer_metrics(&self) -> Option<(u16, usize, &Sizing)> {
+        let buffers = self.buffers.as_ref()?;
+        Some((buffers.entries(), buffers.size(), &self.sizing))
+    }
+
+    /// The setup flags of the ring.
+    pub fn ring_flags(&self) -> RingFlags {
+        self.backend.flags()
+    }
+
+    pub fn loop_stats(&self) -> &LoopStats {
+        &self.loop_stats
+    }
+
+    /// Keeps the last `capacity` driver events, 0 turns the log off.
+    pub fn set_event_log(&mut self, capacity: usize) {
+        self.events.set_capacity(capacity);
+    }
+
+    /// Passes the adaptation events to `hook` at the start of the turn of the loop after
+    /// they happened.
+    pub fn set_adaptation_hook(&mut self, hook: impl Fn(&Event) + 'static) {
+        self.events.set_hook(Rc::new(hook));
+    }
+
+    /// The recorded driver events, oldest first.
+    pub fn events(&self) -> Vec<Event> {
+        self.events.events()
+    }
+
+    /// Makes room for `operations` in flight at once, multishot ones included, so the
+    /// driver allocates nothing for its own bookkeeping until more are.
+    pub fn reserve(&mut self, operations: usize) {
+        let additional = operations.saturating_sub(self.actions.len());
+        self.actions.reserve(additional);
+        self.deferred.reserve(operations);
+        self.memory.reserve_queues(operations);
+    }
+
+    pub fn memory(&mut self) -> &mut Memory {
+        &mut self.memory
+    }
+
+    /// Keys of the operations the kernel has not posted the final completion of.
+    fn in_kernel(&self) -> Vec<u64> {
+        self.actions
+            .iter()
+            .filter(|(_, state)| state.in_kernel())
+            .map(|(slot, _)| (self.generations[slot] as u64) << 32 | slot as u64)
+            .collect()
+    }
+
+    /// Milliseconds since the driver was created, as of when it last woke up.
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    fn tick(&mut self) {
+        self.ticks = self.started.elapsed().as_millis() as u64;
+    }
+
+    /// Number of operations submitted and not yet completed.
+    pub fn in_flight(&self) -> usize {
+        self.actions.len()
+    }
+
+    /// Buffers of the rings held by readers, and the buffers of all rings.
+    #[cfg(feature = "metrics")]
+    pub fn buffers_in_use(&self) -> (usize, usize) {
+        let rings = self.groups.values().chain(&self.buffers);
+        rings.fold((0, 0), |(in_use, all), buffers| {
+            (
+                in_use + buffers.in_use() as usize,
+                all + buffers.entries() as usize,
+            )
+        })
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Whether a multishot recv can be submitted.
+    pub fn recv_multi(&self) -> bool {
+        self.recv_multi && self.buffers.is_some()
+    }
+
+    pub fn disable_recv_multi(&mut self) {
+        self.recv_multi = false;
+    }
+
+    /// The size of the buffers reads select from, `None` without a buffer ring.
+    pub fn buffer_size(&self) -> Option<usize> {
+        self.buffers.as_ref().map(|buffers| buffers.size())
+    }
+
+    /// The size of the buffers of group `bgid`, the default ring's for
+    /// [`buffers::GROUP_ID`], `None` if no ring is registered under it.
+    pub fn group_size(&self, bgid: u16) -> Option<usize> {
+        match bgid {
+            buffers::GROUP_ID => self.buffer_size(),
+            _ => self.groups.get(&bgid).map(|buffers| buffers.size()),
+        }
+    }
+
+    /// The group whose buffers hold `len` bytes with the least room to spare, the one
+    /// with the largest buffers if none holds them. `None` without any buffer ring.
+    pub fn group_for(&self, len: usize) -> Option<u16> {
+        let default = self.buffer_size().map(|size| (buffers::GROUP_ID, size));
+        let groups = self
+            .groups
+            .iter()
+            .map(|(&bgid, buffers)| (bgid, buffers.size()));
+        let all: Vec<(u16, usize)> = groups.chain(default).collect();
+        let fitting = all.iter().filter(|&&(_, size)| size >= len);
+        match fitting.min_by_key(|&&(bgid, size)| (size, bgid)) {
+            Some(&(bgid, _)) => Some(bgid),
+            None => all
+                .iter()
+                .max_by_key(|&&(bgid, size)| (size, bgid))
+                .map(|g| g.0),
+        }
+    }
+
+    /// Remembers that the read `key` selects from group `bgid` rather than the default
+    /// ring, so the buffer it picks is looked up there.
+    pub fn select_from(&mut self, key: u64, bgid: u16) {
+        if bgid != buffers::GROUP_ID {
+            self.selecting.insert(key, bgid);
+        }
+    }
+
+    /// Registers a ring of `entries` buffers of `size` bytes as group `bgid`, replacing
+    /// the ring registered under it before. Buffers of that ring that are still handed
+    /// out stay valid.
+    pub fn register_group(&mut self, bgid: u16, entries: u16, size: usize) -> io::Result<()> {
+        if bgid == buffers::GROUP_ID {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "buffer group id reserved for the default ring",
+            ));
+        }
+        let buffers = Buffers::new(entries, size)?;
+        self.unregister_group(bgid)?;
+        self.backend
+            .register_buf_ring(buffers.ring_addr(), buffers.entries(), bgid)?;
+        buffers.set_registered(true);
+        self.events.record(EventKind::BuffersResized {
+            group: bgid,
+            entries,
+            size,
+        });
+        self.groups.insert(bgid, buffers);
+        Ok(())
+    }
+
+    /// Unregisters the ring of group `bgid`, reads still selecting from it fail with
+    /// `ENOBUFS`.
+    pub fn unregister_group(&mut self, bgid: u16) -> io::Result<()> {
+        if !self.groups.contains_key(&bgid) {
+            return Ok(());
+        }
+        self.backend.unregister_buf_ring(bgid)?;
+        // completions posted until now picked their buffer from this ring.
+        self.reap_all();
+        if let Some(old) = self.groups.remove(&bgid) {
+            old.set_registered(false);
+        }
+        Ok(())
+    }
+
+    /// Replaces the buffer ring with one of `entries` buffers of `size` bytes.
+    ///
+    /// Buffers of the old ring that are still handed out stay valid and are freed once
+    /// dropped. A read that finds no ring while the swap happens fails with `ENOBUFS`
+    /// before consuming any data and is retried with a buffer of its own.
+    pub fn reconfigure_buffers(&mut self, entries: u16, size: usize) -> io::Result<()> {
+        if self
+            .buffers
+            .as_ref()
+            .is_some_and(|buffers| buffers.is_provided())
+        {
+            return Err(Error::KernelFeatureMissing("IORING_REGISTER_PBUF_RING").into());
+        }
+        let buffers = Buffers::new(entries, size)?;
+        if self.buffers.is_some() {
+            self.backend.unregister_buf_ring(buffers::GROUP_ID)?;
+            // completions posted until now picked their buffer from the old ring.
+            self.reap_all();
+            if let Some(old) = self.buffers.take() {
+                old.set_registered(false);
+            }
+        }
+        self.backend.register_buf_ring(
+            buffers.ring_addr(),
+            buffers.entries(),
+            buffers::GROUP_ID,
+        )?;
+        buffers.set_registered(true);
+        self.events.record(EventKind::BuffersResized {
+            group: buffers::GROUP_ID,
+            entries,
+            size,
+        });
+        self.buffers = Some(buffers);
+        Ok(())
     }
+
+    /// Hands `entries` buffers of `size` bytes to the kernel with
+    /// `IORING_OP_PROVIDE_BUFFERS`, for kernels without buffer rings. Their size stays
+    /// as it is.
+    fn provide_buffers(&mut self, entries: u16, size: usize) -> io::Result<()> {
+        let buffers = Buffers::provided(entries, size)?;
+        let len = size as i32;
+        let sqe =
+            opcode::ProvideBuffers::new(buffers.buf_ptr(0), len, entries, buffers::GROUP_ID, 0)
+                .build()
+                .user_data(u64::MAX);
+        self.push(&[sqe])?;
+        buffers.set_registered(true);
+        self.sizing.adaptive = false;
+        self.events.record(EventKind::BuffersResized {
+            group: buffers::GROUP_ID,
+            entries,
+            size,
+        });
+        self.buffers = Some(buffers);
+        Ok(())
+    }
+
+    /// Provides the buffers returned since the last turn to the kernel again, runs of
+    /// consecutive ids with a single entry.
+    fn replenish(&mut self) {
+        let buffers = match &self.buffers {
+            Some(buffers) if buffers.is_provided() => buffers.clone(),
+            _ => return,
+        };
+        let mut returned = mem::take(&mut self.returned);
+        buffers.take_returned(&mut returned);
+        returned.sort_unstable();
+        let len = buffers.size() as i32;
+        let mut i = 0;
+        while i < returned.len() {
+            let first = returned[i];
+            let mut n = 1;
+            while i + n < returned.len() && returned[i + n] == first + n as u16 {
+                n += 1;
+            }
+            let addr = buffers.buf_ptr(first);
+            let sqe = opcode::ProvideBuffers::new(addr, len, n as u16, buffers::GROUP_ID, first)
+                .build()
+                .user_data(u64::MAX);
+            if self.push(&[sqe]).is_err() {
+                buffers.requeue(&returned[i..]);
+                break;
+            }
+            i += n;
+        }
+        returned.clear();
+        self.returned = returned;
+    }
+
+    /// What the kernel supports of the features the driver falls back from.
+    pub fn features(&self) -> Features {
+        self.features
+    }
+
+    /// Asks the kernel to cancel the in-flight operation identified by `key`, the outcome
+    /// is reported through that operation's own completion.
+    pub fn cancel(&mut self, key: u64) {
+        let sqe = opcode::AsyncCancel::new(key).build().user_data(u64::MAX);
+        let _ = self.push(&[sqe]);
+    }
+}
+
+/// The outcome of an operation, copied out of its completion queue entry.
+#[derive(Debug, Clone, Copy)]
+pub struct Cqe {
+    pub result: i32,
+    pub flags: u32,
 }
 
-#[derive(Debug)]
+impl Cqe {
+    /// The id of the provided buffer the kernel picked, if the operation selected one.
+    pub fn buffer_id(&self) -> Option<u16> {
+        cqueue::buffer_select(self.flags)
+    }
+
+    /// More completions follow for the same operation, as posted by multishot
+    /// operations and by zero copy sends ahead of their notification.
+    pub fn more(&self) -> bool {
+        cqueue::more(self.flags)
+    }
+
+    /// The notification of a zero copy send that the kernel no longer reads from its
+    /// buffer, posted after the completion with the result.
+    pub fn notif(&self) -> bool {
+        self.flags & IORING_CQE_F_NOTIF != 0
+    }
+}
+
+/// io-uring 0.5 has no accessor for the notification flag.
+const IORING_CQE_F_NOTIF: u32 = 1 << 3;
+
 pub enum State {
     /// The operation has been submitted to uring and is currently in-flight
     Submitted,
     /// The submitter is waiting for the completion of the operation
     Waiting(Waker),
-    /// The operation has completed.
-    Completed(cqueue::Entry),
+    /// The operation has completed, along with the buffer it selected if any.
+    Completed(Cqe, Option<ProvidedBuf>),
+    /// A multishot operation posted completions that were not taken yet, the last one
+    /// ends the operation unless it is flagged with `more`.
+    Multi(memory::Completions, Option<Waker>),
+    /// The submitter went away before completion, the data the kernel may still
+    /// access is kept here until the operation completes.
+    Ignored(#[allow(dead_code)] Box<dyn Any>),
 }
 
 impl State {
-    pub fn complete(&mut self, cqe: cqueue::Entry) {
+    /// Whether the kernel still owes the operation a completion.
+    fn in_kernel(&self) -> bool {
+        match self {
+            State::Completed(..) => false,
+            State::Multi(queue, _) => queue.back().is_none_or(|(cqe, _)| cqe.more()),
+            State::Submitted | State::Waiting(_) | State::Ignored(_) => true,
+        }
+    }
+
+    /// Records the completion, returns true if the slot can be released. The waiting
+    /// task is woken through `deferred`, a multishot operation's first completion takes
+    /// a queue from `memory`.
+    ///
+    /// An ignored operation keeps its slot, and the data it owns, for as long as the
+    /// kernel flags further completions with `more`.
+    pub fn complete(
+        &mut self,
+        cqe: Cqe,
+        buf: Option<ProvidedBuf>,
+        deferred: &Deferred,
+        memory: &mut Memory,
+    ) -> bool {
         match mem::replace(self, State::Submitted) {
+            State::Submitted if cqe.more() => {
+                *self = State::Multi(memory.queue((cqe, buf)), None);
+                false
+            }
             State::Submitted => {
-                *self = State::Completed(cqe);
+                *self = State::Completed(cqe, buf);
+                false
+            }
+            State::Waiting(waker) if cqe.more() => {
+                *self = State::Multi(memory.queue((cqe, buf)), None);
+                deferred.wake_multishot(waker);
+                false
+            }
+            // an expired timer.
+            State::Waiting(waker) if cqe.result == -libc::ETIME => {
+                *self = State::Completed(cqe, buf);
+                deferred.wake_timer(waker);
+                false
             }
             State::Waiting(waker) => {
-                *self = State::Completed(cqe);
-                waker.wake();
+                *self = State::Completed(cqe, buf);
+                deferred.wake(waker);
+                false
             }
-            State::Completed(_) => unreachable!("invalid operation state"),
-        };
+            State::Multi(mut queue, waker) => {
+                queue.push_back((cqe, buf));
+                *self = State::Multi(queue, None);
+                if let Some(waker) = waker {
+                    deferred.wake_multishot(waker);
+                }
+                false
+            }
+            State::Ignored(action) => {
+                if cqe.more() {
+                    *self = State::Ignored(action);
+                    return false;
+                }
+                deferred.discard(State::Ignored(action));
+                true
+            }
+            State::Completed(..) => unreachable!("invalid operation state"),
+        }
     }
 }
 
+/// What the kernel of the current runtime supports, `None` outside of one.
+pub(crate) fn features() -> Option<Features> {
+    Driver::try_current(|driver| driver.inner.borrow().features())
+}
+
+/// The buffer group of the current runtime a read of `len` bytes selects from, see
+/// [`Inner::group_for`]. `None` outside of a runtime or without any buffer ring.
+pub(crate) fn group_for(len: usize) -> Option<u16> {
+    Driver::try_current(|driver| driver.inner.borrow().group_for(len)).flatten()
+}
+
+/// Records `kind` in the event log of the current runtime, if any.
+pub(crate) fn record_event(kind: EventKind) {
+    Driver::try_current(|driver| {
+        if let Ok(mut inner) = driver.inner.try_borrow_mut() {
+            inner.events.record(kind);
+        }
+    });
+}
+
+/// The slot of the operation table `key` names, the lower half of the key.
+pub(crate) fn slot(key: u64) -> usize {
+    key as u32 as usize
+}
+
+#[cfg(not(miri))]
+fn default_backend(flags: RingFlags) -> io::Result<Box<dyn Backend>> {
+    Ok(Box::new(uring::Uring::new(256, flags)?))
+}
+
+#[cfg(miri)]
+fn default_backend(_: RingFlags) -> io::Result<Box<dyn Backend>> {
+    Ok(Box::new(mock::Mock::new(256)))
+}
+
+fn is_transient(err: &io::Error) -> bool {
+    err.raw_os_error() == Some(libc::EBUSY)
+        || err.raw_os_error() == Some(libc::EAGAIN)
+        || err.kind() == io::ErrorKind::Interrupted
+}
+
 unsafe fn to_socket_addr(storage: *const libc::sockaddr_storage) -> io::Result<SocketAddr> {
     match (*storage).ss_family as libc::c_int {
         libc::AF_INET => {
@@ -235,12 +1155,50 @@ fn cmsghdr(msg_name: *mut libc::sockaddr_storage, bufs: &mut [MaybeUninitSlice])
     msg
 }
 
+/// A `msghdr` together with the address and iovec it points to, boxed so the pointers
+/// stay valid for as long as the kernel may use them.
+struct MsgHdr {
+    storage: libc::sockaddr_storage,
+    iovec: [MaybeUninitSlice; 1],
+    msghdr: libc::msghdr,
+}
+
+impl MsgHdr {
+    fn new(buf: &mut [u8], len: usize) -> Box<MsgHdr> {
+        let mut msg = Box::new(MsgHdr {
+            storage: unsafe { mem::zeroed() },
+            iovec: [MaybeUninitSlice::new(buf, len)],
+            msghdr: unsafe { mem::zeroed() },
+        });
+        msg.msghdr = cmsghdr(&mut msg.storage, &mut msg.iovec);
+        msg
+    }
+
+    fn with_addr(buf: &mut [u8], len: usize, addr: &SocketAddr) -> Box<MsgHdr> {
+        let mut msg = MsgHdr::new(buf, len);
+        let (sockaddr, socklen) = socket_addr(addr);
+        unsafe {
+            ptr::copy_nonoverlapping(
+                sockaddr.as_ptr() as *const u8,
+                &mut msg.storage as *mut _ as *mut u8,
+                socklen as usize,
+            );
+        }
+        msg.msghdr.msg_namelen = socklen;
+        msg
+    }
+
+    fn addr(&self) -> io::Result<SocketAddr> {
+        unsafe { to_socket_addr(&self.storage) }
+    }
+}
+
 #[repr(transparent)]
 struct MaybeUninitSlice {
     vec: libc::iovec,
 }
 
-impl<'a> MaybeUninitSlice {
+impl MaybeUninitSlice {
     pub(crate) fn new(buf: &mut [u8], len: usize) -> MaybeUninitSlice {
         MaybeUninitSlice {
             vec: libc::iovec {
@@ -272,3 +1230,132 @@ impl DerefMut for MaybeUninitSlice {
         self.as_mut_slice()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::driver::mock::{Completions, Mock};
+
+    /// `IORING_CQE_F_MORE`.
+    const MORE: u32 = 1 << 1;
+
+    fn driver() -> (Driver, Completions) {
+        let mock = Mock::new(64);
+        let completions = mock.completions();
+        (Driver::with_backend(Box::new(mock)).unwrap(), completions)
+    }
+
+    fn post(completions: &Completions, key: u64, result: i32, flags: u32) {
+        completions
+            .borrow_mut()
+            .push_back((key, Cqe { result, flags }));
+    }
+
+    #[test]
+    fn a_reused_slot_gets_a_new_key() {
+        let (driver, _) = driver();
+        let mut inner = driver.inner.borrow_mut();
+        let old = inner.insert();
+        inner.actions.remove(slot(old));
+        let new = inner.insert();
+        assert_eq!(slot(old), slot(new));
+        assert_ne!(old, new);
+    }
+
+    #[test]
+    fn a_stale_completion_is_dropped() {
+        let (driver, completions) = driver();
+        let mut inner = driver.inner.borrow_mut();
+        let old = inner.insert();
+        inner.actions.remove(slot(old));
+        let new = inner.insert();
+
+        post(&completions, old, 1, 0);
+        inner.reap();
+        assert!(matches!(inner.actions[slot(new)], State::Submitted));
+        assert_eq!(inner.loop_stats.stale, 1);
+
+        post(&completions, new, 2, 0);
+        inner.reap();
+        assert!(matches!(
+            inner.actions[slot(new)],
+            State::Completed(Cqe { result: 2, .. }, _)
+        ));
+    }
+
+    #[test]
+    fn an_abandoned_multishot_keeps_its_slot_until_the_last_completion() {
+        let (driver, completions) = driver();
+        let mut inner = driver.inner.borrow_mut();
+        let key = inner.insert();
+        post(&completions, key, 1, MORE);
+        inner.reap();
+        assert!(matches!(inner.actions[slot(key)], State::Multi(..)));
+
+        inner.abandon(key, Box::new(()), false);
+        post(&completions, key, 2, MORE);
+        inner.reap();
+        assert!(matches!(inner.actions[slot(key)], State::Ignored(_)));
+
+        post(&completions, key, 3, 0);
+        inner.reap();
+        assert!(!inner.actions.contains(slot(key)));
+    }
+
+    #[test]
+    fn a_dropped_operation_is_only_submitted_if_it_did_not_reach_the_kernel() {
+        let (driver, _) = driver();
+        driver.with(|| {
+            let nop = || opcode::Nop::new().build();
+            let action = Action::submit((), nop()).unwrap();
+            driver.submit_queued().unwrap();
+            let submits = driver.inner.borrow().loop_stats.submits;
+            // the cancel waits for the next turn of the loop.
+            drop(action);
+            let mut inner = driver.inner.borrow_mut();
+            assert_eq!(inner.loop_stats.submits, submits);
+            assert_eq!(inner.backend.sq_len(), 1);
+            inner.submit_queued().unwrap();
+            drop(inner);
+
+            let action = Action::submit((), nop()).unwrap();
+            drop(action);
+            let mut inner = driver.inner.borrow_mut();
+            assert_eq!(inner.loop_stats.submits, submits + 2);
+            assert_eq!(inner.backend.sq_len(), 0);
+        });
+    }
+
+    #[test]
+    fn an_operation_cancelled_while_the_driver_is_borrowed_is_cancelled_on_release() {
+        let (driver, _) = driver();
+        driver.with(|| {
+            let action = Action::submit((), opcode::Nop::new().build()).unwrap();
+            driver.submit_queued().unwrap();
+            let borrowed = driver.inner.borrow_mut();
+            action.cancel();
+            drop(borrowed);
+            driver.flush();
+
+            let mut inner = driver.inner.borrow_mut();
+            inner.reap();
+            assert!(matches!(
+                inner.actions[slot(action.key)],
+                State::Completed(Cqe { result, .. }, _) if result == -libc::ECANCELED
+            ));
+        });
+    }
+
+    #[test]
+    fn a_multishot_abandoned_after_its_last_completion_releases_its_slot() {
+        let (driver, completions) = driver();
+        let mut inner = driver.inner.borrow_mut();
+        let key = inner.insert();
+        post(&completions, key, 1, MORE);
+        post(&completions, key, 2, 0);
+        inner.reap();
+
+        inner.abandon(key, Box::new(()), false);
+        assert!(!inner.actions.contains(slot(key)));
+    }
+}
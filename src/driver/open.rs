@@ -0,0 +1,32 @@
+use std::ffi::CString;
+use std::io;
+use std::os::unix::io::{FromRawFd, OwnedFd, RawFd};
+use std::path::Path;
+
+use io_uring::{opcode, types};
+
+use crate::driver::path::cstring;
+use crate::driver::{Action, Completable};
+
+pub struct Open {
+    _path: CString,
+}
+
+impl Completable for Open {
+    type Output = OwnedFd;
+
+    fn complete(result: u32) -> OwnedFd {
+        unsafe { OwnedFd::from_raw_fd(result as RawFd) }
+    }
+}
+
+impl Action<Open> {
+    pub fn open(path: &Path, flags: libc::c_int, mode: libc::mode_t) -> io::Result<Action<Open>> {
+        let path = cstring(path)?;
+        let entry = opcode::OpenAt::new(types::Fd(libc::AT_FDCWD), path.as_ptr())
+            .flags(flags | libc::O_CLOEXEC)
+            .mode(mode)
+            .build();
+        Action::submit(Open { _path: path }, entry)
+    }
+}
@@ -0,0 +1,75 @@
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+use io_uring::{opcode, types};
+
+use crate::driver::{Action, Completable};
+
+/// An operation on the directory entries at one or two paths, which it keeps alive
+/// until the kernel is done with them.
+pub struct PathOp {
+    _paths: (CString, Option<CString>),
+}
+
+impl Completable for PathOp {
+    type Output = ();
+
+    fn complete(_: u32) {}
+}
+
+const CWD: types::Fd = types::Fd(libc::AT_FDCWD);
+
+impl Action<PathOp> {
+    /// Creates the directory at `path`.
+    pub fn mkdir(path: &Path, mode: libc::mode_t) -> io::Result<Action<PathOp>> {
+        let path = cstring(path)?;
+        let entry = opcode::MkDirAt::new(CWD, path.as_ptr()).mode(mode).build();
+        Action::submit(PathOp::new(path, None), entry)
+    }
+
+    /// Removes the file at `path`, or the empty directory if `dir` is set.
+    pub fn unlink(path: &Path, dir: bool) -> io::Result<Action<PathOp>> {
+        let path = cstring(path)?;
+        let flags = if dir { libc::AT_REMOVEDIR } else { 0 };
+        let entry = opcode::UnlinkAt::new(CWD, path.as_ptr())
+            .flags(flags)
+            .build();
+        Action::submit(PathOp::new(path, None), entry)
+    }
+
+    pub fn rename(from: &Path, to: &Path) -> io::Result<Action<PathOp>> {
+        let (from, to) = (cstring(from)?, cstring(to)?);
+        let entry = opcode::RenameAt::new(CWD, from.as_ptr(), CWD, to.as_ptr()).build();
+        Action::submit(PathOp::new(from, Some(to)), entry)
+    }
+
+    /// Creates a symbolic link at `link` pointing to `original`.
+    pub fn symlink(original: &Path, link: &Path) -> io::Result<Action<PathOp>> {
+        let (original, link) = (cstring(original)?, cstring(link)?);
+        let entry = opcode::SymlinkAt::new(CWD, original.as_ptr(), link.as_ptr()).build();
+        Action::submit(PathOp::new(original, Some(link)), entry)
+    }
+
+    /// Creates a hard link at `link` to the file at `original`.
+    pub fn link(original: &Path, link: &Path) -> io::Result<Action<PathOp>> {
+        let (original, link) = (cstring(original)?, cstring(link)?);
+        let entry = opcode::LinkAt::new(CWD, original.as_ptr(), CWD, link.as_ptr()).build();
+        Action::submit(PathOp::new(original, Some(link)), entry)
+    }
+}
+
+impl PathOp {
+    fn new(path: CString, second: Option<CString>) -> PathOp {
+        PathOp {
+            _paths: (path, second),
+        }
+    }
+}
+
+/// `path` as the kernel takes it.
+pub(crate) fn cstring(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains a nul byte"))
+}
@@ -0,0 +1,46 @@
+use std::io;
+use std::os::unix::io::RawFd;
+use std::task::{Context, Poll};
+
+use io_uring::opcode;
+
+use crate::driver::{Action, Completable};
+
+/// A poll that completes once the fd is ready, with the events it is ready for.
+pub struct PollAdd;
+
+impl Completable for PollAdd {
+    type Output = u32;
+
+    fn complete(result: u32) -> u32 {
+        result
+    }
+}
+
+impl Action<PollAdd> {
+    pub(crate) fn poll_add(fd: RawFd, events: u32) -> io::Result<Action<PollAdd>> {
+        let entry = target!(fd, |fd| opcode::PollAdd::new(fd, events).build());
+        Action::submit(PollAdd, entry)
+    }
+}
+
+/// A poll that stays armed and posts a completion every time the fd becomes ready.
+pub struct PollMulti;
+
+impl Action<PollMulti> {
+    pub(crate) fn poll_multi(fd: RawFd, events: u32) -> io::Result<Action<PollMulti>> {
+        let entry = target!(fd, |fd| opcode::PollAdd::new(fd, events)
+            .multi(true)
+            .build());
+        Action::submit(PollMulti, entry)
+    }
+
+    /// Resolves to the events the fd became ready for. Once the poll has finished,
+    /// [`is_finished`](Action::is_finished), it needs to be submitted again.
+    pub fn poll_ready(&mut self, cx: &mut Context) -> Poll<io::Result<u32>> {
+        match ready!(self.poll_next(cx)) {
+            Some(shot) => Poll::Ready(shot.result.map(|events| events as u32)),
+            None => Poll::Ready(Ok(0)),
+        }
+    }
+}
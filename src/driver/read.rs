@@ -2,28 +2,79 @@ use std::future::Future;
 use std::io;
 use std::os::unix::io::RawFd;
 use std::pin::Pin;
+use std::ptr;
 use std::task::{Context, Poll};
 
-use io_uring::{opcode, types};
+use io_uring::{opcode, squeue};
 
-use crate::driver::Action;
+use crate::driver::{buffers, Action, Completable, ProvidedBuf, CURRENT};
 
 pub struct Read {
     buf: Vec<u8>,
 }
 
+impl Completable for Read {
+    type Output = usize;
+
+    fn complete(result: u32) -> usize {
+        result as usize
+    }
+}
+
 impl Action<Read> {
     pub fn read(fd: RawFd, len: u32) -> io::Result<Action<Read>> {
         let mut buf = Vec::with_capacity(len as usize);
-        let entry = opcode::Read::new(types::Fd(fd), buf.as_mut_ptr(), len).build();
+        let entry = target!(fd, |fd| opcode::Read::new(fd, buf.as_mut_ptr(), len)
+            .build());
+        Action::submit(Read { buf }, entry)
+    }
+
+    /// Reads up to `len` bytes starting at `offset` of a seekable file.
+    pub fn read_at(fd: RawFd, len: u32, offset: u64) -> io::Result<Action<Read>> {
+        let mut buf = Vec::with_capacity(len as usize);
+        let entry = target!(fd, |fd| opcode::Read::new(fd, buf.as_mut_ptr(), len)
+            .offset(offset as _)
+            .build());
         Action::submit(Read { buf }, entry)
     }
 
     pub fn poll_read(&mut self, cx: &mut Context) -> Poll<io::Result<Vec<u8>>> {
-        let completion = ready!(Pin::new(&mut *self).poll(cx));
-        let n = completion.result?;
-        let mut action = completion.action;
-        unsafe { action.buf.set_len(n as usize) };
+        let (n, mut action) = ready!(Pin::new(&mut *self).poll(cx)).into_parts();
+        unsafe { action.buf.set_len(n?) };
         Poll::Ready(Ok(action.buf))
     }
 }
+
+/// A read into a buffer the kernel picks from the driver's buffer ring.
+pub struct ReadProvided;
+
+impl Action<ReadProvided> {
+    /// Returns `None` if the driver has no buffer ring to select from.
+    pub fn read_provided(fd: RawFd) -> io::Result<Option<Action<ReadProvided>>> {
+        Action::read_provided_from(fd, buffers::GROUP_ID)
+    }
+
+    /// Like `read_provided`, selecting from the ring registered as group `bgid`.
+    /// Returns `None` if no ring is registered under `bgid`.
+    pub fn read_provided_from(fd: RawFd, bgid: u16) -> io::Result<Option<Action<ReadProvided>>> {
+        let len = match CURRENT.with(|driver| driver.inner.borrow().group_size(bgid)) {
+            Some(len) => len as u32,
+            None => return Ok(None),
+        };
+        let entry = target!(fd, |fd| opcode::Read::new(fd, ptr::null_mut(), len)
+            .buf_group(bgid)
+            .build())
+        .flags(squeue::Flags::BUFFER_SELECT);
+        let action = Action::submit(ReadProvided, entry)?;
+        // queued entries reach the kernel at the end of the turn, before any completion.
+        CURRENT.with(|driver| driver.inner.borrow_mut().select_from(action.key, bgid));
+        Ok(Some(action))
+    }
+
+    /// Resolves to the filled buffer, `None` at end of file.
+    pub fn poll_read(&mut self, cx: &mut Context) -> Poll<io::Result<Option<ProvidedBuf>>> {
+        let completion = ready!(Pin::new(&mut *self).poll(cx));
+        completion.result?;
+        Poll::Ready(Ok(completion.buf.filter(|buf| !buf.is_empty())))
+    }
+}
@@ -3,28 +3,91 @@ use std::io;
 use std::os::unix::io::RawFd;
 use std::pin::Pin;
 use std::task::{Context, Poll};
+use std::time::Duration;
 
-use io_uring::{opcode, types};
+use io_uring::opcode;
 
-use crate::driver::Action;
+use crate::driver::{buffers, Action, Completable, ProvidedBuf, CURRENT};
 
 pub struct Recv {
     buf: Vec<u8>,
 }
 
+impl Completable for Recv {
+    type Output = usize;
+
+    fn complete(result: u32) -> usize {
+        result as usize
+    }
+}
+
 impl Action<Recv> {
     pub fn recv(fd: RawFd, len: usize) -> io::Result<Action<Recv>> {
+        Action::recv_with(fd, len, None)
+    }
+
+    /// Like `recv`, cancelled by the kernel once `timeout` elapsed.
+    pub fn recv_timeout(fd: RawFd, len: usize, timeout: Duration) -> io::Result<Action<Recv>> {
+        Action::recv_with(fd, len, Some(timeout))
+    }
+
+    fn recv_with(fd: RawFd, len: usize, timeout: Option<Duration>) -> io::Result<Action<Recv>> {
         let mut buf = Vec::with_capacity(len);
-        let entry = opcode::Recv::new(types::Fd(fd), buf.as_mut_ptr(), len as u32).build();
+        let entry = target!(fd, |fd| opcode::Recv::new(fd, buf.as_mut_ptr(), len as u32)
+            .build());
+        Action::submit_maybe_timeout(Recv { buf }, entry, timeout)
+    }
+
+    /// Like `recv`, leaving the bytes in the socket for the next recv.
+    pub fn recv_peek(fd: RawFd, len: usize) -> io::Result<Action<Recv>> {
+        let mut buf = Vec::with_capacity(len);
+        let entry = target!(fd, |fd| opcode::Recv::new(fd, buf.as_mut_ptr(), len as u32)
+            .flags(libc::MSG_PEEK)
+            .build());
         Action::submit(Recv { buf }, entry)
     }
 
     pub fn poll_recv(&mut self, cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
-        let completion = ready!(Pin::new(&mut *self).poll(cx));
-        let n = completion.result? as usize;
-        let mut action = completion.action;
-        unsafe { action.buf.set_len(n as usize) };
+        let (n, mut action) = ready!(Pin::new(&mut *self).poll(cx)).into_parts();
+        let n = n?;
+        unsafe { action.buf.set_len(n) };
+        // a recv started by a dropped future may have used a larger buffer.
+        let n = n.min(buf.len());
         buf[..n].copy_from_slice(&action.buf[..n]);
         Poll::Ready(Ok(n))
     }
 }
+
+/// A recv that stays armed and fills one buffer of the driver's ring per completion.
+pub struct RecvMulti;
+
+impl Action<RecvMulti> {
+    /// Returns `None` if the driver has no buffer ring or the kernel has no multishot
+    /// recv.
+    pub fn recv_multi(fd: RawFd) -> io::Result<Option<Action<RecvMulti>>> {
+        if !CURRENT.with(|driver| driver.inner.borrow().recv_multi()) {
+            return Ok(None);
+        }
+        let entry = target!(fd, |fd| opcode::RecvMulti::new(fd, buffers::GROUP_ID)
+            .build());
+        Action::submit(RecvMulti, entry).map(Some)
+    }
+
+    /// Resolves to the next filled buffer, `None` at end of stream. Once the recv has
+    /// finished, [`is_finished`](Action::is_finished), it needs to be submitted again.
+    pub fn poll_recv_multi(&mut self, cx: &mut Context) -> Poll<io::Result<Option<ProvidedBuf>>> {
+        let shot = match ready!(self.poll_next(cx)) {
+            Some(shot) => shot,
+            None => return Poll::Ready(Ok(None)),
+        };
+        match shot.result {
+            // without IORING_RECV_MULTISHOT the kernel refuses the recv up front.
+            Err(e) if e.raw_os_error() == Some(libc::EINVAL) => {
+                self.driver.inner.borrow_mut().disable_recv_multi();
+                Poll::Ready(Err(e))
+            }
+            Err(e) => Poll::Ready(Err(e)),
+            Ok(_) => Poll::Ready(Ok(shot.buf.filter(|buf| !buf.is_empty()))),
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use std::cell::RefCell;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::rc::Rc;
+
+use io_uring::opcode;
+
+use crate::driver::{Action, Completable};
+
+/// One half of a recv linked to a send of the same buffer.
+///
+/// Both halves share the buffer, it is released once the later of the two completes.
+pub struct RecvSend {
+    buf: Rc<RefCell<Vec<u8>>>,
+}
+
+impl Completable for RecvSend {
+    type Output = usize;
+
+    fn complete(result: u32) -> usize {
+        result as usize
+    }
+}
+
+impl Action<RecvSend> {
+    /// Receives exactly `len` bytes and sends them straight back on the same socket.
+    ///
+    /// A short receive breaks the link and the send completes with `Error::Cancelled`.
+    pub fn recv_send(fd: RawFd, len: usize) -> io::Result<(Action<RecvSend>, Action<RecvSend>)> {
+        let buf = Rc::new(RefCell::new(Vec::with_capacity(len)));
+        let ptr = buf.borrow_mut().as_mut_ptr();
+        let recv = target!(fd, |fd| opcode::Recv::new(fd, ptr, len as u32)
+            .flags(libc::MSG_WAITALL)
+            .build());
+        let send = target!(fd, |fd| opcode::Send::new(fd, ptr, len as u32).build());
+        Action::submit_link(RecvSend { buf: buf.clone() }, recv, RecvSend { buf }, send)
+    }
+}
+
+impl RecvSend {
+    /// The first `n` received bytes, `n` being the recv result.
+    pub fn received(&self, n: usize) -> Vec<u8> {
+        let mut buf = self.buf.borrow_mut();
+        let n = n.min(buf.capacity());
+        unsafe { buf.set_len(n) };
+        buf.clone()
+    }
+}
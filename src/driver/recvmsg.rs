@@ -1,29 +1,44 @@
 use std::future::Future;
 use std::io;
-use std::mem::MaybeUninit;
 use std::net::SocketAddr;
 use std::os::unix::io::RawFd;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 
-use io_uring::{opcode, types};
+use io_uring::opcode;
 
-use crate::driver::Action;
-use crate::driver::{cmsghdr, to_socket_addr, MaybeUninitSlice};
+use crate::driver::{Action, Completable, MsgHdr};
 
 pub struct RecvMsg {
-    storage: Box<MaybeUninit<libc::sockaddr_storage>>,
+    msg: Box<MsgHdr>,
     buf: Vec<u8>,
 }
 
+impl Completable for RecvMsg {
+    type Output = usize;
+
+    fn complete(result: u32) -> usize {
+        result as usize
+    }
+}
+
 impl Action<RecvMsg> {
     pub fn recvmsg(fd: RawFd, len: usize) -> io::Result<Action<RecvMsg>> {
-        let mut storage = Box::new(MaybeUninit::<libc::sockaddr_storage>::zeroed());
         let mut buf = Vec::with_capacity(len);
-        let mut iovec = [MaybeUninitSlice::new(&mut buf, len)];
-        let mut msghdr = cmsghdr(storage.as_mut_ptr() as *mut _, &mut iovec);
-        let entry = opcode::RecvMsg::new(types::Fd(fd), &mut msghdr as *mut _).build();
-        Action::submit(RecvMsg { storage, buf }, entry)
+        let mut msg = MsgHdr::new(&mut buf, len);
+        let entry = target!(fd, |fd| opcode::RecvMsg::new(fd, &mut msg.msghdr as *mut _)
+            .build());
+        Action::submit(RecvMsg { msg, buf }, entry)
+    }
+
+    /// Like `recvmsg`, leaving the datagram in the socket for the next recv.
+    pub fn recvmsg_peek(fd: RawFd, len: usize) -> io::Result<Action<RecvMsg>> {
+        let mut buf = Vec::with_capacity(len);
+        let mut msg = MsgHdr::new(&mut buf, len);
+        let entry = target!(fd, |fd| opcode::RecvMsg::new(fd, &mut msg.msghdr as *mut _)
+            .flags(libc::MSG_PEEK as u32)
+            .build());
+        Action::submit(RecvMsg { msg, buf }, entry)
     }
 
     pub fn poll_recv_from(
@@ -31,12 +46,13 @@ impl Action<RecvMsg> {
         cx: &mut Context,
         buf: &mut [u8],
     ) -> Poll<io::Result<(usize, SocketAddr)>> {
-        let completion = ready!(Pin::new(&mut *self).poll(cx));
-        let n = completion.result? as usize;
-        let mut action = completion.action;
+        let (n, mut action) = ready!(Pin::new(&mut *self).poll(cx)).into_parts();
+        let n = n?;
         unsafe { action.buf.set_len(n) };
+        // a recv started by a dropped future may have used a larger buffer.
+        let n = n.min(buf.len());
         buf[..n].copy_from_slice(&action.buf[..n]);
-        let addr = unsafe { to_socket_addr(&(*action.storage).assume_init() as *const _)? };
+        let addr = action.msg.addr()?;
         Poll::Ready(Ok((n, addr)))
     }
 }
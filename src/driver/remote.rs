@@ -0,0 +1,269 @@
+use std::io;
+use std::mem;
+use std::os::unix::io::RawFd;
+use std::task::Waker;
+
+use io_uring::opcode;
+use io_uring::squeue::Entry;
+use slab::Slab;
+
+use crate::driver::{Deferred, Driver};
+use crate::loom::thread::{self, ThreadId};
+use crate::loom::{Arc, Mutex};
+
+/// User data of the eventfd read the driver parks on.
+pub const WAKE_KEY: u64 = u64::MAX - 1;
+
+/// Lets other threads wake tasks of the driver.
+///
+/// Task wakers queue their task on the thread they are woken on, so they must not be
+/// called from another thread. Instead, wakers are kept here by key, other threads
+/// queue the keys to wake and write the eventfd the parked driver reads from, and the
+/// driver wakes them on its own thread.
+pub struct Remote {
+    shared: Arc<Shared>,
+    wakers: Slab<Option<Waker>>,
+    /// Whether the eventfd read is in the kernel.
+    armed: bool,
+    buf: Box<u64>,
+}
+
+struct Shared {
+    fd: RawFd,
+    thread: ThreadId,
+    /// Keys notified since the driver last woke their wakers.
+    woken: Mutex<Vec<usize>>,
+    /// Keys whose registration was dropped on another thread.
+    dropped: Mutex<Vec<usize>>,
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+impl Remote {
+    pub fn new() -> io::Result<Remote> {
+        let fd = syscall!(eventfd(0, libc::EFD_CLOEXEC))?;
+        Ok(Remote {
+            shared: Arc::new(Shared {
+                fd,
+                thread: thread::current().id(),
+                woken: Mutex::new(Vec::new()),
+                dropped: Mutex::new(Vec::new()),
+            }),
+            wakers: Slab::new(),
+            armed: false,
+            buf: Box::new(0),
+        })
+    }
+
+    /// Queues the wakers notified since the last call on `deferred` and releases dropped
+    /// keys, returns whether any waker was queued.
+    pub fn wake(&mut self, deferred: &Deferred) -> bool {
+        for key in mem::take(&mut *self.shared.dropped.lock().unwrap()) {
+            self.wakers.try_remove(key);
+        }
+        let woken = mem::take(&mut *self.shared.woken.lock().unwrap());
+        let mut any = false;
+        for key in woken {
+            if let Some(waker) = self.wakers.get_mut(key).and_then(Option::take) {
+                deferred.wake(waker);
+                any = true;
+            }
+        }
+        any
+    }
+
+    /// The eventfd read to park on, `None` if it is in the kernel already or nothing is
+    /// registered to be woken.
+    pub fn arm(&mut self) -> Option<Entry> {
+        if self.armed || self.wakers.is_empty() {
+            return None;
+        }
+        self.armed = true;
+        let buf = &mut *self.buf as *mut u64 as *mut u8;
+        let sqe = opcode::Read::new(io_uring::types::Fd(self.shared.fd), buf, 8).build();
+        Some(sqe.user_data(WAKE_KEY))
+    }
+
+    /// Records the completion of the eventfd read.
+    pub fn completed(&mut self) {
+        self.armed = false;
+    }
+
+    /// Whether the eventfd read is in the kernel.
+    pub fn armed(&self) -> bool {
+        self.armed
+    }
+
+    /// Leaks the buffer of an eventfd read still in the kernel, which may write it
+    /// until the ring is torn down.
+    pub fn release(&mut self) {
+        if self.armed {
+            mem::forget(mem::replace(&mut self.buf, Box::new(0)));
+            self.armed = false;
+        }
+    }
+
+    fn owns(&self, notifier: &Notifier) -> bool {
+        Arc::ptr_eq(&self.shared, &notifier.shared)
+    }
+}
+
+/// Queues a registered waker to be woken by its driver, from any thread.
+#[derive(Clone)]
+pub struct Notifier {
+    shared: Arc<Shared>,
+    key: usize,
+}
+
+impl Notifier {
+    pub fn notify(&self) {
+        let mut woken = self.shared.woken.lock().unwrap();
+        if woken.contains(&self.key) {
+            return;
+        }
+        woken.push(self.key);
+        // the driver wakes queued keys before parking, it only needs the eventfd when
+        // this is the first key queued from another thread.
+        if woken.len() == 1 && thread::current().id() != self.shared.thread {
+            drop(woken);
+            let one: u64 = 1;
+            unsafe { libc::write(self.shared.fd, &one as *const u64 as *const _, 8) };
+        }
+    }
+}
+
+/// Where to deliver a wakeup, see [`RemoteWaker::handle`].
+#[derive(Clone)]
+pub enum Wake {
+    Notifier(Notifier),
+    Waker(Waker),
+}
+
+impl Wake {
+    pub fn wake(self) {
+        match self {
+            Wake::Notifier(notifier) => notifier.notify(),
+            Wake::Waker(waker) => waker.wake(),
+        }
+    }
+}
+
+/// A waker that may be woken from any thread.
+///
+/// Inside a runtime the waker is registered with the driver and woken through its
+/// [`Notifier`], outside of one it is woken directly.
+#[derive(Default)]
+pub struct RemoteWaker {
+    wake: Option<Wake>,
+}
+
+impl RemoteWaker {
+    pub fn new() -> RemoteWaker {
+        RemoteWaker { wake: None }
+    }
+
+    /// Registers `waker` to be woken through the handles returned by `handle`.
+    pub fn register(&mut self, waker: &Waker) {
+        let wake = &mut self.wake;
+        let registered = Driver::try_current(|driver| {
+            let remote = &mut driver.inner.borrow_mut().remote;
+            let key = match wake {
+                Some(Wake::Notifier(notifier)) if remote.owns(notifier) => notifier.key,
+                _ => {
+                    let key = remote.wakers.insert(None);
+                    let notifier = Notifier {
+                        shared: remote.shared.clone(),
+                        key,
+                    };
+                    // a notifier of another runtime is released by replacing it.
+                    if let Some(Wake::Notifier(old)) = wake.replace(Wake::Notifier(notifier)) {
+                        release(&old);
+                    }
+                    key
+                }
+            };
+            remote.wakers[key] = Some(waker.clone());
+        });
+        if registered.is_none() {
+            if let Some(Wake::Notifier(old)) = wake.replace(Wake::Waker(waker.clone())) {
+                release(&old);
+            }
+        }
+    }
+
+    /// A handle that wakes the registered waker, `None` before `register`.
+    pub fn handle(&self) -> Option<Wake> {
+        self.wake.clone()
+    }
+}
+
+impl Drop for RemoteWaker {
+    fn drop(&mut self) {
+        if let Some(Wake::Notifier(notifier)) = self.wake.take() {
+            release(&notifier);
+        }
+    }
+}
+
+/// Frees the key of `notifier`, right away on the driver thread and on its next wakeup
+/// otherwise.
+fn release(notifier: &Notifier) {
+    let released = Driver::try_current(|driver| match driver.inner.try_borrow_mut() {
+        Ok(mut inner) if inner.remote.owns(notifier) => {
+            inner.remote.wakers.try_remove(notifier.key);
+            true
+        }
+        _ => false,
+    });
+    if released != Some(true) {
+        notifier.shared.dropped.lock().unwrap().push(notifier.key);
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use super::*;
+    use crate::waker_fn::waker_fn;
+
+    /// Whether the eventfd was written, without consuming the count.
+    fn signalled(fd: RawFd) -> bool {
+        let mut pollfd = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        unsafe { libc::poll(&mut pollfd, 1, 0) == 1 }
+    }
+
+    #[test]
+    fn notifications_from_other_threads_wake_the_parked_driver() {
+        loom::model(|| {
+            let mut remote = Remote::new().unwrap();
+            let deferred = Deferred::default();
+            let notifiers: Vec<_> = (0..2)
+                .map(|_| Notifier {
+                    shared: remote.shared.clone(),
+                    key: remote.wakers.insert(Some(waker_fn(|| ()))),
+                })
+                .collect();
+            let keys: Vec<_> = notifiers.iter().map(|notifier| notifier.key).collect();
+            let threads: Vec<_> = notifiers
+                .into_iter()
+                .map(|notifier| thread::spawn(move || notifier.notify()))
+                .collect();
+            // the driver wakes the queued keys, then parks on the eventfd.
+            remote.wake(&deferred);
+            for thread in threads {
+                thread.join().unwrap();
+            }
+            let missed = keys.iter().any(|&key| remote.wakers[key].is_some());
+            assert!(!missed || signalled(remote.shared.fd));
+            remote.wake(&deferred);
+            assert!(keys.iter().all(|&key| remote.wakers[key].is_none()));
+        });
+    }
+}
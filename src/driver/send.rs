@@ -4,26 +4,32 @@ use std::os::unix::io::RawFd;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 
-use io_uring::{opcode, types};
+use io_uring::opcode;
 
-use crate::driver::Action;
+use crate::driver::{Action, Completable};
 
 pub struct Send {
     _buf: Vec<u8>,
 }
 
+impl Completable for Send {
+    type Output = usize;
+
+    fn complete(result: u32) -> usize {
+        result as usize
+    }
+}
+
 impl Action<Send> {
     pub fn send(fd: RawFd, buf: &[u8]) -> io::Result<Action<Send>> {
         let buf = buf.to_vec();
         let ptr = buf.as_ptr();
         let len = buf.len() as u32;
-        let entry = opcode::Send::new(types::Fd(fd), ptr, len).build();
+        let entry = target!(fd, |fd| opcode::Send::new(fd, ptr, len).build());
         Action::submit(Send { _buf: buf }, entry)
     }
 
     pub(crate) fn poll_send(&mut self, cx: &mut Context) -> Poll<io::Result<usize>> {
-        let complete = ready!(Pin::new(self).poll(cx));
-        let n = complete.result? as usize;
-        Poll::Ready(Ok(n))
+        Poll::Ready(ready!(Pin::new(self).poll(cx)).output())
     }
 }
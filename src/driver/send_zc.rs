@@ -0,0 +1,169 @@
+use std::future::Future;
+use std::io;
+use std::mem;
+use std::net::SocketAddr;
+use std::os::unix::io::RawFd;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_util::future::poll_fn;
+use io_uring::opcode;
+use io_uring::squeue::Entry;
+
+use crate::driver::{self, Action, Features, MsgHdr};
+
+/// `IORING_OP_SEND_ZC` and `IORING_OP_SENDMSG_ZC`, io-uring 0.5 has no builders for them.
+const OP_SEND_ZC: u8 = 47;
+const OP_SENDMSG_ZC: u8 = 48;
+
+/// A send whose bytes go out of `buf` without being copied into the kernel.
+///
+/// The kernel posts two completions: the first with the result, the second once it
+/// no longer reads from `buf`. The buffer stays owned by the operation until then.
+pub struct SendZc {
+    _msg: Option<Box<MsgHdr>>,
+    buf: Vec<u8>,
+}
+
+impl Action<SendZc> {
+    pub fn send_zc(fd: RawFd, buf: Vec<u8>) -> io::Result<Action<SendZc>> {
+        let ptr = buf.as_ptr();
+        let len = buf.len() as u32;
+        let entry = target!(fd, |fd| opcode::Send::new(fd, ptr, len).build());
+        let send = SendZc { _msg: None, buf };
+        Action::submit(send, zero_copy(entry, OP_SEND_ZC, |f| f.send_zc))
+    }
+
+    pub fn sendmsg_zc(
+        fd: RawFd,
+        mut buf: Vec<u8>,
+        addr: &SocketAddr,
+    ) -> io::Result<Action<SendZc>> {
+        let len = buf.len();
+        let msg = MsgHdr::with_addr(&mut buf, len, addr);
+        let entry = target!(fd, |fd| opcode::SendMsg::new(fd, &msg.msghdr).build());
+        let send = SendZc {
+            _msg: Some(msg),
+            buf,
+        };
+        Action::submit(send, zero_copy(entry, OP_SENDMSG_ZC, |f| f.sendmsg_zc))
+    }
+
+    /// Waits for the result of the send, the buffer is released in the background once
+    /// the kernel is done with it.
+    pub async fn sent(self) -> io::Result<usize> {
+        ZcWrite::new(self).await
+    }
+}
+
+/// A zero copy send, which resolves to how many bytes were sent once the kernel
+/// accepted them.
+///
+/// The kernel keeps reading from the buffer after that, until the bytes left the
+/// machine or were dropped. [`flushed`](ZcWrite::flushed) waits for that and hands the
+/// buffer back, so it can be filled again. Dropping the `ZcWrite` once it resolved
+/// releases the buffer in the background instead, dropping it before cancels the send.
+pub struct ZcWrite {
+    /// `None` once the completion that ended the send was taken.
+    action: Option<Action<SendZc>>,
+    /// The buffer, handed back by the completion that ended the send.
+    buf: Option<Vec<u8>>,
+    /// Whether the result of the send was taken.
+    sent: bool,
+    /// Told how many bytes were sent, by the stream counting its writes.
+    on_sent: Option<Box<dyn FnOnce(usize)>>,
+}
+
+impl ZcWrite {
+    pub fn new(action: Action<SendZc>) -> ZcWrite {
+        ZcWrite {
+            action: Some(action),
+            buf: None,
+            sent: false,
+            on_sent: None,
+        }
+    }
+
+    pub(crate) fn on_sent(mut self, f: impl FnOnce(usize) + 'static) -> ZcWrite {
+        self.on_sent = Some(Box::new(f));
+        self
+    }
+
+    /// Waits until the kernel no longer reads from the buffer and hands it back. The
+    /// result of the send is dropped unless the `ZcWrite` was awaited first.
+    pub async fn flushed(mut self) -> Vec<u8> {
+        poll_fn(|cx| self.poll_flushed(cx)).await
+    }
+
+    fn poll_flushed(&mut self, cx: &mut Context) -> Poll<Vec<u8>> {
+        loop {
+            if let Some(buf) = self.buf.take() {
+                return Poll::Ready(buf);
+            }
+            // the send failed to report its result if it was not awaited, there is
+            // nothing to tell then.
+            let _ = ready!(self.poll_shot(cx));
+        }
+    }
+
+    /// Takes the next completion, the result of the send first and the notification
+    /// after it.
+    fn poll_shot(&mut self, cx: &mut Context) -> Poll<io::Result<usize>> {
+        let action = self.action.as_mut().expect("send polled after it ended");
+        let (shot, send) =
+            ready!(action.poll_next_owned(cx)).expect("send completes at least once");
+        // without `more` no notification follows and the buffer is released already.
+        if let Some(send) = send {
+            self.buf = Some(send.buf);
+            self.action = None;
+        }
+        if self.sent {
+            return Poll::Ready(Ok(0));
+        }
+        self.sent = true;
+        let n = shot.result? as usize;
+        if let Some(on_sent) = self.on_sent.take() {
+            on_sent(n);
+        }
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl Future for ZcWrite {
+    type Output = io::Result<usize>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<usize>> {
+        assert!(!self.sent, "ZcWrite polled after it resolved");
+        self.poll_shot(cx)
+    }
+}
+
+impl Drop for ZcWrite {
+    fn drop(&mut self) {
+        // the send is done once it resolved, only the notification is left to wait for.
+        if self.sent {
+            if let Some(action) = self.action.take() {
+                drop(action.detach());
+            }
+        }
+    }
+}
+
+/// Turns the copying send `entry` into its zero copy counterpart `opcode`, unless the
+/// kernel has none. The copying send completes once, which releases the buffer along
+/// with the result.
+fn zero_copy(entry: Entry, opcode: u8, supported: impl FnOnce(&Features) -> bool) -> Entry {
+    match driver::features() {
+        Some(features) if !supported(&features) => entry,
+        _ => with_opcode(entry, opcode),
+    }
+}
+
+/// Swaps the opcode of `entry`, the zero copy sends share the layout of their copying
+/// counterparts.
+fn with_opcode(entry: Entry, opcode: u8) -> Entry {
+    // `Entry` is a `repr(C)` wrapper of `io_uring_sqe`, which starts with the opcode.
+    let mut sqe: [u8; 64] = unsafe { mem::transmute(entry) };
+    sqe[0] = opcode;
+    unsafe { mem::transmute(sqe) }
+}
@@ -0,0 +1,124 @@
+use std::fmt;
+use std::io;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd};
+use std::rc::{Rc, Weak};
+
+use crate::driver::{Stream, StreamParts};
+
+/// A file descriptor shared by several owners, closed once the last one is dropped.
+///
+/// Streams hold their socket through a `SharedFd`, and hand out clones of it. A clone
+/// keeps the socket open after the stream is dropped, without keeping the stream's
+/// buffers and pending operations around. A [`WeakFd`] refers to the socket without
+/// keeping it open.
+#[derive(Clone)]
+pub struct SharedFd {
+    fd: Rc<OwnedFd>,
+}
+
+/// A reference to a [`SharedFd`] that does not keep the fd open.
+#[derive(Clone)]
+pub struct WeakFd {
+    fd: Weak<OwnedFd>,
+}
+
+impl SharedFd {
+    pub fn new(fd: OwnedFd) -> SharedFd {
+        SharedFd { fd: Rc::new(fd) }
+    }
+
+    pub fn downgrade(&self) -> WeakFd {
+        WeakFd {
+            fd: Rc::downgrade(&self.fd),
+        }
+    }
+
+    /// Takes the fd back if this is the only owner left, otherwise hands `self` back.
+    pub fn try_unwrap(self) -> Result<OwnedFd, SharedFd> {
+        Rc::try_unwrap(self.fd).map_err(|fd| SharedFd { fd })
+    }
+
+    /// Number of owners keeping the fd open.
+    pub fn owners(&self) -> usize {
+        Rc::strong_count(&self.fd)
+    }
+}
+
+/// Takes `stream` apart like [`Stream::into_parts`], failing up front if the fd is
+/// shared, as it can only leave the runtime with its last owner.
+pub(crate) async fn into_owned_parts(stream: Stream<SharedFd>) -> io::Result<StreamParts<OwnedFd>> {
+    let shared = || io::Error::new(io::ErrorKind::ResourceBusy, "the fd is shared");
+    if stream.get_ref().owners() > 1 {
+        return Err(shared());
+    }
+    let parts = stream.into_parts().await?;
+    Ok(StreamParts {
+        io: parts.io.try_unwrap().map_err(|_| shared())?,
+        read: parts.read,
+        write: parts.write,
+    })
+}
+
+impl WeakFd {
+    /// The fd, `None` once every owner is gone and it was closed.
+    pub fn upgrade(&self) -> Option<SharedFd> {
+        self.fd.upgrade().map(|fd| SharedFd { fd })
+    }
+}
+
+impl From<OwnedFd> for SharedFd {
+    fn from(fd: OwnedFd) -> SharedFd {
+        SharedFd::new(fd)
+    }
+}
+
+impl AsFd for SharedFd {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl AsRawFd for SharedFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl fmt::Debug for SharedFd {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_tuple("SharedFd")
+            .field(&self.as_raw_fd())
+            .finish()
+    }
+}
+
+impl fmt::Debug for WeakFd {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_tuple("WeakFd")
+            .field(&self.upgrade().map(|fd| fd.as_raw_fd()))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixStream;
+
+    #[test]
+    fn the_last_owner_takes_the_fd_back() {
+        let (sock, _peer) = UnixStream::pair().unwrap();
+        let fd = SharedFd::new(OwnedFd::from(sock));
+        let raw = fd.as_raw_fd();
+        let weak = fd.downgrade();
+        let clone = fd.clone();
+        assert_eq!(fd.owners(), 2);
+
+        let fd = fd.try_unwrap().unwrap_err();
+        drop(clone);
+        assert_eq!(weak.upgrade().map(|fd| fd.owners()), Some(2));
+        let owned = fd.try_unwrap().unwrap();
+        assert_eq!(owned.as_raw_fd(), raw);
+        assert!(weak.upgrade().is_none());
+    }
+}
@@ -0,0 +1,29 @@
+use std::io;
+use std::net;
+use std::os::unix::io::RawFd;
+
+use io_uring::opcode;
+
+use crate::driver::{Action, Completable};
+
+pub struct Shutdown;
+
+impl Completable for Shutdown {
+    type Output = ();
+
+    fn complete(_: u32) {}
+}
+
+impl Action<Shutdown> {
+    /// Shuts down the read side, the write side or both of a socket. Needs Linux 5.11,
+    /// see [`Features::shutdown`](crate::driver::Features::shutdown).
+    pub fn shutdown(fd: RawFd, how: net::Shutdown) -> io::Result<Action<Shutdown>> {
+        let how = match how {
+            net::Shutdown::Read => libc::SHUT_RD,
+            net::Shutdown::Write => libc::SHUT_WR,
+            net::Shutdown::Both => libc::SHUT_RDWR,
+        };
+        let entry = target!(fd, |fd| opcode::Shutdown::new(fd, how).build());
+        Action::submit(Shutdown, entry)
+    }
+}
@@ -0,0 +1,37 @@
+use std::io;
+use std::os::unix::io::RawFd;
+
+use io_uring::{opcode, types};
+
+use crate::driver::{Action, Completable};
+
+pub struct Splice;
+
+impl Completable for Splice {
+    type Output = usize;
+
+    fn complete(result: u32) -> usize {
+        result as usize
+    }
+}
+
+impl Action<Splice> {
+    /// Moves up to `len` bytes from `fd_in` to `fd_out`, one of which must be a pipe.
+    pub fn splice(fd_in: RawFd, fd_out: RawFd, len: u32) -> io::Result<Action<Splice>> {
+        let entry = opcode::Splice::new(types::Fd(fd_in), -1, types::Fd(fd_out), -1, len).build();
+        Action::submit(Splice, entry)
+    }
+
+    /// Like `splice`, reading `fd_in` at `offset` instead of its file position.
+    pub fn splice_at(
+        fd_in: RawFd,
+        offset: u64,
+        fd_out: RawFd,
+        len: u32,
+    ) -> io::Result<Action<Splice>> {
+        let entry =
+            opcode::Splice::new(types::Fd(fd_in), offset as i64, types::Fd(fd_out), -1, len)
+                .build();
+        Action::submit(Splice, entry)
+    }
+}
@@ -0,0 +1,55 @@
+use std::ffi::CString;
+use std::io;
+use std::mem;
+use std::os::unix::io::RawFd;
+use std::path::Path;
+
+use io_uring::{opcode, types};
+
+use crate::driver::path::cstring;
+use crate::driver::{Action, Completable};
+
+/// The fields asked for, those of `stat` and the creation time.
+const MASK: u32 = libc::STATX_BASIC_STATS | libc::STATX_BTIME;
+
+pub struct Statx {
+    _path: CString,
+    buf: Box<libc::statx>,
+}
+
+impl Completable for Statx {
+    type Output = ();
+
+    fn complete(_: u32) {}
+}
+
+impl Action<Statx> {
+    /// Reads the metadata of the file at `path`, of the link itself if `follow` is
+    /// unset and it is a symbolic link.
+    pub fn statx(path: &Path, follow: bool) -> io::Result<Action<Statx>> {
+        let flags = if follow { 0 } else { libc::AT_SYMLINK_NOFOLLOW };
+        Action::statx_at(libc::AT_FDCWD, cstring(path)?, flags)
+    }
+
+    /// Reads the metadata of the file open as `fd`.
+    pub fn statx_fd(fd: RawFd) -> io::Result<Action<Statx>> {
+        Action::statx_at(fd, CString::default(), libc::AT_EMPTY_PATH)
+    }
+
+    fn statx_at(dirfd: RawFd, path: CString, flags: i32) -> io::Result<Action<Statx>> {
+        let mut buf: Box<libc::statx> = Box::new(unsafe { mem::zeroed() });
+        let statxbuf = &mut *buf as *mut libc::statx as *mut types::statx;
+        let entry = opcode::Statx::new(types::Fd(dirfd), path.as_ptr(), statxbuf)
+            .flags(flags | libc::AT_STATX_SYNC_AS_STAT)
+            .mask(MASK)
+            .build();
+        Action::submit(Statx { _path: path, buf }, entry)
+    }
+}
+
+impl Statx {
+    /// What the kernel filled in, once the operation completed.
+    pub fn into_inner(self) -> libc::statx {
+        *self.buf
+    }
+}
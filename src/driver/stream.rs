@@ -1,34 +1,225 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::future::Future;
 use std::io;
+use std::mem;
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::pin::Pin;
-use std::task::{Context, Poll};
+use std::rc::{Rc, Weak};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
 
-use crate::driver::{self, Action};
+use futures_util::future::poll_fn;
+
+use crate::driver::chain::{Buf, BufChain};
+use crate::driver::cmsg::Cmsgs;
+use crate::driver::files::FixedFile;
+use crate::driver::fixed::FixedBuf;
+use crate::driver::iobuf::{self, IoBuf, IoBufMut};
+use crate::driver::send_zc::ZcWrite;
+use crate::driver::vectored::{self, IoSliceOwned};
+use crate::driver::{self, Action, Deferred, EventKind};
 
 use crate::driver::DEFAULT_BUFFER_SIZE;
+use crate::error::Error;
+use crate::time;
+use crate::waker_fn::waker_fn;
+
+/// Most segments handed to a single vectored write.
+const IOV_MAX: usize = 1024;
 
 pub struct Stream<T> {
     inner: Inner,
+    /// Declared before `io` so the slot is cleared before the fd is closed.
+    fixed: Option<FixedFile>,
     io: T,
+    context: Option<Box<dyn Any>>,
+}
+
+/// Counters of the traffic that went through a stream.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StreamStats {
+    /// Bytes received.
+    pub bytes_read: u64,
+    /// Bytes sent.
+    pub bytes_written: u64,
+    /// Completed operations that received data or reached end of stream.
+    pub reads: u64,
+    /// Completed operations that sent data.
+    pub writes: u64,
+    /// When the last operation completed, `None` before the first one.
+    pub last_activity: Option<Instant>,
+}
+
+/// A stream taken apart by [`Stream::into_parts`], to be put back together by
+/// [`Stream::from_parts`], possibly on the runtime of another thread.
+#[derive(Debug)]
+pub struct StreamParts<T> {
+    pub io: T,
+    /// Bytes received and not consumed yet.
+    pub read: Vec<u8>,
+    /// Bytes reported as written that were not written yet.
+    pub write: Vec<u8>,
+}
+
+impl StreamStats {
+    fn read(&mut self, n: usize) {
+        self.bytes_read += n as u64;
+        self.reads += 1;
+        self.last_activity = Some(Instant::now());
+    }
+
+    fn wrote(&mut self, n: usize) {
+        self.bytes_written += n as u64;
+        self.writes += 1;
+        self.last_activity = Some(Instant::now());
+    }
 }
 
 impl<T: AsRawFd> Stream<T> {
     pub fn new(io: T) -> Stream<T> {
+        let fd = io.as_raw_fd();
         Stream {
             io,
             inner: Inner {
                 read_pos: 0,
-                rd: vec![],
+                rd: Buf::Owned(Vec::new()),
                 read: Read::Idle,
-                write: Write::Idle,
+                writer: Rc::new(RefCell::new(Writer {
+                    fd,
+                    write: Write::Idle,
+                    limit: 0,
+                    pending: Vec::new(),
+                    queued: false,
+                    error: None,
+                    waker: None,
+                    next_ticket: 0,
+                    stats: StreamStats::default(),
+                })),
+                stats: StreamStats::default(),
+                timeouts: Timeouts::default(),
+                read_ahead: false,
             },
+            fixed: None,
+            context: None,
+        }
+    }
+
+    /// Puts a stream taken apart by [`Stream::into_parts`] back together. The received
+    /// bytes are read first, the bytes still to write go out once the runtime is idle,
+    /// or with the next write if there is no runtime yet.
+    pub fn from_parts(parts: StreamParts<T>) -> Stream<T> {
+        let stream = Stream::new(parts.io);
+        let mut inner = stream.inner;
+        inner.rd = Buf::Owned(parts.read);
+        if !parts.write.is_empty() {
+            let mut writer = inner.writer.borrow_mut();
+            writer.pending = parts.write;
+            if driver::CURRENT.is_set() {
+                inner.queue_idle(&mut writer);
+            }
+        }
+        Stream { inner, ..stream }
+    }
+
+    /// Takes the stream apart, so it can be moved to another runtime and resumed there
+    /// with [`Stream::from_parts`]. A write in flight is waited for and fails this if it
+    /// failed, a read in flight is cancelled and what it received is kept. Settings,
+    /// timeouts, stats and the context stay behind.
+    pub async fn into_parts(mut self) -> io::Result<StreamParts<T>> {
+        let mut read = self.inner.rd[self.inner.read_pos..].to_vec();
+        self.inner.cancel_read(&mut read).await?;
+        // nothing may run between the write settling and taking the held back bytes, or
+        // the idle hook would start a write for them.
+        poll_fn(|cx| self.inner.writer.borrow_mut().poll_settle(cx)).await?;
+        let Stream {
+            inner, fixed, io, ..
+        } = self;
+        // the slot goes before the fd leaves the runtime.
+        drop(fixed);
+        let write = mem::take(&mut inner.writer.borrow_mut().pending);
+        Ok(StreamParts { io, read, write })
+    }
+
+    /// Registers the fd in the runtime's fixed file table, later operations refer to it
+    /// by slot.
+    pub fn register_fd(&mut self) -> io::Result<()> {
+        if self.fixed.is_none() {
+            self.fixed = Some(FixedFile::register(self.io.as_raw_fd())?);
         }
+        Ok(())
     }
 
     pub fn get_ref(&self) -> &T {
         &self.io
     }
 
+    pub fn stats(&self) -> StreamStats {
+        self.inner.stats()
+    }
+
+    /// Holds back writes while they add up to at most `limit` bytes and reports them as
+    /// written right away. They go out as one write once a larger write comes in, the
+    /// stream is flushed or the runtime has nothing else to do, so a burst of small
+    /// writes costs a single operation without waiting for a timer. A failed write of
+    /// held back bytes fails the next write or flush. Bytes still held back when the
+    /// stream is dropped are written in the background, where a failure goes unnoticed,
+    /// and are lost if the runtime is gone, so flush before dropping the stream. 0 turns
+    /// coalescing off.
+    pub fn set_write_coalescing(&mut self, limit: usize) {
+        self.inner.writer.borrow_mut().limit = limit;
+    }
+
+    /// Sets `SO_RCVLOWAT`, a pending read is only woken once at least `n` bytes arrived
+    /// or the peer closed its write side. Bytes already received are still returned as
+    /// soon as they are read, so this is a hint that saves wakeups for fragmented
+    /// messages rather than a guarantee.
+    pub fn set_recv_low_watermark(&self, n: usize) -> io::Result<()> {
+        let value = n.min(libc::c_int::MAX as usize) as libc::c_int;
+        syscall!(setsockopt(
+            self.io.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_RCVLOWAT,
+            &value as *const _ as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t
+        ))?;
+        Ok(())
+    }
+
+    /// Starts the next read as soon as one completed with data, so the bytes that follow
+    /// are already on their way while the caller handles what it got. Only applies to
+    /// single reads, a multishot recv stays armed anyway.
+    pub fn set_read_ahead(&mut self, enabled: bool) {
+        self.inner.read_ahead = enabled;
+    }
+
+    /// Fails pending reads and writes with `TimedOut` once no bytes moved in either
+    /// direction for `timeout`. A slow transfer that keeps making progress never times
+    /// out. `None` turns the idle timeout off.
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) {
+        self.inner.timeouts.idle = timeout.map(|timeout| (timeout, Instant::now()));
+        self.inner.timeouts.timers = Default::default();
+    }
+
+    /// Fails pending reads and writes with `TimedOut` once `deadline` passed, however
+    /// much progress was made until then. `None` removes the deadline.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.inner.timeouts.deadline = deadline;
+        self.inner.timeouts.timers = Default::default();
+    }
+
+    pub fn set_context<C: 'static>(&mut self, context: C) {
+        self.context = Some(Box::new(context));
+    }
+
+    pub fn context<C: 'static>(&self) -> Option<&C> {
+        self.context.as_ref()?.downcast_ref()
+    }
+
+    pub fn context_mut<C: 'static>(&mut self) -> Option<&mut C> {
+        self.context.as_mut()?.downcast_mut()
+    }
+
     pub fn poll_read(&mut self, cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
         let src = ready!(self.inner.poll_fill_buf(cx, self.io.as_raw_fd()))?;
         let n = buf.len().min(src.len());
@@ -45,71 +236,1107 @@ impl<T: AsRawFd> Stream<T> {
         self.inner.consume(amt)
     }
 
+    /// Whether no received bytes are buffered and no read is in flight.
+    pub fn is_read_idle(&self) -> bool {
+        self.inner.is_read_idle()
+    }
+
     pub fn poll_write(&mut self, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
-        self.inner.poll_write(cx, buf, self.io.as_raw_fd())
+        self.inner.poll_write(cx, buf, None)
+    }
+
+    /// Like `poll_write`, for a caller that keeps `ticket` across the polls of the write.
+    pub(crate) fn poll_write_ticket(
+        &mut self,
+        cx: &mut Context,
+        buf: &[u8],
+        ticket: &mut Ticket,
+    ) -> Poll<io::Result<usize>> {
+        self.inner.poll_write(cx, buf, Some(ticket))
+    }
+
+    /// Writes some bytes from `buf`, returning how many were written.
+    pub async fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut ticket = Ticket::default();
+        poll_fn(|cx| self.poll_write_ticket(cx, buf, &mut ticket)).await
+    }
+
+    pub fn poll_flush(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        self.inner.poll_flush(cx)
+    }
+
+    /// Like `poll_read`, failing with `ErrorKind::TimedOut` if nothing arrived within
+    /// `timeout`.
+    pub async fn read_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        // buffered bytes or an armed recv leave nothing to link the timeout to, so the
+        // armed recv is waited on instead and stays armed if the timer fires.
+        if !self.inner.is_read_idle() {
+            return time::timeout(timeout, poll_fn(|cx| self.poll_read(cx, buf))).await?;
+        }
+        let mut action = Action::recv_timeout(self.io.as_raw_fd(), buf.len(), timeout)?;
+        let n = poll_fn(|cx| action.poll_recv(cx, buf)).await?;
+        self.inner.stats.read(n);
+        Ok(n)
+    }
+
+    /// Like `poll_read`, the kernel picking the buffer the bytes land in from the ring
+    /// registered as group `bgid` rather than from the default ring. Bytes that do not
+    /// fit `buf` stay buffered in the stream. Buffered bytes or a read already in
+    /// flight are handed out first.
+    pub async fn read_with_group(&mut self, buf: &mut [u8], bgid: u16) -> io::Result<usize> {
+        if self.inner.is_read_idle() {
+            let fd = self.io.as_raw_fd();
+            self.inner.read = match Action::read_provided_from(fd, bgid)? {
+                Some(action) => Read::Selecting(action),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        "no buffer ring registered for the group",
+                    ))
+                }
+            };
+        }
+        poll_fn(|cx| self.poll_read(cx, buf)).await
+    }
+
+    /// Like `read_with_group`, picking the group whose buffers fit `buf` most closely,
+    /// see [`Inner::group_for`](driver::Inner::group_for). Reads into a buffer of
+    /// the stream's own without any buffer ring.
+    pub async fn read_sized(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match driver::group_for(buf.len()) {
+            Some(bgid) => self.read_with_group(buf, bgid).await,
+            None => poll_fn(|cx| self.poll_read(cx, buf)).await,
+        }
+    }
+
+    /// Like `poll_write`, failing with `ErrorKind::TimedOut` if `buf` could not be
+    /// written within `timeout`. Bytes written until then are reported as a short write.
+    pub async fn write_timeout(&mut self, buf: &[u8], timeout: Duration) -> io::Result<usize> {
+        let deadline = Instant::now() + timeout;
+        time::timeout(timeout, poll_fn(|cx| self.poll_flush(cx))).await??;
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        let mut action = Action::write_timeout(self.io.as_raw_fd(), buf, timeout)?;
+        let n = poll_fn(|cx| action.poll_write(cx)).await?;
+        self.inner.wrote(n);
+        Ok(n)
+    }
+
+    /// Moves the next received bytes into `chain` without copying them, returning how
+    /// many were added. `Ok(0)` means the peer closed its write side.
+    pub fn poll_read_chain(
+        &mut self,
+        cx: &mut Context,
+        chain: &mut BufChain,
+    ) -> Poll<io::Result<usize>> {
+        ready!(self.inner.poll_fill_buf(cx, self.io.as_raw_fd()))?;
+        let pos = self.inner.read_pos;
+        let buf = mem::replace(&mut self.inner.rd, Buf::Owned(Vec::new()));
+        self.inner.read_pos = 0;
+        let n = buf.len() - pos;
+        if n > 0 {
+            chain.push(buf, pos);
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    /// Writes every segment of `chain` with vectored writes, returning how many bytes
+    /// were written. Written bytes are removed from `chain`.
+    pub async fn write_chain(&mut self, chain: &mut BufChain) -> io::Result<usize> {
+        poll_fn(|cx| self.poll_flush(cx)).await?;
+        let mut total = 0;
+        while !chain.is_empty() {
+            let completion = Action::writev(self.io.as_raw_fd(), chain.take(IOV_MAX))?.await;
+            let (n, writev) = completion.into_parts();
+            chain.put_back(writev.into_segments());
+            let n = match n {
+                Ok(n) => n,
+                // report the progress made so far, the error shows up on the next write.
+                Err(_) if total > 0 => return Ok(total),
+                Err(e) => return Err(e),
+            };
+            if n == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            chain.advance(n);
+            self.inner.wrote(n);
+            total += n;
+        }
+        Ok(total)
+    }
+
+    /// Reads into `buf` up to its capacity, replacing its contents, and returns how many
+    /// bytes were read together with the buffer, which the read owns until the kernel is
+    /// done with it. Bytes buffered by an earlier read are handed out first.
+    pub async fn read_buf<B: IoBufMut>(&mut self, mut buf: B) -> (io::Result<usize>, B) {
+        let fd = self.io.as_raw_fd();
+        if !self.inner.is_read_idle() {
+            if let Err(e) = poll_fn(|cx| self.inner.poll_fill_buf(cx, fd).map_ok(drop)).await {
+                return (Err(e), buf);
+            }
+            let n = iobuf::fill(&mut buf, &self.inner.rd[self.inner.read_pos..]);
+            self.inner.consume(n);
+            return (Ok(n), buf);
+        }
+        let action = match Action::read_buf(fd, buf, None) {
+            Ok(action) => action,
+            Err((e, buf)) => return (Err(e), buf),
+        };
+        let (n, read) = action.await.into_parts();
+        match n {
+            Ok(n) => {
+                self.inner.stats.read(n);
+                (Ok(n), read.filled(n))
+            }
+            Err(e) => (Err(e), read.into_buf()),
+        }
+    }
+
+    /// Writes the contents of `buf`, returning how many bytes were written together with
+    /// the buffer, which the write owns until the kernel is done with it.
+    pub async fn write_buf<B: IoBuf>(&mut self, buf: B) -> (io::Result<usize>, B) {
+        if let Err(e) = poll_fn(|cx| self.poll_flush(cx)).await {
+            return (Err(e), buf);
+        }
+        let action = match Action::write_buf(self.io.as_raw_fd(), buf, None) {
+            Ok(action) => action,
+            Err((e, buf)) => return (Err(e), buf),
+        };
+        let (n, write) = action.await.into_parts();
+        if let Ok(n) = n {
+            self.inner.wrote(n);
+        }
+        (n, write.into_buf())
+    }
+
+    /// Reads into `bufs` in order with a single `readv`, replacing their contents, and
+    /// returns how many bytes were read together with the buffers. Bytes buffered by an
+    /// earlier read are handed out first.
+    pub async fn read_vectored(
+        &mut self,
+        mut bufs: Vec<IoSliceOwned>,
+    ) -> io::Result<(usize, Vec<IoSliceOwned>)> {
+        let fd = self.io.as_raw_fd();
+        if !self.inner.is_read_idle() {
+            poll_fn(|cx| self.inner.poll_fill_buf(cx, fd).map_ok(drop)).await?;
+            let n = vectored::fill(&mut bufs, &self.inner.rd[self.inner.read_pos..]);
+            self.inner.consume(n);
+            return Ok((n, bufs));
+        }
+        let (n, readv) = Action::readv(fd, bufs, None)?.await.into_parts();
+        let n = n?;
+        self.inner.stats.read(n);
+        Ok((n, readv.filled(n)))
+    }
+
+    /// Writes the contents of `bufs` in order with a single `writev`, returning how many
+    /// bytes were written together with the buffers.
+    pub async fn write_vectored(
+        &mut self,
+        bufs: Vec<IoSliceOwned>,
+    ) -> io::Result<(usize, Vec<IoSliceOwned>)> {
+        poll_fn(|cx| self.poll_flush(cx)).await?;
+        let (n, writev) = Action::writev_owned(self.io.as_raw_fd(), bufs, None)?
+            .await
+            .into_parts();
+        let n = n?;
+        self.inner.wrote(n);
+        Ok((n, writev.into_bufs()))
+    }
+
+    pub async fn write_fixed(&mut self, buf: FixedBuf) -> io::Result<(usize, FixedBuf)> {
+        poll_fn(|cx| self.poll_flush(cx)).await?;
+        let completion = Action::write_fixed(self.io.as_raw_fd(), buf, None)?.await;
+        let (n, buf) = completion.into_parts();
+        let n = n?;
+        self.inner.wrote(n);
+        Ok((n, buf.into_buf()))
+    }
+
+    pub async fn send_zc(&mut self, buf: Vec<u8>) -> io::Result<usize> {
+        self.write_zc(buf).await?.await
+    }
+
+    /// Starts a zero copy send of `buf` once the buffered bytes are written, the
+    /// stream counts the bytes when the returned send resolves.
+    pub async fn write_zc(&mut self, buf: Vec<u8>) -> io::Result<ZcWrite> {
+        poll_fn(|cx| self.poll_flush(cx)).await?;
+        let action = Action::send_zc(self.io.as_raw_fd(), buf)?;
+        let writer = self.inner.writer.clone();
+        Ok(ZcWrite::new(action).on_sent(move |n| writer.borrow_mut().stats.wrote(n)))
+    }
+
+    pub async fn send_file(&mut self, file: RawFd, offset: u64, len: u64) -> io::Result<u64> {
+        poll_fn(|cx| self.poll_flush(cx)).await?;
+        let n = crate::io::send_file(file, offset, self.io.as_raw_fd(), len).await?;
+        if n > 0 {
+            self.inner.wrote(n as usize);
+        }
+        Ok(n)
+    }
+
+    /// Receives `len` bytes and writes them back, returning how many were echoed. The
+    /// recv and the send go to the kernel as one linked submission.
+    pub async fn recv_send(&mut self, len: usize) -> io::Result<usize> {
+        poll_fn(|cx| self.poll_flush(cx)).await?;
+        // bytes buffered by an earlier read have to go out first, so take the slow path.
+        if !self.inner.is_read_idle() {
+            let mut buf = vec![0; len];
+            let n = poll_fn(|cx| self.poll_read(cx, &mut buf)).await?;
+            self.write_all(&buf[..n]).await?;
+            return Ok(n);
+        }
+
+        let (recv, send) = Action::recv_send(self.io.as_raw_fd(), len)?;
+        let recv = recv.await;
+        let send = send.await;
+        let (n, recv) = recv.into_parts();
+        let n = n?;
+        let sent = match send.output() {
+            Ok(sent) => sent,
+            // a short recv broke the link, the bytes that did arrive are sent below.
+            Err(_) if n < len => 0,
+            Err(e) => return Err(e),
+        };
+        self.inner.stats.read(n);
+        if sent > 0 {
+            self.inner.wrote(sent);
+        }
+        if sent < n {
+            let received = recv.received(n);
+            self.write_all(&received[sent..]).await?;
+        }
+        Ok(n)
+    }
+
+    /// Receives into `buf` along with control messages, up to the capacity of
+    /// `control`. Bytes buffered by an earlier read are handed out first, without
+    /// control messages.
+    pub async fn recv_msg(&mut self, buf: &mut [u8], control: Cmsgs) -> io::Result<(usize, Cmsgs)> {
+        let fd = self.io.as_raw_fd();
+        let inner = &mut self.inner;
+        // a read an earlier plain read left armed would take the next message and drop
+        // its control messages.
+        inner.settle_read().await?;
+        let src = &inner.rd[inner.read_pos..];
+        if !src.is_empty() {
+            let n = src.len().min(buf.len());
+            buf[..n].copy_from_slice(&src[..n]);
+            inner.consume(n);
+            return Ok((n, control));
+        }
+        let (n, recvmsg) = Action::recvmsg_control(fd, buf.len(), control)?
+            .await
+            .into_parts();
+        let n = n?;
+        let (data, control) = recvmsg.received_from_peer(n);
+        buf[..n].copy_from_slice(&data);
+        inner.stats.read(n);
+        Ok((n, control))
+    }
+
+    /// Copies the next bytes into `buf` without consuming them, returning how many were
+    /// copied. Bytes buffered by an earlier read are copied first, otherwise the socket
+    /// is peeked.
+    pub async fn peek(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let fd = self.io.as_raw_fd();
+        let inner = &mut self.inner;
+        // a read left armed could take the bytes the peek waits for.
+        inner.settle_read().await?;
+        let src = &inner.rd[inner.read_pos..];
+        if !src.is_empty() {
+            let n = src.len().min(buf.len());
+            buf[..n].copy_from_slice(&src[..n]);
+            return Ok(n);
+        }
+        let mut action = Action::recv_peek(fd, buf.len())?;
+        poll_fn(|cx| action.poll_recv(cx, buf)).await
+    }
+
+    /// Writes `buf` along with the control messages in `control` as one message,
+    /// returning how many bytes were written.
+    pub async fn send_msg(&mut self, buf: &[u8], control: Cmsgs) -> io::Result<usize> {
+        poll_fn(|cx| self.poll_flush(cx)).await?;
+        let completion = Action::send_control(self.io.as_raw_fd(), buf.to_vec(), control)?.await;
+        let n = completion.output()?;
+        self.inner.wrote(n);
+        Ok(n)
+    }
+
+    /// Writes all of `buf`, failing with `WriteZero` if the stream stops taking bytes.
+    pub async fn write_all(&mut self, mut buf: &[u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            let n = self.write(buf).await?;
+            if n == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            buf = &buf[n..];
+        }
+        Ok(())
+    }
+}
+
+/// Dropped before the stream's fd is closed.
+impl Drop for Inner {
+    fn drop(&mut self) {
+        // bytes already handed to the kernel are still delivered.
+        let mut writer = self.writer.borrow_mut();
+        match mem::replace(&mut writer.write, Write::Idle) {
+            // bytes are only held back while no write is in flight, so none are left
+            // behind this one.
+            Write::Writing { action, .. } => drop(action.detach()),
+            // held back bytes were reported as written, so they go out as well.
+            Write::Idle if !writer.pending.is_empty() && driver::CURRENT.is_set() => {
+                if let Ok(action) = Action::write(writer.fd, &writer.pending) {
+                    drop(action.detach());
+                }
+            }
+            Write::Idle => {}
+        }
     }
 }
 
 struct Inner {
-    rd: Vec<u8>,
+    rd: Buf,
     read_pos: usize,
     read: Read,
+    writer: Rc<RefCell<Writer>>,
+    /// Counts the reads, the writes are counted by the writer.
+    stats: StreamStats,
+    timeouts: Timeouts,
+    /// Whether a single read is started again as soon as one completed with data.
+    read_ahead: bool,
+}
+
+/// When a stream stops waiting for its reads and writes, see
+/// [`Stream::set_idle_timeout`] and [`Stream::set_deadline`].
+#[derive(Default)]
+struct Timeouts {
+    /// The idle timeout and when it was set, which counts as the first progress.
+    idle: Option<(Duration, Instant)>,
+    deadline: Option<Instant>,
+    /// One timer per [`Direction`], a read and a write may wait in different tasks.
+    timers: [Option<time::Delay>; 2],
+}
+
+#[derive(Clone, Copy)]
+enum Direction {
+    Read,
+    Write,
+}
+
+/// The write side of a stream, shared with the idle hook that writes out held back
+/// bytes, see [`Stream::set_write_coalescing`].
+struct Writer {
+    fd: RawFd,
     write: Write,
+    /// Most bytes held back, 0 when writes are not coalesced.
+    limit: usize,
+    /// Bytes reported as written that no write was started for yet.
+    pending: Vec<u8>,
+    /// Whether an idle hook is registered to write out `pending`.
+    queued: bool,
+    /// The failure of a write the idle hook finished, reported by the next write.
+    error: Option<io::Error>,
+    /// The task waiting for a write, woken when the idle hook finished the write.
+    waker: Option<Waker>,
+    /// The id the next write started for a [`Ticket`] is given.
+    next_ticket: u64,
+    stats: StreamStats,
 }
 
 enum Write {
     Idle,
-    Writing(Action<driver::Write>),
+    Writing {
+        action: Action<driver::Write>,
+        owner: Owner,
+    },
+}
+
+/// Whom a write in flight was started for.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Owner {
+    /// Held back bytes, reported as written already, which no caller waits for.
+    HeldBack,
+    /// The caller holding the ticket with this id.
+    Ticket(u64),
+    /// A caller of `poll_write`, which keeps nothing across polls. The write answers
+    /// the next `poll_write` whose buffer starts with the bytes it writes, so whatever it
+    /// reports is on the wire, and a write of other bytes waits for it to finish.
+    Poll,
 }
 
+/// Kept by a caller across the polls of one write, so that its write in flight is told
+/// apart from the write of a caller that has since been dropped.
+#[derive(Default)]
+pub(crate) struct Ticket(Option<u64>);
+
 enum Read {
     Idle,
     Reading(Action<driver::Read>),
+    Selecting(Action<driver::ReadProvided>),
+    Receiving(Action<driver::RecvMulti>),
 }
 
-impl Inner {
-    fn poll_write(&mut self, cx: &mut Context, buf: &[u8], fd: RawFd) -> Poll<io::Result<usize>> {
+/// The idle hook of a stream that holds back writes, done once the stream is gone.
+fn idle(writer: &Weak<RefCell<Writer>>, deferred: &Deferred) -> bool {
+    match writer.upgrade() {
+        Some(writer) => writer.borrow_mut().idle(deferred),
+        None => false,
+    }
+}
+
+fn is_retryable(err: &io::Error) -> bool {
+    matches!(err.raw_os_error(), Some(libc::ENOBUFS) | Some(libc::EINVAL))
+}
+
+impl Writer {
+    /// Whether `buf` is held back instead of written right away. Bytes are only held
+    /// back while no write is in flight, so dropping the stream can still write them
+    /// out in order.
+    fn holds_back(&self, buf: &[u8]) -> bool {
+        self.limit != 0
+            && matches!(self.write, Write::Idle)
+            && self.pending.len() + buf.len() <= self.limit
+    }
+
+    /// Polls the write in flight to completion and returns whom it was started for.
+    /// Held back bytes were reported as written, so their write fails unless all of them
+    /// went out.
+    fn poll_in_flight(&mut self, cx: &mut Context) -> Poll<Option<(Owner, io::Result<usize>)>> {
+        let (action, owner) = match &mut self.write {
+            Write::Idle => return Poll::Ready(None),
+            Write::Writing { action, owner } => (action, *owner),
+        };
+        let res = if owner == Owner::HeldBack {
+            ready!(Pin::new(action).poll_write_all(cx))
+        } else {
+            ready!(Pin::new(action).poll_write(cx))
+        };
+        self.write = Write::Idle;
+        if let Ok(n) = res {
+            self.stats.wrote(n);
+        }
+        Poll::Ready(Some((owner, res)))
+    }
+
+    /// Whether the write in flight was started for the caller writing `buf`.
+    fn owns(&self, buf: &[u8], ticket: Option<&Ticket>) -> bool {
+        let (action, owner) = match &self.write {
+            Write::Idle => return false,
+            Write::Writing { action, owner } => (action, *owner),
+        };
+        match (owner, ticket) {
+            (Owner::Ticket(id), Some(ticket)) => ticket.0 == Some(id),
+            (Owner::Poll, None) => buf.starts_with(action.bytes()),
+            _ => false,
+        }
+    }
+
+    /// Reports the failure of a write of held back bytes and waits for a write in
+    /// flight that was not started for the caller, before `buf` may be held back.
+    fn poll_ready(
+        &mut self,
+        cx: &mut Context,
+        buf: &[u8],
+        ticket: Option<&Ticket>,
+    ) -> Poll<io::Result<()>> {
+        if let Some(e) = self.error.take() {
+            return Poll::Ready(Err(e));
+        }
+        // the caller's own write is in flight, it is waited for instead.
+        if self.owns(buf, ticket) {
+            return Poll::Ready(Ok(()));
+        }
+        let (owner, res) = match self.poll_in_flight(cx) {
+            Poll::Ready(Some(done)) => done,
+            Poll::Ready(None) => return Poll::Ready(Ok(())),
+            Poll::Pending => {
+                self.waker = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+        };
+        self.waker = None;
+        // the write of a future that has since been dropped failed for nobody.
+        if owner == Owner::HeldBack {
+            res?;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_write(
+        &mut self,
+        cx: &mut Context,
+        buf: &[u8],
+        mut ticket: Option<&mut Ticket>,
+    ) -> Poll<io::Result<usize>> {
+        if let Some(e) = self.error.take() {
+            return Poll::Ready(Err(e));
+        }
+        loop {
+            if let Write::Idle = self.write {
+                if self.pending.is_empty() {
+                    let action = Action::write(self.fd, buf)?;
+                    let owner = match ticket.as_deref_mut() {
+                        Some(ticket) => {
+                            let id = self.next_ticket;
+                            self.next_ticket += 1;
+                            ticket.0 = Some(id);
+                            Owner::Ticket(id)
+                        }
+                        None => Owner::Poll,
+                    };
+                    self.write = Write::Writing { action, owner };
+                } else {
+                    // held back bytes were written before `buf`, so they go out first.
+                    self.start_pending()?;
+                }
+            }
+            let owned = self.owns(buf, ticket.as_deref());
+            let (owner, res) = match self.poll_in_flight(cx) {
+                Poll::Ready(done) => done.expect("a write is in flight"),
+                Poll::Pending => {
+                    self.waker = Some(cx.waker().clone());
+                    return Poll::Pending;
+                }
+            };
+            if owned {
+                self.waker = None;
+                return Poll::Ready(res);
+            }
+            if owner == Owner::HeldBack {
+                res?;
+            }
+            // the write was started by a future that has since been dropped, its bytes
+            // went out and now the caller's own write can start.
+        }
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        if let Some(e) = self.error.take() {
+            return Poll::Ready(Err(e));
+        }
         loop {
-            match &mut self.write {
+            match &self.write {
+                Write::Idle if self.pending.is_empty() => {
+                    self.waker = None;
+                    return Poll::Ready(Ok(()));
+                }
+                Write::Idle => self.start_pending()?,
+                Write::Writing { .. } => match self.poll_in_flight(cx) {
+                    Poll::Ready(done) => {
+                        done.expect("a write is in flight").1?;
+                    }
+                    Poll::Pending => {
+                        self.waker = Some(cx.waker().clone());
+                        return Poll::Pending;
+                    }
+                },
+            }
+        }
+    }
+
+    /// Waits for the write in flight without starting one for the held back bytes.
+    fn poll_settle(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        if let Some(e) = self.error.take() {
+            return Poll::Ready(Err(e));
+        }
+        match self.poll_in_flight(cx) {
+            Poll::Ready(done) => {
+                self.waker = None;
+                if let Some((_, res)) = done {
+                    res?;
+                }
+                Poll::Ready(Ok(()))
+            }
+            Poll::Pending => {
+                self.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
+    fn start_pending(&mut self) -> io::Result<()> {
+        let action = Action::write(self.fd, &self.pending)?;
+        self.pending.clear();
+        self.write = Write::Writing {
+            action,
+            owner: Owner::HeldBack,
+        };
+        Ok(())
+    }
+
+    /// Writes out the held back bytes while the runtime is idle, returns whether there
+    /// is more to do before the next park.
+    fn idle(&mut self, deferred: &Deferred) -> bool {
+        // polling with the waiting task's waker keeps it registered.
+        let waker = self.waker.clone().unwrap_or_else(|| waker_fn(|| ()));
+        let cx = &mut Context::from_waker(&waker);
+        let mut progress = false;
+        loop {
+            match &self.write {
+                Write::Idle if self.pending.is_empty() => break,
                 Write::Idle => {
-                    let action = Action::write(fd, buf)?;
-                    self.write = Write::Writing(action);
+                    if let Err(e) = self.start_pending() {
+                        self.error = Some(e);
+                        progress = true;
+                        break;
+                    }
                 }
-                Write::Writing(action) => {
-                    let n = ready!(Pin::new(action).poll_write(cx))?;
-                    self.write = Write::Idle;
-                    return Poll::Ready(Ok(n));
+                // the caller waiting for its own write writes the held back bytes next.
+                Write::Writing { owner, .. } if *owner != Owner::HeldBack => break,
+                Write::Writing { .. } => {
+                    let res = match self.poll_in_flight(cx) {
+                        Poll::Ready(done) => done.expect("a write is in flight").1,
+                        Poll::Pending => return true,
+                    };
+                    progress = true;
+                    if let Err(e) = res {
+                        self.error = Some(e);
+                        break;
+                    }
                 }
             }
         }
+        // a task waiting for the write looks at the stream again.
+        if progress {
+            if let Some(waker) = self.waker.take() {
+                deferred.wake(waker);
+            }
+        }
+        self.queued = false;
+        false
+    }
+}
+
+impl Inner {
+    fn poll_write(
+        &mut self,
+        cx: &mut Context,
+        buf: &[u8],
+        ticket: Option<&mut Ticket>,
+    ) -> Poll<io::Result<usize>> {
+        let res = self.poll_write_once(cx, buf, ticket);
+        self.or_expired(cx, Direction::Write, res)
+    }
+
+    fn poll_write_once(
+        &mut self,
+        cx: &mut Context,
+        buf: &[u8],
+        ticket: Option<&mut Ticket>,
+    ) -> Poll<io::Result<usize>> {
+        let mut writer = self.writer.borrow_mut();
+        if writer.limit != 0 {
+            ready!(writer.poll_ready(cx, buf, ticket.as_deref()))?;
+        }
+        if !writer.holds_back(buf) {
+            return writer.poll_write(cx, buf, ticket);
+        }
+        writer.pending.extend_from_slice(buf);
+        self.queue_idle(&mut writer);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    /// Registers the idle hook that writes out the held back bytes, unless it already
+    /// is.
+    fn queue_idle(&self, writer: &mut Writer) {
+        if !writer.queued {
+            writer.queued = true;
+            let writer = Rc::downgrade(&self.writer);
+            driver::CURRENT.with(|driver| driver.on_idle(move |deferred| idle(&writer, deferred)));
+        }
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        let res = self.writer.borrow_mut().poll_flush(cx);
+        self.or_expired(cx, Direction::Write, res)
+    }
+
+    /// Reads are counted here, writes by the writer.
+    fn stats(&self) -> StreamStats {
+        let wrote = self.writer.borrow().stats;
+        StreamStats {
+            bytes_written: wrote.bytes_written,
+            writes: wrote.writes,
+            last_activity: self.stats.last_activity.max(wrote.last_activity),
+            ..self.stats
+        }
+    }
+
+    fn wrote(&mut self, n: usize) {
+        self.writer.borrow_mut().stats.wrote(n);
     }
 
     fn poll_fill_buf(&mut self, cx: &mut Context, fd: RawFd) -> Poll<io::Result<&[u8]>> {
+        let res = self.poll_fill(cx, fd);
+        ready!(self.or_expired(cx, Direction::Read, res))?;
+        Poll::Ready(Ok(&self.rd[self.read_pos..]))
+    }
+
+    /// Fills `rd` unless it still holds unconsumed bytes, leaving it empty at end of
+    /// stream.
+    fn poll_fill(&mut self, cx: &mut Context, fd: RawFd) -> Poll<io::Result<()>> {
         loop {
+            // an armed multishot recv may be running while buffered bytes are consumed.
+            if !self.rd[self.read_pos..].is_empty() {
+                return Poll::Ready(Ok(()));
+            }
             match &mut self.read {
                 Read::Idle => {
-                    if !self.rd[self.read_pos..].is_empty() {
-                        return Poll::Ready(Ok(&self.rd[self.read_pos..]));
+                    self.read_pos = 0;
+                    self.rd = Buf::Owned(Vec::new());
+                    self.read = match Action::recv_multi(fd)? {
+                        Some(action) => Read::Receiving(action),
+                        None => Inner::start_read(fd)?,
+                    };
+                }
+                Read::Receiving(action) => {
+                    let res = ready!(action.poll_recv_multi(cx));
+                    let finished = action.is_finished();
+                    self.rd = match res {
+                        Ok(Some(buf)) => Buf::Provided(buf),
+                        Ok(None) => Buf::Owned(Vec::new()),
+                        // the ring ran dry or the kernel has no multishot recv, both end
+                        // the recv before it consumed anything.
+                        Err(e) if finished && is_retryable(&e) => {
+                            self.read = Inner::start_read(fd)?;
+                            continue;
+                        }
+                        Err(e) => {
+                            self.read = Read::Idle;
+                            return Poll::Ready(Err(e));
+                        }
+                    };
+                    self.read_pos = 0;
+                    self.stats.read(self.rd.len());
+                    if finished {
+                        // the next fill arms the recv again unless the peer is done.
+                        if !self.rd.is_empty() {
+                            driver::record_event(EventKind::Rearm {
+                                key: action.key,
+                                result: self.rd.len() as i32,
+                            });
+                        }
+                        self.read = Read::Idle;
                     }
-
+                    if self.rd.is_empty() {
+                        return Poll::Ready(Ok(()));
+                    }
+                }
+                Read::Selecting(action) => {
+                    self.rd = match ready!(Pin::new(action).poll_read(cx)) {
+                        Ok(Some(buf)) => Buf::Provided(buf),
+                        Ok(None) => Buf::Owned(Vec::new()),
+                        // every ring buffer is in use, read into a buffer of our own.
+                        Err(e) if e.raw_os_error() == Some(libc::ENOBUFS) => {
+                            let action = Action::read(fd, DEFAULT_BUFFER_SIZE as u32)?;
+                            self.read = Read::Reading(action);
+                            continue;
+                        }
+                        Err(e) => {
+                            self.read = Read::Idle;
+                            return Poll::Ready(Err(e));
+                        }
+                    };
+                    self.read = Read::Idle;
                     self.read_pos = 0;
-                    self.rd = vec![];
-                    let action = Action::read(fd, DEFAULT_BUFFER_SIZE as u32)?;
-                    self.read = Read::Reading(action);
+                    self.stats.read(self.rd.len());
+                    if self.rd.is_empty() {
+                        return Poll::Ready(Ok(()));
+                    }
+                    self.read_ahead(fd);
                 }
                 Read::Reading(action) => {
-                    self.rd = ready!(Pin::new(action).poll_read(cx))?;
+                    let res = ready!(Pin::new(action).poll_read(cx));
                     self.read = Read::Idle;
+                    self.rd = Buf::Owned(res?);
                     self.read_pos = 0;
+                    self.stats.read(self.rd.len());
                     if self.rd.is_empty() {
-                        return Poll::Ready(Ok(&self.rd[..]));
+                        return Poll::Ready(Ok(()));
+                    }
+                    self.read_ahead(fd);
+                }
+            }
+        }
+    }
+
+    /// Passes `res` through, or fails a pending operation with `TimedOut` once the
+    /// stream expired.
+    fn or_expired<T>(
+        &mut self,
+        cx: &mut Context,
+        direction: Direction,
+        res: Poll<io::Result<T>>,
+    ) -> Poll<io::Result<T>> {
+        match res {
+            Poll::Pending => match self.poll_expired(cx, direction) {
+                Ok(()) => Poll::Pending,
+                Err(e) => Poll::Ready(Err(e)),
+            },
+            res => res,
+        }
+    }
+
+    /// The instant the stream expires at, `None` without timeouts.
+    fn expires_at(&self) -> Option<Instant> {
+        let timeouts = &self.timeouts;
+        let idle = timeouts.idle.map(|(idle, since)| {
+            let last = self
+                .stats()
+                .last_activity
+                .map_or(since, |last| last.max(since));
+            last + idle
+        });
+        match (idle, timeouts.deadline) {
+            (Some(idle), Some(deadline)) => Some(idle.min(deadline)),
+            (idle, deadline) => idle.or(deadline),
+        }
+    }
+
+    /// Fails with `TimedOut` once the stream expired, otherwise arms the timer so the
+    /// waiting task is woken when it may expire.
+    fn poll_expired(&mut self, cx: &mut Context, direction: Direction) -> io::Result<()> {
+        let at = match self.expires_at() {
+            Some(at) => at,
+            None => return Ok(()),
+        };
+        loop {
+            if at <= Instant::now() {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "stream timed out"));
+            }
+            let timer = self.timeouts.timers[direction as usize]
+                .get_or_insert_with(|| time::delay_until(at));
+            if timer.deadline() > at {
+                timer.reset(at);
+            }
+            if Pin::new(&mut *timer).poll(cx).is_pending() {
+                return Ok(());
+            }
+            // bytes moved since the timer was armed, which pushed the expiry back.
+            timer.reset(at);
+        }
+    }
+
+    /// Starts a single read, into a ring buffer when there is a ring.
+    fn start_read(fd: RawFd) -> io::Result<Read> {
+        Ok(match Action::read_provided(fd)? {
+            Some(action) => Read::Selecting(action),
+            None => Read::Reading(Action::read(fd, DEFAULT_BUFFER_SIZE as u32)?),
+        })
+    }
+
+    /// Starts the next read while the bytes just read are consumed, if asked to. A read
+    /// that fails to start is left to the next `poll_fill`, which reports the error.
+    fn read_ahead(&mut self, fd: RawFd) {
+        if self.read_ahead {
+            if let Ok(read) = Inner::start_read(fd) {
+                self.read = read;
+            }
+        }
+    }
+
+    /// Cancels the read in flight and appends the bytes it received to `buf`.
+    async fn cancel_read(&mut self, buf: &mut Vec<u8>) -> io::Result<()> {
+        let res = match mem::replace(&mut self.read, Read::Idle) {
+            Read::Idle => Ok(()),
+            Read::Receiving(mut action) => {
+                action.cancel();
+                // shots queued before the cancellation still carry data.
+                loop {
+                    match poll_fn(|cx| action.poll_recv_multi(cx)).await {
+                        Ok(received) => buf.extend_from_slice(received.as_deref().unwrap_or(&[])),
+                        Err(e) => break Err(e),
+                    }
+                    if action.is_finished() {
+                        break Ok(());
                     }
                 }
             }
+            Read::Selecting(mut action) => {
+                action.cancel();
+                poll_fn(|cx| Pin::new(&mut action).poll_read(cx))
+                    .await
+                    .map(|received| buf.extend_from_slice(received.as_deref().unwrap_or(&[])))
+            }
+            Read::Reading(mut action) => {
+                action.cancel();
+                poll_fn(|cx| Pin::new(&mut action).poll_read(cx))
+                    .await
+                    .map(|received| buf.extend_from_slice(&received))
+            }
+        };
+        match res {
+            Err(e) if is_retryable(&e) || matches!(Error::from_io(&e), Some(Error::Cancelled)) => {
+                Ok(())
+            }
+            res => res,
         }
     }
 
+    /// Cancels the read in flight, keeping what it received buffered after the bytes
+    /// that are left.
+    async fn settle_read(&mut self) -> io::Result<()> {
+        let mut received = Vec::new();
+        self.cancel_read(&mut received).await?;
+        if !received.is_empty() {
+            self.stats.read(received.len());
+            let mut rd = self.rd[self.read_pos..].to_vec();
+            rd.append(&mut received);
+            self.rd = Buf::Owned(rd);
+            self.read_pos = 0;
+        }
+        Ok(())
+    }
+
+    fn is_read_idle(&self) -> bool {
+        matches!(self.read, Read::Idle) && self.rd[self.read_pos..].is_empty()
+    }
+
     fn consume(&mut self, amt: usize) {
         self.read_pos += amt;
+        if self.read_pos >= self.rd.len() {
+            // hand a ring buffer back as soon as it is drained.
+            self.rd = Buf::Owned(Vec::new());
+            self.read_pos = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::time::Duration;
+
+    use futures_util::io::AsyncWriteExt;
+
+    use crate::net::UnixStream;
+    use crate::time::{delay_for, timeout};
+    use crate::Runtime;
+
+    /// More than the socket buffers hold, so the write stays in flight until the peer
+    /// reads.
+    const LARGE: usize = 4 << 20;
+
+    /// Reads `len` bytes from `peer`.
+    async fn read_exact(peer: &mut UnixStream, len: usize) -> Vec<u8> {
+        let mut buf = vec![0; len];
+        let mut read = 0;
+        while read < len {
+            read += peer.read(&mut buf[read..]).await.unwrap();
+        }
+        buf
+    }
+
+    #[test]
+    fn a_dropped_write_does_not_answer_a_later_one_from_the_same_buffer() {
+        Runtime::new().unwrap().block_on(async {
+            let (mut stream, mut peer) = UnixStream::pair().unwrap();
+            let mut buf = vec![1; LARGE];
+            let dropped = timeout(Duration::from_millis(10), stream.write(&buf)).await;
+            assert!(dropped.is_err());
+            buf.fill(2);
+            let reader = crate::spawn(async move { read_exact(&mut peer, 2 * LARGE).await });
+            let mut written = 0;
+            while written < LARGE {
+                written += stream.write(&buf[written..]).await.unwrap();
+            }
+            let received = reader.await.unwrap();
+            assert!(received[..LARGE].iter().all(|&b| b == 1));
+            assert!(received[LARGE..].iter().all(|&b| b == 2));
+        });
+    }
+
+    #[test]
+    fn a_dropped_poll_write_does_not_answer_a_later_one_of_other_bytes() {
+        Runtime::new().unwrap().block_on(async {
+            let (mut stream, mut peer) = UnixStream::pair().unwrap();
+            let mut buf = vec![1; LARGE];
+            let dropped = timeout(
+                Duration::from_millis(10),
+                AsyncWriteExt::write(&mut stream, &buf),
+            )
+            .await;
+            assert!(dropped.is_err());
+            buf.fill(2);
+            let reader = crate::spawn(async move { read_exact(&mut peer, 2 * LARGE).await });
+            AsyncWriteExt::write_all(&mut stream, &buf).await.unwrap();
+            let received = reader.await.unwrap();
+            assert!(received[..LARGE].iter().all(|&b| b == 1));
+            assert!(received[LARGE..].iter().all(|&b| b == 2));
+        });
+    }
+
+    #[test]
+    fn held_back_writes_go_out_on_flush() {
+        Runtime::new().unwrap().block_on(async {
+            let (mut stream, mut peer) = UnixStream::pair().unwrap();
+            stream.set_write_coalescing(64);
+            stream.write_all(b"ab").await.unwrap();
+            stream.write_all(b"cd").await.unwrap();
+            assert_eq!(stream.stats().bytes_written, 0);
+            stream.flush().await.unwrap();
+            assert_eq!(stream.stats().bytes_written, 4);
+            let mut buf = [0; 8];
+            let n = peer.read(&mut buf).await.unwrap();
+            assert_eq!(&buf[..n], b"abcd");
+        });
+    }
+
+    #[test]
+    fn held_back_writes_go_out_while_the_runtime_is_idle() {
+        Runtime::new().unwrap().block_on(async {
+            let (mut stream, mut peer) = UnixStream::pair().unwrap();
+            stream.set_write_coalescing(64);
+            stream.write_all(b"ab").await.unwrap();
+            let mut buf = [0; 8];
+            let n = peer.read(&mut buf).await.unwrap();
+            assert_eq!(&buf[..n], b"ab");
+        });
+    }
+
+    #[test]
+    fn a_larger_write_goes_out_after_the_held_back_bytes() {
+        Runtime::new().unwrap().block_on(async {
+            let (mut stream, mut peer) = UnixStream::pair().unwrap();
+            stream.set_write_coalescing(4);
+            stream.write_all(b"ab").await.unwrap();
+            stream.write_all(b"cdefgh").await.unwrap();
+            assert_eq!(stream.stats().bytes_written, 8);
+            let mut buf = [0; 16];
+            let mut read = 0;
+            while read < 8 {
+                read += peer.read(&mut buf[read..]).await.unwrap();
+            }
+            assert_eq!(&buf[..read], b"abcdefgh");
+        });
+    }
+
+    #[test]
+    fn a_failed_write_of_held_back_bytes_fails_the_flush() {
+        Runtime::new().unwrap().block_on(async {
+            let (mut stream, peer) = UnixStream::pair().unwrap();
+            drop(peer);
+            stream.set_write_coalescing(64);
+            stream.write_all(b"ab").await.unwrap();
+            let err = stream.flush().await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+            // the failure is reported once.
+            stream.flush().await.unwrap();
+        });
+    }
+
+    #[test]
+    fn a_failed_write_behind_fails_the_next_write() {
+        Runtime::new().unwrap().block_on(async {
+            let (mut stream, peer) = UnixStream::pair().unwrap();
+            drop(peer);
+            stream.set_write_coalescing(64);
+            stream.write_all(b"ab").await.unwrap();
+            // the runtime writes the held back bytes while it waits for the timer.
+            delay_for(Duration::from_millis(10)).await;
+            let err = stream.write(b"cd").await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+        });
     }
 }
@@ -1,22 +1,44 @@
 use std::future::Future;
 use std::io;
+use std::mem;
 use std::pin::Pin;
 use std::task::{Context, Poll};
+use std::time::Duration;
 
+use io_uring::squeue::Entry;
 use io_uring::{opcode, types};
 
 use crate::driver::Action;
 
+/// `IORING_TIMEOUT_BOOTTIME`, io-uring 0.5 has no flag for it.
+const TIMEOUT_BOOTTIME: u32 = 1 << 2;
+
 pub struct Timeout {
-    spec: types::Timespec,
+    /// Boxed, the kernel reads it once the entry is submitted, after the action moved.
+    spec: Box<types::Timespec>,
 }
 
 impl Action<Timeout> {
     pub fn timeout(sec: u64, nsec: u32) -> io::Result<Action<Timeout>> {
         let timeout = Timeout {
-            spec: types::Timespec::new().sec(sec).nsec(nsec),
+            spec: Box::new(types::Timespec::new().sec(sec).nsec(nsec)),
+        };
+        let entry = opcode::Timeout::new(&*timeout.spec).build();
+        Action::submit(timeout, entry)
+    }
+
+    /// A timeout measured on `CLOCK_BOOTTIME`, which keeps counting while the system is
+    /// suspended. Needs Linux 5.15.
+    pub fn timeout_boottime(sec: u64, nsec: u32) -> io::Result<Action<Timeout>> {
+        let timeout = Timeout {
+            spec: Box::new(types::Timespec::new().sec(sec).nsec(nsec)),
         };
-        let entry = opcode::Timeout::new(&timeout.spec as *const _).build();
+        let entry = opcode::Timeout::new(&*timeout.spec).build();
+        // `Entry` is a `repr(C)` wrapper of `io_uring_sqe`, which holds the timeout
+        // flags at offset 28.
+        let mut sqe: [u8; 64] = unsafe { mem::transmute(entry) };
+        sqe[28..32].copy_from_slice(&TIMEOUT_BOOTTIME.to_ne_bytes());
+        let entry: Entry = unsafe { mem::transmute(sqe) };
         Action::submit(timeout, entry)
     }
 
@@ -27,10 +49,26 @@ impl Action<Timeout> {
         match result {
             Err(err) if err.raw_os_error() == Some(libc::ETIME) => Poll::Ready(Ok(())),
             Err(err) => Poll::Ready(Err(err)),
-            Ok(n) => Poll::Ready(Err(io::Error::new(
-                io::ErrorKind::TimedOut,
-                format!("result {}", n),
-            ))),
+            // no completion count is set, so a successful result also means the timer fired.
+            Ok(_) => Poll::Ready(Ok(())),
         }
     }
 }
+
+/// A timeout linked to the operation submitted before it, cancelling that operation
+/// once it fires.
+pub struct LinkTimeout {
+    _spec: Box<types::Timespec>,
+}
+
+impl LinkTimeout {
+    pub fn new(timeout: Duration) -> (LinkTimeout, Entry) {
+        let spec = Box::new(
+            types::Timespec::new()
+                .sec(timeout.as_secs())
+                .nsec(timeout.subsec_nanos()),
+        );
+        let entry = opcode::LinkTimeout::new(&*spec).build();
+        (LinkTimeout { _spec: spec }, entry)
+    }
+}
@@ -0,0 +1,190 @@
+use std::io;
+use std::os::unix::io::RawFd;
+use std::time::Duration;
+
+use io_uring::squeue::Entry;
+use io_uring::types::{SubmitArgs, Timespec};
+use io_uring::{IoUring, Probe};
+
+use crate::driver::{Backend, Cqe, RingFlags};
+use crate::error::Error;
+
+/// Asks `io_uring_enter` to post completions, not exported by `io_uring`.
+const IORING_ENTER_GETEVENTS: u32 = 1;
+
+/// The io_uring backend.
+pub struct Uring {
+    ring: IoUring,
+    flags: RingFlags,
+    /// `None` before Linux 5.6, which can not tell what it supports.
+    probe: Option<Probe>,
+}
+
+impl Uring {
+    /// Sets up a ring with `flags`, leaving out those the kernel does not support.
+    pub fn new(entries: u32, flags: RingFlags) -> io::Result<Uring> {
+        let mut flags = flags;
+        if flags.defer_taskrun {
+            flags.single_issuer = true;
+            flags.taskrun_flag = true;
+        }
+        // SINGLE_ISSUER and DEFER_TASKRUN need Linux 6.0 and 6.1, COOP_TASKRUN and
+        // TASKRUN_FLAG need Linux 5.19.
+        let fallbacks = [
+            flags,
+            RingFlags {
+                defer_taskrun: false,
+                single_issuer: false,
+                ..flags
+            },
+            RingFlags::NONE,
+        ];
+        let mut last = None;
+        for flags in fallbacks {
+            if last == Some(flags) {
+                continue;
+            }
+            last = Some(flags);
+            let mut builder = IoUring::builder();
+            if flags.coop_taskrun {
+                builder.setup_coop_taskrun();
+            }
+            if flags.taskrun_flag {
+                builder.setup_taskrun_flag();
+            }
+            if flags.single_issuer {
+                builder.setup_single_issuer();
+            }
+            if flags.defer_taskrun {
+                builder.setup_defer_taskrun();
+            }
+            let ring = match builder.build(entries) {
+                Ok(ring) => ring,
+                Err(e) if e.raw_os_error() == Some(libc::EINVAL) => continue,
+                Err(e) => return Err(e),
+            };
+            // check if IORING_FEAT_FAST_POLL is supported
+            if !ring.params().is_feature_fast_poll() {
+                return Err(Error::KernelFeatureMissing("IORING_FEAT_FAST_POLL").into());
+            }
+            let mut probe = Probe::new();
+            let probe = match ring.submitter().register_probe(&mut probe) {
+                Ok(()) => Some(probe),
+                Err(_) => None,
+            };
+            return Ok(Uring { ring, flags, probe });
+        }
+        Err(io::Error::from_raw_os_error(libc::EINVAL))
+    }
+}
+
+impl Backend for Uring {
+    unsafe fn push(&mut self, sqe: &Entry) -> bool {
+        self.ring.submission().push(sqe).is_ok()
+    }
+
+    fn sq_space(&mut self) -> usize {
+        let sq = self.ring.submission();
+        sq.capacity() - sq.len()
+    }
+
+    fn sq_len(&mut self) -> usize {
+        self.ring.submission().len()
+    }
+
+    fn supports(&mut self, opcode: u8) -> bool {
+        self.probe
+            .as_ref()
+            .is_some_and(|probe| probe.is_supported(opcode))
+    }
+
+    fn flags(&self) -> RingFlags {
+        self.flags
+    }
+
+    fn submit(&mut self) -> io::Result<usize> {
+        if !self.flags.defer_taskrun {
+            return self.ring.submit();
+        }
+        // deferred completions are only posted when asked for with GETEVENTS, which
+        // returns right away when none are wanted.
+        let len = self.ring.submission().len() as u32;
+        unsafe {
+            self.ring
+                .submitter()
+                .enter::<libc::sigset_t>(len, 0, IORING_ENTER_GETEVENTS, None)
+        }
+    }
+
+    fn submit_and_wait(&mut self, want: usize) -> io::Result<usize> {
+        self.ring.submit_and_wait(want)
+    }
+
+    fn submit_and_wait_timeout(&mut self, want: usize, timeout: Duration) -> io::Result<usize> {
+        // passing a timeout to io_uring_enter needs Linux 5.11.
+        if !self.ring.params().is_feature_ext_arg() {
+            return Err(Error::KernelFeatureMissing("IORING_FEAT_EXT_ARG").into());
+        }
+        let ts = Timespec::new()
+            .sec(timeout.as_secs())
+            .nsec(timeout.subsec_nanos());
+        let args = SubmitArgs::new().timespec(&ts);
+        self.ring.submitter().submit_with_args(want, &args)
+    }
+
+    fn taskrun(&mut self) -> bool {
+        self.ring.submission().taskrun()
+    }
+
+    fn has_completions(&mut self) -> bool {
+        !self.ring.completion().is_empty()
+    }
+
+    fn post_completions(&mut self) -> io::Result<()> {
+        // GETEVENTS runs task work and flushes the overflow list before it would wait.
+        unsafe {
+            self.ring
+                .submitter()
+                .enter::<libc::sigset_t>(0, 0, IORING_ENTER_GETEVENTS, None)?;
+        }
+        Ok(())
+    }
+
+    fn register_buf_ring(&mut self, ring_addr: u64, entries: u16, bgid: u16) -> io::Result<()> {
+        self.ring
+            .submitter()
+            .register_buf_ring(ring_addr, entries, bgid)
+    }
+
+    fn unregister_buf_ring(&mut self, bgid: u16) -> io::Result<()> {
+        self.ring.submitter().unregister_buf_ring(bgid)
+    }
+
+    fn register_buffers(&mut self, bufs: &[libc::iovec]) -> io::Result<()> {
+        self.ring.submitter().register_buffers(bufs)
+    }
+
+    fn unregister_buffers(&mut self) -> io::Result<()> {
+        self.ring.submitter().unregister_buffers()
+    }
+
+    fn register_files_sparse(&mut self, nr: u32) -> io::Result<()> {
+        self.ring.submitter().register_files_sparse(nr)
+    }
+
+    fn update_files(&mut self, offset: u32, fds: &[RawFd]) -> io::Result<usize> {
+        self.ring.submitter().register_files_update(offset, fds)
+    }
+
+    fn reap(&mut self, f: &mut dyn FnMut(u64, Cqe)) {
+        for cqe in self.ring.completion() {
+            f(
+                cqe.user_data(),
+                Cqe {
+                    result: cqe.result(),
+                    flags: cqe.flags(),
+                },
+            );
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use std::io;
+use std::ops::Deref;
+use std::os::unix::io::RawFd;
+
+use io_uring::opcode;
+
+use crate::driver::iobuf::{IoBuf, IoBufMut};
+use crate::driver::{Action, Completable};
+
+/// A buffer owned by a vectored read or write while the kernel uses it.
+///
+/// A write sends its contents, a read replaces them with up to its capacity of bytes.
+#[derive(Debug, Default, Clone)]
+pub struct IoSliceOwned {
+    buf: Vec<u8>,
+}
+
+impl IoSliceOwned {
+    pub fn new(buf: Vec<u8>) -> IoSliceOwned {
+        IoSliceOwned { buf }
+    }
+
+    /// An empty buffer a read fills with up to `capacity` bytes.
+    pub fn with_capacity(capacity: usize) -> IoSliceOwned {
+        IoSliceOwned::new(Vec::with_capacity(capacity))
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buf.capacity()
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+impl From<Vec<u8>> for IoSliceOwned {
+    fn from(buf: Vec<u8>) -> IoSliceOwned {
+        IoSliceOwned::new(buf)
+    }
+}
+
+impl Deref for IoSliceOwned {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
+unsafe impl IoBuf for IoSliceOwned {
+    fn stable_ptr(&self) -> *const u8 {
+        self.buf.stable_ptr()
+    }
+
+    fn bytes_init(&self) -> usize {
+        self.buf.bytes_init()
+    }
+
+    fn bytes_total(&self) -> usize {
+        self.buf.bytes_total()
+    }
+}
+
+unsafe impl IoBufMut for IoSliceOwned {
+    fn stable_mut_ptr(&mut self) -> *mut u8 {
+        self.buf.stable_mut_ptr()
+    }
+
+    unsafe fn set_init(&mut self, len: usize) {
+        self.buf.set_init(len)
+    }
+}
+
+/// Replaces the contents of `bufs` with `src`, filling each up to its capacity in
+/// order, and returns how many bytes were copied.
+pub(crate) fn fill(bufs: &mut [IoSliceOwned], mut src: &[u8]) -> usize {
+    let mut n = 0;
+    for buf in bufs {
+        buf.buf.clear();
+        let len = src.len().min(buf.capacity());
+        buf.buf.extend_from_slice(&src[..len]);
+        src = &src[len..];
+        n += len;
+    }
+    n
+}
+
+pub struct Readv {
+    iovecs: Vec<libc::iovec>,
+    bufs: Vec<IoSliceOwned>,
+}
+
+impl Completable for Readv {
+    type Output = usize;
+
+    fn complete(result: u32) -> usize {
+        result as usize
+    }
+}
+
+impl Action<Readv> {
+    /// Reads into `bufs` in order with a single `readv`, at `offset` for seekable files.
+    pub fn readv(
+        fd: RawFd,
+        mut bufs: Vec<IoSliceOwned>,
+        offset: Option<u64>,
+    ) -> io::Result<Action<Readv>> {
+        let iovecs: Vec<libc::iovec> = bufs
+            .iter_mut()
+            .map(|buf| {
+                buf.buf.clear();
+                libc::iovec {
+                    iov_base: buf.buf.as_mut_ptr() as *mut _,
+                    iov_len: buf.buf.capacity(),
+                }
+            })
+            .collect();
+        let entry = target!(fd, |fd| opcode::Readv::new(
+            fd,
+            iovecs.as_ptr(),
+            iovecs.len() as u32
+        )
+        .offset(offset.unwrap_or(0) as _)
+        .build());
+        Action::submit(Readv { iovecs, bufs }, entry)
+    }
+}
+
+impl Readv {
+    /// Takes the buffers back once the kernel filled `n` bytes of them.
+    pub fn filled(mut self, mut n: usize) -> Vec<IoSliceOwned> {
+        drop(self.iovecs);
+        for buf in &mut self.bufs {
+            let len = n.min(buf.capacity());
+            unsafe { buf.buf.set_len(len) };
+            n -= len;
+        }
+        self.bufs
+    }
+}
+
+pub struct WritevOwned {
+    iovecs: Vec<libc::iovec>,
+    bufs: Vec<IoSliceOwned>,
+}
+
+impl Completable for WritevOwned {
+    type Output = usize;
+
+    fn complete(result: u32) -> usize {
+        result as usize
+    }
+}
+
+impl Action<WritevOwned> {
+    /// Writes the contents of `bufs` in order with a single `writev`, at `offset` for
+    /// seekable files.
+    pub fn writev_owned(
+        fd: RawFd,
+        bufs: Vec<IoSliceOwned>,
+        offset: Option<u64>,
+    ) -> io::Result<Action<WritevOwned>> {
+        let iovecs: Vec<libc::iovec> = bufs
+            .iter()
+            .map(|buf| libc::iovec {
+                iov_base: buf.as_ptr() as *mut _,
+                iov_len: buf.len(),
+            })
+            .collect();
+        let entry = target!(fd, |fd| opcode::Writev::new(
+            fd,
+            iovecs.as_ptr(),
+            iovecs.len() as u32
+        )
+        .offset(offset.unwrap_or(0) as _)
+        .build());
+        Action::submit(WritevOwned { iovecs, bufs }, entry)
+    }
+}
+
+impl WritevOwned {
+    pub fn into_bufs(self) -> Vec<IoSliceOwned> {
+        drop(self.iovecs);
+        self.bufs
+    }
+}
@@ -0,0 +1,51 @@
+use std::io;
+use std::mem;
+
+use io_uring::opcode;
+use io_uring::squeue::Entry;
+
+use crate::driver::{Action, Completable};
+
+/// `IORING_OP_WAITID`, Linux 6.7. io-uring 0.5 has no builder for it.
+pub(crate) const OP_WAITID: u8 = 50;
+
+// `build` edits the entry as the bytes of an `io_uring_sqe`.
+const _: () = assert!(mem::size_of::<Entry>() == 64);
+
+pub struct Waitid {
+    /// Boxed, the kernel writes it once the child exited, after the action moved.
+    info: Box<libc::siginfo_t>,
+}
+
+impl Completable for Waitid {
+    type Output = ();
+
+    fn complete(_: u32) {}
+}
+
+impl Action<Waitid> {
+    /// Waits for the child `pid` to exit, leaving it to be reaped with `waitpid`. Needs
+    /// Linux 6.7, see [`Features::waitid`](crate::driver::Features::waitid).
+    pub fn waitid(pid: u32) -> io::Result<Action<Waitid>> {
+        let mut waitid = Waitid {
+            info: Box::new(unsafe { mem::zeroed() }),
+        };
+        let info = &mut *waitid.info as *mut libc::siginfo_t as u64;
+        let options = (libc::WEXITED | libc::WNOWAIT) as u32;
+        let entry = build(pid as i32, libc::P_PID, info, options);
+        Action::submit(waitid, entry)
+    }
+}
+
+fn build(id: i32, idtype: libc::idtype_t, info: u64, options: u32) -> Entry {
+    // `Entry` is a `repr(C)` wrapper of `io_uring_sqe`, which holds the id at offset 4,
+    // the siginfo address at offset 8, the id type at offset 24 and the options at
+    // offset 44.
+    let mut sqe: [u8; 64] = unsafe { mem::transmute(opcode::Nop::new().build()) };
+    sqe[0] = OP_WAITID;
+    sqe[4..8].copy_from_slice(&id.to_ne_bytes());
+    sqe[8..16].copy_from_slice(&info.to_ne_bytes());
+    sqe[24..28].copy_from_slice(&idtype.to_ne_bytes());
+    sqe[44..48].copy_from_slice(&options.to_ne_bytes());
+    unsafe { mem::transmute(sqe) }
+}
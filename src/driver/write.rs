@@ -3,27 +3,111 @@ use std::io;
 use std::os::unix::io::RawFd;
 use std::pin::Pin;
 use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 
-use io_uring::{opcode, types};
+use io_uring::opcode;
 
-use crate::driver::Action;
+use crate::driver::{Action, Completable};
 
 pub struct Write {
-    _buf: Vec<u8>,
+    fd: RawFd,
+    buf: Vec<u8>,
+    pos: usize,
+    /// File offset of `buf[0]`, `None` for sockets and pipes.
+    offset: Option<u64>,
+    /// When a write with a timeout is cancelled, short writes are resubmitted with the
+    /// time that is left.
+    deadline: Option<Instant>,
+}
+
+impl Completable for Write {
+    type Output = usize;
+
+    fn complete(result: u32) -> usize {
+        result as usize
+    }
 }
 
 impl Action<Write> {
     pub fn write(fd: RawFd, buf: &[u8]) -> io::Result<Action<Write>> {
-        let buf = buf.to_vec();
-        let ptr = buf.as_ptr();
-        let len = buf.len() as u32;
-        let entry = opcode::Write::new(types::Fd(fd), ptr, len).build();
-        Action::submit(Write { _buf: buf }, entry)
+        Action::write_from(fd, buf.to_vec(), 0, None, None)
+    }
+
+    /// Like `write`, cancelled by the kernel once `timeout` elapsed.
+    pub fn write_timeout(fd: RawFd, buf: &[u8], timeout: Duration) -> io::Result<Action<Write>> {
+        let deadline = Instant::now() + timeout;
+        Action::write_from(fd, buf.to_vec(), 0, None, Some(deadline))
+    }
+
+    /// Writes `buf` starting at `offset` of a seekable file.
+    pub fn write_at(fd: RawFd, buf: &[u8], offset: u64) -> io::Result<Action<Write>> {
+        Action::write_from(fd, buf.to_vec(), 0, Some(offset), None)
     }
 
+    fn write_from(
+        fd: RawFd,
+        buf: Vec<u8>,
+        pos: usize,
+        offset: Option<u64>,
+        deadline: Option<Instant>,
+    ) -> io::Result<Action<Write>> {
+        let ptr = buf[pos..].as_ptr();
+        let len = (buf.len() - pos) as u32;
+        let entry = target!(fd, |fd| {
+            let mut entry = opcode::Write::new(fd, ptr, len);
+            if let Some(offset) = offset {
+                entry = entry.offset((offset + pos as u64) as _);
+            }
+            entry.build()
+        });
+        let timeout = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        Action::submit_maybe_timeout(
+            Write {
+                fd,
+                buf,
+                pos,
+                offset,
+                deadline,
+            },
+            entry,
+            timeout,
+        )
+    }
+
+    /// The bytes handed to this action.
+    pub(crate) fn bytes(&self) -> &[u8] {
+        self.action.as_ref().map_or(&[], |write| &write.buf)
+    }
+
+    /// Resolves once every byte handed to this action is written, a short write is
+    /// resubmitted for the remainder.
     pub(crate) fn poll_write(&mut self, cx: &mut Context) -> Poll<io::Result<usize>> {
-        let complete = ready!(Pin::new(self).poll(cx));
-        let n = complete.result? as usize;
-        Poll::Ready(Ok(n))
+        self.poll_write_until(cx, false)
+    }
+
+    /// Like `poll_write`, failing unless every byte was written. For bytes already
+    /// reported as written, which nobody is left to write the rest of.
+    pub(crate) fn poll_write_all(&mut self, cx: &mut Context) -> Poll<io::Result<usize>> {
+        self.poll_write_until(cx, true)
+    }
+
+    fn poll_write_until(&mut self, cx: &mut Context, all: bool) -> Poll<io::Result<usize>> {
+        loop {
+            let (res, mut write) = ready!(Pin::new(&mut *self).poll(cx)).into_parts();
+            let n = match res {
+                Ok(0) if all => return Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+                Ok(n) => n,
+                // report the progress made so far, writing the rest runs into the error
+                // again.
+                Err(_) if write.pos > 0 && !all => return Poll::Ready(Ok(write.pos)),
+                Err(e) => return Poll::Ready(Err(e)),
+            };
+            write.pos += n;
+            if n == 0 || write.pos == write.buf.len() {
+                return Poll::Ready(Ok(write.pos));
+            }
+            *self =
+                Action::write_from(write.fd, write.buf, write.pos, write.offset, write.deadline)?;
+        }
     }
 }
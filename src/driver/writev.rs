@@ -0,0 +1,50 @@
+use std::io;
+use std::os::unix::io::RawFd;
+
+use io_uring::opcode;
+
+use crate::driver::chain::Segment;
+use crate::driver::{Action, Completable};
+
+pub struct Writev {
+    iovecs: Vec<libc::iovec>,
+    segments: Vec<Segment>,
+}
+
+impl Completable for Writev {
+    type Output = usize;
+
+    fn complete(result: u32) -> usize {
+        result as usize
+    }
+}
+
+impl Action<Writev> {
+    /// Writes `segments` in order with a single `writev`.
+    pub fn writev(fd: RawFd, segments: Vec<Segment>) -> io::Result<Action<Writev>> {
+        let iovecs: Vec<libc::iovec> = segments
+            .iter()
+            .map(|segment| {
+                let slice = segment.as_slice();
+                libc::iovec {
+                    iov_base: slice.as_ptr() as *mut _,
+                    iov_len: slice.len(),
+                }
+            })
+            .collect();
+        let entry = target!(fd, |fd| opcode::Writev::new(
+            fd,
+            iovecs.as_ptr(),
+            iovecs.len() as u32
+        )
+        .build());
+        Action::submit(Writev { iovecs, segments }, entry)
+    }
+}
+
+impl Writev {
+    pub fn into_segments(self) -> Vec<Segment> {
+        drop(self.iovecs);
+        self.segments
+    }
+}
@@ -0,0 +1,72 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+/// Errors produced by the runtime itself, as opposed to the operation it performed.
+///
+/// Every public API still returns `io::Result`, an `Error` travels inside the returned
+/// `io::Error` and can be recovered with [`Error::from_io`].
+#[derive(Debug)]
+pub enum Error {
+    /// The running kernel lacks an io_uring feature the runtime depends on.
+    KernelFeatureMissing(&'static str),
+    /// The submission queue had no room left for another entry.
+    RingFull,
+    /// The operation was cancelled before it completed.
+    Cancelled,
+    /// An error reported by the operating system.
+    Os(io::Error),
+}
+
+impl Error {
+    /// Returns the runtime error carried by `err`, if any.
+    pub fn from_io(err: &io::Error) -> Option<&Error> {
+        err.get_ref().and_then(|e| e.downcast_ref::<Error>())
+    }
+
+    /// The kind of the `io::Error` carrying this error. `Interrupted` and `WouldBlock`
+    /// are avoided, callers retry on the first and wait for readiness on the second, so
+    /// a cancelled operation would be started again and a full ring waited on forever.
+    fn kind(&self) -> io::ErrorKind {
+        match self {
+            Error::KernelFeatureMissing(_) => io::ErrorKind::Unsupported,
+            Error::RingFull | Error::Cancelled => io::ErrorKind::Other,
+            Error::Os(err) => err.kind(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::KernelFeatureMissing(feature) => write!(fmt, "{} not supported", feature),
+            Error::RingFull => "submission queue is full".fmt(fmt),
+            Error::Cancelled => "operation cancelled".fmt(fmt),
+            Error::Os(err) => err.fmt(fmt),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Os(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Os(err)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> io::Error {
+        match err {
+            Error::Os(err) => err,
+            err => io::Error::new(err.kind(), err),
+        }
+    }
+}
@@ -0,0 +1,417 @@
+use std::fs;
+use std::io::{self, SeekFrom};
+use std::os::unix::io::{AsRawFd, IntoRawFd, RawFd};
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_util::future::poll_fn;
+use futures_util::io::{AsyncRead, AsyncSeek, AsyncWrite};
+
+use crate::buf::{FixedBuf, IoBuf, IoBufMut, IoSliceOwned};
+use crate::driver::files::FixedFile;
+use crate::driver::{self, Action};
+use crate::fs::Metadata;
+
+/// A file opened through the ring.
+///
+/// `read_at` and `write_at` take an explicit offset. The `AsyncRead`, `AsyncWrite` and
+/// `AsyncSeek` impls share a cursor kept in userspace, it only advances by the bytes
+/// handed back to the caller. A read or write future that is dropped before completion
+/// cancels the operation, its buffer stays owned by the runtime until the kernel is done
+/// with it.
+pub struct File {
+    /// Declared before `inner` so the slot is cleared before the file is closed.
+    fixed: Option<FixedFile>,
+    inner: fs::File,
+    pos: u64,
+    read: Option<Action<driver::Read>>,
+    write: Option<Write>,
+}
+
+struct Write {
+    action: Action<driver::Write>,
+    /// Address and length of the buffer the write was started for.
+    src: (*const u8, usize),
+}
+
+impl AsRawFd for File {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+impl File {
+    /// Opens a file in read-only mode.
+    pub async fn open<P: AsRef<Path>>(path: P) -> io::Result<File> {
+        File::open_with(path.as_ref(), libc::O_RDONLY, 0).await
+    }
+
+    /// Opens a file in write-only mode, creating it if needed and truncating it
+    /// otherwise.
+    pub async fn create<P: AsRef<Path>>(path: P) -> io::Result<File> {
+        let flags = libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC;
+        File::open_with(path.as_ref(), flags, 0o666).await
+    }
+
+    async fn open_with(path: &Path, flags: libc::c_int, mode: libc::mode_t) -> io::Result<File> {
+        let fd = Action::open(path, flags, mode)?.await.output()?;
+        Ok(File::from_std(fs::File::from(fd)))
+    }
+
+    pub fn from_std(file: fs::File) -> File {
+        File {
+            fixed: None,
+            inner: file,
+            pos: 0,
+            read: None,
+            write: None,
+        }
+    }
+
+    /// Registers the file with the runtime's fixed file table, so operations on it refer
+    /// to it by slot and skip the fd lookup. It is unregistered when the file is dropped
+    /// or closed.
+    pub fn register_fd(&mut self) -> io::Result<()> {
+        if self.fixed.is_none() {
+            self.fixed = Some(FixedFile::register(self.as_raw_fd())?);
+        }
+        Ok(())
+    }
+
+    /// Reads some bytes at `pos` into `buf`, returning how many were read. `Ok(0)` means
+    /// `pos` is at or past the end of the file.
+    pub async fn read_at(&self, buf: &mut [u8], pos: u64) -> io::Result<usize> {
+        let mut action = Action::read_at(self.as_raw_fd(), buf.len() as u32, pos)?;
+        let src = poll_fn(|cx| action.poll_read(cx)).await?;
+        let n = src.len().min(buf.len());
+        buf[..n].copy_from_slice(&src[..n]);
+        Ok(n)
+    }
+
+    /// Writes `buf` at `pos`, returning how many bytes were written.
+    pub async fn write_at(&self, buf: &[u8], pos: u64) -> io::Result<usize> {
+        let mut action = Action::write_at(self.as_raw_fd(), buf, pos)?;
+        poll_fn(|cx| action.poll_write(cx)).await
+    }
+
+    /// Reads at `pos` into `buf` up to its capacity, replacing its contents, and returns
+    /// how many bytes were read together with the buffer, which the read owns until the
+    /// kernel is done with it.
+    pub async fn read_buf_at<B: IoBufMut>(&self, buf: B, pos: u64) -> (io::Result<usize>, B) {
+        let action = match Action::read_buf(self.as_raw_fd(), buf, Some(pos)) {
+            Ok(action) => action,
+            Err((e, buf)) => return (Err(e), buf),
+        };
+        let (n, read) = action.await.into_parts();
+        match n {
+            Ok(n) => (Ok(n), read.filled(n)),
+            Err(e) => (Err(e), read.into_buf()),
+        }
+    }
+
+    /// Writes the contents of `buf` at `pos`, returning how many bytes were written
+    /// together with the buffer, which the write owns until the kernel is done with it.
+    pub async fn write_buf_at<B: IoBuf>(&self, buf: B, pos: u64) -> (io::Result<usize>, B) {
+        let action = match Action::write_buf(self.as_raw_fd(), buf, Some(pos)) {
+            Ok(action) => action,
+            Err((e, buf)) => return (Err(e), buf),
+        };
+        let (n, write) = action.await.into_parts();
+        (n, write.into_buf())
+    }
+
+    /// Reads at `pos` into `buf`, up to its capacity and replacing its contents. The
+    /// length of the returned buffer is how many bytes were read. On error the buffer
+    /// goes back to its registry.
+    pub async fn read_fixed_at(&self, buf: FixedBuf, pos: u64) -> io::Result<FixedBuf> {
+        let completion = Action::read_fixed_at(self.as_raw_fd(), buf, pos)?.await;
+        let (n, buf) = completion.into_parts();
+        Ok(buf.filled(n?))
+    }
+
+    /// Writes the contents of `buf` at `pos`, returning how many bytes were written
+    /// together with the buffer.
+    pub async fn write_fixed_at(&self, buf: FixedBuf, pos: u64) -> io::Result<(usize, FixedBuf)> {
+        let completion = Action::write_fixed(self.as_raw_fd(), buf, Some(pos))?.await;
+        let (n, buf) = completion.into_parts();
+        Ok((n?, buf.into_buf()))
+    }
+
+    /// Reads into `bufs` in order at the cursor with a single `readv`, replacing their
+    /// contents, and returns how many bytes were read together with the buffers.
+    pub async fn read_vectored(
+        &mut self,
+        bufs: Vec<IoSliceOwned>,
+    ) -> io::Result<(usize, Vec<IoSliceOwned>)> {
+        poll_fn(|cx| self.poll_flush_write(cx)).await?;
+        self.read = None;
+        let (n, readv) = Action::readv(self.as_raw_fd(), bufs, Some(self.pos))?
+            .await
+            .into_parts();
+        let n = n?;
+        self.pos += n as u64;
+        Ok((n, readv.filled(n)))
+    }
+
+    /// Writes the contents of `bufs` in order at the cursor with a single `writev`,
+    /// returning how many bytes were written together with the buffers.
+    pub async fn write_vectored(
+        &mut self,
+        bufs: Vec<IoSliceOwned>,
+    ) -> io::Result<(usize, Vec<IoSliceOwned>)> {
+        poll_fn(|cx| self.poll_flush_write(cx)).await?;
+        self.read = None;
+        let (n, writev) = Action::writev_owned(self.as_raw_fd(), bufs, Some(self.pos))?
+            .await
+            .into_parts();
+        let n = n?;
+        self.pos += n as u64;
+        Ok((n, writev.into_bufs()))
+    }
+
+    /// Flushes data and metadata to the device.
+    pub async fn sync_all(&self) -> io::Result<()> {
+        Action::fsync(self.as_raw_fd(), false)?.await.output()
+    }
+
+    /// Flushes data to the device, metadata is only flushed if needed to read the data back.
+    pub async fn sync_data(&self) -> io::Result<()> {
+        Action::fsync(self.as_raw_fd(), true)?.await.output()
+    }
+
+    /// Allocates `len` bytes of disk space from `offset`, so writes there do not fail
+    /// for lack of space. `mode` takes the `FALLOC_FL_*` flags of `fallocate(2)`, with 0
+    /// the file grows to cover the range.
+    pub async fn allocate(&self, offset: u64, len: u64, mode: i32) -> io::Result<()> {
+        Action::fallocate(self.as_raw_fd(), offset, len, mode)?
+            .await
+            .output()
+    }
+
+    /// Truncates or extends the file to `len` bytes, an extension reads as zeros.
+    pub async fn set_len(&self, len: u64) -> io::Result<()> {
+        if !super::supported(|features| features.ftruncate) {
+            let file = self.inner.try_clone()?;
+            return super::run_blocking(move || file.set_len(len)).await;
+        }
+        Action::ftruncate(self.as_raw_fd(), len)?.await.output()
+    }
+
+    /// Tells the kernel how `len` bytes from `offset` will be accessed, so it can read
+    /// ahead or drop them from the page cache. A `len` of 0 covers the rest of the file.
+    pub async fn advise(&self, offset: u64, len: u32, advice: Advice) -> io::Result<()> {
+        Action::fadvise(self.as_raw_fd(), offset, len, advice as i32)?
+            .await
+            .output()
+    }
+
+    /// Reads the metadata of the file.
+    pub async fn metadata(&self) -> io::Result<Metadata> {
+        Metadata::read(Action::statx_fd(self.as_raw_fd())?).await
+    }
+
+    /// Closes the file, reporting errors that dropping it would ignore.
+    pub async fn close(mut self) -> io::Result<()> {
+        poll_fn(|cx| self.poll_flush_write(cx)).await?;
+        // a registered slot would keep the file open past the close.
+        self.fixed = None;
+        let fd = self.inner.into_raw_fd();
+        Action::close(fd)?.await.output()
+    }
+
+    /// Drives a write left behind by a dropped future to completion.
+    fn poll_flush_write(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        if let Some(write) = &mut self.write {
+            let res = ready!(write.action.poll_write(cx));
+            self.write = None;
+            self.pos += res? as u64;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncRead for File {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let me = self.get_mut();
+        ready!(me.poll_flush_write(cx))?;
+        let fd = me.inner.as_raw_fd();
+        let action = match &mut me.read {
+            Some(action) => action,
+            None => me
+                .read
+                .get_or_insert(Action::read_at(fd, buf.len() as u32, me.pos)?),
+        };
+        let res = ready!(action.poll_read(cx));
+        me.read = None;
+        // a read started by a dropped future may have been larger, the rest is read
+        // again from the file later.
+        let src = res?;
+        let n = src.len().min(buf.len());
+        buf[..n].copy_from_slice(&src[..n]);
+        me.pos += n as u64;
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl AsyncWrite for File {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let me = self.get_mut();
+        me.read = None;
+        loop {
+            match &mut me.write {
+                None => {
+                    let action = Action::write_at(me.inner.as_raw_fd(), buf, me.pos)?;
+                    me.write = Some(Write {
+                        action,
+                        src: (buf.as_ptr(), buf.len()),
+                    });
+                }
+                Some(write) => {
+                    let res = ready!(write.action.poll_write(cx));
+                    let owned = write.src == (buf.as_ptr(), buf.len());
+                    me.write = None;
+                    let n = res?;
+                    me.pos += n as u64;
+                    if owned {
+                        return Poll::Ready(Ok(n));
+                    }
+                }
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        self.get_mut().poll_flush_write(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        self.get_mut().poll_flush_write(cx)
+    }
+}
+
+impl AsyncSeek for File {
+    fn poll_seek(self: Pin<&mut Self>, cx: &mut Context, pos: SeekFrom) -> Poll<io::Result<u64>> {
+        let me = self.get_mut();
+        ready!(me.poll_flush_write(cx))?;
+        me.read = None;
+        let (base, offset) = match pos {
+            SeekFrom::Start(n) => (n, 0),
+            SeekFrom::Current(n) => (me.pos, n),
+            SeekFrom::End(n) => (me.inner.metadata()?.len(), n),
+        };
+        me.pos = match base.checked_add_signed(offset) {
+            Some(pos) => pos,
+            None => {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "invalid seek to a negative or overflowing position",
+                )))
+            }
+        };
+        Poll::Ready(Ok(me.pos))
+    }
+}
+
+/// How a range of a file will be accessed, see [`File::advise`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Advice {
+    /// No particular pattern, the default.
+    Normal = libc::POSIX_FADV_NORMAL as isize,
+    /// In order, the kernel reads further ahead.
+    Sequential = libc::POSIX_FADV_SEQUENTIAL as isize,
+    /// In no order, the kernel does not read ahead.
+    Random = libc::POSIX_FADV_RANDOM as isize,
+    /// Once.
+    NoReuse = libc::POSIX_FADV_NOREUSE as isize,
+    /// Soon, the kernel starts reading it into the page cache.
+    WillNeed = libc::POSIX_FADV_WILLNEED as isize,
+    /// Not again soon, the kernel drops its clean pages from the page cache.
+    DontNeed = libc::POSIX_FADV_DONTNEED as isize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Runtime;
+    use futures_util::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("cptio-{}-{}", std::process::id(), name))
+    }
+
+    fn read_write(path: &Path) -> File {
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .unwrap();
+        File::from_std(file)
+    }
+
+    #[test]
+    fn positional_writes_read_back_and_truncation_extends_with_zeros() {
+        let path = temp_path("file-positional");
+        Runtime::new().unwrap().block_on(async {
+            let file = read_write(&path);
+            assert_eq!(file.write_at(b"hello", 0).await.unwrap(), 5);
+            assert_eq!(file.write_at(b"world", 5).await.unwrap(), 5);
+            let mut buf = [0; 10];
+            assert_eq!(file.read_at(&mut buf, 0).await.unwrap(), 10);
+            assert_eq!(&buf, b"helloworld");
+            assert_eq!(file.read_at(&mut buf, 10).await.unwrap(), 0);
+
+            file.set_len(16).await.unwrap();
+            assert_eq!(file.metadata().await.unwrap().len(), 16);
+            let mut buf = [1; 6];
+            assert_eq!(file.read_at(&mut buf, 10).await.unwrap(), 6);
+            assert_eq!(buf, [0; 6]);
+            file.set_len(5).await.unwrap();
+            assert_eq!(file.metadata().await.unwrap().len(), 5);
+            file.close().await.unwrap();
+        });
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn reads_and_writes_move_the_cursor() {
+        let path = temp_path("file-cursor");
+        Runtime::new().unwrap().block_on(async {
+            let mut file = read_write(&path);
+            file.write_all(b"0123456789").await.unwrap();
+            file.flush().await.unwrap();
+            assert_eq!(file.seek(SeekFrom::Current(0)).await.unwrap(), 10);
+
+            assert_eq!(file.seek(SeekFrom::Start(2)).await.unwrap(), 2);
+            let mut buf = [0; 3];
+            file.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"234");
+            assert_eq!(file.seek(SeekFrom::End(-2)).await.unwrap(), 8);
+            let mut rest = Vec::new();
+            file.read_to_end(&mut rest).await.unwrap();
+            assert_eq!(rest, b"89");
+            let err = file.seek(SeekFrom::Current(-11)).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            file.close().await.unwrap();
+
+            // a created file is write-only, one opened for reading starts at the
+            // beginning.
+            let mut file = File::create(&path).await.unwrap();
+            file.write_all(b"0123456789").await.unwrap();
+            let mut buf = [0; 1];
+            assert!(file.read_at(&mut buf, 0).await.is_err());
+            file.close().await.unwrap();
+            let mut file = File::open(&path).await.unwrap();
+            let mut all = String::new();
+            file.read_to_string(&mut all).await.unwrap();
+            assert_eq!(all, "0123456789");
+        });
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,175 @@
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::driver::{Action, Statx};
+
+/// What `statx` reported about a file, see [`metadata`](super::metadata).
+#[derive(Clone, Copy)]
+pub struct Metadata {
+    statx: libc::statx,
+}
+
+/// The type of a file, see [`Metadata::file_type`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileType {
+    mode: u32,
+}
+
+/// Reads the metadata of the file at `path`, following symbolic links.
+pub async fn metadata<P: AsRef<Path>>(path: P) -> io::Result<Metadata> {
+    Metadata::read(Action::statx(path.as_ref(), true)?).await
+}
+
+/// Reads the metadata of the file at `path`, of the link itself if it is a symbolic
+/// link.
+pub async fn symlink_metadata<P: AsRef<Path>>(path: P) -> io::Result<Metadata> {
+    Metadata::read(Action::statx(path.as_ref(), false)?).await
+}
+
+impl Metadata {
+    pub(crate) async fn read(action: Action<Statx>) -> io::Result<Metadata> {
+        let (res, statx) = action.await.into_parts();
+        res?;
+        Ok(Metadata {
+            statx: statx.into_inner(),
+        })
+    }
+
+    pub fn file_type(&self) -> FileType {
+        FileType::new(self.mode())
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.file_type().is_dir()
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.file_type().is_file()
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.file_type().is_symlink()
+    }
+
+    /// Size of the file in bytes.
+    pub fn len(&self) -> u64 {
+        self.statx.stx_size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The type and permission bits of the file, as `st_mode`.
+    pub fn mode(&self) -> u32 {
+        self.statx.stx_mode as u32
+    }
+
+    pub fn uid(&self) -> u32 {
+        self.statx.stx_uid
+    }
+
+    pub fn gid(&self) -> u32 {
+        self.statx.stx_gid
+    }
+
+    pub fn nlink(&self) -> u64 {
+        self.statx.stx_nlink as u64
+    }
+
+    pub fn ino(&self) -> u64 {
+        self.statx.stx_ino
+    }
+
+    /// Blocks of 512 bytes allocated to the file.
+    pub fn blocks(&self) -> u64 {
+        self.statx.stx_blocks
+    }
+
+    /// When the file's contents were last changed.
+    pub fn modified(&self) -> io::Result<SystemTime> {
+        self.time(libc::STATX_MTIME, self.statx.stx_mtime)
+    }
+
+    /// When the file was last read.
+    pub fn accessed(&self) -> io::Result<SystemTime> {
+        self.time(libc::STATX_ATIME, self.statx.stx_atime)
+    }
+
+    /// When the file's metadata was last changed.
+    pub fn changed(&self) -> io::Result<SystemTime> {
+        self.time(libc::STATX_CTIME, self.statx.stx_ctime)
+    }
+
+    /// When the file was created. `Unsupported` where the filesystem does not keep it.
+    pub fn created(&self) -> io::Result<SystemTime> {
+        self.time(libc::STATX_BTIME, self.statx.stx_btime)
+    }
+
+    /// The time in `timestamp`, if the kernel set `field` in the mask of the fields it
+    /// filled in.
+    fn time(&self, field: u32, timestamp: libc::statx_timestamp) -> io::Result<SystemTime> {
+        if self.statx.stx_mask & field == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "time not available on this filesystem",
+            ));
+        }
+        let nanos = Duration::new(0, timestamp.tv_nsec);
+        let time = match timestamp.tv_sec {
+            secs if secs >= 0 => UNIX_EPOCH + Duration::from_secs(secs as u64) + nanos,
+            secs => UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs()) + nanos,
+        };
+        Ok(time)
+    }
+}
+
+impl fmt::Debug for Metadata {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("Metadata")
+            .field("file_type", &self.file_type())
+            .field("len", &self.len())
+            .field("mode", &format_args!("{:o}", self.mode()))
+            .field("modified", &self.modified())
+            .finish_non_exhaustive()
+    }
+}
+
+impl FileType {
+    /// The type the `S_IFMT` bits of `mode` tell.
+    pub(crate) fn new(mode: u32) -> FileType {
+        FileType {
+            mode: mode & libc::S_IFMT,
+        }
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.mode == libc::S_IFDIR
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.mode == libc::S_IFREG
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.mode == libc::S_IFLNK
+    }
+
+    pub fn is_fifo(&self) -> bool {
+        self.mode == libc::S_IFIFO
+    }
+
+    pub fn is_socket(&self) -> bool {
+        self.mode == libc::S_IFSOCK
+    }
+
+    pub fn is_block_device(&self) -> bool {
+        self.mode == libc::S_IFBLK
+    }
+
+    pub fn is_char_device(&self) -> bool {
+        self.mode == libc::S_IFCHR
+    }
+}
@@ -0,0 +1,213 @@
+pub mod file;
+mod metadata;
+mod read_dir;
+
+use std::fs;
+use std::io;
+use std::panic;
+use std::path::Path;
+
+use crate::blocking;
+use crate::driver::{self, Action, Features};
+
+pub use file::{Advice, File};
+pub use metadata::{metadata, symlink_metadata, FileType, Metadata};
+pub use read_dir::{read_dir, DirEntry, ReadDir};
+
+/// Creates a directory at `path`, its parent has to exist.
+pub async fn create_dir<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let path = path.as_ref();
+    if !supported(|features| features.mkdir) {
+        let path = path.to_owned();
+        return run_blocking(move || fs::create_dir(path)).await;
+    }
+    Action::mkdir(path, 0o777)?.await.output()
+}
+
+/// Creates a directory at `path` along with the parents it is missing. A directory
+/// already there is fine.
+pub async fn create_dir_all<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let path = path.as_ref();
+    // the directories to create, deepest first.
+    let mut missing = Vec::new();
+    for dir in path.ancestors() {
+        if dir.as_os_str().is_empty() {
+            break;
+        }
+        match create_dir(dir).await {
+            Ok(()) => break,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => missing.push(dir),
+            Err(e) => {
+                exists(dir, e).await?;
+                break;
+            }
+        }
+    }
+    for dir in missing.into_iter().rev() {
+        if let Err(e) = create_dir(dir).await {
+            // created by someone else meanwhile.
+            exists(dir, e).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Succeeds if `dir` failed to be created because a directory is there already,
+/// returns `err` otherwise.
+async fn exists(dir: &Path, err: io::Error) -> io::Result<()> {
+    match metadata(dir).await {
+        Ok(metadata) if metadata.is_dir() => Ok(()),
+        _ => Err(err),
+    }
+}
+
+/// Removes the file at `path`, or the link if it is a symbolic link.
+pub async fn remove_file<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let path = path.as_ref();
+    if !supported(|features| features.unlink) {
+        let path = path.to_owned();
+        return run_blocking(move || fs::remove_file(path)).await;
+    }
+    Action::unlink(path, false)?.await.output()
+}
+
+/// Removes the directory at `path`, which has to be empty.
+pub async fn remove_dir<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let path = path.as_ref();
+    if !supported(|features| features.unlink) {
+        let path = path.to_owned();
+        return run_blocking(move || fs::remove_dir(path)).await;
+    }
+    Action::unlink(path, true)?.await.output()
+}
+
+/// Renames `from` to `to`, replacing what `to` names if it exists.
+pub async fn rename<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> io::Result<()> {
+    let (from, to) = (from.as_ref(), to.as_ref());
+    if !supported(|features| features.unlink) {
+        let (from, to) = (from.to_owned(), to.to_owned());
+        return run_blocking(move || fs::rename(from, to)).await;
+    }
+    Action::rename(from, to)?.await.output()
+}
+
+/// Creates a symbolic link at `link` pointing to `original`.
+pub async fn symlink<P: AsRef<Path>, Q: AsRef<Path>>(original: P, link: Q) -> io::Result<()> {
+    let (original, link) = (original.as_ref(), link.as_ref());
+    if !supported(|features| features.mkdir) {
+        let (original, link) = (original.to_owned(), link.to_owned());
+        return run_blocking(move || std::os::unix::fs::symlink(original, link)).await;
+    }
+    Action::symlink(original, link)?.await.output()
+}
+
+/// Creates a hard link at `link` to the file at `original`.
+pub async fn hard_link<P: AsRef<Path>, Q: AsRef<Path>>(original: P, link: Q) -> io::Result<()> {
+    let (original, link) = (original.as_ref(), link.as_ref());
+    if !supported(|features| features.mkdir) {
+        let (original, link) = (original.to_owned(), link.to_owned());
+        return run_blocking(move || fs::hard_link(original, link)).await;
+    }
+    Action::link(original, link)?.await.output()
+}
+
+/// Whether the kernel of the current runtime has the opcodes `feature` picks.
+fn supported(feature: impl FnOnce(&Features) -> bool) -> bool {
+    driver::features().is_some_and(|features| feature(&features))
+}
+
+/// Runs `f` on the blocking pool, for kernels without the opcode.
+async fn run_blocking<F>(f: F) -> io::Result<()>
+where
+    F: FnOnce() -> io::Result<()> + Send + 'static,
+{
+    match blocking::run(f).await {
+        Ok(res) => res,
+        Err(panic) => panic::resume_unwind(panic),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Runtime;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("cptio-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn directories_are_created_listed_and_removed() {
+        let root = temp_path("fs-dirs");
+        // left behind by a run that failed.
+        let _ = fs::remove_dir_all(&root);
+        Runtime::new().unwrap().block_on(async {
+            let deep = root.join("a/b/c");
+            create_dir_all(&deep).await.unwrap();
+            assert!(metadata(&deep).await.unwrap().is_dir());
+            // a directory already there is fine for create_dir_all only.
+            create_dir_all(&deep).await.unwrap();
+            let err = create_dir(&deep).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+            let err = create_dir(root.join("x/y")).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+            // a file in the way is not.
+            let file = root.join("a/file");
+            fs::write(&file, b"data").unwrap();
+            assert!(create_dir_all(file.join("d")).await.is_err());
+            assert!(create_dir_all(&file).await.is_err());
+
+            let mut names = Vec::new();
+            let mut entries = read_dir(root.join("a")).await.unwrap();
+            while let Some(entry) = entries.next_entry().await.unwrap() {
+                let is_dir = entry.file_type().await.unwrap().is_dir();
+                names.push((entry.file_name().into_string().unwrap(), is_dir));
+            }
+            names.sort();
+            assert_eq!(names, [("b".to_owned(), true), ("file".to_owned(), false)]);
+
+            let err = remove_dir(root.join("a/b")).await.unwrap_err();
+            assert_eq!(err.raw_os_error(), Some(libc::ENOTEMPTY));
+            remove_dir(&deep).await.unwrap();
+            remove_dir(root.join("a/b")).await.unwrap();
+            remove_file(&file).await.unwrap();
+            assert!(metadata(&file).await.is_err());
+        });
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn files_are_renamed_and_linked() {
+        let root = temp_path("fs-links");
+        let _ = fs::remove_dir_all(&root);
+        Runtime::new().unwrap().block_on(async {
+            create_dir(&root).await.unwrap();
+            let original = root.join("original");
+            fs::write(&original, b"data").unwrap();
+
+            let renamed = root.join("renamed");
+            rename(&original, &renamed).await.unwrap();
+            assert!(metadata(&original).await.is_err());
+            assert_eq!(fs::read(&renamed).unwrap(), b"data");
+
+            let hard = root.join("hard");
+            hard_link(&renamed, &hard).await.unwrap();
+            assert_eq!(metadata(&renamed).await.unwrap().nlink(), 2);
+            assert_eq!(
+                metadata(&hard).await.unwrap().ino(),
+                metadata(&renamed).await.unwrap().ino()
+            );
+
+            let soft = root.join("soft");
+            symlink("renamed", &soft).await.unwrap();
+            assert!(symlink_metadata(&soft).await.unwrap().is_symlink());
+            assert_eq!(fs::read(&soft).unwrap(), b"data");
+            // removing the link leaves the file.
+            remove_file(&soft).await.unwrap();
+            assert!(metadata(&renamed).await.unwrap().is_file());
+        });
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,204 @@
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::ffi::{CStr, OsStr, OsString};
+use std::future::Future;
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, OwnedFd};
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures_util::future::poll_fn;
+use futures_util::stream::Stream;
+
+use crate::blocking::{self, Blocking};
+use crate::driver::Action;
+use crate::fs::{symlink_metadata, FileType, Metadata};
+
+/// Bytes of directory entries read with one `getdents64`.
+const BUF_SIZE: usize = 32 * 1024;
+
+/// Opens the directory at `path` to list its entries, see [`ReadDir`].
+pub async fn read_dir<P: AsRef<Path>>(path: P) -> io::Result<ReadDir> {
+    let path = path.as_ref();
+    let flags = libc::O_RDONLY | libc::O_DIRECTORY;
+    let fd = Action::open(path, flags, 0)?.await.output()?;
+    Ok(ReadDir {
+        dir: path.into(),
+        entries: VecDeque::new(),
+        state: State::Idle(Box::new(Dents {
+            fd,
+            buf: vec![0; BUF_SIZE],
+        })),
+    })
+}
+
+/// The entries of a directory, as a stream.
+///
+/// `getdents64` has no opcode, so the entries are read in batches on the blocking pool,
+/// whose results wake the runtime through the driver. `.` and `..` are left out, the
+/// order is the filesystem's.
+pub struct ReadDir {
+    dir: Arc<Path>,
+    entries: VecDeque<DirEntry>,
+    state: State,
+}
+
+enum State {
+    Idle(Box<Dents>),
+    Reading(Blocking<Batch>),
+    Done,
+}
+
+/// The open directory and the buffer its entries are read into, moved to the blocking
+/// pool for each batch.
+struct Dents {
+    fd: OwnedFd,
+    buf: Vec<u8>,
+}
+
+type Batch = (Box<Dents>, io::Result<Vec<DirEntry>>);
+
+/// An entry of a directory listed by [`ReadDir`].
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    dir: Arc<Path>,
+    name: OsString,
+    ino: u64,
+    d_type: u8,
+}
+
+impl ReadDir {
+    pub fn poll_next_entry(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<Option<DirEntry>>> {
+        loop {
+            if let Some(entry) = self.entries.pop_front() {
+                return Poll::Ready(Ok(Some(entry)));
+            }
+            match &mut self.state {
+                State::Idle(_) => {
+                    let dents = match mem::replace(&mut self.state, State::Done) {
+                        State::Idle(dents) => dents,
+                        _ => unreachable!(),
+                    };
+                    let dir = self.dir.clone();
+                    self.state = State::Reading(blocking::run(move || dents.read(&dir)));
+                }
+                State::Reading(batch) => {
+                    let (dents, res) = match ready!(Pin::new(batch).poll(cx)) {
+                        Ok(batch) => batch,
+                        Err(panic) => panic::resume_unwind(panic),
+                    };
+                    match res {
+                        // the directory ran out.
+                        Ok(batch) if batch.is_empty() => self.state = State::Done,
+                        Ok(batch) => {
+                            self.entries.extend(batch);
+                            self.state = State::Idle(dents);
+                        }
+                        Err(e) => {
+                            self.state = State::Done;
+                            return Poll::Ready(Err(e));
+                        }
+                    }
+                }
+                State::Done => return Poll::Ready(Ok(None)),
+            }
+        }
+    }
+
+    /// The next entry, `None` once all were listed.
+    pub async fn next_entry(&mut self) -> io::Result<Option<DirEntry>> {
+        poll_fn(|cx| self.poll_next_entry(cx)).await
+    }
+}
+
+impl Stream for ReadDir {
+    type Item = io::Result<DirEntry>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Poll::Ready(ready!(self.poll_next_entry(cx)).transpose())
+    }
+}
+
+impl Dents {
+    /// Reads entries until there is one besides `.` and `..`, an empty batch means the
+    /// directory ran out.
+    fn read(mut self: Box<Self>, dir: &Arc<Path>) -> Batch {
+        let mut entries = Vec::new();
+        while entries.is_empty() {
+            let n = syscall!(syscall(
+                libc::SYS_getdents64,
+                self.fd.as_raw_fd(),
+                self.buf.as_mut_ptr(),
+                self.buf.len()
+            ));
+            let n = match n {
+                Ok(0) => break,
+                Ok(n) => n as usize,
+                Err(e) => return (self, Err(e)),
+            };
+            let mut pos = 0;
+            // each record is a `linux_dirent64`: inode, offset, record length, type and
+            // the nul terminated name.
+            while pos < n {
+                let record = &self.buf[pos..n];
+                let ino = u64::from_ne_bytes(record[..8].try_into().unwrap());
+                let reclen = u16::from_ne_bytes(record[16..18].try_into().unwrap()) as usize;
+                let d_type = record[18];
+                let name = CStr::from_bytes_until_nul(&record[19..reclen])
+                    .expect("directory entry without a nul terminated name")
+                    .to_bytes();
+                if name != b"." && name != b".." {
+                    entries.push(DirEntry {
+                        dir: dir.clone(),
+                        name: OsStr::from_bytes(name).to_owned(),
+                        ino,
+                        d_type,
+                    });
+                }
+                pos += reclen;
+            }
+        }
+        (self, Ok(entries))
+    }
+}
+
+impl DirEntry {
+    /// The path of the entry, the directory given to [`read_dir`] joined with the name.
+    pub fn path(&self) -> PathBuf {
+        self.dir.join(&self.name)
+    }
+
+    pub fn file_name(&self) -> OsString {
+        self.name.clone()
+    }
+
+    pub fn ino(&self) -> u64 {
+        self.ino
+    }
+
+    /// The type of the entry, a symbolic link is not followed. The directory listing
+    /// tells it on most filesystems, it is looked up with `statx` on the others.
+    pub async fn file_type(&self) -> io::Result<FileType> {
+        let mode = match self.d_type {
+            libc::DT_DIR => libc::S_IFDIR,
+            libc::DT_REG => libc::S_IFREG,
+            libc::DT_LNK => libc::S_IFLNK,
+            libc::DT_FIFO => libc::S_IFIFO,
+            libc::DT_SOCK => libc::S_IFSOCK,
+            libc::DT_BLK => libc::S_IFBLK,
+            libc::DT_CHR => libc::S_IFCHR,
+            _ => return Ok(self.metadata().await?.file_type()),
+        };
+        Ok(FileType::new(mode))
+    }
+
+    /// The metadata of the entry, of the link itself if it is a symbolic link.
+    pub async fn metadata(&self) -> io::Result<Metadata> {
+        symlink_metadata(self.path()).await
+    }
+}
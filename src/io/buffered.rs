@@ -0,0 +1,250 @@
+use std::cell::RefCell;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures_util::io::{AsyncBufRead, AsyncRead, AsyncWrite};
+use pin_project_lite::pin_project;
+
+use crate::driver::{Driver, DEFAULT_BUFFER_SIZE};
+
+/// Most buffers kept around per thread for the next reader or writer.
+const MAX_SPARE: usize = 256;
+
+thread_local! {
+    static SPARE: RefCell<Vec<Box<[u8]>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Storage of a buffered reader or writer, handed to the next one once dropped so a
+/// server does not allocate a fresh buffer for every connection.
+pub(super) struct Pooled {
+    pub(super) buf: Box<[u8]>,
+}
+
+impl Pooled {
+    /// Takes a spare buffer of `capacity` bytes, or allocates one if there is none.
+    pub(super) fn take(capacity: usize) -> Pooled {
+        let spare = SPARE.with(|spare| {
+            let mut spare = spare.borrow_mut();
+            let i = spare.iter().rposition(|buf| buf.len() == capacity)?;
+            Some(spare.swap_remove(i))
+        });
+        Pooled {
+            buf: spare.unwrap_or_else(|| vec![0; capacity].into_boxed_slice()),
+        }
+    }
+}
+
+impl Drop for Pooled {
+    fn drop(&mut self) {
+        let buf = std::mem::take(&mut self.buf);
+        // buffers dropped during thread exit are freed instead.
+        let _ = SPARE.try_with(|spare| {
+            let mut spare = spare.borrow_mut();
+            if spare.len() < MAX_SPARE {
+                spare.push(buf);
+            }
+        });
+    }
+}
+
+/// The size of the buffers in the runtime's buffer ring, so a buffered reader holds as
+/// much as a single read hands back.
+pub(super) fn default_capacity() -> usize {
+    Driver::try_current(|driver| driver.inner.try_borrow().ok()?.buffer_size())
+        .flatten()
+        .unwrap_or(DEFAULT_BUFFER_SIZE)
+}
+
+pin_project! {
+    /// Adds a buffer to a reader, so small reads are served from memory instead of each
+    /// costing an operation.
+    ///
+    /// The buffer is as large as the buffers of the runtime's buffer ring by default and
+    /// comes from a pool of spare buffers, it goes back to the pool once the reader is
+    /// dropped.
+    ///
+    /// Line-based protocols read from it with the helpers of
+    /// [`AsyncBufReadExt`](crate::AsyncBufReadExt): `read_until` a delimiter,
+    /// `read_line`, or the stream of [`lines`](crate::AsyncBufReadExt::lines).
+    pub struct BufReader<R> {
+        #[pin]
+        inner: R,
+        buf: Pooled,
+        pos: usize,
+        filled: usize,
+    }
+}
+
+impl<R: AsyncRead> BufReader<R> {
+    pub fn new(inner: R) -> BufReader<R> {
+        BufReader::with_capacity(default_capacity(), inner)
+    }
+
+    pub fn with_capacity(capacity: usize, inner: R) -> BufReader<R> {
+        BufReader {
+            inner,
+            buf: Pooled::take(capacity),
+            pos: 0,
+            filled: 0,
+        }
+    }
+}
+
+impl<R> BufReader<R> {
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// The bytes read but not consumed yet.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf.buf[self.pos..self.filled]
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buf.buf.len()
+    }
+
+    /// Unwraps the reader, the bytes still buffered are lost.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: AsyncRead> AsyncRead for BufReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        // a read at least as large as the buffer gains nothing from copying through it.
+        if self.pos == self.filled && buf.len() >= self.capacity() {
+            return self.project().inner.poll_read(cx, buf);
+        }
+        let src = ready!(self.as_mut().poll_fill_buf(cx))?;
+        let n = src.len().min(buf.len());
+        buf[..n].copy_from_slice(&src[..n]);
+        self.consume(n);
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl<R: AsyncRead> AsyncBufRead for BufReader<R> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let me = self.project();
+        if *me.pos >= *me.filled {
+            *me.filled = ready!(me.inner.poll_read(cx, &mut me.buf.buf))?;
+            *me.pos = 0;
+        }
+        Poll::Ready(Ok(&me.buf.buf[*me.pos..*me.filled]))
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        let me = self.project();
+        *me.pos = (*me.pos + amt).min(*me.filled);
+    }
+}
+
+pin_project! {
+    /// Adds a buffer to a writer, so small writes are gathered in memory and go out
+    /// together once the buffer is full or flushed.
+    ///
+    /// The buffer comes from the same pool as the one of a [`BufReader`]. Flush before
+    /// dropping the writer, bytes still buffered are lost otherwise.
+    pub struct BufWriter<W> {
+        #[pin]
+        inner: W,
+        buf: Pooled,
+        len: usize,
+        written: usize,
+    }
+}
+
+impl<W: AsyncWrite> BufWriter<W> {
+    pub fn new(inner: W) -> BufWriter<W> {
+        BufWriter::with_capacity(default_capacity(), inner)
+    }
+
+    pub fn with_capacity(capacity: usize, inner: W) -> BufWriter<W> {
+        BufWriter {
+            inner,
+            buf: Pooled::take(capacity),
+            len: 0,
+            written: 0,
+        }
+    }
+
+    /// Writes out the buffered bytes, without flushing the inner writer.
+    fn poll_flush_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut me = self.project();
+        while *me.written < *me.len {
+            let src = &me.buf.buf[*me.written..*me.len];
+            let n = ready!(me.inner.as_mut().poll_write(cx, src))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            *me.written += n;
+        }
+        *me.len = 0;
+        *me.written = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W> BufWriter<W> {
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// The bytes written but not handed to the inner writer yet.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf.buf[self.written..self.len]
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buf.buf.len()
+    }
+
+    /// Unwraps the writer, the bytes still buffered are lost.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: AsyncWrite> AsyncWrite for BufWriter<W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.len + buf.len() > self.capacity() {
+            ready!(self.as_mut().poll_flush_buf(cx))?;
+        }
+        // a write at least as large as the buffer goes out right away.
+        if buf.len() >= self.capacity() {
+            return self.project().inner.poll_write(cx, buf);
+        }
+        let me = self.project();
+        me.buf.buf[*me.len..*me.len + buf.len()].copy_from_slice(buf);
+        *me.len += buf.len();
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.as_mut().poll_flush_buf(cx))?;
+        self.project().inner.poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.as_mut().poll_flush_buf(cx))?;
+        self.project().inner.poll_close(cx)
+    }
+}
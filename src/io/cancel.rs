@@ -0,0 +1,103 @@
+use std::future::Future;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use pin_project_lite::pin_project;
+
+use crate::driver::{Action, Driver};
+use crate::util::cancellation::Waiter;
+use crate::util::CancellationToken;
+
+/// Wraps `future` so the operations it waits for can be cancelled through the returned
+/// handle, without dropping the future.
+///
+/// Once cancelled, every operation the future polls asks the kernel to cancel it. Each
+/// completes with [`Error::Cancelled`](crate::Error::Cancelled), or with what it did
+/// before the cancellation reached it, and the future goes on from there, usually
+/// failing with the cancellation. Operations started after the cancellation are
+/// cancelled as soon as they are polled.
+pub fn cancellable<F: Future>(future: F) -> (Cancellable<F>, CancelHandle) {
+    let token = CancellationToken::new();
+    let future = Cancellable::new(future, Waiter::new(token.clone()));
+    (future, CancelHandle { token })
+}
+
+pin_project! {
+    /// A future whose operations can be cancelled, see [`cancellable`] and
+    /// [`CancellationToken::cancellable`].
+    pub struct Cancellable<F> {
+        #[pin]
+        future: F,
+        waiter: Waiter,
+    }
+}
+
+impl<F> Cancellable<F> {
+    pub(crate) fn new(future: F, waiter: Waiter) -> Cancellable<F> {
+        Cancellable { future, waiter }
+    }
+}
+
+/// Cancels the operations of a [`Cancellable`] future.
+#[derive(Clone)]
+pub struct CancelHandle {
+    token: CancellationToken,
+}
+
+impl CancelHandle {
+    /// Cancels the operations the future waits for, the next time it is polled.
+    pub fn cancel(&self) {
+        self.token.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+}
+
+impl<F: Future> Future for Cancellable<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        let this = self.project();
+        if this.waiter.poll_cancelled(cx).is_pending() {
+            return this.future.poll(cx);
+        }
+        let _scope = Driver::try_current(Scope::enter);
+        this.future.poll(cx)
+    }
+}
+
+/// Makes the operations polled while held cancel themselves, restoring the previous
+/// setting when dropped, which nested cancellable futures rely on.
+struct Scope {
+    driver: Driver,
+    previous: bool,
+}
+
+impl Scope {
+    fn enter(driver: &Driver) -> Scope {
+        Scope {
+            driver: driver.clone(),
+            previous: driver.set_cancelling(true),
+        }
+    }
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        self.driver.set_cancelling(self.previous);
+    }
+}
+
+/// Cancels every operation in flight on `io`, returning how many were cancelled. Each
+/// completes with [`Error::Cancelled`](crate::Error::Cancelled), or with what it did
+/// before the cancellation reached it. Needs Linux 5.19.
+pub async fn cancel_fd<T: AsRawFd>(io: &T) -> io::Result<usize> {
+    match Action::cancel_fd(io.as_raw_fd())?.await.output() {
+        Err(e) if e.raw_os_error() == Some(libc::ENOENT) => Ok(0),
+        res => res,
+    }
+}
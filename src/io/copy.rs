@@ -0,0 +1,189 @@
+use std::cell::Cell;
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use futures_util::future::{self, poll_fn};
+use futures_util::io::{AsyncRead, AsyncWrite};
+
+use super::buffered::{default_capacity, Pooled};
+use super::{Pipe, PIPE_CHUNK};
+use crate::driver::Action;
+
+/// Copies the bytes `reader` sends to `writer` until `reader` reaches EOF, returning how
+/// many were copied. `writer` is flushed at the end.
+///
+/// When the kernel can splice both fds, which holds for sockets, pipes and regular
+/// files, the bytes go through a pipe and never reach userspace. Otherwise they are
+/// copied through a pooled buffer. Bytes `reader` buffered in userspace before the
+/// call are not copied when splicing.
+pub async fn copy<R, W>(reader: &mut R, writer: &mut W) -> io::Result<u64>
+where
+    R: AsyncRead + AsRawFd + Unpin,
+    W: AsyncWrite + AsRawFd + Unpin,
+{
+    if can_splice(reader.as_raw_fd())? && can_splice(writer.as_raw_fd())? {
+        let last = Cell::new(Instant::now());
+        return forward(reader.as_raw_fd(), writer.as_raw_fd(), &last, false).await;
+    }
+    let mut buf = CopyBuffer::new();
+    poll_fn(|cx| buf.poll_copy(cx, Pin::new(&mut *reader), Pin::new(&mut *writer), false)).await
+}
+
+/// Copies bytes between `a` and `b` in both directions until both reached EOF,
+/// returning how many were copied from `a` to `b` and from `b` to `a`.
+///
+/// Once one side reaches EOF the other side is closed for writing, and the opposite
+/// direction keeps going. The bytes are spliced or copied through pooled buffers as
+/// [`copy`] describes, splicing works like [`proxy`](crate::net::proxy).
+pub async fn copy_bidirectional<A, B>(a: &mut A, b: &mut B) -> io::Result<(u64, u64)>
+where
+    A: AsyncRead + AsyncWrite + AsRawFd + Unpin,
+    B: AsyncRead + AsyncWrite + AsRawFd + Unpin,
+{
+    if can_splice(a.as_raw_fd())? && can_splice(b.as_raw_fd())? {
+        let last = Cell::new(Instant::now());
+        let (a, b) = (a.as_raw_fd(), b.as_raw_fd());
+        return future::try_join(forward(a, b, &last, true), forward(b, a, &last, true)).await;
+    }
+    let (mut a_to_b, mut b_to_a) = (CopyBuffer::new(), CopyBuffer::new());
+    let (mut a_done, mut b_done) = (None, None);
+    poll_fn(|cx| {
+        let (mut a, mut b) = (Pin::new(&mut *a), Pin::new(&mut *b));
+        if a_done.is_none() {
+            if let Poll::Ready(n) = a_to_b.poll_copy(cx, a.as_mut(), b.as_mut(), true) {
+                a_done = Some(n?);
+            }
+        }
+        if b_done.is_none() {
+            if let Poll::Ready(n) = b_to_a.poll_copy(cx, b, a, true) {
+                b_done = Some(n?);
+            }
+        }
+        match (a_done, b_done) {
+            (Some(a), Some(b)) => Poll::Ready(Ok((a, b))),
+            _ => Poll::Pending,
+        }
+    })
+    .await
+}
+
+/// Whether the kernel splices to and from `fd`.
+fn can_splice(fd: RawFd) -> io::Result<bool> {
+    let mut stat = MaybeUninit::<libc::stat>::uninit();
+    syscall!(fstat(fd, stat.as_mut_ptr()))?;
+    let mode = unsafe { stat.assume_init() }.st_mode & libc::S_IFMT;
+    Ok(matches!(
+        mode,
+        libc::S_IFSOCK | libc::S_IFIFO | libc::S_IFREG
+    ))
+}
+
+/// Splices everything from `from` to `to` through a pipe until `from` reaches EOF,
+/// recording every move in `last`. With `shutdown`, the write side of `to` is shut
+/// down at EOF.
+pub(crate) async fn forward(
+    from: RawFd,
+    to: RawFd,
+    last: &Cell<Instant>,
+    shutdown: bool,
+) -> io::Result<u64> {
+    let pipe = Pipe::new()?;
+    let mut total = 0;
+    loop {
+        let n = Action::splice(from, pipe.write, PIPE_CHUNK)?
+            .await
+            .output()?;
+        if n == 0 {
+            if shutdown {
+                shutdown_write(to)?;
+            }
+            return Ok(total);
+        }
+        last.set(Instant::now());
+
+        let mut pending = n as u32;
+        while pending > 0 {
+            let n = Action::splice(pipe.read, to, pending)?.await.output()?;
+            if n == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            pending -= n as u32;
+            total += n as u64;
+            last.set(Instant::now());
+        }
+    }
+}
+
+fn shutdown_write(fd: RawFd) -> io::Result<()> {
+    match syscall!(shutdown(fd, libc::SHUT_WR)) {
+        // the peer is already gone, there is nobody left to tell.
+        Err(err) if err.raw_os_error() == Some(libc::ENOTCONN) => Ok(()),
+        res => res.map(drop),
+    }
+}
+
+/// One direction of a copy through a pooled buffer.
+struct CopyBuffer {
+    buf: Pooled,
+    /// The bytes read and not written yet.
+    pos: usize,
+    end: usize,
+    eof: bool,
+    total: u64,
+}
+
+impl CopyBuffer {
+    fn new() -> CopyBuffer {
+        CopyBuffer {
+            buf: Pooled::take(default_capacity()),
+            pos: 0,
+            end: 0,
+            eof: false,
+            total: 0,
+        }
+    }
+
+    /// Copies until `reader` reached EOF, then flushes `writer`, or closes it with
+    /// `close`.
+    fn poll_copy<R, W>(
+        &mut self,
+        cx: &mut Context,
+        mut reader: Pin<&mut R>,
+        mut writer: Pin<&mut W>,
+        close: bool,
+    ) -> Poll<io::Result<u64>>
+    where
+        R: AsyncRead,
+        W: AsyncWrite,
+    {
+        loop {
+            if self.pos == self.end && !self.eof {
+                let n = ready!(reader.as_mut().poll_read(cx, &mut self.buf.buf))?;
+                self.pos = 0;
+                self.end = n;
+                self.eof = n == 0;
+            }
+            while self.pos < self.end {
+                let src = &self.buf.buf[self.pos..self.end];
+                let n = ready!(writer.as_mut().poll_write(cx, src))?;
+                if n == 0 {
+                    return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+                }
+                self.pos += n;
+                self.total += n as u64;
+            }
+            if self.eof {
+                if close {
+                    ready!(writer.as_mut().poll_close(cx))?;
+                } else {
+                    ready!(writer.as_mut().poll_flush(cx))?;
+                }
+                return Poll::Ready(Ok(self.total));
+            }
+        }
+    }
+}
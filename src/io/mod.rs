@@ -0,0 +1,97 @@
+//! Moving bytes between file descriptors inside the kernel, and buffering the ones that
+//! pass through userspace.
+
+mod buffered;
+mod cancel;
+mod copy;
+
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use crate::driver::Action;
+
+pub use crate::driver::{SharedFd, WeakFd};
+pub use buffered::{BufReader, BufWriter};
+pub use cancel::{cancel_fd, cancellable, CancelHandle, Cancellable};
+pub(crate) use copy::forward;
+pub use copy::{copy, copy_bidirectional};
+pub use futures_util::io::Lines;
+
+/// Bytes moved through a pipe per splice.
+pub(crate) const PIPE_CHUNK: u32 = 64 * 1024;
+
+/// Moves up to `len` bytes from `from` to `to` without copying them into userspace,
+/// returning how many were moved. One of the two must be a pipe. `Ok(0)` means `from`
+/// reached end of file.
+pub async fn splice<A, B>(from: &A, to: &B, len: usize) -> io::Result<usize>
+where
+    A: AsRawFd,
+    B: AsRawFd,
+{
+    let len = len.min(u32::MAX as usize) as u32;
+    Action::splice(from.as_raw_fd(), to.as_raw_fd(), len)?
+        .await
+        .output()
+}
+
+/// Sends up to `len` bytes of `file` starting at `offset` to `to` through a pipe,
+/// returning how many were sent. Fewer than `len` means the file ended.
+pub(crate) async fn send_file(
+    file: RawFd,
+    mut offset: u64,
+    to: RawFd,
+    len: u64,
+) -> io::Result<u64> {
+    let pipe = Pipe::new()?;
+    let mut total = 0;
+    while total < len {
+        let chunk = (len - total).min(PIPE_CHUNK as u64) as u32;
+        let n = Action::splice_at(file, offset, pipe.write, chunk)?
+            .await
+            .output()?;
+        if n == 0 {
+            break;
+        }
+        offset += n as u64;
+        drain(&pipe, to, n as u32).await?;
+        total += n as u64;
+    }
+    Ok(total)
+}
+
+/// Moves the `pending` bytes sitting in `pipe` to `to`.
+pub(crate) async fn drain(pipe: &Pipe, to: RawFd, mut pending: u32) -> io::Result<()> {
+    while pending > 0 {
+        let n = Action::splice(pipe.read, to, pending)?.await.output()?;
+        if n == 0 {
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+        pending -= n as u32;
+    }
+    Ok(())
+}
+
+pub(crate) struct Pipe {
+    pub(crate) read: RawFd,
+    pub(crate) write: RawFd,
+}
+
+impl Pipe {
+    pub(crate) fn new() -> io::Result<Pipe> {
+        let mut fds = [0; 2];
+        syscall!(pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC))?;
+        Ok(Pipe {
+            read: fds[0],
+            write: fds[1],
+        })
+    }
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.read);
+            libc::close(self.write);
+        }
+    }
+}
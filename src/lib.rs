@@ -34,20 +34,38 @@ macro_rules! ready {
     };
 }
 
+mod blocking;
+pub mod buf;
+pub mod client;
+pub mod codec;
 mod driver;
+pub mod error;
+pub mod fs;
+pub mod io;
 mod local_executor;
+mod loom;
 pub mod net;
+pub mod op;
+pub mod process;
 pub mod runtime;
+pub mod signal;
+pub mod sync;
+pub mod task;
 pub mod time;
+pub mod util;
 mod waker_fn;
+mod workers;
 
 use std::future::Future;
 
-pub use local_executor::spawn_local;
+pub use error::Error;
 pub use runtime::Runtime;
+pub use task::{spawn, spawn_blocking, spawn_local, JoinError, JoinHandle};
 
-pub use async_task::Task;
-pub use futures_util::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+pub use futures_util::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt, AsyncWrite,
+    AsyncWriteExt,
+};
 
 pub fn block_on<F>(future: F) -> F::Output
 where
@@ -1,16 +1,117 @@
 use std::cell::RefCell;
 use std::collections::VecDeque;
-use std::future::Future;
+use std::future::{poll_fn, Future};
+use std::mem;
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::Waker;
 
 use async_task::{Runnable, Task};
+use slab::Slab;
+
+use crate::driver::remote::Wake;
 
 const MAX_TASKS_PER_TICK: usize = 64;
 
 thread_local! {
     static GLOBAL_QUEUE: RefCell<VecDeque<Runnable>> = RefCell::new(VecDeque::with_capacity(64));
+    /// The waker each live task was last polled with, `None` until its first poll.
+    static LIVE_TASKS: RefCell<Slab<Option<Waker>>> = const { RefCell::new(Slab::new()) };
+    static INBOX: Arc<Inbox> = Arc::new(Inbox::default());
+}
+
+/// Tasks of this thread woken on another thread, queued here for this thread to run.
+#[derive(Default)]
+pub struct Inbox {
+    tasks: Mutex<Vec<Runnable>>,
+    pending: AtomicBool,
+    /// Wakes the runtime running on the thread, `None` while none is.
+    wake: Mutex<Option<Wake>>,
+}
+
+impl Inbox {
+    /// The inbox of the current thread.
+    pub fn current() -> Arc<Inbox> {
+        INBOX.with(Arc::clone)
+    }
+
+    /// Whether this is the inbox of the current thread.
+    pub fn is_local(self: &Arc<Inbox>) -> bool {
+        // the thread is exiting once its inbox is gone, any other thread's would be.
+        INBOX
+            .try_with(|inbox| Arc::ptr_eq(inbox, self))
+            .unwrap_or(false)
+    }
+
+    /// Sets how the runtime running on the thread is woken, returning the previous one to
+    /// restore.
+    pub fn set_wake(&self, wake: Option<Wake>) -> Option<Wake> {
+        mem::replace(&mut *self.wake.lock().unwrap(), wake)
+    }
+
+    /// Wakes the runtime running on the thread, if any.
+    pub fn wake(&self) {
+        let wake = self.wake.lock().unwrap().clone();
+        if let Some(wake) = wake {
+            wake.wake();
+        }
+    }
+
+    fn push(&self, runnable: Runnable) {
+        self.tasks.lock().unwrap().push(runnable);
+        self.pending.store(true, Ordering::Release);
+        self.wake();
+    }
+
+    /// Moves the tasks woken on other threads to the run queue.
+    fn drain(&self) {
+        if !self.pending.swap(false, Ordering::Acquire) {
+            return;
+        }
+        let tasks = mem::take(&mut *self.tasks.lock().unwrap());
+        GLOBAL_QUEUE.with(|queue| queue.borrow_mut().extend(tasks));
+    }
+}
+
+/// Number of spawned tasks that have not finished or been dropped.
+pub fn live_tasks() -> usize {
+    LIVE_TASKS.with(|live| live.borrow().len())
+}
+
+/// Number of tasks waiting to be polled.
+pub fn queued_tasks() -> usize {
+    GLOBAL_QUEUE.with(|queue| queue.borrow().len())
+}
+
+/// Tracks a task as live until its future is dropped.
+struct Live(usize);
+
+impl Live {
+    fn new() -> Live {
+        Live(LIVE_TASKS.with(|live| live.borrow_mut().insert(None)))
+    }
+
+    fn register(&self, waker: &Waker) {
+        LIVE_TASKS.with(|live| {
+            let mut live = live.borrow_mut();
+            let slot = &mut live[self.0];
+            if !slot.as_ref().is_some_and(|old| old.will_wake(waker)) {
+                *slot = Some(waker.clone());
+            }
+        })
+    }
+}
+
+impl Drop for Live {
+    fn drop(&mut self) {
+        // tasks still queued at thread exit are dropped during TLS teardown.
+        let _ = LIVE_TASKS.try_with(|live| live.borrow_mut().remove(self.0));
+    }
 }
 
 pub fn tick() -> bool {
+    INBOX.with(|inbox| inbox.drain());
     for _ in 0..MAX_TASKS_PER_TICK {
         match next_task() {
             Some(task) => {
@@ -22,15 +123,49 @@ pub fn tick() -> bool {
     true
 }
 
+/// Drops every live task without running it again, the ones waiting to be woken as
+/// well as the queued ones.
+pub fn clear() {
+    // waking a task queues it, so it is dropped along with the others.
+    let wakers: Vec<_> = LIVE_TASKS.with(|live| {
+        let mut live = live.borrow_mut();
+        live.iter_mut()
+            .filter_map(|(_, waker)| waker.take())
+            .collect()
+    });
+    for waker in wakers {
+        waker.wake();
+    }
+    // dropping a task may wake and queue another one.
+    while let Some(task) = next_task() {
+        drop(task);
+    }
+}
+
 fn next_task() -> Option<Runnable> {
     GLOBAL_QUEUE.with(|queue| queue.borrow_mut().pop_front())
 }
 
 pub fn spawn_local<T: 'static>(future: impl Future<Output = T> + 'static) -> Task<T> {
+    // a task woken on another thread still runs on this one.
+    let inbox = Inbox::current();
     let schedule = move |runnable| {
-        let _ = GLOBAL_QUEUE.with(|queue| queue.borrow_mut().push_back(runnable));
+        if inbox.is_local() {
+            GLOBAL_QUEUE.with(|queue| queue.borrow_mut().push_back(runnable));
+        } else {
+            inbox.push(runnable);
+        }
     };
 
+    let live = Live::new();
+    let future = async move {
+        let mut future = pin!(future);
+        poll_fn(|cx| {
+            live.register(cx.waker());
+            future.as_mut().poll(cx)
+        })
+        .await
+    };
     let (runnable, task) = unsafe { async_task::spawn_unchecked(future, schedule) };
     runnable.schedule();
     task
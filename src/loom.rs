@@ -0,0 +1,19 @@
+//! The primitives state shared with other threads is built on, swapped for the models of
+//! `loom` when built with `--cfg loom`:
+//!
+//! ```text
+//! RUSTFLAGS="--cfg loom" cargo test --release --lib loom
+//! ```
+//!
+//! Only the `loom` tests may run in such a build, anything else touching the modelled
+//! primitives outside of `loom::model` panics.
+
+#[cfg(loom)]
+pub(crate) use loom::sync::{Arc, Mutex};
+#[cfg(loom)]
+pub(crate) use loom::thread;
+
+#[cfg(not(loom))]
+pub(crate) use std::sync::{Arc, Mutex};
+#[cfg(not(loom))]
+pub(crate) use std::thread;
@@ -0,0 +1,121 @@
+use std::io;
+use std::net::{self, IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::panic;
+
+use crate::blocking;
+
+/// Values that convert or resolve to socket addresses, without blocking the runtime.
+///
+/// Implemented for the same types as std's `ToSocketAddrs`. Addresses are converted
+/// right away, host names are resolved with `getaddrinfo` on the blocking pool, see
+/// [`lookup_host`].
+pub trait ToSocketAddrs: sealed::ToSocketAddrsPriv {}
+
+mod sealed {
+    use super::Resolve;
+
+    pub trait ToSocketAddrsPriv {
+        fn resolve(&self) -> Resolve;
+    }
+}
+
+/// Addresses known right away, or a `host:port` to look up.
+pub enum Resolve {
+    Ready(Vec<SocketAddr>),
+    Lookup(String),
+}
+
+/// Resolves `host` to the socket addresses it refers to. A host name is looked up on the
+/// blocking pool while the runtime keeps going.
+pub async fn lookup_host<A: ToSocketAddrs>(
+    host: A,
+) -> io::Result<impl Iterator<Item = SocketAddr>> {
+    Ok(resolve(&host).await?.into_iter())
+}
+
+pub(crate) async fn resolve<A: ToSocketAddrs + ?Sized>(addr: &A) -> io::Result<Vec<SocketAddr>> {
+    let host = match addr.resolve() {
+        Resolve::Ready(addrs) => return Ok(addrs),
+        Resolve::Lookup(host) => host,
+    };
+    let lookup = blocking::run(move || net::ToSocketAddrs::to_socket_addrs(&host));
+    match lookup.await {
+        Ok(addrs) => Ok(addrs?.collect()),
+        Err(panic) => panic::resume_unwind(panic),
+    }
+}
+
+macro_rules! impl_ready {
+    ($ty:ty, |$addr:ident| $conv:expr) => {
+        impl ToSocketAddrs for $ty {}
+
+        impl sealed::ToSocketAddrsPriv for $ty {
+            fn resolve(&self) -> Resolve {
+                let $addr = *self;
+                Resolve::Ready(vec![$conv])
+            }
+        }
+    };
+}
+
+impl_ready!(SocketAddr, |addr| addr);
+impl_ready!(SocketAddrV4, |addr| SocketAddr::V4(addr));
+impl_ready!(SocketAddrV6, |addr| SocketAddr::V6(addr));
+impl_ready!((IpAddr, u16), |addr| SocketAddr::from(addr));
+impl_ready!((Ipv4Addr, u16), |addr| SocketAddr::from(addr));
+impl_ready!((Ipv6Addr, u16), |addr| SocketAddr::from(addr));
+
+impl ToSocketAddrs for [SocketAddr] {}
+
+impl sealed::ToSocketAddrsPriv for [SocketAddr] {
+    fn resolve(&self) -> Resolve {
+        Resolve::Ready(self.to_vec())
+    }
+}
+
+impl ToSocketAddrs for str {}
+
+impl sealed::ToSocketAddrsPriv for str {
+    fn resolve(&self) -> Resolve {
+        match self.parse() {
+            Ok(addr) => Resolve::Ready(vec![addr]),
+            Err(_) => Resolve::Lookup(self.to_owned()),
+        }
+    }
+}
+
+impl ToSocketAddrs for String {}
+
+impl sealed::ToSocketAddrsPriv for String {
+    fn resolve(&self) -> Resolve {
+        self.as_str().resolve()
+    }
+}
+
+impl ToSocketAddrs for (&str, u16) {}
+
+impl sealed::ToSocketAddrsPriv for (&str, u16) {
+    fn resolve(&self) -> Resolve {
+        let (host, port) = *self;
+        match host.parse::<IpAddr>() {
+            Ok(ip) => Resolve::Ready(vec![SocketAddr::new(ip, port)]),
+            Err(_) => Resolve::Lookup(format!("{}:{}", host, port)),
+        }
+    }
+}
+
+impl ToSocketAddrs for (String, u16) {}
+
+impl sealed::ToSocketAddrsPriv for (String, u16) {
+    fn resolve(&self) -> Resolve {
+        (self.0.as_str(), self.1).resolve()
+    }
+}
+
+impl<T: ToSocketAddrs + ?Sized> ToSocketAddrs for &T {}
+
+impl<T: ToSocketAddrs + ?Sized> sealed::ToSocketAddrsPriv for &T {
+    fn resolve(&self) -> Resolve {
+        (**self).resolve()
+    }
+}
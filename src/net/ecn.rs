@@ -0,0 +1,71 @@
+use std::convert::TryInto;
+
+use crate::driver::cmsg::Cmsgs;
+
+/// The Explicit Congestion Notification codepoint of a datagram, the low two bits of
+/// its TOS or traffic class byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ecn {
+    /// ECN capable transport, ECT(1).
+    Ect1 = 0b01,
+    /// ECN capable transport, ECT(0).
+    Ect0 = 0b10,
+    /// Congestion experienced.
+    Ce = 0b11,
+}
+
+impl Ecn {
+    /// Reads the codepoint from a TOS or traffic class byte, `None` for not-ECT.
+    pub fn from_bits(bits: u8) -> Option<Ecn> {
+        match bits & 0b11 {
+            0b01 => Some(Ecn::Ect1),
+            0b10 => Some(Ecn::Ect0),
+            0b11 => Some(Ecn::Ce),
+            _ => None,
+        }
+    }
+}
+
+/// Marks an outgoing datagram with `ecn`, `v4` tells whether it is sent over IPv4.
+pub(crate) fn push(cmsgs: &mut Cmsgs, ecn: Ecn, v4: bool) {
+    let bits = ecn as libc::c_int;
+    if v4 {
+        cmsgs.push(libc::IPPROTO_IP, libc::IP_TOS, bits);
+    } else {
+        cmsgs.push(libc::IPPROTO_IPV6, libc::IPV6_TCLASS, bits);
+    }
+}
+
+/// The codepoint of a received datagram, if the message carries it.
+pub(crate) fn parse(level: libc::c_int, ty: libc::c_int, data: &[u8]) -> Option<Ecn> {
+    match (level, ty) {
+        // IPv4 reports the TOS as a single byte, IPv6 the traffic class as an int.
+        (libc::IPPROTO_IP, libc::IP_TOS) => Ecn::from_bits(*data.first()?),
+        (libc::IPPROTO_IPV6, libc::IPV6_TCLASS) => {
+            let class = libc::c_int::from_ne_bytes(data.get(..4)?.try_into().ok()?);
+            Ecn::from_bits(class as u8)
+        }
+        _ => None,
+    }
+}
+
+/// Asks the kernel to report the codepoint of received datagrams.
+pub(crate) fn enable_recv(fd: libc::c_int, v6: bool) -> std::io::Result<()> {
+    let on: libc::c_int = 1;
+    let len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let on = &on as *const _ as *const libc::c_void;
+    if v6 {
+        syscall!(setsockopt(
+            fd,
+            libc::IPPROTO_IPV6,
+            libc::IPV6_RECVTCLASS,
+            on,
+            len
+        ))?;
+        // v4-mapped traffic on a dual stack socket reports the TOS instead.
+        let _ = syscall!(setsockopt(fd, libc::IPPROTO_IP, libc::IP_RECVTOS, on, len));
+    } else {
+        syscall!(setsockopt(fd, libc::IPPROTO_IP, libc::IP_RECVTOS, on, len))?;
+    }
+    Ok(())
+}
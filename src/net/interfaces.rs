@@ -0,0 +1,216 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::mem::{self, size_of};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::os::unix::io::RawFd;
+use std::ptr;
+
+use futures_util::future::poll_fn;
+
+use crate::driver::Action;
+
+/// Large enough for any message of a route netlink dump.
+const RECV_LEN: usize = 64 * 1024;
+
+/// A network interface and the addresses assigned to it.
+#[derive(Debug, Clone)]
+pub struct Interface {
+    pub name: String,
+    pub index: u32,
+    /// The `IFF_*` flags of the interface.
+    pub flags: u32,
+    pub addrs: Vec<InterfaceAddr>,
+}
+
+/// An address assigned to an interface, along with the prefix length of its network.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterfaceAddr {
+    pub ip: IpAddr,
+    pub prefix_len: u8,
+}
+
+impl Interface {
+    pub fn is_up(&self) -> bool {
+        self.flags & libc::IFF_UP as u32 != 0
+    }
+
+    pub fn is_loopback(&self) -> bool {
+        self.flags & libc::IFF_LOOPBACK as u32 != 0
+    }
+}
+
+/// Lists the network interfaces of the host ordered by index, each with its addresses.
+///
+/// The interfaces are read from a route netlink socket driven by the ring, so the
+/// thread is not blocked the way `getifaddrs` would.
+pub async fn interfaces() -> io::Result<Vec<Interface>> {
+    let socket = Netlink::new()?;
+    let mut interfaces = BTreeMap::new();
+
+    let mut link: libc::ifinfomsg = unsafe { mem::zeroed() };
+    link.ifi_family = libc::AF_UNSPEC as u8;
+    for msg in socket.dump(libc::RTM_GETLINK, link).await? {
+        if let Some(interface) = parse_link(&msg) {
+            interfaces.insert(interface.index, interface);
+        }
+    }
+
+    let mut addr: libc::ifaddrmsg = unsafe { mem::zeroed() };
+    addr.ifa_family = libc::AF_UNSPEC as u8;
+    for msg in socket.dump(libc::RTM_GETADDR, addr).await? {
+        if let Some((index, addr)) = parse_addr(&msg) {
+            if let Some(interface) = interfaces.get_mut(&index) {
+                interface.addrs.push(addr);
+            }
+        }
+    }
+    Ok(interfaces.into_values().collect())
+}
+
+/// The type and payload of a netlink message.
+struct Message {
+    ty: u16,
+    payload: Vec<u8>,
+}
+
+struct Netlink(RawFd);
+
+impl Netlink {
+    fn new() -> io::Result<Netlink> {
+        let fd = syscall!(socket(
+            libc::AF_NETLINK,
+            libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+            libc::NETLINK_ROUTE
+        ))?;
+        Ok(Netlink(fd))
+    }
+
+    /// Requests a dump of `ty` and collects every message of the reply.
+    async fn dump<T: Copy>(&self, ty: u16, body: T) -> io::Result<Vec<Message>> {
+        let header = libc::nlmsghdr {
+            nlmsg_len: (size_of::<libc::nlmsghdr>() + size_of::<T>()) as u32,
+            nlmsg_type: ty,
+            nlmsg_flags: (libc::NLM_F_REQUEST | libc::NLM_F_DUMP) as u16,
+            nlmsg_seq: 1,
+            nlmsg_pid: 0,
+        };
+        let mut request = vec![0; header.nlmsg_len as usize];
+        unsafe {
+            ptr::write_unaligned(request.as_mut_ptr().cast(), header);
+            let at = request.as_mut_ptr().add(size_of::<libc::nlmsghdr>());
+            ptr::write_unaligned(at.cast(), body);
+        }
+        let mut send = Action::send(self.0, &request)?;
+        poll_fn(|cx| send.poll_send(cx)).await?;
+
+        let mut messages = Vec::new();
+        let mut buf = vec![0; RECV_LEN];
+        loop {
+            let mut recv = Action::recv(self.0, buf.len())?;
+            let n = poll_fn(|cx| recv.poll_recv(cx, &mut buf)).await?;
+            let mut data = &buf[..n];
+            while let Some(header) = read::<libc::nlmsghdr>(data) {
+                let len = header.nlmsg_len as usize;
+                if len < size_of::<libc::nlmsghdr>() || len > data.len() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "truncated netlink message",
+                    ));
+                }
+                let payload = &data[size_of::<libc::nlmsghdr>()..len];
+                match header.nlmsg_type as libc::c_int {
+                    libc::NLMSG_DONE => return Ok(messages),
+                    libc::NLMSG_ERROR => match read::<libc::c_int>(payload) {
+                        Some(0) => {}
+                        Some(code) => return Err(io::Error::from_raw_os_error(-code)),
+                        None => return Err(io::ErrorKind::InvalidData.into()),
+                    },
+                    _ => messages.push(Message {
+                        ty: header.nlmsg_type,
+                        payload: payload.to_vec(),
+                    }),
+                }
+                data = &data[align(len).min(data.len())..];
+            }
+        }
+    }
+}
+
+impl Drop for Netlink {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0) };
+    }
+}
+
+fn parse_link(msg: &Message) -> Option<Interface> {
+    if msg.ty != libc::RTM_NEWLINK {
+        return None;
+    }
+    let info = read::<libc::ifinfomsg>(&msg.payload)?;
+    let mut interface = Interface {
+        name: String::new(),
+        index: info.ifi_index as u32,
+        flags: info.ifi_flags,
+        addrs: Vec::new(),
+    };
+    for (ty, data) in attrs(&msg.payload[align(size_of::<libc::ifinfomsg>())..]) {
+        if ty == libc::IFLA_IFNAME {
+            let name = data.split(|&b| b == 0).next().unwrap_or(data);
+            interface.name = String::from_utf8_lossy(name).into_owned();
+        }
+    }
+    Some(interface)
+}
+
+fn parse_addr(msg: &Message) -> Option<(u32, InterfaceAddr)> {
+    if msg.ty != libc::RTM_NEWADDR {
+        return None;
+    }
+    let info = read::<libc::ifaddrmsg>(&msg.payload)?;
+    let mut address = None;
+    let mut local = None;
+    for (ty, data) in attrs(&msg.payload[align(size_of::<libc::ifaddrmsg>())..]) {
+        let slot = match ty {
+            libc::IFA_ADDRESS => &mut address,
+            libc::IFA_LOCAL => &mut local,
+            _ => continue,
+        };
+        *slot = match info.ifa_family as libc::c_int {
+            libc::AF_INET => Some(IpAddr::from(Ipv4Addr::from(read::<[u8; 4]>(data)?))),
+            libc::AF_INET6 => Some(IpAddr::from(Ipv6Addr::from(read::<[u8; 16]>(data)?))),
+            _ => return None,
+        };
+    }
+    // on point to point links `IFA_ADDRESS` is the peer, `IFA_LOCAL` our own address.
+    let addr = InterfaceAddr {
+        ip: local.or(address)?,
+        prefix_len: info.ifa_prefixlen,
+    };
+    Some((info.ifa_index, addr))
+}
+
+/// The type and data of each route attribute in `data`.
+fn attrs(mut data: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    std::iter::from_fn(move || {
+        let attr = read::<libc::rtattr>(data)?;
+        let len = attr.rta_len as usize;
+        if len < size_of::<libc::rtattr>() || len > data.len() {
+            return None;
+        }
+        let value = &data[align(size_of::<libc::rtattr>())..len];
+        data = &data[align(len).min(data.len())..];
+        Some((attr.rta_type, value))
+    })
+}
+
+/// Rounds `len` up to the 4 byte alignment of netlink messages and attributes.
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+fn read<T: Copy>(data: &[u8]) -> Option<T> {
+    if data.len() < size_of::<T>() {
+        return None;
+    }
+    Some(unsafe { ptr::read_unaligned(data.as_ptr().cast()) })
+}
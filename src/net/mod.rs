@@ -1,6 +1,22 @@
+mod addr;
+mod ecn;
+mod interfaces;
+pub mod proxy;
+pub mod quic;
+mod socket;
 pub mod tcp;
 pub mod udp;
+pub mod unix;
 
-pub use tcp::TcpListener;
-pub use tcp::TcpStream;
-pub use udp::UdpSocket;
+pub use crate::driver::chain::BufChain;
+pub use crate::driver::{StreamParts, StreamStats, ZcWrite};
+pub use addr::{lookup_host, ToSocketAddrs};
+pub use ecn::Ecn;
+pub use interfaces::{interfaces, Interface, InterfaceAddr};
+pub use proxy::{proxy, proxy_with_idle_timeout};
+pub use quic::{QuicSocket, RecvMeta, Transmit};
+pub use socket::Keepalive;
+pub use tcp::{Admission, ListenOptions, Overflow, ProxyHeader, RateLimit, Rearm, TcpListener};
+pub use tcp::{OwnedReadHalf, OwnedWriteHalf, ReadHalf, ReuniteError, TcpStream, WriteHalf};
+pub use udp::{PeerDemux, PeerSession, UdpSocket};
+pub use unix::{Ancillary, Credentials, UnixDatagram, UnixListener, UnixStream};
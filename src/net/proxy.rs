@@ -0,0 +1,58 @@
+use std::cell::Cell;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::time::{Duration, Instant};
+
+use futures_util::future::{self, Either};
+
+use crate::io::forward;
+use crate::time::delay_until;
+
+/// Forwards bytes between `a` and `b` in both directions until both have reached EOF,
+/// returning the number of bytes copied from `a` to `b` and from `b` to `a`.
+///
+/// Data is spliced through a pipe per direction and never copied into userspace. When
+/// one side reaches EOF, the write half of the other side is shut down and the opposite
+/// direction keeps running. Bytes already buffered by the streams' `AsyncBufRead`
+/// implementations are not forwarded.
+pub async fn proxy<A, B>(a: &mut A, b: &mut B) -> io::Result<(u64, u64)>
+where
+    A: AsRawFd,
+    B: AsRawFd,
+{
+    let last = Cell::new(Instant::now());
+    let (a, b) = (a.as_raw_fd(), b.as_raw_fd());
+    future::try_join(forward(a, b, &last, true), forward(b, a, &last, true)).await
+}
+
+/// Like [`proxy`], but fails with `TimedOut` once no bytes have moved in either
+/// direction for `idle`.
+pub async fn proxy_with_idle_timeout<A, B>(
+    a: &mut A,
+    b: &mut B,
+    idle: Duration,
+) -> io::Result<(u64, u64)>
+where
+    A: AsRawFd,
+    B: AsRawFd,
+{
+    let last = Cell::new(Instant::now());
+    let (a, b) = (a.as_raw_fd(), b.as_raw_fd());
+    let copy = future::try_join(forward(a, b, &last, true), forward(b, a, &last, true));
+    let watchdog = idle_timeout(&last, idle);
+    pin_mut!(copy);
+    pin_mut!(watchdog);
+    match future::select(copy, watchdog).await {
+        Either::Left((res, _)) => res,
+        Either::Right((err, _)) => Err(err),
+    }
+}
+
+async fn idle_timeout(last: &Cell<Instant>, idle: Duration) -> io::Error {
+    loop {
+        delay_until(last.get() + idle).await;
+        if last.get() + idle <= Instant::now() {
+            return io::Error::new(io::ErrorKind::TimedOut, "proxy idle timeout");
+        }
+    }
+}
@@ -0,0 +1,288 @@
+use std::io;
+use std::mem;
+use std::net::{self, IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6, ToSocketAddrs};
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use super::ecn::{self, Ecn};
+use crate::driver::cmsg::Cmsgs;
+use crate::driver::Action;
+
+/// Most segments the kernel sends with one GSO `sendmsg`.
+const MAX_GSO_SEGMENTS: usize = 64;
+
+/// Room for every control message a datagram is sent or received with.
+const CONTROL_LEN: usize = 128;
+
+/// A UDP socket set up the way QUIC implementations drive their I/O.
+///
+/// - Generic segmentation offload: a [`Transmit`] with a `segment_size` carries several
+///   datagrams of that size in one send, see [`max_gso_segments`].
+/// - Generic receive offload: one receive may return several datagrams from the same
+///   sender back to back, [`RecvMeta::stride`] tells their size.
+/// - ECN: sent datagrams are marked with [`Transmit::ecn`] and the codepoint of received
+///   ones is reported in [`RecvMeta::ecn`].
+/// - Packet info: [`RecvMeta::dst_ip`] is the local address a datagram arrived on, and
+///   [`Transmit::src_ip`] picks the address one is sent from, for servers bound to a
+///   wildcard address.
+///
+/// Path MTU discovery is left to the caller: datagrams are sent with the don't
+/// fragment bit set and are never fragmented by the kernel.
+///
+/// [`max_gso_segments`]: QuicSocket::max_gso_segments
+pub struct QuicSocket {
+    inner: net::UdpSocket,
+    v6: bool,
+    max_gso_segments: usize,
+    gro_segments: usize,
+}
+
+/// An outgoing datagram, or several of equal size when `segment_size` is set.
+#[derive(Debug, Clone)]
+pub struct Transmit<'a> {
+    pub destination: SocketAddr,
+    pub ecn: Option<Ecn>,
+    pub contents: &'a [u8],
+    /// Splits `contents` into datagrams of this size, the last one may be shorter.
+    pub segment_size: Option<usize>,
+    /// The local address to send from, `None` lets the kernel pick.
+    pub src_ip: Option<IpAddr>,
+}
+
+/// Describes what one receive returned.
+#[derive(Debug, Clone, Copy)]
+pub struct RecvMeta {
+    pub addr: SocketAddr,
+    /// Bytes received, the datagrams of a GRO batch back to back.
+    pub len: usize,
+    /// Size of each received datagram but the last, `len` without GRO batching.
+    pub stride: usize,
+    pub ecn: Option<Ecn>,
+    /// The local address the datagrams were sent to.
+    pub dst_ip: Option<IpAddr>,
+}
+
+impl AsRawFd for QuicSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+impl QuicSocket {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<QuicSocket> {
+        QuicSocket::from_std(net::UdpSocket::bind(addr)?)
+    }
+
+    /// Sets up `socket` for QUIC. Offloads the kernel lacks are left off.
+    pub fn from_std(socket: net::UdpSocket) -> io::Result<QuicSocket> {
+        let fd = socket.as_raw_fd();
+        let v6 = socket.local_addr()?.is_ipv6();
+        ecn::enable_recv(fd, v6)?;
+        if v6 {
+            set_option(fd, libc::IPPROTO_IPV6, libc::IPV6_RECVPKTINFO, 1)?;
+            set_option(
+                fd,
+                libc::IPPROTO_IPV6,
+                libc::IPV6_MTU_DISCOVER,
+                libc::IPV6_PMTUDISC_PROBE,
+            )?;
+            // v4-mapped traffic on a dual stack socket.
+            let _ = set_option(fd, libc::IPPROTO_IP, libc::IP_PKTINFO, 1);
+            let _ = set_option(
+                fd,
+                libc::IPPROTO_IP,
+                libc::IP_MTU_DISCOVER,
+                libc::IP_PMTUDISC_PROBE,
+            );
+        } else {
+            set_option(fd, libc::IPPROTO_IP, libc::IP_PKTINFO, 1)?;
+            set_option(
+                fd,
+                libc::IPPROTO_IP,
+                libc::IP_MTU_DISCOVER,
+                libc::IP_PMTUDISC_PROBE,
+            )?;
+        }
+        // both offloads need Linux 5.0.
+        let gro_segments = match set_option(fd, libc::SOL_UDP, libc::UDP_GRO, 1) {
+            Ok(_) => MAX_GSO_SEGMENTS,
+            Err(_) => 1,
+        };
+        let max_gso_segments = match get_option(fd, libc::SOL_UDP, libc::UDP_SEGMENT) {
+            Ok(_) => MAX_GSO_SEGMENTS,
+            Err(_) => 1,
+        };
+        Ok(QuicSocket {
+            inner: socket,
+            v6,
+            max_gso_segments,
+            gro_segments,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    /// Most datagrams one [`Transmit`] may carry, 1 without segmentation offload.
+    pub fn max_gso_segments(&self) -> usize {
+        self.max_gso_segments
+    }
+
+    /// Most datagrams one receive may return, 1 without receive offload. Receive
+    /// buffers should have room for this many datagrams of the largest size expected.
+    pub fn gro_segments(&self) -> usize {
+        self.gro_segments
+    }
+
+    /// Sends `transmit`, returning how many bytes were sent.
+    pub async fn send(&self, transmit: &Transmit<'_>) -> io::Result<usize> {
+        let mut destination = transmit.destination;
+        let mut v4 = destination.is_ipv4();
+        if self.v6 {
+            if let SocketAddr::V4(addr) = destination {
+                let ip = addr.ip().to_ipv6_mapped();
+                destination = SocketAddrV6::new(ip, addr.port(), 0, 0).into();
+            }
+        } else if !v4 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "IPv6 destination on an IPv4 socket",
+            ));
+        }
+        if let SocketAddr::V6(addr) = destination {
+            v4 = addr.ip().to_ipv4_mapped().is_some();
+        }
+
+        let mut control = Cmsgs::with_capacity(CONTROL_LEN);
+        if let Some(ecn) = transmit.ecn {
+            ecn::push(&mut control, ecn, v4);
+        }
+        if let Some(size) = transmit.segment_size {
+            if size < transmit.contents.len() {
+                control.push(libc::SOL_UDP, libc::UDP_SEGMENT, size as u16);
+            }
+        }
+        match transmit.src_ip {
+            Some(IpAddr::V4(ip)) => control.push(libc::IPPROTO_IP, libc::IP_PKTINFO, pktinfo(ip)),
+            Some(IpAddr::V6(ip)) => match ip.to_ipv4_mapped() {
+                Some(ip) if v4 => control.push(libc::IPPROTO_IP, libc::IP_PKTINFO, pktinfo(ip)),
+                _ => control.push(libc::IPPROTO_IPV6, libc::IPV6_PKTINFO, pktinfo6(ip)),
+            },
+            None => {}
+        }
+
+        let buf = transmit.contents.to_vec();
+        let completion =
+            Action::sendmsg_control(self.as_raw_fd(), buf, &destination, control)?.await;
+        completion.output()
+    }
+
+    /// Receives into `buf`, which should hold `gro_segments` datagrams.
+    pub async fn recv(&self, buf: &mut [u8]) -> io::Result<RecvMeta> {
+        let control = Cmsgs::with_capacity(CONTROL_LEN);
+        let completion = Action::recvmsg_control(self.as_raw_fd(), buf.len(), control)?.await;
+        let (n, recvmsg) = completion.into_parts();
+        let n = n?;
+        let (data, addr, control) = recvmsg.received(n)?;
+        buf[..n].copy_from_slice(&data);
+
+        let mut meta = RecvMeta {
+            addr: unmap(addr),
+            len: n,
+            stride: n,
+            ecn: None,
+            dst_ip: None,
+        };
+        for (level, ty, data) in control.iter() {
+            match (level, ty) {
+                (libc::SOL_UDP, libc::UDP_GRO) => {
+                    meta.stride = read::<libc::c_int>(data).map_or(n, |size| size as usize);
+                }
+                (libc::IPPROTO_IP, libc::IP_PKTINFO) => {
+                    if let Some(info) = read::<libc::in_pktinfo>(data) {
+                        let ip = Ipv4Addr::from(u32::from_be(info.ipi_addr.s_addr));
+                        meta.dst_ip = Some(ip.into());
+                    }
+                }
+                (libc::IPPROTO_IPV6, libc::IPV6_PKTINFO) => {
+                    if let Some(info) = read::<libc::in6_pktinfo>(data) {
+                        let ip = Ipv6Addr::from(info.ipi6_addr.s6_addr);
+                        meta.dst_ip = Some(ip.to_ipv4_mapped().map_or(ip.into(), IpAddr::V4));
+                    }
+                }
+                _ => {
+                    if let Some(ecn) = ecn::parse(level, ty, data) {
+                        meta.ecn = Some(ecn);
+                    }
+                }
+            }
+        }
+        Ok(meta)
+    }
+}
+
+/// Reports v4-mapped senders of a dual stack socket as IPv4.
+fn unmap(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(v6) => match v6.ip().to_ipv4_mapped() {
+            Some(ip) => SocketAddr::new(ip.into(), v6.port()),
+            None => addr,
+        },
+        SocketAddr::V4(_) => addr,
+    }
+}
+
+fn pktinfo(ip: Ipv4Addr) -> libc::in_pktinfo {
+    libc::in_pktinfo {
+        ipi_ifindex: 0,
+        ipi_spec_dst: libc::in_addr {
+            s_addr: u32::from_ne_bytes(ip.octets()),
+        },
+        ipi_addr: libc::in_addr { s_addr: 0 },
+    }
+}
+
+fn pktinfo6(ip: Ipv6Addr) -> libc::in6_pktinfo {
+    libc::in6_pktinfo {
+        ipi6_addr: libc::in6_addr {
+            s6_addr: ip.octets(),
+        },
+        ipi6_ifindex: 0,
+    }
+}
+
+fn read<T: Copy>(data: &[u8]) -> Option<T> {
+    if data.len() < mem::size_of::<T>() {
+        return None;
+    }
+    Some(unsafe { std::ptr::read_unaligned(data.as_ptr().cast()) })
+}
+
+fn set_option(
+    fd: RawFd,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: libc::c_int,
+) -> io::Result<()> {
+    syscall!(setsockopt(
+        fd,
+        level,
+        name,
+        &value as *const _ as *const libc::c_void,
+        mem::size_of::<libc::c_int>() as libc::socklen_t
+    ))?;
+    Ok(())
+}
+
+fn get_option(fd: RawFd, level: libc::c_int, name: libc::c_int) -> io::Result<libc::c_int> {
+    let mut value: libc::c_int = 0;
+    let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+    syscall!(getsockopt(
+        fd,
+        level,
+        name,
+        &mut value as *mut _ as *mut libc::c_void,
+        &mut len
+    ))?;
+    Ok(value)
+}
@@ -0,0 +1,133 @@
+use std::io;
+use std::mem;
+use std::os::unix::io::RawFd;
+use std::time::Duration;
+
+/// TCP keepalive probing of an idle connection, see
+/// [`TcpStream::set_keepalive`](crate::net::TcpStream::set_keepalive).
+///
+/// Fields left at `None` keep the system defaults when set, and are all filled in when
+/// read back.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Keepalive {
+    /// How long the connection is idle before the first probe goes out.
+    pub time: Option<Duration>,
+    /// How long to wait for an answer before the next probe.
+    pub interval: Option<Duration>,
+    /// How many unanswered probes drop the connection.
+    pub retries: Option<u32>,
+}
+
+pub(crate) fn setsockopt<T>(
+    fd: RawFd,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: T,
+) -> io::Result<()> {
+    syscall!(setsockopt(
+        fd,
+        level,
+        name,
+        &value as *const T as *const libc::c_void,
+        mem::size_of::<T>() as libc::socklen_t
+    ))?;
+    Ok(())
+}
+
+/// Reads an option of type `T`, which has to be valid for any bit pattern.
+pub(crate) fn getsockopt<T: Copy>(
+    fd: RawFd,
+    level: libc::c_int,
+    name: libc::c_int,
+) -> io::Result<T> {
+    let mut value = mem::MaybeUninit::<T>::zeroed();
+    let mut len = mem::size_of::<T>() as libc::socklen_t;
+    syscall!(getsockopt(
+        fd,
+        level,
+        name,
+        value.as_mut_ptr() as *mut libc::c_void,
+        &mut len
+    ))?;
+    Ok(unsafe { value.assume_init() })
+}
+
+pub(crate) fn set_nodelay(fd: RawFd, nodelay: bool) -> io::Result<()> {
+    setsockopt(
+        fd,
+        libc::IPPROTO_TCP,
+        libc::TCP_NODELAY,
+        nodelay as libc::c_int,
+    )
+}
+
+pub(crate) fn nodelay(fd: RawFd) -> io::Result<bool> {
+    getsockopt::<libc::c_int>(fd, libc::IPPROTO_TCP, libc::TCP_NODELAY).map(|on| on != 0)
+}
+
+pub(crate) fn set_ttl(fd: RawFd, ttl: u32) -> io::Result<()> {
+    setsockopt(fd, libc::IPPROTO_IP, libc::IP_TTL, ttl as libc::c_int)
+}
+
+pub(crate) fn ttl(fd: RawFd) -> io::Result<u32> {
+    getsockopt::<libc::c_int>(fd, libc::IPPROTO_IP, libc::IP_TTL).map(|ttl| ttl as u32)
+}
+
+pub(crate) fn set_linger(fd: RawFd, linger: Option<Duration>) -> io::Result<()> {
+    let linger = libc::linger {
+        l_onoff: linger.is_some() as libc::c_int,
+        l_linger: linger.map_or(0, seconds),
+    };
+    setsockopt(fd, libc::SOL_SOCKET, libc::SO_LINGER, linger)
+}
+
+pub(crate) fn linger(fd: RawFd) -> io::Result<Option<Duration>> {
+    let linger = getsockopt::<libc::linger>(fd, libc::SOL_SOCKET, libc::SO_LINGER)?;
+    Ok((linger.l_onoff != 0).then(|| Duration::from_secs(linger.l_linger as u64)))
+}
+
+/// Sets a zero linger timeout on `fd`, which turns its close into a reset.
+pub(crate) fn reset_on_close(fd: RawFd) -> io::Result<()> {
+    set_linger(fd, Some(Duration::ZERO))
+}
+
+pub(crate) fn set_keepalive(fd: RawFd, keepalive: Option<Keepalive>) -> io::Result<()> {
+    let keepalive = match keepalive {
+        Some(keepalive) => keepalive,
+        None => return setsockopt(fd, libc::SOL_SOCKET, libc::SO_KEEPALIVE, 0 as libc::c_int),
+    };
+    if let Some(time) = keepalive.time {
+        setsockopt(fd, libc::IPPROTO_TCP, libc::TCP_KEEPIDLE, seconds(time))?;
+    }
+    if let Some(interval) = keepalive.interval {
+        setsockopt(
+            fd,
+            libc::IPPROTO_TCP,
+            libc::TCP_KEEPINTVL,
+            seconds(interval),
+        )?;
+    }
+    if let Some(retries) = keepalive.retries {
+        let retries = retries.min(libc::c_int::MAX as u32) as libc::c_int;
+        setsockopt(fd, libc::IPPROTO_TCP, libc::TCP_KEEPCNT, retries)?;
+    }
+    setsockopt(fd, libc::SOL_SOCKET, libc::SO_KEEPALIVE, 1 as libc::c_int)
+}
+
+pub(crate) fn keepalive(fd: RawFd) -> io::Result<Option<Keepalive>> {
+    if getsockopt::<libc::c_int>(fd, libc::SOL_SOCKET, libc::SO_KEEPALIVE)? == 0 {
+        return Ok(None);
+    }
+    let option = |name| getsockopt::<libc::c_int>(fd, libc::IPPROTO_TCP, name);
+    Ok(Some(Keepalive {
+        time: Some(Duration::from_secs(option(libc::TCP_KEEPIDLE)? as u64)),
+        interval: Some(Duration::from_secs(option(libc::TCP_KEEPINTVL)? as u64)),
+        retries: Some(option(libc::TCP_KEEPCNT)? as u32),
+    }))
+}
+
+/// Whole seconds, rounded up so a short timeout does not turn into none at all.
+fn seconds(duration: Duration) -> libc::c_int {
+    let secs = duration.as_secs() + (duration.subsec_nanos() > 0) as u64;
+    secs.min(libc::c_int::MAX as u64) as libc::c_int
+}
@@ -1,35 +1,586 @@
+use std::cell::RefCell;
+use std::future::Future;
 use std::io;
-use std::net::{self, Ipv4Addr, SocketAddr, SocketAddrV4, ToSocketAddrs};
-use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::net::{self, SocketAddr};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 
+use futures_util::future::poll_fn;
+
+use super::proxy_protocol;
+use super::rate_limit::{RateLimit, RateLimiter};
 use super::stream::TcpStream;
-use crate::driver::Action;
+use crate::driver::accept::AcceptMulti;
+use crate::driver::connect;
+use crate::driver::{self, Action, EventKind};
+use crate::net::addr::{self, ToSocketAddrs};
+use crate::net::socket::{self, Keepalive};
+use crate::runtime::{self, LoadMetrics};
+use crate::time::{self, Delay};
+
+/// What to do with a connection right after it was accepted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    /// Hand the connection to the caller of `accept`.
+    Accept,
+    /// Close the connection gracefully.
+    Close,
+    /// Close the connection with a reset, the peer sees `ECONNRESET`.
+    Reset,
+}
+
+/// Why a listener re-armed its multishot accept, passed to the hook set with
+/// [`TcpListener::set_rearm_hook`].
+#[derive(Debug)]
+pub struct Rearm {
+    /// The error the kernel ended the accept with, `None` if it ended it without one,
+    /// as it does when the completion queue overflowed.
+    pub error: Option<io::Error>,
+    /// Re-arms since the last accepted connection, starting at 1.
+    pub attempt: u32,
+    /// How long the listener waits before arming the accept again.
+    pub backoff: Duration,
+}
+
+/// How the socket of a listener is set up, see [`TcpListener::bind_with`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ListenOptions {
+    /// Sets `SO_REUSEPORT`, so other listeners that set it as well can bind the same
+    /// address while this one is open, the kernel spreading the connections over them.
+    /// Off by default: binding an address another listener holds fails with
+    /// `ErrorKind::AddrInUse`.
+    pub reuse_port: bool,
+    /// Length of the queue of connections not accepted yet, 128 by default as with std.
+    pub backlog: i32,
+    /// Sets `TCP_FASTOPEN` with this queue length, so clients connecting with
+    /// [`TcpStream::connect_with_data`] get their data to the listener with the SYN. At
+    /// most that many such connections wait for their handshake to complete at once. 0,
+    /// the default, turns TCP Fast Open off.
+    pub fast_open: u32,
+}
+
+impl Default for ListenOptions {
+    fn default() -> ListenOptions {
+        ListenOptions {
+            reuse_port: false,
+            backlog: 128,
+            fast_open: 0,
+        }
+    }
+}
+
+/// Backoff after the first failed accept, doubled on every further failure.
+const MIN_BACKOFF: Duration = Duration::from_millis(1);
 
+const MAX_BACKOFF: Duration = Duration::from_secs(1);
+
+type Admit = Rc<dyn Fn(&SocketAddr, &LoadMetrics) -> Admission>;
+
+type RearmHook = Rc<dyn Fn(&Rearm)>;
+
+/// A connection waiting for its PROXY protocol header.
+type Handshake = Pin<Box<dyn Future<Output = io::Result<(TcpStream, SocketAddr)>>>>;
+
+/// Accepts connections with a single multishot accept.
+///
+/// # Re-arming
+///
+/// The kernel ends a multishot accept when it runs out of resources, such as file
+/// descriptors, or when the completion queue overflows. The listener then arms a new one
+/// on its own, waiting with an exponential backoff while the accept keeps failing, and
+/// reports every re-arm to the hook set with [`set_rearm_hook`]. Running out of
+/// resources is not returned from `accept`, which keeps waiting for the next
+/// connection, other errors are.
+///
+/// On kernels without multishot accept (before Linux 5.19) every `accept` submits an
+/// accept of its own.
+///
+/// [`set_rearm_hook`]: TcpListener::set_rearm_hook
 pub struct TcpListener {
     inner: net::TcpListener,
+    admit: Option<Admit>,
+    /// Shared with the listeners created by `rebind`.
+    rate_limit: Option<Rc<RefCell<RateLimiter>>>,
+    rearm: Option<RearmHook>,
+    /// How long an accepted connection gets to send its PROXY protocol header, `None`
+    /// if none is expected.
+    proxy_protocol: Option<Duration>,
+    handshakes: RefCell<Vec<Handshake>>,
+    incoming: RefCell<Incoming>,
+}
+
+/// The multishot accept connections are taken from.
+struct Incoming {
+    action: Option<Action<AcceptMulti>>,
+    /// Cleared once the kernel rejected a multishot accept, and from the start if it
+    /// has none.
+    multi: bool,
+    /// Re-arms since the last accepted connection.
+    attempt: u32,
+    /// Delays arming the accept again after it failed.
+    backoff: Option<Delay>,
+}
+
+impl Default for Incoming {
+    fn default() -> Incoming {
+        Incoming {
+            action: None,
+            multi: driver::features().is_none_or(|features| features.multishot_accept),
+            attempt: 0,
+            backoff: None,
+        }
+    }
+}
+
+impl Drop for Incoming {
+    fn drop(&mut self) {
+        if let Some(action) = self.action.as_mut() {
+            action.close_accepted();
+        }
+    }
+}
+
+impl AsRawFd for TcpListener {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
 }
 
 impl TcpListener {
+    /// Binds a listener to the first of the resolved addresses that works. Binding to
+    /// port 0 picks a free port, `local_addr` tells which.
     pub async fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<TcpListener> {
-        let listener = net::TcpListener::bind(addr)?;
-        Ok(TcpListener { inner: listener })
+        TcpListener::bind_with(addr, ListenOptions::default()).await
+    }
+
+    /// Like `bind`, setting the socket up with `options`.
+    pub async fn bind_with<A: ToSocketAddrs>(
+        addr: A,
+        options: ListenOptions,
+    ) -> io::Result<TcpListener> {
+        let mut last_err = None;
+        for addr in addr::resolve(&addr).await? {
+            match connect::listen(addr, options.backlog, options.reuse_port, options.fast_open) {
+                Ok(fd) => {
+                    return TcpListener::from_std(unsafe { net::TcpListener::from_raw_fd(fd) })
+                }
+                Err(e) => last_err = Some(e),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "could not resolve to any address",
+            )
+        }))
+    }
+
+    /// Creates a new listener on the address this one is bound to, resolved port
+    /// included, with its socket set up with `options`. It keeps the admission hook and
+    /// the PROXY protocol setting and shares the rate limit.
+    ///
+    /// Binding the address while this listener is open needs `SO_REUSEPORT` on both, see
+    /// [`ListenOptions::reuse_port`]. Both then accept connections until this one is
+    /// dropped, so the new listener can be swapped in without refusing any connection.
+    /// Without it, drop this listener first.
+    pub fn rebind(&self, options: ListenOptions) -> io::Result<TcpListener> {
+        let fd = connect::listen(
+            self.local_addr()?,
+            options.backlog,
+            options.reuse_port,
+            options.fast_open,
+        )?;
+        Ok(TcpListener {
+            inner: unsafe { net::TcpListener::from_raw_fd(fd) },
+            admit: self.admit.clone(),
+            rate_limit: self.rate_limit.clone(),
+            rearm: self.rearm.clone(),
+            proxy_protocol: self.proxy_protocol,
+            handshakes: RefCell::default(),
+            incoming: RefCell::default(),
+        })
     }
 
     pub fn from_std(listener: net::TcpListener) -> io::Result<TcpListener> {
-        Ok(TcpListener { inner: listener })
+        Ok(TcpListener {
+            inner: listener,
+            admit: None,
+            rate_limit: None,
+            rearm: None,
+            proxy_protocol: None,
+            handshakes: RefCell::default(),
+            incoming: RefCell::default(),
+        })
+    }
+
+    /// Sets a hook that decides, given the peer address and the current runtime load,
+    /// whether an accepted connection is handed out by `accept` or shed right away.
+    pub fn set_admission<F>(&mut self, admit: F)
+    where
+        F: Fn(&SocketAddr, &LoadMetrics) -> Admission + 'static,
+    {
+        self.admit = Some(Rc::new(admit));
+    }
+
+    /// Limits how fast connections from a single source IP are accepted. Connections over
+    /// the limit are shed before the admission hook sees them, see [`RateLimit`]. `None`
+    /// removes the limit.
+    pub fn set_rate_limit(&mut self, limit: Option<RateLimit>) {
+        self.rate_limit = limit.map(|limit| Rc::new(RefCell::new(RateLimiter::new(limit))));
+    }
+
+    /// Sets a hook called whenever the kernel ended the multishot accept and the listener
+    /// re-arms it, see [Re-arming](TcpListener#re-arming).
+    pub fn set_rearm_hook<F>(&mut self, hook: F)
+    where
+        F: Fn(&Rearm) + 'static,
+    {
+        self.rearm = Some(Rc::new(hook));
     }
 
+    /// Expects every accepted connection to start with a PROXY protocol header, version 1
+    /// or 2, as load balancers send to pass on the client's address. `accept` returns
+    /// the client's address from the header, and the stream keeps the whole header, see
+    /// [`TcpStream::proxy_header`]. The rate limit and the admission hook see the
+    /// client's address as well.
+    ///
+    /// A connection that sends no valid header within `timeout` is closed. Headers are
+    /// read while `accept` waits, the connections sending theirs do not hold up each
+    /// other. `None` turns this off.
+    pub fn set_proxy_protocol(&mut self, timeout: Option<Duration>) {
+        self.proxy_protocol = timeout;
+    }
+
+    /// Accepts the next connection, along with the address of its peer.
+    ///
+    /// A connection whose peer reset it before it was accepted fails with
+    /// `ErrorKind::ConnectionAborted`, as with `accept(2)`. The listener keeps working, the
+    /// next call takes the next connection.
     pub async fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
-        let completion = Action::accept(self.inner.as_raw_fd())?.await;
-        let fd = completion.result?;
-        let stream = unsafe { TcpStream::from_raw_fd(fd) };
-        let addr = stream
-            .peer_addr()
-            .unwrap_or_else(|_| SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), 0)));
-        Ok((stream, addr))
+        self.accept_until(None).await
+    }
+
+    /// Like `accept`, failing with `ErrorKind::TimedOut` if no connection was admitted
+    /// within `timeout`.
+    pub async fn accept_timeout(&self, timeout: Duration) -> io::Result<(TcpStream, SocketAddr)> {
+        self.accept_until(Some(Instant::now() + timeout)).await
+    }
+
+    async fn accept_until(&self, deadline: Option<Instant>) -> io::Result<(TcpStream, SocketAddr)> {
+        loop {
+            // connections shed by the admission hook count against the timeout.
+            let (stream, addr) = self.next_admissible(deadline).await?;
+            let limited = self
+                .rate_limit
+                .as_ref()
+                .and_then(|limiter| limiter.borrow_mut().admit(addr.ip(), Instant::now()));
+            let admission = match (limited, &self.admit) {
+                (Some(shed), _) => shed,
+                (None, Some(admit)) => admit(&addr, &runtime::load()),
+                (None, None) => Admission::Accept,
+            };
+            match admission {
+                Admission::Accept => return Ok((stream, addr)),
+                Admission::Close => drop(stream),
+                Admission::Reset => {
+                    let _ = socket::reset_on_close(stream.as_raw_fd());
+                    drop(stream);
+                }
+            }
+        }
+    }
+
+    /// The next connection to admit along with the client's address. Behind the PROXY
+    /// protocol, that is the next connection that sent a valid header.
+    async fn next_admissible(
+        &self,
+        deadline: Option<Instant>,
+    ) -> io::Result<(TcpStream, SocketAddr)> {
+        let timeout = match self.proxy_protocol {
+            Some(timeout) => timeout,
+            None => {
+                let (stream, addr) = self.next(deadline).await?;
+                return Ok((TcpStream::from_std_with_peer(stream, addr), addr));
+            }
+        };
+        let next = self.next(deadline);
+        pin_mut!(next);
+        poll_fn(|cx| loop {
+            if let Poll::Ready(accepted) = self.poll_handshakes(cx) {
+                return Poll::Ready(Ok(accepted));
+            }
+            let (stream, addr) = ready!(next.as_mut().poll(cx))?;
+            next.set(self.next(deadline));
+            let stream = TcpStream::from_std_with_peer(stream, addr);
+            let handshake = proxy_protocol::handshake(stream, timeout);
+            self.handshakes.borrow_mut().push(Box::pin(handshake));
+        })
+        .await
+    }
+
+    /// Takes the first connection that sent its PROXY protocol header, closing the ones
+    /// that failed to.
+    fn poll_handshakes(&self, cx: &mut Context<'_>) -> Poll<(TcpStream, SocketAddr)> {
+        let mut handshakes = self.handshakes.borrow_mut();
+        let mut i = 0;
+        while i < handshakes.len() {
+            match handshakes[i].as_mut().poll(cx) {
+                Poll::Ready(res) => {
+                    drop(handshakes.swap_remove(i));
+                    if let Ok(accepted) = res {
+                        return Poll::Ready(accepted);
+                    }
+                }
+                Poll::Pending => i += 1,
+            }
+        }
+        Poll::Pending
+    }
+
+    /// The next accepted connection along with its peer address.
+    async fn next(&self, deadline: Option<Instant>) -> io::Result<(net::TcpStream, SocketAddr)> {
+        let listener = self.inner.as_raw_fd();
+        while self.incoming.borrow().multi {
+            let accept = poll_fn(|cx| self.poll_accept(cx));
+            let accepted = match deadline {
+                Some(deadline) => time::timeout_at(deadline, accept).await?,
+                None => accept.await,
+            };
+            match accepted {
+                Ok(fd) => {
+                    let stream = unsafe { net::TcpStream::from_raw_fd(fd) };
+                    // a multishot accept leaves out the address, every shot would write
+                    // it to the same place. A peer that is gone already has none, which
+                    // `accept(2)` reports as an aborted connection.
+                    return match stream.peer_addr() {
+                        Ok(addr) => Ok((stream, addr)),
+                        Err(e) if e.raw_os_error() == Some(libc::ENOTCONN) => {
+                            Err(io::Error::from_raw_os_error(libc::ECONNABORTED))
+                        }
+                        Err(e) => Err(e),
+                    };
+                }
+                Err(e) if !self.incoming.borrow().multi => drop(e),
+                Err(e) => return Err(e),
+            }
+        }
+        let action = match deadline {
+            Some(deadline) => {
+                let timeout = deadline.saturating_duration_since(Instant::now());
+                Action::accept_timeout(listener, timeout)?
+            }
+            None => Action::accept(listener)?,
+        };
+        let (fd, accept) = action.await.into_parts();
+        Ok((net::TcpStream::from(fd?), accept.peer_addr()?))
+    }
+
+    /// Takes the next connection from the multishot accept, arming it first if needed.
+    fn poll_accept(&self, cx: &mut Context<'_>) -> Poll<io::Result<RawFd>> {
+        let mut incoming = self.incoming.borrow_mut();
+        let incoming = &mut *incoming;
+        loop {
+            if let Some(backoff) = incoming.backoff.as_mut() {
+                ready!(Pin::new(backoff).poll(cx));
+                incoming.backoff = None;
+            }
+            let action = match incoming.action.as_mut() {
+                Some(action) => action,
+                None => incoming
+                    .action
+                    .insert(Action::accept_multi(self.inner.as_raw_fd())?),
+            };
+            let shot = match ready!(action.poll_next(cx)) {
+                Some(shot) => shot,
+                None => {
+                    incoming.action = None;
+                    continue;
+                }
+            };
+            let key = action.key;
+            let ended = action.is_finished();
+            if ended {
+                incoming.action = None;
+            }
+            match shot.result {
+                Ok(fd) => {
+                    incoming.attempt = 0;
+                    if ended {
+                        self.rearmed(incoming, key, None);
+                    }
+                    return Poll::Ready(Ok(fd));
+                }
+                // without IORING_ACCEPT_MULTISHOT the kernel refuses the accept up front.
+                Err(e) if e.raw_os_error() == Some(libc::EINVAL) && incoming.attempt == 0 => {
+                    incoming.multi = false;
+                    return Poll::Ready(Err(e));
+                }
+                Err(e) if is_exhausted(&e) => self.rearmed(incoming, key, Some(e)),
+                Err(e) => {
+                    let reported = match e.raw_os_error() {
+                        Some(code) => io::Error::from_raw_os_error(code),
+                        None => io::Error::new(e.kind(), e.to_string()),
+                    };
+                    self.rearmed(incoming, key, Some(reported));
+                    return Poll::Ready(Err(e));
+                }
+            }
+        }
+    }
+
+    /// Reports that the accept ended, backing off before it is armed again if it failed.
+    fn rearmed(&self, incoming: &mut Incoming, key: u64, error: Option<io::Error>) {
+        incoming.attempt += 1;
+        let result = error
+            .as_ref()
+            .and_then(io::Error::raw_os_error)
+            .map_or(0, |e| -e);
+        driver::record_event(EventKind::Rearm { key, result });
+        let backoff = match error {
+            Some(_) => {
+                let doublings = (incoming.attempt - 1).min(16);
+                (MIN_BACKOFF * (1 << doublings)).min(MAX_BACKOFF)
+            }
+            None => Duration::ZERO,
+        };
+        if backoff > Duration::ZERO {
+            incoming.backoff = Some(time::delay_for(backoff));
+        }
+        if let Some(hook) = &self.rearm {
+            hook(&Rearm {
+                error,
+                attempt: incoming.attempt,
+                backoff,
+            });
+        }
+    }
+
+    /// Binds the listener to the interface or VRF device with index `ifindex`, so it only
+    /// accepts connections arriving on that device. `None` removes the binding.
+    pub fn bind_device_by_index(&self, ifindex: Option<u32>) -> io::Result<()> {
+        connect::bind_device(self.inner.as_raw_fd(), ifindex)
+    }
+
+    /// The index of the device the listener is bound to, `None` if it is not bound.
+    pub fn device_index(&self) -> io::Result<Option<u32>> {
+        connect::device_index(self.inner.as_raw_fd())
+    }
+
+    /// Whether accepted streams start with `TCP_NODELAY`, which they inherit from the
+    /// listener.
+    pub fn nodelay(&self) -> io::Result<bool> {
+        socket::nodelay(self.inner.as_raw_fd())
+    }
+
+    pub fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        socket::set_nodelay(self.inner.as_raw_fd(), nodelay)
+    }
+
+    pub fn ttl(&self) -> io::Result<u32> {
+        socket::ttl(self.inner.as_raw_fd())
     }
 
+    /// Sets the time to live of the packets sent by the listener and the streams it
+    /// accepts.
+    pub fn set_ttl(&self, ttl: u32) -> io::Result<()> {
+        socket::set_ttl(self.inner.as_raw_fd(), ttl)
+    }
+
+    pub fn linger(&self) -> io::Result<Option<Duration>> {
+        socket::linger(self.inner.as_raw_fd())
+    }
+
+    /// Sets `SO_LINGER`, which accepted streams inherit, see
+    /// [`TcpStream::set_linger`].
+    pub fn set_linger(&self, linger: Option<Duration>) -> io::Result<()> {
+        socket::set_linger(self.inner.as_raw_fd(), linger)
+    }
+
+    pub fn keepalive(&self) -> io::Result<Option<Keepalive>> {
+        socket::keepalive(self.inner.as_raw_fd())
+    }
+
+    /// Sets the keepalive probing accepted streams inherit, see
+    /// [`TcpStream::set_keepalive`].
+    pub fn set_keepalive(&self, keepalive: Option<Keepalive>) -> io::Result<()> {
+        socket::set_keepalive(self.inner.as_raw_fd(), keepalive)
+    }
+
+    /// Returns the address this listener is bound to, with the port resolved.
     pub fn local_addr(&self) -> io::Result<SocketAddr> {
         self.inner.local_addr()
     }
 }
+
+/// Whether `err` means the host ran out of resources, which passes on its own.
+fn is_exhausted(err: &io::Error) -> bool {
+    matches!(
+        err.raw_os_error(),
+        Some(libc::EMFILE | libc::ENFILE | libc::ENOBUFS | libc::ENOMEM)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Runtime;
+
+    #[test]
+    fn a_second_listener_on_the_address_fails_unless_both_reuse_the_port() {
+        Runtime::new().unwrap().block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let err = TcpListener::bind(addr).await.err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+            let reuse = ListenOptions {
+                reuse_port: true,
+                ..ListenOptions::default()
+            };
+            let err = listener.rebind(reuse).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+        });
+    }
+
+    #[test]
+    fn a_listener_reusing_the_port_can_be_rebound() {
+        Runtime::new().unwrap().block_on(async {
+            let reuse = ListenOptions {
+                reuse_port: true,
+                backlog: 16,
+                ..ListenOptions::default()
+            };
+            let listener = TcpListener::bind_with("127.0.0.1:0", reuse).await.unwrap();
+            let rebound = listener.rebind(reuse).unwrap();
+            assert_eq!(
+                rebound.local_addr().unwrap(),
+                listener.local_addr().unwrap()
+            );
+            drop(listener);
+            let addr = rebound.local_addr().unwrap();
+            let connect = crate::spawn(TcpStream::connect(addr));
+            let (_, peer) = rebound.accept().await.unwrap();
+            let connected = connect.await.unwrap().unwrap();
+            assert_eq!(peer, connected.local_addr().unwrap());
+        });
+    }
+
+    #[test]
+    fn a_connection_reset_before_it_was_accepted_is_reported() {
+        Runtime::new().unwrap().block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let reset = net::TcpStream::connect(addr).unwrap();
+            socket::reset_on_close(reset.as_raw_fd()).unwrap();
+            drop(reset);
+            // over loopback, the reset arrived before the accept took the connection.
+            let err = listener.accept().await.err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+            let connected = net::TcpStream::connect(addr).unwrap();
+            let (_, peer) = listener.accept().await.unwrap();
+            assert_eq!(peer, connected.local_addr().unwrap());
+        });
+    }
+}
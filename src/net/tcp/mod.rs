@@ -1,5 +1,11 @@
 pub mod listener;
+pub mod proxy_protocol;
+pub mod rate_limit;
+pub mod split;
 pub mod stream;
 
-pub use listener::TcpListener;
+pub use listener::{Admission, ListenOptions, Rearm, TcpListener};
+pub use proxy_protocol::ProxyHeader;
+pub use rate_limit::{Overflow, RateLimit};
+pub use split::{OwnedReadHalf, OwnedWriteHalf, ReadHalf, ReuniteError, WriteHalf};
 pub use stream::TcpStream;
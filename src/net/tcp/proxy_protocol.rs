@@ -0,0 +1,362 @@
+use std::convert::TryFrom;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::str;
+use std::time::Duration;
+
+use futures_util::future::poll_fn;
+use futures_util::io::AsyncBufRead;
+
+use super::TcpStream;
+use crate::time;
+
+/// The addresses a load balancer passed in the PROXY protocol header of a connection,
+/// see [`TcpListener::set_proxy_protocol`](super::TcpListener::set_proxy_protocol).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProxyHeader {
+    /// The client that connected to the load balancer.
+    pub source: SocketAddr,
+    /// The address the client connected to.
+    pub destination: SocketAddr,
+}
+
+/// Starts a version 1 header, which is a single line of text.
+const V1_PREFIX: &[u8] = b"PROXY ";
+
+/// Longest version 1 header, line ending included.
+const V1_MAX_LEN: usize = 107;
+
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Signature, version and command, family and protocol, and length of the addresses.
+const V2_HEADER_LEN: usize = 16;
+
+/// Reads the PROXY protocol header of a freshly accepted connection within `timeout`
+/// and returns the stream with the client's address, the peer's own if the header
+/// carries none.
+pub(crate) async fn handshake(
+    mut stream: TcpStream,
+    timeout: Duration,
+) -> io::Result<(TcpStream, SocketAddr)> {
+    let header = time::timeout(timeout, read_header(&mut stream)).await??;
+    let addr = match header {
+        Some(header) => header.source,
+        None => stream.peer_addr()?,
+    };
+    stream.set_proxy_header(header);
+    Ok((stream, addr))
+}
+
+/// Consumes the header, leaving the bytes after it buffered in the stream.
+async fn read_header(stream: &mut TcpStream) -> io::Result<Option<ProxyHeader>> {
+    let mut data = Vec::new();
+    loop {
+        let start = data.len();
+        let n = poll_fn(|cx| {
+            Pin::new(&mut *stream).poll_fill_buf(cx).map_ok(|buf| {
+                data.extend_from_slice(buf);
+                buf.len()
+            })
+        })
+        .await?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        match parse(&data)? {
+            Some((len, header)) => {
+                Pin::new(&mut *stream).consume(len - start);
+                return Ok(header);
+            }
+            None => Pin::new(&mut *stream).consume(n),
+        }
+    }
+}
+
+/// Parses the header at the start of `data`, returning its length and the addresses it
+/// carries, or `None` if `data` ends before the header does.
+fn parse(data: &[u8]) -> io::Result<Option<(usize, Option<ProxyHeader>)>> {
+    if data.starts_with(V2_SIGNATURE) {
+        return parse_v2(data);
+    }
+    if data.starts_with(V1_PREFIX) {
+        return parse_v1(data);
+    }
+    if V2_SIGNATURE.starts_with(data) || V1_PREFIX.starts_with(data) {
+        return Ok(None);
+    }
+    Err(invalid("missing PROXY protocol header"))
+}
+
+fn parse_v1(data: &[u8]) -> io::Result<Option<(usize, Option<ProxyHeader>)>> {
+    let end = match data.windows(2).position(|w| w == b"\r\n") {
+        Some(end) if end + 2 <= V1_MAX_LEN => end,
+        None if data.len() < V1_MAX_LEN => return Ok(None),
+        _ => return Err(invalid("PROXY protocol header too long")),
+    };
+    let line =
+        str::from_utf8(&data[..end]).map_err(|_| invalid("malformed PROXY protocol header"))?;
+    let fields: Vec<&str> = line.split(' ').collect();
+    let header = match fields[..] {
+        // the load balancer could not tell, the connection is used as it is.
+        ["PROXY", "UNKNOWN", ..] => None,
+        ["PROXY", "TCP4", src, dst, sport, dport] | ["PROXY", "TCP6", src, dst, sport, dport] => {
+            let v6 = fields[1] == "TCP6";
+            Some(ProxyHeader {
+                source: SocketAddr::new(parse_ip(src, v6)?, parse_port(sport)?),
+                destination: SocketAddr::new(parse_ip(dst, v6)?, parse_port(dport)?),
+            })
+        }
+        _ => return Err(invalid("malformed PROXY protocol header")),
+    };
+    Ok(Some((end + 2, header)))
+}
+
+fn parse_ip(ip: &str, v6: bool) -> io::Result<IpAddr> {
+    let ip = match v6 {
+        false => ip.parse::<Ipv4Addr>().map(IpAddr::V4),
+        true => ip.parse::<Ipv6Addr>().map(IpAddr::V6),
+    };
+    ip.map_err(|_| invalid("malformed address in PROXY protocol header"))
+}
+
+fn parse_port(port: &str) -> io::Result<u16> {
+    port.parse()
+        .map_err(|_| invalid("malformed port in PROXY protocol header"))
+}
+
+fn parse_v2(data: &[u8]) -> io::Result<Option<(usize, Option<ProxyHeader>)>> {
+    if data.len() < V2_HEADER_LEN {
+        return Ok(None);
+    }
+    let len = V2_HEADER_LEN + u16::from_be_bytes([data[14], data[15]]) as usize;
+    if data.len() < len {
+        return Ok(None);
+    }
+    let (version, command) = (data[12] >> 4, data[12] & 0x0f);
+    if version != 2 {
+        return Err(invalid("unsupported PROXY protocol version"));
+    }
+    let addrs = &data[V2_HEADER_LEN..len];
+    let header = match (command, data[13]) {
+        // LOCAL, the load balancer's own connection, such as a health check.
+        (0, _) => None,
+        // PROXY over TCP on IPv4 or IPv6, other families are used as they are.
+        (1, 0x11) if addrs.len() >= 12 => {
+            let ip = |at: usize| IpAddr::from(<[u8; 4]>::try_from(&addrs[at..at + 4]).unwrap());
+            Some(ProxyHeader {
+                source: SocketAddr::new(ip(0), port(&addrs[8..])),
+                destination: SocketAddr::new(ip(4), port(&addrs[10..])),
+            })
+        }
+        (1, 0x21) if addrs.len() >= 36 => {
+            let ip = |at: usize| IpAddr::from(<[u8; 16]>::try_from(&addrs[at..at + 16]).unwrap());
+            Some(ProxyHeader {
+                source: SocketAddr::new(ip(0), port(&addrs[32..])),
+                destination: SocketAddr::new(ip(16), port(&addrs[34..])),
+            })
+        }
+        (1, 0x11) | (1, 0x21) => return Err(invalid("truncated PROXY protocol header")),
+        (1, _) => None,
+        _ => return Err(invalid("unsupported PROXY protocol command")),
+    };
+    Ok(Some((len, header)))
+}
+
+fn port(data: &[u8]) -> u16 {
+    u16::from_be_bytes([data[0], data[1]])
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::thread;
+
+    use crate::net::TcpListener;
+    use crate::Runtime;
+
+    fn addr(addr: &str) -> SocketAddr {
+        addr.parse().unwrap()
+    }
+
+    /// A version 2 header with `command`, `family` and the address block `addrs`.
+    fn v2(command: u8, family: u8, addrs: &[u8]) -> Vec<u8> {
+        let mut data = V2_SIGNATURE.to_vec();
+        data.push(0x20 | command);
+        data.push(family);
+        data.extend_from_slice(&(addrs.len() as u16).to_be_bytes());
+        data.extend_from_slice(addrs);
+        data
+    }
+
+    /// Parses `data` fed a byte at a time, as if every byte came with a read of its own.
+    fn parse_split(data: &[u8]) -> io::Result<(usize, Option<ProxyHeader>)> {
+        for end in 0..data.len() {
+            if let Some(parsed) = parse(&data[..end])? {
+                return Ok(parsed);
+            }
+        }
+        Ok(parse(data)?.expect("the header is complete"))
+    }
+
+    #[test]
+    fn a_v1_header_carries_the_addresses() {
+        let data = b"PROXY TCP4 192.0.2.1 198.51.100.2 56324 443\r\nGET /";
+        let (len, header) = parse_split(data).unwrap();
+        assert_eq!(&data[len..], b"GET /");
+        let header = header.unwrap();
+        assert_eq!(header.source, addr("192.0.2.1:56324"));
+        assert_eq!(header.destination, addr("198.51.100.2:443"));
+
+        let data = b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 443\r\n";
+        let (len, header) = parse_split(data).unwrap();
+        assert_eq!(len, data.len());
+        let header = header.unwrap();
+        assert_eq!(header.source, addr("[2001:db8::1]:56324"));
+        assert_eq!(header.destination, addr("[2001:db8::2]:443"));
+    }
+
+    #[test]
+    fn a_v1_unknown_header_carries_no_addresses() {
+        let data = b"PROXY UNKNOWN ffff:f...f:ffff 443\r\nrest";
+        assert_eq!(parse_split(data).unwrap(), (data.len() - 4, None));
+        let data = b"PROXY UNKNOWN\r\n";
+        assert_eq!(parse_split(data).unwrap(), (data.len(), None));
+    }
+
+    #[test]
+    fn a_v1_header_without_a_line_ending_is_rejected() {
+        let mut data = b"PROXY TCP4 192.0.2.1 198.51.100.2 56324 443 ".to_vec();
+        data.resize(V1_MAX_LEN - 1, b' ');
+        assert!(parse(&data).unwrap().is_none());
+        data.push(b' ');
+        let err = parse(&data).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // a line ending past the limit does not make it valid.
+        data.extend_from_slice(b"\r\n");
+        assert!(parse(&data).is_err());
+    }
+
+    #[test]
+    fn a_malformed_v1_header_is_rejected() {
+        for data in [
+            &b"PROXY TCP4 192.0.2.1 198.51.100.2 56324\r\n"[..],
+            b"PROXY TCP4 2001:db8::1 198.51.100.2 56324 443\r\n",
+            b"PROXY TCP4 192.0.2.1 198.51.100.2 65536 443\r\n",
+            b"PROXY UDP4 192.0.2.1 198.51.100.2 56324 443\r\n",
+        ] {
+            assert!(parse(data).is_err());
+        }
+    }
+
+    #[test]
+    fn a_v2_proxy_header_carries_the_addresses() {
+        let mut addrs = vec![192, 0, 2, 1, 198, 51, 100, 2];
+        addrs.extend_from_slice(&56324u16.to_be_bytes());
+        addrs.extend_from_slice(&443u16.to_be_bytes());
+        let mut data = v2(1, 0x11, &addrs);
+        let len = data.len();
+        data.extend_from_slice(b"GET /");
+        let (parsed, header) = parse_split(&data).unwrap();
+        assert_eq!(parsed, len);
+        let header = header.unwrap();
+        assert_eq!(header.source, addr("192.0.2.1:56324"));
+        assert_eq!(header.destination, addr("198.51.100.2:443"));
+
+        let src: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        let dst: Ipv6Addr = "2001:db8::2".parse().unwrap();
+        let mut addrs = src.octets().to_vec();
+        addrs.extend_from_slice(&dst.octets());
+        addrs.extend_from_slice(&56324u16.to_be_bytes());
+        addrs.extend_from_slice(&443u16.to_be_bytes());
+        // TLVs after the addresses are skipped.
+        addrs.extend_from_slice(&[0x04, 0, 1, 0]);
+        let data = v2(1, 0x21, &addrs);
+        let (parsed, header) = parse_split(&data).unwrap();
+        assert_eq!(parsed, data.len());
+        let header = header.unwrap();
+        assert_eq!(header.source, addr("[2001:db8::1]:56324"));
+        assert_eq!(header.destination, addr("[2001:db8::2]:443"));
+    }
+
+    #[test]
+    fn a_v2_local_header_carries_no_addresses() {
+        let data = v2(0, 0x11, &[0; 12]);
+        assert_eq!(parse_split(&data).unwrap(), (data.len(), None));
+        let data = v2(0, 0, &[]);
+        assert_eq!(parse_split(&data).unwrap(), (data.len(), None));
+    }
+
+    #[test]
+    fn a_v2_header_of_another_family_carries_no_addresses() {
+        // PROXY over UDP on IPv4, and over a unix stream socket.
+        let data = v2(1, 0x12, &[0; 12]);
+        assert_eq!(parse(&data).unwrap(), Some((data.len(), None)));
+        let data = v2(1, 0x31, &[0; 216]);
+        assert_eq!(parse(&data).unwrap(), Some((data.len(), None)));
+    }
+
+    #[test]
+    fn a_truncated_v2_address_block_is_rejected() {
+        assert!(parse(&v2(1, 0x11, &[0; 11])).is_err());
+        assert!(parse(&v2(1, 0x21, &[0; 35])).is_err());
+        // a header announcing more than was read yet waits for the rest.
+        let data = v2(1, 0x11, &[0; 12]);
+        assert!(parse(&data[..data.len() - 1]).unwrap().is_none());
+    }
+
+    #[test]
+    fn a_v2_header_of_another_version_or_command_is_rejected() {
+        let mut data = v2(1, 0x11, &[0; 12]);
+        data[12] = 0x11;
+        assert!(parse(&data).is_err());
+        assert!(parse(&v2(2, 0x11, &[0; 12])).is_err());
+    }
+
+    #[test]
+    fn a_connection_without_a_header_is_rejected() {
+        let err = parse(b"GET / HTTP/1.1\r\n").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(parse(b"PROXX").is_err());
+        assert!(parse(b"\r\n\r\n\0\r\nQUIX").is_err());
+        // a prefix of either signature may still turn into a header.
+        assert!(parse(b"PROX").unwrap().is_none());
+        assert!(parse(b"\r\n\r\n").unwrap().is_none());
+    }
+
+    #[test]
+    fn a_header_split_across_reads_is_consumed_before_the_data() {
+        Runtime::new().unwrap().block_on(async {
+            let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            listener.set_proxy_protocol(Some(Duration::from_secs(5)));
+            let local = listener.local_addr().unwrap();
+            let client = thread::spawn(move || {
+                let mut stream = std::net::TcpStream::connect(local).unwrap();
+                stream.set_nodelay(true).unwrap();
+                stream.write_all(b"PROXY TCP4 192.0.2.1 ").unwrap();
+                thread::sleep(Duration::from_millis(20));
+                stream
+                    .write_all(b"198.51.100.2 56324 443\r\nhello")
+                    .unwrap();
+                stream
+            });
+            let (mut stream, peer) = listener.accept().await.unwrap();
+            assert_eq!(peer, addr("192.0.2.1:56324"));
+            let header = stream.proxy_header().unwrap();
+            assert_eq!(header.destination, addr("198.51.100.2:443"));
+            let mut buf = [0; 5];
+            let mut read = 0;
+            while read < buf.len() {
+                read += stream.read(&mut buf[read..]).await.unwrap();
+            }
+            assert_eq!(&buf, b"hello");
+            drop(client.join().unwrap());
+        });
+    }
+}
@@ -0,0 +1,264 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use super::listener::Admission;
+
+/// Sources tracked at most by default.
+const MAX_SOURCES: usize = 65536;
+
+/// The longest time a token takes to come back, a year.
+const MAX_REFILL: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// Limits how fast a listener accepts connections from a single source IP, see
+/// [`TcpListener::set_rate_limit`](super::TcpListener::set_rate_limit).
+///
+/// Every source has a bucket of `burst` connections that refills by one every `refill`.
+/// A connection from a source with an empty bucket is shed right after the accept, with
+/// the admission set by [`shed_with`](RateLimit::shed_with). Sources whose bucket is
+/// full again are forgotten, so the table only holds the sources that connected
+/// recently.
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    burst: u32,
+    refill: Duration,
+    max_sources: usize,
+    overflow: Overflow,
+    shed: Admission,
+}
+
+/// What happens to a new source once the table of tracked sources is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// Accept connections from untracked sources without limiting them.
+    Permissive,
+    /// Shed every connection from an untracked source until the table has room again.
+    DenyAll,
+}
+
+impl RateLimit {
+    /// Allows `burst` connections from a source at once, and one more every `refill`.
+    /// A `refill` longer than a year counts as a year.
+    pub fn new(burst: u32, refill: Duration) -> RateLimit {
+        RateLimit {
+            burst: burst.max(1),
+            refill: refill.min(MAX_REFILL),
+            max_sources: MAX_SOURCES,
+            overflow: Overflow::Permissive,
+            shed: Admission::Close,
+        }
+    }
+
+    /// Tracks at most `max` sources, 65536 by default.
+    pub fn max_sources(mut self, max: usize) -> RateLimit {
+        self.max_sources = max;
+        self
+    }
+
+    /// What happens to new sources once `max_sources` are tracked, `Permissive` by
+    /// default.
+    pub fn overflow(mut self, overflow: Overflow) -> RateLimit {
+        self.overflow = overflow;
+        self
+    }
+
+    /// How connections over the limit are shed, `Close` by default. `Accept` turns the
+    /// limit into accounting only.
+    pub fn shed_with(mut self, shed: Admission) -> RateLimit {
+        self.shed = shed;
+        self
+    }
+}
+
+/// The buckets of the sources a listener accepted from recently.
+pub(crate) struct RateLimiter {
+    limit: RateLimit,
+    /// When the bucket of each source is full again, past the last connection by
+    /// `refill` for every token in use.
+    sources: HashMap<IpAddr, Instant>,
+    /// No bucket is full again before then, a full table is only scanned for buckets
+    /// to forget once it passed.
+    prune_at: Option<Instant>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limit: RateLimit) -> RateLimiter {
+        RateLimiter {
+            limit,
+            sources: HashMap::new(),
+            prune_at: None,
+        }
+    }
+
+    /// Takes a token from the bucket of `ip`, returns how to shed the connection if
+    /// there was none.
+    pub(crate) fn admit(&mut self, ip: IpAddr, now: Instant) -> Option<Admission> {
+        let limit = self.limit;
+        // an IPv4 peer of a dual stack socket counts as the same source as over IPv4.
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            ip => ip,
+        };
+        if !self.sources.contains_key(&ip) && self.sources.len() >= limit.max_sources {
+            self.prune(now);
+            if self.sources.len() >= limit.max_sources {
+                return match limit.overflow {
+                    Overflow::Permissive => None,
+                    Overflow::DenyAll => Some(limit.shed),
+                };
+            }
+        }
+        let full = self.sources.entry(ip).or_insert(now);
+        let start = (*full).max(now);
+        // the bucket is empty once the last token only comes back after a burst.
+        let burst = limit.refill.checked_mul(limit.burst - 1);
+        if burst.is_some_and(|burst| start.saturating_duration_since(now) > burst) {
+            return Some(limit.shed);
+        }
+        // a bucket refilling later than the clock goes is as good as empty.
+        *full = match start.checked_add(limit.refill) {
+            Some(next) => next,
+            None => return Some(limit.shed),
+        };
+        let full = *full;
+        self.prune_at = Some(self.prune_at.map_or(full, |at| at.min(full)));
+        None
+    }
+
+    /// Forgets the sources whose bucket is full again, unless none can be yet.
+    fn prune(&mut self, now: Instant) {
+        if self.prune_at.is_some_and(|at| at > now) {
+            return;
+        }
+        let mut prune_at = None;
+        self.sources.retain(|_, &mut full| {
+            let keep = full > now;
+            if keep {
+                prune_at = Some(prune_at.map_or(full, |at: Instant| at.min(full)));
+            }
+            keep
+        });
+        self.prune_at = prune_at;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    const SEC: Duration = Duration::from_secs(1);
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(192, 0, 2, last))
+    }
+
+    #[test]
+    fn a_source_gets_its_burst_then_one_per_refill() {
+        let mut limiter = RateLimiter::new(RateLimit::new(3, SEC));
+        let now = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(limiter.admit(ip(1), now), None);
+        }
+        assert_eq!(limiter.admit(ip(1), now), Some(Admission::Close));
+        // other sources have buckets of their own.
+        assert_eq!(limiter.admit(ip(2), now), None);
+
+        // the last token came back, the bucket holds one again.
+        let later = now + SEC;
+        assert_eq!(limiter.admit(ip(1), later), None);
+        assert_eq!(limiter.admit(ip(1), later), Some(Admission::Close));
+        // just short of the next token.
+        let almost = later + SEC - Duration::from_millis(1);
+        assert_eq!(limiter.admit(ip(1), almost), Some(Admission::Close));
+
+        // a full bucket holds no more than a burst, however long the source was gone.
+        let much_later = now + 100 * SEC;
+        for _ in 0..3 {
+            assert_eq!(limiter.admit(ip(1), much_later), None);
+        }
+        assert_eq!(limiter.admit(ip(1), much_later), Some(Admission::Close));
+    }
+
+    #[test]
+    fn a_source_is_shed_with_the_admission_set() {
+        let limit = RateLimit::new(1, SEC).shed_with(Admission::Reset);
+        let mut limiter = RateLimiter::new(limit);
+        let now = Instant::now();
+        assert_eq!(limiter.admit(ip(1), now), None);
+        assert_eq!(limiter.admit(ip(1), now), Some(Admission::Reset));
+    }
+
+    #[test]
+    fn a_full_table_denies_new_sources_until_a_bucket_is_full_again() {
+        let limit = RateLimit::new(2, SEC)
+            .max_sources(2)
+            .overflow(Overflow::DenyAll);
+        let mut limiter = RateLimiter::new(limit);
+        let now = Instant::now();
+        assert_eq!(limiter.admit(ip(1), now), None);
+        assert_eq!(limiter.admit(ip(2), now), None);
+        assert_eq!(limiter.admit(ip(2), now), None);
+        assert_eq!(limiter.admit(ip(3), now), Some(Admission::Close));
+        // tracked sources keep their buckets.
+        assert_eq!(limiter.admit(ip(2), now), Some(Admission::Close));
+
+        // the bucket of the first source is full again first.
+        let later = now + SEC;
+        assert_eq!(limiter.admit(ip(3), later), None);
+        assert_eq!(limiter.sources.len(), 2);
+        assert!(!limiter.sources.contains_key(&ip(1)));
+    }
+
+    #[test]
+    fn a_full_table_lets_new_sources_through_when_permissive() {
+        let mut limiter = RateLimiter::new(RateLimit::new(1, SEC).max_sources(1));
+        let now = Instant::now();
+        assert_eq!(limiter.admit(ip(1), now), None);
+        for _ in 0..3 {
+            assert_eq!(limiter.admit(ip(2), now), None);
+        }
+        assert_eq!(limiter.sources.len(), 1);
+    }
+
+    #[test]
+    fn a_full_table_is_only_scanned_once_a_bucket_can_be_full() {
+        let limit = RateLimit::new(1, SEC)
+            .max_sources(1)
+            .overflow(Overflow::DenyAll);
+        let mut limiter = RateLimiter::new(limit);
+        let now = Instant::now();
+        assert_eq!(limiter.admit(ip(1), now), None);
+        assert_eq!(limiter.prune_at, Some(now + SEC));
+        assert_eq!(limiter.admit(ip(2), now), Some(Admission::Close));
+        assert_eq!(limiter.prune_at, Some(now + SEC));
+
+        assert_eq!(limiter.admit(ip(2), now + SEC), None);
+        assert_eq!(limiter.prune_at, Some(now + 2 * SEC));
+    }
+
+    #[test]
+    fn an_ipv4_mapped_peer_shares_the_bucket_of_the_ipv4_source() {
+        let mut limiter = RateLimiter::new(RateLimit::new(1, SEC));
+        let now = Instant::now();
+        let v4 = Ipv4Addr::new(192, 0, 2, 1);
+        assert_eq!(limiter.admit(IpAddr::V4(v4), now), None);
+        let mapped = IpAddr::V6(v4.to_ipv6_mapped());
+        assert_eq!(limiter.admit(mapped, now), Some(Admission::Close));
+        // a plain IPv6 source is a source of its own.
+        let v6 = IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1));
+        assert_eq!(limiter.admit(v6, now), None);
+    }
+
+    #[test]
+    fn a_long_refill_does_not_overflow_the_clock() {
+        let mut limiter = RateLimiter::new(RateLimit::new(u32::MAX, Duration::MAX));
+        let now = Instant::now();
+        assert_eq!(limiter.admit(ip(1), now), None);
+        assert_eq!(limiter.admit(ip(1), now), None);
+
+        let mut limiter = RateLimiter::new(RateLimit::new(1, Duration::MAX));
+        assert_eq!(limiter.admit(ip(1), now), None);
+        assert_eq!(limiter.admit(ip(1), now), Some(Admission::Close));
+    }
+}
@@ -0,0 +1,264 @@
+use std::error;
+use std::fmt;
+use std::io;
+use std::mem::ManuallyDrop;
+use std::net::{Shutdown, SocketAddr};
+use std::os::unix::io::AsRawFd;
+use std::pin::Pin;
+use std::ptr;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use futures_util::future::poll_fn;
+use futures_util::io::{AsyncRead, AsyncWrite};
+
+use super::TcpStream;
+use crate::driver::{self, Action, Ticket};
+use crate::task;
+
+/// The read half of a [`TcpStream`] borrowed by [`TcpStream::split`].
+pub struct ReadHalf<'a> {
+    stream: &'a TcpStream,
+}
+
+/// The write half of a [`TcpStream`] borrowed by [`TcpStream::split`].
+pub struct WriteHalf<'a> {
+    stream: &'a TcpStream,
+}
+
+/// The read half of a [`TcpStream`] split by [`TcpStream::into_split`].
+pub struct OwnedReadHalf {
+    stream: Rc<TcpStream>,
+}
+
+/// The write half of a [`TcpStream`] split by [`TcpStream::into_split`].
+///
+/// Dropping it shuts the write side of the connection down once the bytes written
+/// through it went out, so the peer reads the end of the stream while the read half
+/// keeps reading. [`reunite`](OwnedWriteHalf::reunite) gives the stream back with both
+/// sides open instead.
+pub struct OwnedWriteHalf {
+    stream: Rc<TcpStream>,
+}
+
+pub(crate) fn split(stream: &mut TcpStream) -> (ReadHalf<'_>, WriteHalf<'_>) {
+    let stream = &*stream;
+    (ReadHalf { stream }, WriteHalf { stream })
+}
+
+pub(crate) fn into_split(stream: TcpStream) -> (OwnedReadHalf, OwnedWriteHalf) {
+    let stream = Rc::new(stream);
+    let read = OwnedReadHalf {
+        stream: stream.clone(),
+    };
+    (read, OwnedWriteHalf { stream })
+}
+
+/// The halves passed to `reunite` came from different streams, they are handed back.
+pub struct ReuniteError(pub OwnedReadHalf, pub OwnedWriteHalf);
+
+impl fmt::Debug for ReuniteError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("ReuniteError").finish_non_exhaustive()
+    }
+}
+
+impl fmt::Display for ReuniteError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        "tried to reunite halves of different streams".fmt(fmt)
+    }
+}
+
+impl error::Error for ReuniteError {}
+
+impl OwnedReadHalf {
+    /// Puts the stream back together, failing if `write` was split off another stream.
+    pub fn reunite(self, write: OwnedWriteHalf) -> Result<TcpStream, ReuniteError> {
+        if !Rc::ptr_eq(&self.stream, &write.stream) {
+            return Err(ReuniteError(self, write));
+        }
+        // the write side stays open, the stream is whole again.
+        let write = ManuallyDrop::new(write);
+        drop(unsafe { ptr::read(&write.stream) });
+        match Rc::try_unwrap(self.stream) {
+            Ok(stream) => Ok(stream),
+            Err(_) => unreachable!("a split stream has exactly two halves"),
+        }
+    }
+}
+
+impl OwnedWriteHalf {
+    /// Puts the stream back together, see [`OwnedReadHalf::reunite`].
+    pub fn reunite(self, read: OwnedReadHalf) -> Result<TcpStream, ReuniteError> {
+        read.reunite(self)
+    }
+}
+
+impl Drop for OwnedWriteHalf {
+    fn drop(&mut self) {
+        let stream = self.stream.clone();
+        match driver::features() {
+            Some(_) => drop(task::spawn(shutdown_write(stream))),
+            // outside of a runtime no write is in flight.
+            None => drop(stream.shutdown(Shutdown::Write)),
+        }
+    }
+}
+
+/// Shuts the write side of `stream` down once the bytes written so far went out. The
+/// stream stays open until then, even if the read half is dropped first.
+async fn shutdown_write(stream: Rc<TcpStream>) {
+    if poll_fn(|cx| stream.poll_flush_shared(cx)).await.is_err() {
+        return;
+    }
+    let uring = driver::features().is_some_and(|features| features.shutdown);
+    let res = match uring {
+        true => match Action::shutdown(stream.as_raw_fd(), Shutdown::Write) {
+            Ok(action) => action.await.output(),
+            Err(e) => Err(e),
+        },
+        false => stream.shutdown(Shutdown::Write),
+    };
+    // the peer may have closed the connection already.
+    drop(res);
+}
+
+macro_rules! impl_read_half {
+    ($ty:ty) => {
+        impl $ty {
+            /// Reads some bytes into `buf`, returning how many were read. `Ok(0)` means
+            /// the peer closed its write side.
+            pub async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                poll_fn(|cx| self.stream.poll_read_shared(cx, buf)).await
+            }
+
+            pub fn local_addr(&self) -> io::Result<SocketAddr> {
+                self.stream.local_addr()
+            }
+
+            pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+                self.stream.peer_addr()
+            }
+        }
+
+        impl AsyncRead for $ty {
+            fn poll_read(
+                self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+                buf: &mut [u8],
+            ) -> Poll<io::Result<usize>> {
+                self.stream.poll_read_shared(cx, buf)
+            }
+        }
+    };
+}
+
+macro_rules! impl_write_half {
+    ($ty:ty) => {
+        impl $ty {
+            /// Writes some bytes from `buf`, returning how many were written.
+            pub async fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                let mut ticket = Ticket::default();
+                poll_fn(|cx| self.stream.poll_write_ticket_shared(cx, buf, &mut ticket)).await
+            }
+
+            pub fn local_addr(&self) -> io::Result<SocketAddr> {
+                self.stream.local_addr()
+            }
+
+            pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+                self.stream.peer_addr()
+            }
+        }
+
+        impl AsyncWrite for $ty {
+            fn poll_write(
+                self: Pin<&mut Self>,
+                cx: &mut Context,
+                buf: &[u8],
+            ) -> Poll<io::Result<usize>> {
+                self.stream.poll_write_shared(cx, buf)
+            }
+
+            fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+                self.stream.poll_flush_shared(cx)
+            }
+
+            /// Flushes and shuts the write side down, the read half keeps reading.
+            fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+                ready!(self.stream.poll_flush_shared(cx))?;
+                self.stream.shutdown(Shutdown::Write)?;
+                Poll::Ready(Ok(()))
+            }
+        }
+    };
+}
+
+impl_read_half!(ReadHalf<'_>);
+impl_read_half!(OwnedReadHalf);
+impl_write_half!(WriteHalf<'_>);
+impl_write_half!(OwnedWriteHalf);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::TcpListener;
+    use crate::Runtime;
+    use futures_util::io::{AsyncReadExt, AsyncWriteExt};
+
+    async fn pair(listener: &TcpListener) -> (TcpStream, TcpStream) {
+        let addr = listener.local_addr().unwrap();
+        let connect = crate::spawn(TcpStream::connect(addr));
+        let (accepted, _) = listener.accept().await.unwrap();
+        (connect.await.unwrap().unwrap(), accepted)
+    }
+
+    #[test]
+    fn dropping_the_owned_write_half_ends_the_stream_for_the_peer() {
+        Runtime::new().unwrap().block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let (stream, mut peer) = pair(&listener).await;
+            let (mut read, mut write) = stream.into_split();
+            write.write_all(b"bye").await.unwrap();
+            drop(write);
+            let mut buf = Vec::new();
+            peer.read_to_end(&mut buf).await.unwrap();
+            assert_eq!(buf, b"bye");
+
+            // the read half keeps reading.
+            peer.write(b"still").await.unwrap();
+            drop(peer);
+            let mut buf = Vec::new();
+            read.read_to_end(&mut buf).await.unwrap();
+            assert_eq!(buf, b"still");
+        });
+    }
+
+    #[test]
+    fn halves_of_different_streams_are_handed_back() {
+        Runtime::new().unwrap().block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let (first, _) = pair(&listener).await;
+            let (second, _) = pair(&listener).await;
+            let first_addr = first.local_addr().unwrap();
+            let (read, _) = first.into_split();
+            let (_, write) = second.into_split();
+            let ReuniteError(read, write) = match read.reunite(write) {
+                Ok(_) => panic!("halves of different streams were reunited"),
+                Err(err) => err,
+            };
+            assert_eq!(read.local_addr().unwrap(), first_addr);
+            drop(write);
+
+            // the write side of a reunited stream stays open.
+            let (stream, mut peer) = pair(&listener).await;
+            let (read, write) = stream.into_split();
+            let mut stream = write.reunite(read).unwrap();
+            stream.write_all(b"open").await.unwrap();
+            drop(stream);
+            let mut buf = Vec::new();
+            peer.read_to_end(&mut buf).await.unwrap();
+            assert_eq!(buf, b"open");
+        });
+    }
+}
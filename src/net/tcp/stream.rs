@@ -1,15 +1,58 @@
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::future::Future;
 use std::io;
-use std::net::{self, SocketAddr, ToSocketAddrs};
-use std::os::unix::io::{FromRawFd, RawFd};
+use std::mem::ManuallyDrop;
+use std::net::{self, SocketAddr};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
 use std::pin::Pin;
 use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 
+use futures_util::future::poll_fn;
 use futures_util::io::{AsyncBufRead, AsyncRead, AsyncWrite};
 
-use crate::driver::{self, Action};
+use crate::buf::{FixedBuf, IoBuf, IoBufMut, IoSliceOwned};
+use crate::driver::action::Completion;
+use crate::driver::chain::BufChain;
+use crate::driver::connect::Connect;
+use crate::driver::shared_fd;
+use crate::driver::{self, Action, SharedFd, StreamParts, StreamStats, Ticket, ZcWrite};
+use crate::fs::File;
+use crate::net::addr::{self, ToSocketAddrs};
+use crate::net::socket::{self, Keepalive};
+use crate::net::tcp::proxy_protocol::ProxyHeader;
+use crate::net::tcp::split::{self, OwnedReadHalf, OwnedWriteHalf, ReadHalf, WriteHalf};
+use crate::time::{self, delay_for};
 
+/// How long an attempt gets before the next address is tried alongside it.
+pub(crate) const ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// A TCP stream between a local and a remote socket.
+///
+/// # Cancellation
+///
+/// Reads are cancellation safe: bytes received for a read future that is dropped stay
+/// buffered in the stream and are returned by the next read.
+///
+/// A write hands its bytes to the kernel and resubmits after short writes until all of
+/// them are written. If the write future is dropped, the write still completes in the
+/// background, and the next write, flush or close on the stream waits for it first. Call
+/// `flush` to make sure no abandoned write is still in flight. Through `AsyncWrite`, which
+/// keeps nothing across polls, a write in flight answers the next `poll_write` whose
+/// buffer starts with the bytes it writes, a `poll_write` of other bytes waits for it and
+/// then writes its own.
+///
+/// With [`set_write_coalescing`](TcpStream::set_write_coalescing), small writes are
+/// reported as written before they reach the kernel, so their failure only shows up with
+/// a later write or flush, and bytes still held back when the stream is dropped are
+/// written without anyone learning whether that worked.
 pub struct TcpStream {
-    inner: driver::Stream<net::TcpStream>,
+    /// Borrowed mutably by every read and write, shared by the halves of a split.
+    inner: RefCell<driver::Stream<SharedFd>>,
+    local_addr: Cell<Option<SocketAddr>>,
+    peer_addr: Cell<Option<SocketAddr>>,
+    proxy_header: Cell<Option<ProxyHeader>>,
 }
 
 impl FromRawFd for TcpStream {
@@ -18,68 +61,494 @@ impl FromRawFd for TcpStream {
     }
 }
 
+impl AsRawFd for TcpStream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.borrow().get_ref().as_raw_fd()
+    }
+}
+
 impl TcpStream {
     pub fn from_std(stream: net::TcpStream) -> TcpStream {
         TcpStream {
-            inner: driver::Stream::new(stream),
+            inner: RefCell::new(driver::Stream::new(SharedFd::from(OwnedFd::from(stream)))),
+            local_addr: Cell::new(None),
+            peer_addr: Cell::new(None),
+            proxy_header: Cell::new(None),
         }
     }
 
+    pub(crate) fn from_std_with_peer(stream: net::TcpStream, peer_addr: SocketAddr) -> TcpStream {
+        let stream = TcpStream::from_std(stream);
+        stream.peer_addr.set(Some(peer_addr));
+        stream
+    }
+
     async fn connect_addr(addr: SocketAddr) -> io::Result<TcpStream> {
-        let completion = Action::connect(addr)?.await;
+        TcpStream::connected(Action::connect(addr)?.await)
+    }
+
+    /// Connects to `addr`, failing with `ErrorKind::TimedOut` if the connection was not
+    /// established within `timeout`. The kernel cancels the connect itself through a
+    /// linked timeout.
+    pub async fn connect_timeout(addr: &SocketAddr, timeout: Duration) -> io::Result<TcpStream> {
+        TcpStream::connected(Action::connect_timeout(*addr, timeout)?.await)
+    }
+
+    /// Connects to `addr` through the interface or VRF device with index `ifindex`. The
+    /// socket is bound to the device before connecting, so routing only considers that
+    /// device's table.
+    pub async fn connect_bound(addr: &SocketAddr, ifindex: u32) -> io::Result<TcpStream> {
+        TcpStream::connected(Action::connect_bound(*addr, ifindex)?.await)
+    }
+
+    /// Connects to `addr` with TCP Fast Open and writes `data`.
+    ///
+    /// Once the server handed out a Fast Open cookie on an earlier connection, `data`
+    /// goes out with the SYN and reaches the server a round trip sooner, otherwise it is
+    /// written once connected. The server has to accept Fast Open, see
+    /// [`ListenOptions::fast_open`](crate::net::ListenOptions::fast_open), and clients
+    /// have to be allowed it by `net.ipv4.tcp_fastopen`. Data sent with the SYN may be
+    /// delivered twice if the SYN is, so it should be safe to replay.
+    pub async fn connect_with_data(addr: &SocketAddr, data: &[u8]) -> io::Result<TcpStream> {
+        let mut stream = TcpStream::connected(Action::connect_fast_open(*addr)?.await)?;
+        stream.inner.get_mut().write_all(data).await?;
+        Ok(stream)
+    }
+
+    fn connected(completion: Completion<Connect>) -> io::Result<TcpStream> {
         let fd = completion.action.get_socket(completion.result)?;
-        Ok(TcpStream::from_std(unsafe {
-            net::TcpStream::from_raw_fd(fd)
-        }))
+        let stream = unsafe { net::TcpStream::from_raw_fd(fd) };
+        Ok(TcpStream::from_std_with_peer(
+            stream,
+            completion.action.peer_addr(),
+        ))
     }
 
+    /// Connects to the first of the resolved addresses that accepts. Addresses of both
+    /// families are tried alternately and an attempt that takes longer than 250ms gets
+    /// the next address tried alongside it, as RFC 8305 (happy eyeballs) describes. The
+    /// attempts still running are cancelled once one succeeded.
     pub async fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<TcpStream> {
-        let addrs = addr.to_socket_addrs()?;
+        TcpStream::connect_racing(addr, ATTEMPT_DELAY).await
+    }
 
-        let mut last_err = None;
-        for addr in addrs {
-            match TcpStream::connect_addr(addr).await {
-                Ok(stream) => return Ok(stream),
-                Err(e) => last_err = Some(e),
-            }
-        }
+    /// Like `connect`, starting the next attempt after `delay`.
+    pub(crate) async fn connect_racing<A: ToSocketAddrs>(
+        addr: A,
+        delay: Duration,
+    ) -> io::Result<TcpStream> {
+        let addrs = interleave(addr::resolve(&addr).await?);
+        happy_eyeballs(addrs, delay).await
+    }
+
+    /// Reads some bytes into `buf`, returning how many were read. `Ok(0)` means the
+    /// peer closed its write side.
+    pub async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        poll_fn(|cx| self.inner.get_mut().poll_read(cx, buf)).await
+    }
+
+    /// Writes some bytes from `buf`, returning how many were written.
+    pub async fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.get_mut().write(buf).await
+    }
+
+    /// Like `read`, failing with `ErrorKind::TimedOut` if nothing arrived within
+    /// `timeout`. The kernel cancels the receive itself through a linked timeout.
+    pub async fn read_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        self.inner.get_mut().read_timeout(buf, timeout).await
+    }
+
+    /// Like `read`, the kernel picking the buffer the bytes land in from the ring
+    /// registered as group `bgid`, see
+    /// [`Runtime::register_buffer_group`](crate::Runtime::register_buffer_group).
+    pub async fn read_with_group(&mut self, buf: &mut [u8], bgid: u16) -> io::Result<usize> {
+        self.inner.get_mut().read_with_group(buf, bgid).await
+    }
+
+    /// Like `read`, the kernel picking the buffer from the registered buffer group
+    /// whose buffers hold `buf.len()` bytes with the least room to spare, so small
+    /// reads do not tie up large buffers. The group with the largest buffers is used
+    /// when none holds that many.
+    pub async fn read_sized(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.get_mut().read_sized(buf).await
+    }
+
+    /// Like `write`, failing with `ErrorKind::TimedOut` if nothing could be written
+    /// within `timeout`.
+    pub async fn write_timeout(&mut self, buf: &[u8], timeout: Duration) -> io::Result<usize> {
+        self.inner.get_mut().write_timeout(buf, timeout).await
+    }
+
+    /// Registers the socket with the runtime's fixed file table, so reads and writes
+    /// refer to it by slot and skip the fd lookup on every operation. It is unregistered
+    /// when the stream is dropped.
+    pub fn register_fd(&mut self) -> io::Result<()> {
+        self.inner.get_mut().register_fd()
+    }
+
+    /// Traffic counters of this stream.
+    pub fn stats(&self) -> StreamStats {
+        self.inner.borrow().stats()
+    }
+
+    /// Fails pending reads and writes with `TimedOut` once no bytes moved in either
+    /// direction for `timeout`, so a silent peer is dropped while a slow transfer that
+    /// keeps making progress is not. `None` turns the idle timeout off.
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) {
+        self.inner.get_mut().set_idle_timeout(timeout)
+    }
+
+    /// Fails pending reads and writes with `TimedOut` once `deadline` passed, however
+    /// much progress was made until then. `None` removes the deadline.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.inner.get_mut().set_deadline(deadline)
+    }
+
+    /// Holds back writes while they add up to at most `limit` bytes and writes them as
+    /// one once a larger write comes in, the stream is flushed or the runtime has nothing
+    /// else to do. A failed write of held back bytes fails the next write or flush.
+    /// Bytes still held back when the stream is dropped are written in the background,
+    /// where a failure goes unnoticed, so flush before dropping the stream. 0 turns
+    /// coalescing off.
+    pub fn set_write_coalescing(&mut self, limit: usize) {
+        self.inner.get_mut().set_write_coalescing(limit)
+    }
+
+    /// Sets `SO_RCVLOWAT`, a pending read is only woken once at least `n` bytes arrived
+    /// or the peer closed its write side. Bytes already received are still returned
+    /// right away, so this saves wakeups for fragmented messages without delaying any.
+    pub fn set_recv_low_watermark(&self, n: usize) -> io::Result<()> {
+        self.inner.borrow().set_recv_low_watermark(n)
+    }
+
+    /// Starts the next read as soon as one completed with data, so the bytes that follow
+    /// are already on their way while the caller handles what it got.
+    pub fn set_read_ahead(&mut self, enabled: bool) {
+        self.inner.get_mut().set_read_ahead(enabled)
+    }
+
+    /// Attaches a value to this stream, replacing any previous one.
+    pub fn set_context<C: 'static>(&mut self, context: C) {
+        self.inner.get_mut().set_context(context);
+    }
+
+    /// The value attached with `set_context`, `None` if there is none of type `C`.
+    pub fn context<C: 'static>(&self) -> Option<&C> {
+        // SAFETY: the stream is only borrowed mutably through `&mut self`, or by the
+        // halves of a split while they hold it to themselves.
+        unsafe { self.inner.try_borrow_unguarded() }.ok()?.context()
+    }
+
+    pub fn context_mut<C: 'static>(&mut self) -> Option<&mut C> {
+        self.inner.get_mut().context_mut()
+    }
+
+    /// Moves the next received bytes into `chain` without copying them, returning how
+    /// many were added. `Ok(0)` means the peer closed its write side.
+    pub async fn read_chain(&mut self, chain: &mut BufChain) -> io::Result<usize> {
+        poll_fn(|cx| self.inner.get_mut().poll_read_chain(cx, chain)).await
+    }
+
+    /// Writes all of `chain` with vectored writes, returning how many bytes were written.
+    /// Written bytes are removed from `chain`, segments in flight when the future is
+    /// dropped are lost.
+    pub async fn write_chain(&mut self, chain: &mut BufChain) -> io::Result<usize> {
+        self.inner.get_mut().write_chain(chain).await
+    }
+
+    /// Reads into `buf` up to its capacity, replacing its contents, and returns how many
+    /// bytes were read together with the buffer, which the read owns until the kernel is
+    /// done with it.
+    pub async fn read_buf<B: IoBufMut>(&mut self, buf: B) -> (io::Result<usize>, B) {
+        self.inner.get_mut().read_buf(buf).await
+    }
 
-        Err(last_err.unwrap_or_else(|| {
-            io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "could not resolve to any address",
-            )
-        }))
+    /// Writes the contents of `buf`, returning how many bytes were written together with
+    /// the buffer, which the write owns until the kernel is done with it.
+    pub async fn write_buf<B: IoBuf>(&mut self, buf: B) -> (io::Result<usize>, B) {
+        self.inner.get_mut().write_buf(buf).await
     }
 
+    /// Reads into `bufs` in order with a single `readv`, replacing their contents, and
+    /// returns how many bytes were read together with the buffers.
+    pub async fn read_vectored(
+        &mut self,
+        bufs: Vec<IoSliceOwned>,
+    ) -> io::Result<(usize, Vec<IoSliceOwned>)> {
+        self.inner.get_mut().read_vectored(bufs).await
+    }
+
+    /// Writes the contents of `bufs` in order with a single `writev`, returning how many
+    /// bytes were written together with the buffers.
+    pub async fn write_vectored(
+        &mut self,
+        bufs: Vec<IoSliceOwned>,
+    ) -> io::Result<(usize, Vec<IoSliceOwned>)> {
+        self.inner.get_mut().write_vectored(bufs).await
+    }
+
+    /// Writes the contents of `buf`, returning how many bytes were written together with
+    /// the buffer. On error the buffer goes back to its registry.
+    pub async fn write_fixed(&mut self, buf: FixedBuf) -> io::Result<(usize, FixedBuf)> {
+        self.inner.get_mut().write_fixed(buf).await
+    }
+
+    /// Copies the next bytes received into `buf` without consuming them, returning how
+    /// many were copied, so a protocol can be told apart by its first bytes before the
+    /// stream is handed on. Waits for bytes to arrive, 0 means the stream ended.
+    pub async fn peek(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.get_mut().peek(buf).await
+    }
+
+    /// Sends `buf` without copying it into the kernel, returning how many bytes were
+    /// sent. Worth it for large payloads, small ones are cheaper to copy. Before Linux 6.0
+    /// the bytes are copied like any send.
+    pub async fn send_zc(&mut self, buf: Vec<u8>) -> io::Result<usize> {
+        self.inner.get_mut().send_zc(buf).await
+    }
+
+    /// Like `send_zc`, handing out the send to wait for the kernel to release `buf`, see
+    /// [`ZcWrite::flushed`]. The send resolves once the kernel accepted the bytes, bytes
+    /// written before that may go out ahead of them.
+    pub async fn write_zc(&mut self, buf: Vec<u8>) -> io::Result<ZcWrite> {
+        self.inner.get_mut().write_zc(buf).await
+    }
+
+    /// Sends up to `len` bytes of `file` starting at `offset`, returning how many were
+    /// sent. Fewer than `len` means the file ended. The bytes are spliced through a pipe
+    /// and never copied into userspace, the file position is left unchanged.
+    pub async fn send_file(&mut self, file: &File, offset: u64, len: u64) -> io::Result<u64> {
+        self.inner
+            .get_mut()
+            .send_file(file.as_raw_fd(), offset, len)
+            .await
+    }
+
+    /// Receives exactly `len` bytes and writes them back to the peer, returning how many
+    /// were echoed. Fewer than `len` means the peer closed its write side.
+    ///
+    /// The recv and the send are linked and submitted together, halving submissions for
+    /// echo-style request/response traffic.
+    pub async fn recv_send(&mut self, len: usize) -> io::Result<usize> {
+        self.inner.get_mut().recv_send(len).await
+    }
+
+    /// Returns the local address of this stream.
+    ///
+    /// The address is queried once and cached, use [`refresh`] to query it again.
+    ///
+    /// [`refresh`]: TcpStream::refresh
     pub fn local_addr(&self) -> io::Result<SocketAddr> {
-        self.inner.get_ref().local_addr()
+        if let Some(addr) = self.local_addr.get() {
+            return Ok(addr);
+        }
+        let addr = self.io().local_addr()?;
+        self.local_addr.set(Some(addr));
+        Ok(addr)
     }
 
+    /// Returns the remote address of this stream.
+    ///
+    /// Streams created by `accept` or `connect` already know their peer, so this does
+    /// not issue a syscall. Use [`refresh`] to query it again.
+    ///
+    /// [`refresh`]: TcpStream::refresh
     pub fn peer_addr(&self) -> io::Result<SocketAddr> {
-        self.inner.get_ref().peer_addr()
+        if let Some(addr) = self.peer_addr.get() {
+            return Ok(addr);
+        }
+        let addr = self.io().peer_addr()?;
+        self.peer_addr.set(Some(addr));
+        Ok(addr)
+    }
+
+    /// The addresses the load balancer in front passed in the PROXY protocol header,
+    /// `None` unless the listener reads one and the header carried addresses, see
+    /// [`TcpListener::set_proxy_protocol`](crate::net::TcpListener::set_proxy_protocol).
+    /// `peer_addr` stays the address of the load balancer.
+    pub fn proxy_header(&self) -> Option<ProxyHeader> {
+        self.proxy_header.get()
+    }
+
+    pub(crate) fn set_proxy_header(&self, header: Option<ProxyHeader>) {
+        self.proxy_header.set(header);
+    }
+
+    /// Drops the cached addresses and queries them from the socket again.
+    pub fn refresh(&self) -> io::Result<()> {
+        let io = self.io();
+        self.local_addr.set(Some(io.local_addr()?));
+        self.peer_addr.set(Some(io.peer_addr()?));
+        Ok(())
+    }
+
+    /// Splits the stream into a read half and a write half borrowing it, so a read and a
+    /// write can wait at the same time, for example in the two branches of a `select`.
+    pub fn split(&mut self) -> (ReadHalf<'_>, WriteHalf<'_>) {
+        split::split(self)
+    }
+
+    /// Splits the stream into a read half and a write half owning it, so one task can
+    /// read while another writes. Dropping the write half shuts the write side down,
+    /// [`OwnedReadHalf::reunite`] puts the stream back together.
+    pub fn into_split(self) -> (OwnedReadHalf, OwnedWriteHalf) {
+        split::into_split(self)
+    }
+
+    /// The socket, shared with the stream. It stays open while a clone is held, after
+    /// the stream was dropped.
+    pub fn shared_fd(&self) -> SharedFd {
+        self.inner.borrow().get_ref().clone()
+    }
+
+    /// Detaches the connection from the runtime, so it can be moved to the runtime of
+    /// another thread and resumed there with [`TcpStream::from_parts`]. The bytes
+    /// received and not read yet and the writes held back are part of the
+    /// [`StreamParts`]. Fails if a clone of the [`shared_fd`](TcpStream::shared_fd) is
+    /// still around.
+    pub async fn into_parts(self) -> io::Result<StreamParts<OwnedFd>> {
+        shared_fd::into_owned_parts(self.inner.into_inner()).await
+    }
+
+    /// Resumes a connection detached by [`TcpStream::into_parts`] on the current
+    /// runtime.
+    pub fn from_parts(parts: StreamParts<OwnedFd>) -> TcpStream {
+        let parts = StreamParts {
+            io: SharedFd::from(parts.io),
+            read: parts.read,
+            write: parts.write,
+        };
+        TcpStream {
+            inner: RefCell::new(driver::Stream::from_parts(parts)),
+            local_addr: Cell::new(None),
+            peer_addr: Cell::new(None),
+            proxy_header: Cell::new(None),
+        }
+    }
+
+    /// The socket as std sees it, to query or set options. The fd stays owned by the
+    /// stream.
+    fn io(&self) -> ManuallyDrop<net::TcpStream> {
+        ManuallyDrop::new(unsafe { net::TcpStream::from_raw_fd(self.as_raw_fd()) })
+    }
+
+    pub(crate) fn poll_read_shared(
+        &self,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.inner.borrow_mut().poll_read(cx, buf)
+    }
+
+    pub(crate) fn poll_write_shared(
+        &self,
+        cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.inner.borrow_mut().poll_write(cx, buf)
+    }
+
+    /// Like `poll_write_shared`, for a caller that keeps `ticket` across the polls.
+    pub(crate) fn poll_write_ticket_shared(
+        &self,
+        cx: &mut Context,
+        buf: &[u8],
+        ticket: &mut Ticket,
+    ) -> Poll<io::Result<usize>> {
+        self.inner.borrow_mut().poll_write_ticket(cx, buf, ticket)
+    }
+
+    /// Whether no received bytes are buffered and no read is in flight.
+    pub(crate) fn is_read_idle(&self) -> bool {
+        self.inner.borrow().is_read_idle()
+    }
+
+    pub(crate) fn poll_flush_shared(&self, cx: &mut Context) -> Poll<io::Result<()>> {
+        self.inner.borrow_mut().poll_flush(cx)
+    }
+
+    /// Closes the connection the orderly way. Writes still buffered go out, the write
+    /// side is shut down and the bytes the peer still sends are read and discarded
+    /// until it closes its side too, so the close does not turn into a reset for
+    /// unread data.
+    ///
+    /// Fails with `ErrorKind::TimedOut` if the peer did not close its side within
+    /// `timeout`, the stream is closed either way.
+    pub async fn close_graceful(mut self, timeout: Duration) -> io::Result<()> {
+        let io = self.io();
+        let drain = async {
+            let inner = self.inner.get_mut();
+            poll_fn(|cx| inner.poll_flush(cx)).await?;
+            io.shutdown(net::Shutdown::Write)?;
+            loop {
+                let n = poll_fn(|cx| inner.poll_fill_buf(cx).map_ok(|buf| buf.len())).await?;
+                if n == 0 {
+                    return Ok(());
+                }
+                inner.consume(n);
+            }
+        };
+        time::timeout(timeout, drain).await?
+    }
+
+    /// Closes the connection with a reset right away, instead of the orderly close of
+    /// dropping the stream. Bytes not yet sent are discarded.
+    pub fn close_abort(self) -> io::Result<()> {
+        socket::reset_on_close(self.as_raw_fd())
     }
 
     pub fn shutdown(&self, how: net::Shutdown) -> std::io::Result<()> {
-        self.inner.get_ref().shutdown(how)
+        self.io().shutdown(how)
     }
 
     pub fn nodelay(&self) -> io::Result<bool> {
-        self.inner.get_ref().nodelay()
+        socket::nodelay(self.as_raw_fd())
     }
 
     pub fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
-        self.inner.get_ref().set_nodelay(nodelay)
+        socket::set_nodelay(self.as_raw_fd(), nodelay)
+    }
+
+    pub fn ttl(&self) -> io::Result<u32> {
+        socket::ttl(self.as_raw_fd())
+    }
+
+    /// Sets the time to live of the packets sent on this stream.
+    pub fn set_ttl(&self, ttl: u32) -> io::Result<()> {
+        socket::set_ttl(self.as_raw_fd(), ttl)
+    }
+
+    pub fn linger(&self) -> io::Result<Option<Duration>> {
+        socket::linger(self.as_raw_fd())
+    }
+
+    /// Sets `SO_LINGER`, closing the stream then blocks until the bytes not yet sent
+    /// went out or `linger` elapsed, whole seconds rounded up. A zero linger turns the
+    /// close into a reset, see [`close_abort`](TcpStream::close_abort). `None` closes
+    /// in the background, which is the default.
+    pub fn set_linger(&self, linger: Option<Duration>) -> io::Result<()> {
+        socket::set_linger(self.as_raw_fd(), linger)
+    }
+
+    /// The keepalive probing of the connection, `None` if it is off.
+    pub fn keepalive(&self) -> io::Result<Option<Keepalive>> {
+        socket::keepalive(self.as_raw_fd())
+    }
+
+    /// Turns keepalive probing on, so a peer that vanished without closing the
+    /// connection is noticed while it is idle. `None` turns it off.
+    pub fn set_keepalive(&self, keepalive: Option<Keepalive>) -> io::Result<()> {
+        socket::set_keepalive(self.as_raw_fd(), keepalive)
     }
 }
 
 impl AsyncBufRead for TcpStream {
     fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
-        self.get_mut().inner.poll_fill_buf(cx)
+        self.get_mut().inner.get_mut().poll_fill_buf(cx)
     }
 
     fn consume(self: Pin<&mut Self>, amt: usize) {
-        self.get_mut().inner.consume(amt);
+        self.get_mut().inner.get_mut().consume(amt);
     }
 }
 
@@ -89,21 +558,128 @@ impl AsyncRead for TcpStream {
         cx: &mut Context<'_>,
         buf: &mut [u8],
     ) -> Poll<io::Result<usize>> {
-        self.get_mut().inner.poll_read(cx, buf)
+        self.get_mut().inner.get_mut().poll_read(cx, buf)
     }
 }
 
 impl AsyncWrite for TcpStream {
     fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
-        self.get_mut().inner.poll_write(cx, buf)
+        self.get_mut().inner.get_mut().poll_write(cx, buf)
     }
 
-    fn poll_flush(self: Pin<&mut Self>, _: &mut Context) -> Poll<io::Result<()>> {
-        Poll::Ready(Ok(()))
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        self.get_mut().inner.get_mut().poll_flush(cx)
     }
 
-    fn poll_close(self: Pin<&mut Self>, _: &mut Context) -> Poll<io::Result<()>> {
-        self.shutdown(net::Shutdown::Write)?;
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let me = self.get_mut();
+        ready!(me.inner.get_mut().poll_flush(cx))?;
+        me.shutdown(net::Shutdown::Write)?;
         Poll::Ready(Ok(()))
     }
 }
+
+/// Orders addresses so the families alternate, starting with the first one resolved.
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let first_v6 = addrs.first().is_none_or(|addr| addr.is_ipv6());
+    let (mut first, mut second): (VecDeque<_>, VecDeque<_>) = addrs
+        .into_iter()
+        .partition(|addr| addr.is_ipv6() == first_v6);
+    let mut ordered = Vec::with_capacity(first.len() + second.len());
+    loop {
+        match (first.pop_front(), second.pop_front()) {
+            (None, None) => return ordered,
+            (a, b) => ordered.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+type Attempt = Pin<Box<dyn Future<Output = io::Result<TcpStream>>>>;
+
+/// Connects to the first address that accepts, starting an attempt every `delay` or as
+/// soon as the previous ones failed. Attempts still running are cancelled.
+async fn happy_eyeballs(addrs: Vec<SocketAddr>, delay: Duration) -> io::Result<TcpStream> {
+    let mut addrs = addrs.into_iter().peekable();
+    let mut attempts: Vec<Attempt> = Vec::new();
+    let mut next = delay_for(delay);
+    let mut last_err = None;
+    poll_fn(|cx| loop {
+        let start = attempts.is_empty() || Pin::new(&mut next).poll(cx).is_ready();
+        if start {
+            if let Some(addr) = addrs.next() {
+                attempts.push(Box::pin(TcpStream::connect_addr(addr)));
+                next.reset(Instant::now() + delay);
+            }
+        }
+
+        let mut i = 0;
+        while i < attempts.len() {
+            match attempts[i].as_mut().poll(cx) {
+                Poll::Ready(Ok(stream)) => return Poll::Ready(Ok(stream)),
+                Poll::Ready(Err(e)) => {
+                    last_err = Some(e);
+                    drop(attempts.swap_remove(i));
+                }
+                Poll::Pending => i += 1,
+            }
+        }
+
+        if addrs.peek().is_none() {
+            if attempts.is_empty() {
+                return Poll::Ready(Err(last_err.take().unwrap_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "could not resolve to any address",
+                    )
+                })));
+            }
+            return Poll::Pending;
+        }
+        // a failed attempt makes room for the next one right away, otherwise the
+        // timer is polled again so it wakes us.
+        if !attempts.is_empty() && Pin::new(&mut next).poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::{ListenOptions, TcpListener};
+    use crate::Runtime;
+
+    #[test]
+    fn data_sent_with_the_connect_reaches_the_listener() {
+        Runtime::new().unwrap().block_on(async {
+            let options = ListenOptions {
+                fast_open: 16,
+                ..ListenOptions::default()
+            };
+            let listener = TcpListener::bind_with("127.0.0.1:0", options)
+                .await
+                .unwrap();
+            let addr = listener.local_addr().unwrap();
+            // the first connection gets the cookie the second sends its data with.
+            for data in [&b"first"[..], b"second"] {
+                let mut stream = TcpStream::connect_with_data(&addr, data).await.unwrap();
+                let (mut peer, _) = listener.accept().await.unwrap();
+                let mut buf = vec![0; data.len()];
+                let mut read = 0;
+                while read < buf.len() {
+                    read += peer.read(&mut buf[read..]).await.unwrap();
+                }
+                assert_eq!(buf, data);
+
+                peer.write(b"ok").await.unwrap();
+                let mut buf = [0; 2];
+                let mut read = 0;
+                while read < buf.len() {
+                    read += stream.read(&mut buf[read..]).await.unwrap();
+                }
+                assert_eq!(&buf, b"ok");
+            }
+        });
+    }
+}
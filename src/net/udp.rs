@@ -1,75 +0,0 @@
-use std::io;
-use std::net::{self, SocketAddr, ToSocketAddrs};
-
-use futures_util::future::poll_fn;
-
-use crate::driver::Packet;
-
-pub struct UdpSocket {
-    inner: Packet<net::UdpSocket>,
-}
-
-impl UdpSocket {
-    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<UdpSocket> {
-        let addrs = addr.to_socket_addrs()?;
-        let mut last_err = None;
-
-        for addr in addrs {
-            match UdpSocket::bind_addr(addr) {
-                Ok(socket) => return Ok(socket),
-                Err(e) => last_err = Some(e),
-            }
-        }
-        Err(last_err.unwrap_or_else(|| {
-            io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "could not resolve to any address",
-            )
-        }))
-    }
-
-    fn bind_addr(addr: SocketAddr) -> io::Result<UdpSocket> {
-        Ok(UdpSocket {
-            inner: Packet::new(net::UdpSocket::bind(addr)?),
-        })
-    }
-
-    pub fn local_addr(&self) -> io::Result<SocketAddr> {
-        self.inner.get_ref().local_addr()
-    }
-
-    pub fn connect<A: ToSocketAddrs>(&self, addr: A) -> io::Result<()> {
-        let addrs = addr.to_socket_addrs()?;
-        let mut last_err = None;
-
-        for addr in addrs {
-            match self.inner.get_ref().connect(addr) {
-                Ok(_) => return Ok(()),
-                Err(e) => last_err = Some(e),
-            }
-        }
-        Err(last_err.unwrap_or_else(|| {
-            io::Error::new(
-                io::ErrorKind::InvalidInput,
-                "could not resolve to any address",
-            )
-        }))
-    }
-
-    pub async fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
-        poll_fn(|cx| self.inner.poll_recv(cx, buf)).await
-    }
-
-    pub async fn send(&self, buf: &[u8]) -> io::Result<usize> {
-        poll_fn(|cx| self.inner.poll_send(cx, buf)).await
-    }
-
-    pub async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
-        poll_fn(|cx| self.inner.poll_recv_from(cx, buf)).await
-    }
-
-    pub async fn send_to<A: Into<SocketAddr>>(&self, buf: &[u8], target: A) -> io::Result<usize> {
-        let addr = target.into();
-        poll_fn(|cx| self.inner.poll_send_to(cx, buf, &addr)).await
-    }
-}
@@ -0,0 +1,343 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::os::unix::io::AsRawFd;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use futures_util::future::poll_fn;
+
+use super::UdpSocket;
+use crate::driver::Action;
+use crate::time::{self, Delay};
+
+/// Room for the largest datagram.
+const MAX_DATAGRAM: usize = 65536;
+
+/// Most datagrams queued for a session by default.
+const DEFAULT_MAX_QUEUED: usize = 64;
+
+/// Most sessions waiting to be accepted, datagrams from further new peers are dropped.
+const MAX_PENDING: usize = 1024;
+
+/// Groups the datagrams a socket receives by their source address, see
+/// [`UdpSocket::framed_by_peer`].
+///
+/// The first datagram from an address the demultiplexer has no session for starts a
+/// new one, which [`accept`](PeerDemux::accept) hands out. The datagrams that follow
+/// are queued for that session until it is dropped, a datagram arriving after that
+/// starts a new session again.
+///
+/// Whichever of `accept` and the sessions' `recv` is waiting receives from the socket
+/// on behalf of all of them, so sessions are served while any of them is polled.
+pub struct PeerDemux {
+    shared: Rc<Shared>,
+}
+
+/// The datagrams exchanged with a single peer, accepted from a [`PeerDemux`].
+pub struct PeerSession {
+    shared: Rc<Shared>,
+    peer: SocketAddr,
+}
+
+struct Shared {
+    socket: UdpSocket,
+    buf: RefCell<Vec<u8>>,
+    state: RefCell<State>,
+}
+
+struct State {
+    sessions: HashMap<SocketAddr, Session>,
+    /// Sessions started and not accepted yet, in the order they started.
+    pending: VecDeque<SocketAddr>,
+    /// Cleared once the demultiplexer is dropped, new peers are ignored from then on.
+    accepting: bool,
+    acceptor: Option<Waker>,
+    idle_timeout: Option<Duration>,
+    max_queued: usize,
+}
+
+struct Session {
+    queue: VecDeque<Vec<u8>>,
+    waker: Option<Waker>,
+    /// When a datagram was last received from or sent to the peer.
+    last_activity: Instant,
+}
+
+impl UdpSocket {
+    /// Turns the socket into a demultiplexer handing out a session per peer, each with
+    /// its own queue of received datagrams and idle timeout, the core of a UDP server
+    /// that keeps state per client.
+    pub fn framed_by_peer(self) -> PeerDemux {
+        PeerDemux {
+            shared: Rc::new(Shared {
+                socket: self,
+                buf: RefCell::new(vec![0; MAX_DATAGRAM]),
+                state: RefCell::new(State {
+                    sessions: HashMap::new(),
+                    pending: VecDeque::new(),
+                    accepting: true,
+                    acceptor: None,
+                    idle_timeout: None,
+                    max_queued: DEFAULT_MAX_QUEUED,
+                }),
+            }),
+        }
+    }
+}
+
+impl PeerDemux {
+    /// Waits for a datagram from a peer without a session and returns the session it
+    /// started, that datagram is the first one it receives.
+    pub async fn accept(&self) -> io::Result<PeerSession> {
+        let _handoff = Handoff(&self.shared);
+        let peer = poll_fn(|cx| self.poll_accept(cx)).await?;
+        Ok(PeerSession {
+            shared: self.shared.clone(),
+            peer,
+        })
+    }
+
+    fn poll_accept(&self, cx: &mut Context) -> Poll<io::Result<SocketAddr>> {
+        let pumped = self.shared.poll_pump(cx);
+        let mut state = self.shared.state.borrow_mut();
+        if let Some(peer) = state.pending.pop_front() {
+            state.acceptor = None;
+            return Poll::Ready(Ok(peer));
+        }
+        pumped?;
+        state.acceptor = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    /// Fails a session's `recv` with `TimedOut` once no datagram was received from or
+    /// sent to its peer for `timeout`. The session stays around until it is dropped.
+    /// `None` turns the idle timeout off.
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) {
+        self.shared.state.borrow_mut().idle_timeout = timeout;
+    }
+
+    /// Limits how many datagrams are queued for a session that is not receiving them,
+    /// further ones are dropped as the socket's own buffer would. 64 by default.
+    pub fn set_max_queued(&mut self, max: usize) {
+        self.shared.state.borrow_mut().max_queued = max;
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.shared.socket.local_addr()
+    }
+}
+
+impl Drop for PeerDemux {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.borrow_mut();
+        state.accepting = false;
+        let State {
+            sessions, pending, ..
+        } = &mut *state;
+        for peer in pending.drain(..) {
+            sessions.remove(&peer);
+        }
+    }
+}
+
+impl PeerSession {
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer
+    }
+
+    /// Receives the next datagram from the peer into `buf`, returning its length. The
+    /// part of a datagram that does not fit is dropped.
+    pub async fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let _handoff = Handoff(&self.shared);
+        let mut timer = None;
+        poll_fn(|cx| self.poll_recv(cx, buf, &mut timer)).await
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        buf: &mut [u8],
+        timer: &mut Option<Delay>,
+    ) -> Poll<io::Result<usize>> {
+        let pumped = self.shared.poll_pump(cx);
+        let mut state = self.shared.state.borrow_mut();
+        let idle_timeout = state.idle_timeout;
+        let session = state
+            .sessions
+            .get_mut(&self.peer)
+            .expect("a session lives as long as its handle");
+        if let Some(datagram) = session.queue.pop_front() {
+            session.waker = None;
+            let n = datagram.len().min(buf.len());
+            buf[..n].copy_from_slice(&datagram[..n]);
+            return Poll::Ready(Ok(n));
+        }
+        pumped?;
+        if let Some(idle_timeout) = idle_timeout {
+            let at = session.last_activity + idle_timeout;
+            let timer = timer.get_or_insert_with(|| time::delay_until(at));
+            // a datagram since the timer was armed pushed the expiry back.
+            if timer.deadline() != at {
+                timer.reset(at);
+            }
+            if Pin::new(timer).poll(cx).is_ready() {
+                session.waker = None;
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "session timed out",
+                )));
+            }
+        }
+        session.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    /// Sends `buf` to the peer, returning how many bytes were sent.
+    pub async fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        let fd = self.shared.socket.as_raw_fd();
+        let mut action = Action::sendmsg(fd, buf, &self.peer)?;
+        let n = poll_fn(|cx| Pin::new(&mut action).poll_send_to(cx)).await?;
+        if let Some(session) = self.shared.state.borrow_mut().sessions.get_mut(&self.peer) {
+            session.last_activity = Instant::now();
+        }
+        Ok(n)
+    }
+}
+
+impl Drop for PeerSession {
+    fn drop(&mut self) {
+        self.shared.state.borrow_mut().sessions.remove(&self.peer);
+    }
+}
+
+impl Shared {
+    /// Receives every datagram that is ready and queues it for its session, starting
+    /// sessions for new peers. The socket wakes the task polling last.
+    fn poll_pump(&self, cx: &mut Context) -> io::Result<()> {
+        let mut buf = self.buf.borrow_mut();
+        loop {
+            match self.socket.inner.poll_recv_from(cx, &mut buf) {
+                Poll::Ready(Ok((n, peer))) => self.dispatch(peer, &buf[..n]),
+                Poll::Ready(Err(e)) => return Err(e),
+                Poll::Pending => return Ok(()),
+            }
+        }
+    }
+
+    fn dispatch(&self, peer: SocketAddr, datagram: &[u8]) {
+        let mut state = self.state.borrow_mut();
+        let max_queued = state.max_queued;
+        if let Some(session) = state.sessions.get_mut(&peer) {
+            session.last_activity = Instant::now();
+            if session.queue.len() < max_queued {
+                session.queue.push_back(datagram.to_vec());
+            }
+            if let Some(waker) = session.waker.take() {
+                waker.wake();
+            }
+            return;
+        }
+        if !state.accepting || state.pending.len() >= MAX_PENDING {
+            return;
+        }
+        let session = Session {
+            queue: VecDeque::from(vec![datagram.to_vec()]),
+            waker: None,
+            last_activity: Instant::now(),
+        };
+        state.sessions.insert(peer, session);
+        state.pending.push_back(peer);
+        if let Some(waker) = state.acceptor.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Held while waiting for a datagram. The socket wakes whichever task polled it last,
+/// so once that task stops waiting the others are woken to take over.
+struct Handoff<'a>(&'a Shared);
+
+impl Drop for Handoff<'_> {
+    fn drop(&mut self) {
+        let mut state = self.0.state.borrow_mut();
+        let state = &mut *state;
+        let sessions = state.sessions.values_mut().filter_map(|s| s.waker.take());
+        let wakers: Vec<Waker> = sessions.chain(state.acceptor.take()).collect();
+        // wakers run no code of ours, waking with the state borrowed is fine.
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Runtime;
+    use std::net;
+
+    fn client(server: SocketAddr) -> net::UdpSocket {
+        let client = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.connect(server).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        client
+    }
+
+    #[test]
+    fn datagrams_are_grouped_into_a_session_per_peer() {
+        Runtime::new().unwrap().block_on(async {
+            let demux = UdpSocket::bind("127.0.0.1:0").unwrap().framed_by_peer();
+            let server = demux.local_addr().unwrap();
+            let (a, b) = (client(server), client(server));
+            a.send(b"a1").unwrap();
+            a.send(b"a2").unwrap();
+            b.send(b"b1").unwrap();
+
+            let first = demux.accept().await.unwrap();
+            assert_eq!(first.peer_addr(), a.local_addr().unwrap());
+            let second = demux.accept().await.unwrap();
+            assert_eq!(second.peer_addr(), b.local_addr().unwrap());
+            let mut buf = [0; 16];
+            let n = first.recv(&mut buf).await.unwrap();
+            assert_eq!(&buf[..n], b"a1");
+            let n = first.recv(&mut buf).await.unwrap();
+            assert_eq!(&buf[..n], b"a2");
+            let n = second.recv(&mut buf).await.unwrap();
+            assert_eq!(&buf[..n], b"b1");
+
+            second.send(b"reply").await.unwrap();
+            let n = b.recv(&mut buf).unwrap();
+            assert_eq!(&buf[..n], b"reply");
+
+            // a datagram after the session was dropped starts a new one.
+            drop(first);
+            a.send(b"a3").unwrap();
+            let again = demux.accept().await.unwrap();
+            assert_eq!(again.peer_addr(), a.local_addr().unwrap());
+            let n = again.recv(&mut buf).await.unwrap();
+            assert_eq!(&buf[..n], b"a3");
+        });
+    }
+
+    #[test]
+    fn a_quiet_session_times_out() {
+        Runtime::new().unwrap().block_on(async {
+            let mut demux = UdpSocket::bind("127.0.0.1:0").unwrap().framed_by_peer();
+            demux.set_idle_timeout(Some(Duration::from_millis(20)));
+            let a = client(demux.local_addr().unwrap());
+            a.send(b"hello").unwrap();
+            let session = demux.accept().await.unwrap();
+            let mut buf = [0; 16];
+            assert_eq!(session.recv(&mut buf).await.unwrap(), 5);
+            let err = session.recv(&mut buf).await.unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        });
+    }
+}
@@ -0,0 +1,181 @@
+pub mod demux;
+
+use std::io;
+use std::net::{self, SocketAddr, ToSocketAddrs};
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use futures_util::future::poll_fn;
+
+use super::ecn::{self, Ecn};
+use crate::driver::cmsg::Cmsgs;
+use crate::driver::connect;
+use crate::driver::{Action, Packet, ZcWrite};
+
+pub use demux::{PeerDemux, PeerSession};
+
+/// Room for the control message carrying a datagram's ECN codepoint.
+const ECN_CONTROL_LEN: usize = 64;
+
+pub struct UdpSocket {
+    inner: Packet<net::UdpSocket>,
+}
+
+impl AsRawFd for UdpSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.get_ref().as_raw_fd()
+    }
+}
+
+impl UdpSocket {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<UdpSocket> {
+        let addrs = addr.to_socket_addrs()?;
+        let mut last_err = None;
+
+        for addr in addrs {
+            match UdpSocket::bind_addr(addr) {
+                Ok(socket) => return Ok(socket),
+                Err(e) => last_err = Some(e),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "could not resolve to any address",
+            )
+        }))
+    }
+
+    fn bind_addr(addr: SocketAddr) -> io::Result<UdpSocket> {
+        Ok(UdpSocket::from_std(net::UdpSocket::bind(addr)?))
+    }
+
+    pub fn from_std(socket: net::UdpSocket) -> UdpSocket {
+        UdpSocket {
+            inner: Packet::new(socket),
+        }
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.get_ref().local_addr()
+    }
+
+    /// Asks the kernel to report the ECN codepoint of received datagrams, which
+    /// [`recv_from_ecn`] returns.
+    ///
+    /// [`recv_from_ecn`]: UdpSocket::recv_from_ecn
+    pub fn enable_recv_ecn(&self) -> io::Result<()> {
+        let v6 = self.local_addr()?.is_ipv6();
+        ecn::enable_recv(self.as_raw_fd(), v6)
+    }
+
+    /// Binds the socket to the interface or VRF device with index `ifindex`, so it only
+    /// sends and receives through that device. `None` removes the binding.
+    pub fn bind_device_by_index(&self, ifindex: Option<u32>) -> io::Result<()> {
+        connect::bind_device(self.as_raw_fd(), ifindex)
+    }
+
+    /// The index of the device the socket is bound to, `None` if it is not bound.
+    pub fn device_index(&self) -> io::Result<Option<u32>> {
+        connect::device_index(self.as_raw_fd())
+    }
+
+    pub fn connect<A: ToSocketAddrs>(&self, addr: A) -> io::Result<()> {
+        let addrs = addr.to_socket_addrs()?;
+        let mut last_err = None;
+
+        for addr in addrs {
+            match self.inner.get_ref().connect(addr) {
+                Ok(_) => return Ok(()),
+                Err(e) => last_err = Some(e),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "could not resolve to any address",
+            )
+        }))
+    }
+
+    pub async fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        poll_fn(|cx| self.inner.poll_recv(cx, buf)).await
+    }
+
+    pub async fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        poll_fn(|cx| self.inner.poll_send(cx, buf)).await
+    }
+
+    pub async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        poll_fn(|cx| self.inner.poll_recv_from(cx, buf)).await
+    }
+
+    /// Like `recv_from`, leaving the datagram queued so the next receive returns it
+    /// again.
+    pub async fn peek_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let mut action = Action::recvmsg_peek(self.as_raw_fd(), buf.len())?;
+        poll_fn(|cx| action.poll_recv_from(cx, buf)).await
+    }
+
+    pub async fn send_to<A: Into<SocketAddr>>(&self, buf: &[u8], target: A) -> io::Result<usize> {
+        let addr = target.into();
+        poll_fn(|cx| self.inner.poll_send_to(cx, buf, &addr)).await
+    }
+
+    /// Sends `buf` to `target` without copying it into the kernel, returning how many
+    /// bytes were sent. Before Linux 6.1 the bytes are copied like any send.
+    pub async fn send_zc_to<A: Into<SocketAddr>>(
+        &self,
+        buf: Vec<u8>,
+        target: A,
+    ) -> io::Result<usize> {
+        self.write_zc_to(buf, target)?.await
+    }
+
+    /// Like `send_zc_to`, handing out the send to wait for the kernel to release `buf`,
+    /// see [`ZcWrite::flushed`].
+    pub fn write_zc_to<A: Into<SocketAddr>>(&self, buf: Vec<u8>, target: A) -> io::Result<ZcWrite> {
+        let action = Action::sendmsg_zc(self.as_raw_fd(), buf, &target.into())?;
+        Ok(ZcWrite::new(action))
+    }
+
+    /// Sends `buf` to `target` with its ECN bits set to `ecn`, `None` sends it not-ECT.
+    pub async fn send_to_ecn<A: Into<SocketAddr>>(
+        &self,
+        buf: &[u8],
+        target: A,
+        ecn: Option<Ecn>,
+    ) -> io::Result<usize> {
+        let target = target.into();
+        let mut control = Cmsgs::with_capacity(ECN_CONTROL_LEN);
+        if let Some(ecn) = ecn {
+            let v4 = match target {
+                SocketAddr::V4(_) => true,
+                SocketAddr::V6(addr) => addr.ip().to_ipv4_mapped().is_some(),
+            };
+            ecn::push(&mut control, ecn, v4);
+        }
+        let completion =
+            Action::sendmsg_control(self.as_raw_fd(), buf.to_vec(), &target, control)?.await;
+        completion.output()
+    }
+
+    /// Like `recv_from`, also returning the ECN codepoint the datagram arrived with.
+    /// The codepoint is only reported after [`enable_recv_ecn`].
+    ///
+    /// [`enable_recv_ecn`]: UdpSocket::enable_recv_ecn
+    pub async fn recv_from_ecn(
+        &self,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, SocketAddr, Option<Ecn>)> {
+        let control = Cmsgs::with_capacity(ECN_CONTROL_LEN);
+        let completion = Action::recvmsg_control(self.as_raw_fd(), buf.len(), control)?.await;
+        let (n, recvmsg) = completion.into_parts();
+        let n = n?;
+        let (data, addr, control) = recvmsg.received(n)?;
+        buf[..n].copy_from_slice(&data);
+        let ecn = control
+            .iter()
+            .find_map(|(level, ty, data)| ecn::parse(level, ty, data));
+        Ok((n, addr, ecn))
+    }
+}
@@ -0,0 +1,114 @@
+use std::convert::TryInto;
+use std::io;
+use std::mem::size_of;
+use std::os::unix::io::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+
+use crate::driver::cmsg::Cmsgs;
+use crate::net::socket;
+
+/// Most fds received with a single message, the ones beyond are closed by the kernel
+/// and the message is reported [truncated](Ancillary::truncated).
+const MAX_FDS: usize = 32;
+
+/// The process credentials passed with `SCM_CREDENTIALS`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Credentials {
+    pub pid: i32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl Credentials {
+    /// The credentials of the calling process, the only ones an unprivileged process
+    /// may send.
+    pub fn current() -> Credentials {
+        unsafe {
+            Credentials {
+                pid: libc::getpid(),
+                uid: libc::getuid(),
+                gid: libc::getgid(),
+            }
+        }
+    }
+}
+
+/// The control messages received along with the bytes of a message, see
+/// [`UnixStream::recv_msg`](super::UnixStream::recv_msg).
+#[derive(Debug, Default)]
+pub struct Ancillary {
+    /// Fds passed with `SCM_RIGHTS`, owned by the receiver now.
+    pub fds: Vec<OwnedFd>,
+    /// Credentials passed with `SCM_CREDENTIALS`, only received once the socket was set
+    /// to [pass credentials](super::UnixStream::set_pass_credentials). The kernel fills
+    /// in the sender's own if it sent none.
+    pub credentials: Option<Credentials>,
+    /// Whether control messages were dropped for lack of room, fds passed in them were
+    /// closed.
+    pub truncated: bool,
+}
+
+/// Room for the control messages of a received message.
+pub(crate) fn recv_control() -> Cmsgs {
+    let fds = unsafe { libc::CMSG_SPACE((MAX_FDS * size_of::<RawFd>()) as u32) };
+    let credentials = unsafe { libc::CMSG_SPACE(size_of::<libc::ucred>() as u32) };
+    Cmsgs::with_capacity(fds as usize + credentials as usize)
+}
+
+/// The control messages passing `fds` and `credentials`.
+pub(crate) fn send_control(fds: &[BorrowedFd<'_>], credentials: Option<Credentials>) -> Cmsgs {
+    let fds: Vec<RawFd> = fds.iter().map(|fd| fd.as_raw_fd()).collect();
+    let mut len = 0;
+    if !fds.is_empty() {
+        len += unsafe { libc::CMSG_SPACE((fds.len() * size_of::<RawFd>()) as u32) } as usize;
+    }
+    if credentials.is_some() {
+        len += unsafe { libc::CMSG_SPACE(size_of::<libc::ucred>() as u32) } as usize;
+    }
+    let mut control = Cmsgs::with_capacity(len);
+    if !fds.is_empty() {
+        control.push_slice(libc::SOL_SOCKET, libc::SCM_RIGHTS, &fds);
+    }
+    if let Some(credentials) = credentials {
+        let ucred = libc::ucred {
+            pid: credentials.pid,
+            uid: credentials.uid,
+            gid: credentials.gid,
+        };
+        control.push(libc::SOL_SOCKET, libc::SCM_CREDENTIALS, ucred);
+    }
+    control
+}
+
+/// Takes ownership of the fds in `control` and reads the credentials.
+pub(crate) fn parse(control: &Cmsgs) -> Ancillary {
+    let mut ancillary = Ancillary {
+        truncated: control.is_truncated(),
+        ..Ancillary::default()
+    };
+    for (level, ty, data) in control.iter() {
+        match (level, ty) {
+            (libc::SOL_SOCKET, libc::SCM_RIGHTS) => {
+                let fds = data.chunks_exact(size_of::<RawFd>()).map(|fd| {
+                    let fd = RawFd::from_ne_bytes(fd.try_into().unwrap());
+                    unsafe { OwnedFd::from_raw_fd(fd) }
+                });
+                ancillary.fds.extend(fds);
+            }
+            (libc::SOL_SOCKET, libc::SCM_CREDENTIALS) if data.len() >= size_of::<libc::ucred>() => {
+                let ucred: libc::ucred = unsafe { std::ptr::read_unaligned(data.as_ptr().cast()) };
+                ancillary.credentials = Some(Credentials {
+                    pid: ucred.pid,
+                    uid: ucred.uid,
+                    gid: ucred.gid,
+                });
+            }
+            _ => {}
+        }
+    }
+    ancillary
+}
+
+/// Sets `SO_PASSCRED`, so received messages carry the sender's credentials.
+pub(crate) fn set_pass_credentials(fd: RawFd, pass: bool) -> io::Result<()> {
+    socket::setsockopt(fd, libc::SOL_SOCKET, libc::SO_PASSCRED, pass as libc::c_int)
+}
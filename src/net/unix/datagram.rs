@@ -0,0 +1,97 @@
+use std::io;
+use std::os::unix::io::{AsRawFd, BorrowedFd, RawFd};
+use std::os::unix::net;
+use std::path::Path;
+
+use futures_util::future::poll_fn;
+
+use super::ancillary::{self, Ancillary, Credentials};
+use super::SocketAddr;
+use crate::driver::{Action, Packet};
+
+/// A Unix datagram socket, each send is received as one message.
+pub struct UnixDatagram {
+    inner: Packet<net::UnixDatagram>,
+}
+
+impl AsRawFd for UnixDatagram {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.get_ref().as_raw_fd()
+    }
+}
+
+impl UnixDatagram {
+    pub fn bind<P: AsRef<Path>>(path: P) -> io::Result<UnixDatagram> {
+        Ok(UnixDatagram::from_std(net::UnixDatagram::bind(path)?))
+    }
+
+    /// A socket not bound to any address, which can still send once connected.
+    pub fn unbound() -> io::Result<UnixDatagram> {
+        Ok(UnixDatagram::from_std(net::UnixDatagram::unbound()?))
+    }
+
+    /// A pair of sockets connected to each other.
+    pub fn pair() -> io::Result<(UnixDatagram, UnixDatagram)> {
+        let (a, b) = net::UnixDatagram::pair()?;
+        Ok((UnixDatagram::from_std(a), UnixDatagram::from_std(b)))
+    }
+
+    pub fn from_std(socket: net::UnixDatagram) -> UnixDatagram {
+        UnixDatagram {
+            inner: Packet::new(socket),
+        }
+    }
+
+    /// Connects the socket to `path`, where `send` sends to and the only address `recv`
+    /// receives from.
+    pub fn connect<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.inner.get_ref().connect(path)
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        SocketAddr::new(|addr, len| syscall!(getsockname(self.as_raw_fd(), addr, len)))
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        SocketAddr::new(|addr, len| syscall!(getpeername(self.as_raw_fd(), addr, len)))
+    }
+
+    pub async fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        poll_fn(|cx| self.inner.poll_recv(cx, buf)).await
+    }
+
+    pub async fn send(&self, buf: &[u8]) -> io::Result<usize> {
+        poll_fn(|cx| self.inner.poll_send(cx, buf)).await
+    }
+
+    /// Receives a datagram into `buf` along with the fds and credentials passed with it,
+    /// returning its length. The part of the datagram that does not fit is dropped.
+    pub async fn recv_msg(&self, buf: &mut [u8]) -> io::Result<(usize, Ancillary)> {
+        let control = ancillary::recv_control();
+        let completion = Action::recvmsg_control(self.as_raw_fd(), buf.len(), control)?.await;
+        let (n, recvmsg) = completion.into_parts();
+        let n = n?;
+        let (data, control) = recvmsg.received_from_peer(n);
+        buf[..n].copy_from_slice(&data);
+        Ok((n, ancillary::parse(&control)))
+    }
+
+    /// Sends `buf` to the connected peer passing `fds` and `credentials` along,
+    /// returning how many bytes were sent.
+    pub async fn send_msg(
+        &self,
+        buf: &[u8],
+        fds: &[BorrowedFd<'_>],
+        credentials: Option<Credentials>,
+    ) -> io::Result<usize> {
+        let control = ancillary::send_control(fds, credentials);
+        let completion = Action::send_control(self.as_raw_fd(), buf.to_vec(), control)?.await;
+        completion.output()
+    }
+
+    /// Sets `SO_PASSCRED`, so [`recv_msg`](UnixDatagram::recv_msg) reports the sender's
+    /// credentials.
+    pub fn set_pass_credentials(&self, pass: bool) -> io::Result<()> {
+        ancillary::set_pass_credentials(self.as_raw_fd(), pass)
+    }
+}
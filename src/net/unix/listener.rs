@@ -0,0 +1,199 @@
+use std::fs;
+use std::io;
+use std::os::unix::fs::{self as unix_fs, FileTypeExt, MetadataExt, PermissionsExt};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::{SocketAddr, UnixStream};
+use crate::driver::accept::Accept;
+use crate::driver::action::Completion;
+use crate::driver::connect;
+use crate::driver::Action;
+
+/// Pending connection queue length, the same as std uses.
+const BACKLOG: i32 = 128;
+
+pub struct UnixListener {
+    inner: net::UnixListener,
+    /// The socket file removed on drop, with the device and inode it was created with.
+    unlink: Option<(PathBuf, u64, u64)>,
+}
+
+/// Options for binding a [`UnixListener`] to a path.
+///
+/// A daemon that crashed leaves its socket file behind, and binding the same path again
+/// fails with `AddrInUse` until it is removed. [`takeover_stale`] removes such a file
+/// when nothing listens on it anymore, [`unlink_on_drop`] removes the file once the
+/// listener is dropped.
+///
+/// [`mode`] and [`owner`] restrict who may connect. They are applied to the socket file
+/// before the listener starts listening, so no connection gets in before.
+///
+/// [`takeover_stale`]: BindOptions::takeover_stale
+/// [`unlink_on_drop`]: BindOptions::unlink_on_drop
+/// [`mode`]: BindOptions::mode
+/// [`owner`]: BindOptions::owner
+#[derive(Debug, Clone, Default)]
+pub struct BindOptions {
+    takeover_stale: bool,
+    unlink_on_drop: bool,
+    mode: Option<u32>,
+    owner: Option<(Option<u32>, Option<u32>)>,
+}
+
+impl BindOptions {
+    pub fn new() -> BindOptions {
+        BindOptions::default()
+    }
+
+    /// Removes a socket file found at the path if connecting to it is refused, which
+    /// means the process that listened on it is gone. A live listener is left alone and
+    /// binding fails with `AddrInUse`.
+    pub fn takeover_stale(&mut self, takeover: bool) -> &mut BindOptions {
+        self.takeover_stale = takeover;
+        self
+    }
+
+    /// Removes the socket file when the listener is dropped, unless it was replaced by
+    /// another file in the meantime.
+    pub fn unlink_on_drop(&mut self, unlink: bool) -> &mut BindOptions {
+        self.unlink_on_drop = unlink;
+        self
+    }
+
+    /// Sets the permission bits of the socket file, connecting needs write permission.
+    /// By default they follow the umask.
+    pub fn mode(&mut self, mode: u32) -> &mut BindOptions {
+        self.mode = Some(mode);
+        self
+    }
+
+    /// Sets the owner and group of the socket file, `None` keeps the one it was created
+    /// with. Changing the owner needs `CAP_CHOWN`, the group can be changed to any group
+    /// of the process.
+    pub fn owner(&mut self, uid: Option<u32>, gid: Option<u32>) -> &mut BindOptions {
+        self.owner = Some((uid, gid));
+        self
+    }
+
+    pub async fn bind<P: AsRef<Path>>(&self, path: P) -> io::Result<UnixListener> {
+        let path = path.as_ref();
+        let addr = SocketAddr::from_pathname(path)?;
+        let fd = match bind(&addr) {
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse && self.takeover_stale => {
+                if !is_stale(path, &addr)? {
+                    return Err(e);
+                }
+                fs::remove_file(path)?;
+                bind(&addr)?
+            }
+            res => res?,
+        };
+        // a socket file left with the wrong permissions would be worse than none.
+        if let Err(e) = self.restrict(path) {
+            let _ = fs::remove_file(path);
+            return Err(e);
+        }
+        syscall!(listen(fd.as_raw_fd(), BACKLOG))?;
+        let mut unlink = None;
+        if self.unlink_on_drop {
+            let meta = fs::symlink_metadata(path)?;
+            unlink = Some((path.to_path_buf(), meta.dev(), meta.ino()));
+        }
+        Ok(UnixListener {
+            inner: net::UnixListener::from(fd),
+            unlink,
+        })
+    }
+
+    /// Applies `mode` and `owner` to the socket file at `path`.
+    fn restrict(&self, path: &Path) -> io::Result<()> {
+        if let Some((uid, gid)) = self.owner {
+            unix_fs::lchown(path, uid, gid)?;
+        }
+        if let Some(mode) = self.mode {
+            fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+        }
+        Ok(())
+    }
+}
+
+/// Creates a socket bound to `addr`, not listening yet.
+fn bind(addr: &SocketAddr) -> io::Result<OwnedFd> {
+    let fd = connect::new_socket(libc::AF_UNIX, libc::SOCK_STREAM)?;
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    syscall!(bind(fd.as_raw_fd(), addr.as_ptr(), addr.len()))?;
+    Ok(fd)
+}
+
+/// Whether the file at `path` is a socket nobody listens on. The probe does not block,
+/// a listener with a full backlog counts as live.
+fn is_stale(path: &Path, addr: &SocketAddr) -> io::Result<bool> {
+    if !fs::symlink_metadata(path)?.file_type().is_socket() {
+        return Ok(false);
+    }
+    let fd = connect::new_socket(libc::AF_UNIX, libc::SOCK_STREAM | libc::SOCK_NONBLOCK)?;
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    match syscall!(connect(fd.as_raw_fd(), addr.as_ptr(), addr.len())) {
+        Err(e) => Ok(e.raw_os_error() == Some(libc::ECONNREFUSED)),
+        Ok(_) => Ok(false),
+    }
+}
+
+impl AsRawFd for UnixListener {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+impl Drop for UnixListener {
+    fn drop(&mut self) {
+        if let Some((path, dev, ino)) = &self.unlink {
+            let ours = fs::symlink_metadata(path)
+                .is_ok_and(|meta| meta.dev() == *dev && meta.ino() == *ino);
+            if ours {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+}
+
+impl UnixListener {
+    /// Binds a listener to the socket file at `path`, see [`BindOptions`] for cleaning up
+    /// socket files left behind.
+    pub async fn bind<P: AsRef<Path>>(path: P) -> io::Result<UnixListener> {
+        BindOptions::new().bind(path).await
+    }
+
+    pub fn from_std(listener: net::UnixListener) -> UnixListener {
+        UnixListener {
+            inner: listener,
+            unlink: None,
+        }
+    }
+
+    pub async fn accept(&self) -> io::Result<(UnixStream, SocketAddr)> {
+        UnixListener::accepted(Action::accept(self.inner.as_raw_fd())?.await)
+    }
+
+    /// Like `accept`, failing with `ErrorKind::TimedOut` if no connection arrived within
+    /// `timeout`.
+    pub async fn accept_timeout(&self, timeout: Duration) -> io::Result<(UnixStream, SocketAddr)> {
+        let fd = self.inner.as_raw_fd();
+        UnixListener::accepted(Action::accept_timeout(fd, timeout)?.await)
+    }
+
+    fn accepted(completion: Completion<Accept>) -> io::Result<(UnixStream, SocketAddr)> {
+        let (fd, accept) = completion.into_parts();
+        let stream = net::UnixStream::from(fd?);
+        let addr = accept.unix_peer_addr()?;
+        Ok((UnixStream::from_std(stream), addr))
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        let fd = self.inner.as_raw_fd();
+        SocketAddr::new(|addr, len| syscall!(getsockname(fd, addr, len)))
+    }
+}
@@ -0,0 +1,11 @@
+pub mod ancillary;
+pub mod datagram;
+pub mod listener;
+pub mod socketaddr;
+pub mod stream;
+
+pub use ancillary::{Ancillary, Credentials};
+pub use datagram::UnixDatagram;
+pub use listener::{BindOptions, UnixListener};
+pub use socketaddr::SocketAddr;
+pub use stream::UnixStream;
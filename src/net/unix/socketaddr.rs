@@ -0,0 +1,135 @@
+use std::ffi::OsStr;
+use std::fmt;
+use std::io;
+use std::mem::{self, size_of};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+/// An address associated with a Unix socket.
+#[derive(Clone)]
+pub struct SocketAddr {
+    addr: libc::sockaddr_un,
+    len: libc::socklen_t,
+}
+
+fn sun_path_offset(addr: &libc::sockaddr_un) -> usize {
+    let base = addr as *const _ as usize;
+    let path = &addr.sun_path as *const _ as usize;
+    path - base
+}
+
+impl SocketAddr {
+    /// Builds the address of the socket file at `path`.
+    pub(crate) fn from_pathname(path: &Path) -> io::Result<SocketAddr> {
+        let mut addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+        addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+
+        let bytes = path.as_os_str().as_bytes();
+        if bytes.contains(&0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "paths must not contain interior null bytes",
+            ));
+        }
+        // leave room for the trailing null byte.
+        if bytes.len() >= addr.sun_path.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "path must be shorter than SUN_LEN",
+            ));
+        }
+        for (dst, src) in addr.sun_path.iter_mut().zip(bytes) {
+            *dst = *src as libc::c_char;
+        }
+
+        let mut len = sun_path_offset(&addr) + bytes.len();
+        if !bytes.is_empty() {
+            len += 1;
+        }
+        Ok(SocketAddr {
+            addr,
+            len: len as libc::socklen_t,
+        })
+    }
+
+    /// Reads an address the kernel wrote into `storage`.
+    pub(crate) unsafe fn from_storage(
+        storage: *const libc::sockaddr_storage,
+        len: libc::socklen_t,
+    ) -> io::Result<SocketAddr> {
+        if (*storage).ss_family as libc::c_int != libc::AF_UNIX {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+        let addr = *(storage as *const libc::sockaddr_un);
+        let len = len.min(size_of::<libc::sockaddr_un>() as libc::socklen_t);
+        Ok(SocketAddr { addr, len })
+    }
+
+    /// Queries an address with `getsockname` or `getpeername`.
+    pub(crate) fn new<F>(f: F) -> io::Result<SocketAddr>
+    where
+        F: FnOnce(*mut libc::sockaddr, *mut libc::socklen_t) -> io::Result<libc::c_int>,
+    {
+        let mut addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+        let mut len = size_of::<libc::sockaddr_un>() as libc::socklen_t;
+        f(&mut addr as *mut _ as *mut _, &mut len)?;
+        if len == 0 {
+            // some kernels report a zero length for unnamed sockets.
+            addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+            len = sun_path_offset(&addr) as libc::socklen_t;
+        }
+        Ok(SocketAddr { addr, len })
+    }
+
+    pub(crate) fn as_ptr(&self) -> *const libc::sockaddr {
+        &self.addr as *const _ as *const _
+    }
+
+    pub(crate) fn len(&self) -> libc::socklen_t {
+        self.len
+    }
+
+    fn path_bytes(&self) -> &[u8] {
+        let len = self.len as usize - sun_path_offset(&self.addr);
+        let path = unsafe { &*(&self.addr.sun_path as *const [libc::c_char] as *const [u8]) };
+        &path[..len]
+    }
+
+    /// Returns true if the address is unnamed.
+    pub fn is_unnamed(&self) -> bool {
+        self.path_bytes().is_empty()
+    }
+
+    /// Returns the contents of this address if it is a pathname address.
+    pub fn as_pathname(&self) -> Option<&Path> {
+        let bytes = self.path_bytes();
+        match bytes.first() {
+            None | Some(0) => None,
+            // strip the trailing null byte.
+            Some(_) => {
+                let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+                Some(Path::new(OsStr::from_bytes(&bytes[..end])))
+            }
+        }
+    }
+
+    /// Returns the name of an address in the abstract namespace.
+    pub fn as_abstract_name(&self) -> Option<&[u8]> {
+        match self.path_bytes() {
+            [0, name @ ..] => Some(name),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Debug for SocketAddr {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(path) = self.as_pathname() {
+            write!(fmt, "{:?} (pathname)", path)
+        } else if let Some(name) = self.as_abstract_name() {
+            write!(fmt, "{:?} (abstract)", String::from_utf8_lossy(name))
+        } else {
+            write!(fmt, "(unnamed)")
+        }
+    }
+}
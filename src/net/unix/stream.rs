@@ -0,0 +1,338 @@
+use std::io;
+use std::mem::ManuallyDrop;
+use std::net::Shutdown;
+use std::os::unix::io::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net;
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures_util::future::poll_fn;
+use futures_util::io::{AsyncBufRead, AsyncRead, AsyncWrite};
+
+use super::ancillary::{self, Ancillary, Credentials};
+use super::SocketAddr;
+use crate::buf::{IoBuf, IoBufMut, IoSliceOwned};
+use crate::driver::action::Completion;
+use crate::driver::chain::BufChain;
+use crate::driver::connect::ConnectUnix;
+use crate::driver::shared_fd;
+use crate::driver::{self, Action, SharedFd, StreamParts, StreamStats};
+
+/// A Unix stream socket.
+///
+/// Reads and writes follow the same cancellation rules as
+/// [`TcpStream`](crate::net::TcpStream).
+pub struct UnixStream {
+    inner: driver::Stream<SharedFd>,
+}
+
+impl AsRawFd for UnixStream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.get_ref().as_raw_fd()
+    }
+}
+
+impl FromRawFd for UnixStream {
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        UnixStream::from_std(net::UnixStream::from_raw_fd(fd))
+    }
+}
+
+impl UnixStream {
+    pub fn from_std(stream: net::UnixStream) -> UnixStream {
+        UnixStream {
+            inner: driver::Stream::new(SharedFd::from(OwnedFd::from(stream))),
+        }
+    }
+
+    pub async fn connect<P: AsRef<Path>>(path: P) -> io::Result<UnixStream> {
+        let addr = SocketAddr::from_pathname(path.as_ref())?;
+        UnixStream::connected(Action::connect_unix(addr)?.await)
+    }
+
+    /// Connects to the socket at `path`, failing with `ErrorKind::TimedOut` if the
+    /// connection was not established within `timeout`.
+    pub async fn connect_timeout<P: AsRef<Path>>(
+        path: P,
+        timeout: Duration,
+    ) -> io::Result<UnixStream> {
+        let addr = SocketAddr::from_pathname(path.as_ref())?;
+        UnixStream::connected(Action::connect_unix_timeout(addr, timeout)?.await)
+    }
+
+    fn connected(completion: Completion<ConnectUnix>) -> io::Result<UnixStream> {
+        let fd = completion.action.get_socket(completion.result)?;
+        Ok(UnixStream::from_std(unsafe {
+            net::UnixStream::from_raw_fd(fd)
+        }))
+    }
+
+    /// Creates an unnamed pair of connected sockets.
+    pub fn pair() -> io::Result<(UnixStream, UnixStream)> {
+        let (a, b) = net::UnixStream::pair()?;
+        Ok((UnixStream::from_std(a), UnixStream::from_std(b)))
+    }
+
+    /// Reads some bytes into `buf`, returning how many were read. `Ok(0)` means the
+    /// peer closed its write side.
+    pub async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        poll_fn(|cx| self.inner.poll_read(cx, buf)).await
+    }
+
+    /// Writes some bytes from `buf`, returning how many were written.
+    pub async fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf).await
+    }
+
+    /// Like `read`, failing with `ErrorKind::TimedOut` if nothing arrived within
+    /// `timeout`. The kernel cancels the receive itself through a linked timeout.
+    pub async fn read_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        self.inner.read_timeout(buf, timeout).await
+    }
+
+    /// Like `read`, the kernel picking the buffer the bytes land in from the ring
+    /// registered as group `bgid`, see
+    /// [`Runtime::register_buffer_group`](crate::Runtime::register_buffer_group).
+    pub async fn read_with_group(&mut self, buf: &mut [u8], bgid: u16) -> io::Result<usize> {
+        self.inner.read_with_group(buf, bgid).await
+    }
+
+    /// Like `read`, the kernel picking the buffer from the registered buffer group
+    /// whose buffers hold `buf.len()` bytes with the least room to spare, so small
+    /// reads do not tie up large buffers. The group with the largest buffers is used
+    /// when none holds that many.
+    pub async fn read_sized(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read_sized(buf).await
+    }
+
+    /// Like `write`, failing with `ErrorKind::TimedOut` if nothing could be written
+    /// within `timeout`.
+    pub async fn write_timeout(&mut self, buf: &[u8], timeout: Duration) -> io::Result<usize> {
+        self.inner.write_timeout(buf, timeout).await
+    }
+
+    /// Registers the socket with the runtime's fixed file table, so reads and writes
+    /// refer to it by slot and skip the fd lookup on every operation. It is unregistered
+    /// when the stream is dropped.
+    pub fn register_fd(&mut self) -> io::Result<()> {
+        self.inner.register_fd()
+    }
+
+    /// Traffic counters of this stream.
+    pub fn stats(&self) -> StreamStats {
+        self.inner.stats()
+    }
+
+    /// Fails pending reads and writes with `TimedOut` once no bytes moved in either
+    /// direction for `timeout`, so a silent peer is dropped while a slow transfer that
+    /// keeps making progress is not. `None` turns the idle timeout off.
+    pub fn set_idle_timeout(&mut self, timeout: Option<Duration>) {
+        self.inner.set_idle_timeout(timeout)
+    }
+
+    /// Fails pending reads and writes with `TimedOut` once `deadline` passed, however
+    /// much progress was made until then. `None` removes the deadline.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.inner.set_deadline(deadline)
+    }
+
+    /// Holds back writes while they add up to at most `limit` bytes and writes them as
+    /// one once a larger write comes in, the stream is flushed or the runtime has nothing
+    /// else to do. A failed write of held back bytes fails the next write or flush.
+    /// Bytes still held back when the stream is dropped are written in the background,
+    /// where a failure goes unnoticed, so flush before dropping the stream. 0 turns
+    /// coalescing off.
+    pub fn set_write_coalescing(&mut self, limit: usize) {
+        self.inner.set_write_coalescing(limit)
+    }
+
+    /// Sets `SO_RCVLOWAT`, a pending read is only woken once at least `n` bytes arrived
+    /// or the peer closed its write side. Bytes already received are still returned
+    /// right away, so this saves wakeups for fragmented messages without delaying any.
+    pub fn set_recv_low_watermark(&self, n: usize) -> io::Result<()> {
+        self.inner.set_recv_low_watermark(n)
+    }
+
+    /// Starts the next read as soon as one completed with data, so the bytes that follow
+    /// are already on their way while the caller handles what it got.
+    pub fn set_read_ahead(&mut self, enabled: bool) {
+        self.inner.set_read_ahead(enabled)
+    }
+
+    /// Attaches a value to this stream, replacing any previous one.
+    pub fn set_context<C: 'static>(&mut self, context: C) {
+        self.inner.set_context(context);
+    }
+
+    /// The value attached with `set_context`, `None` if there is none of type `C`.
+    pub fn context<C: 'static>(&self) -> Option<&C> {
+        self.inner.context()
+    }
+
+    pub fn context_mut<C: 'static>(&mut self) -> Option<&mut C> {
+        self.inner.context_mut()
+    }
+
+    /// Moves the next received bytes into `chain` without copying them, returning how
+    /// many were added. `Ok(0)` means the peer closed its write side.
+    pub async fn read_chain(&mut self, chain: &mut BufChain) -> io::Result<usize> {
+        poll_fn(|cx| self.inner.poll_read_chain(cx, chain)).await
+    }
+
+    /// Writes all of `chain` with vectored writes, returning how many bytes were written.
+    /// Written bytes are removed from `chain`, segments in flight when the future is
+    /// dropped are lost.
+    pub async fn write_chain(&mut self, chain: &mut BufChain) -> io::Result<usize> {
+        self.inner.write_chain(chain).await
+    }
+
+    /// Reads into `buf` up to its capacity, replacing its contents, and returns how many
+    /// bytes were read together with the buffer, which the read owns until the kernel is
+    /// done with it.
+    pub async fn read_buf<B: IoBufMut>(&mut self, buf: B) -> (io::Result<usize>, B) {
+        self.inner.read_buf(buf).await
+    }
+
+    /// Writes the contents of `buf`, returning how many bytes were written together with
+    /// the buffer, which the write owns until the kernel is done with it.
+    pub async fn write_buf<B: IoBuf>(&mut self, buf: B) -> (io::Result<usize>, B) {
+        self.inner.write_buf(buf).await
+    }
+
+    /// Reads into `bufs` in order with a single `readv`, replacing their contents, and
+    /// returns how many bytes were read together with the buffers.
+    pub async fn read_vectored(
+        &mut self,
+        bufs: Vec<IoSliceOwned>,
+    ) -> io::Result<(usize, Vec<IoSliceOwned>)> {
+        self.inner.read_vectored(bufs).await
+    }
+
+    /// Writes the contents of `bufs` in order with a single `writev`, returning how many
+    /// bytes were written together with the buffers.
+    pub async fn write_vectored(
+        &mut self,
+        bufs: Vec<IoSliceOwned>,
+    ) -> io::Result<(usize, Vec<IoSliceOwned>)> {
+        self.inner.write_vectored(bufs).await
+    }
+
+    /// Receives exactly `len` bytes and writes them back to the peer, returning how many
+    /// were echoed. Fewer than `len` means the peer closed its write side.
+    ///
+    /// The recv and the send are linked and submitted together, halving submissions for
+    /// echo-style request/response traffic.
+    pub async fn recv_send(&mut self, len: usize) -> io::Result<usize> {
+        self.inner.recv_send(len).await
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        let fd = self.as_raw_fd();
+        SocketAddr::new(|addr, len| syscall!(getsockname(fd, addr, len)))
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        let fd = self.as_raw_fd();
+        SocketAddr::new(|addr, len| syscall!(getpeername(fd, addr, len)))
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        // the fd stays owned by the stream.
+        let io = ManuallyDrop::new(unsafe { net::UnixStream::from_raw_fd(self.as_raw_fd()) });
+        io.shutdown(how)
+    }
+
+    /// Receives some bytes into `buf` along with the fds and credentials passed with
+    /// them, returning how many bytes were received. Bytes buffered by an earlier read
+    /// are handed out first, without ancillary data. Plain reads close the fds passed
+    /// with the bytes they receive, and may keep receiving ahead until `recv_msg` is
+    /// called, so a protocol passing fds sticks to `recv_msg`.
+    pub async fn recv_msg(&mut self, buf: &mut [u8]) -> io::Result<(usize, Ancillary)> {
+        let (n, control) = self.inner.recv_msg(buf, ancillary::recv_control()).await?;
+        Ok((n, ancillary::parse(&control)))
+    }
+
+    /// Writes `buf` as one message passing `fds` and `credentials` along, returning how
+    /// many bytes were written. The fds stay open on this side, the peer receives
+    /// duplicates of them.
+    pub async fn send_msg(
+        &mut self,
+        buf: &[u8],
+        fds: &[BorrowedFd<'_>],
+        credentials: Option<Credentials>,
+    ) -> io::Result<usize> {
+        let control = ancillary::send_control(fds, credentials);
+        self.inner.send_msg(buf, control).await
+    }
+
+    /// Sets `SO_PASSCRED`, so [`recv_msg`](UnixStream::recv_msg) reports the sender's
+    /// credentials.
+    pub fn set_pass_credentials(&self, pass: bool) -> io::Result<()> {
+        ancillary::set_pass_credentials(self.as_raw_fd(), pass)
+    }
+
+    /// The socket, shared with the stream. It stays open while a clone is held, after
+    /// the stream was dropped.
+    pub fn shared_fd(&self) -> SharedFd {
+        self.inner.get_ref().clone()
+    }
+
+    /// Detaches the connection from the runtime, see [`TcpStream::into_parts`].
+    ///
+    /// [`TcpStream::into_parts`]: crate::net::TcpStream::into_parts
+    pub async fn into_parts(self) -> io::Result<StreamParts<OwnedFd>> {
+        shared_fd::into_owned_parts(self.inner).await
+    }
+
+    /// Resumes a connection detached by [`UnixStream::into_parts`] on the current
+    /// runtime.
+    pub fn from_parts(parts: StreamParts<OwnedFd>) -> UnixStream {
+        let parts = StreamParts {
+            io: SharedFd::from(parts.io),
+            read: parts.read,
+            write: parts.write,
+        };
+        UnixStream {
+            inner: driver::Stream::from_parts(parts),
+        }
+    }
+}
+
+impl AsyncBufRead for UnixStream {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        self.get_mut().inner.poll_fill_buf(cx)
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        self.get_mut().inner.consume(amt);
+    }
+}
+
+impl AsyncRead for UnixStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.get_mut().inner.poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for UnixStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.get_mut().inner.poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        self.get_mut().inner.poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let me = self.get_mut();
+        ready!(me.inner.poll_flush(cx))?;
+        me.shutdown(Shutdown::Write)?;
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,19 @@
+//! Operations defined outside the crate.
+//!
+//! An operation is a value owning whatever the kernel reads or writes while it runs.
+//! [`Action::submit`] hands it to the runtime's ring along with its submission entry,
+//! and the returned [`Action`] resolves to a [`Completion`] that gives the value back.
+//! Implementing [`Completable`] decodes the kernel's result into a typed output, a
+//! multishot operation takes its completions one by one with [`Action::poll_update`].
+//! Submitting outside a runtime panics.
+//!
+//! The operations of the crate decode their results the same way, the fd of an
+//! [`Accept`] or [`Open`] arrives as an `OwnedFd` and the length of a [`Read`],
+//! [`Write`], [`Recv`] or [`Send`] as a `usize`.
+
+pub use crate::driver::accept::Accept;
+pub use crate::driver::open::Open;
+pub use crate::driver::{Action, Completable, Completion, Cqe, Detached, Shot};
+pub use crate::driver::{Read, Recv, Send, Write};
+pub use io_uring::squeue::Entry;
+pub use io_uring::{opcode, types};
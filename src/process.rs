@@ -0,0 +1,633 @@
+//! Child processes whose pipes and exit are driven by the ring.
+//!
+//! Spawning goes through `std::process::Command`. The pipes to the child are read and
+//! written with ring operations, and the exit is awaited with `IORING_OP_WAITID` on
+//! Linux 6.7 and by polling a pidfd before, so no thread blocks in `waitpid`. Waiting
+//! needs Linux 5.3 for `pidfd_open`.
+//!
+//! Processes this one did not spawn are watched through a [`PidFd`].
+
+use std::ffi::OsStr;
+use std::future::Future;
+use std::io;
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::pin::Pin;
+use std::process::{self, ExitStatus, Output, Stdio};
+use std::ptr;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures_util::future::{poll_fn, try_join3};
+use futures_util::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+
+use crate::driver::{self, Action, PollMulti};
+use crate::task;
+use crate::time;
+
+/// Builds and spawns a child process, see `std::process::Command`.
+#[derive(Debug)]
+pub struct Command {
+    inner: process::Command,
+    timeout: Option<Duration>,
+    kill_on_drop: bool,
+}
+
+impl Command {
+    pub fn new<S: AsRef<OsStr>>(program: S) -> Command {
+        Command::from(process::Command::new(program))
+    }
+
+    pub fn arg<S: AsRef<OsStr>>(&mut self, arg: S) -> &mut Command {
+        self.inner.arg(arg);
+        self
+    }
+
+    pub fn args<I, S>(&mut self, args: I) -> &mut Command
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.inner.args(args);
+        self
+    }
+
+    pub fn env<K: AsRef<OsStr>, V: AsRef<OsStr>>(&mut self, key: K, val: V) -> &mut Command {
+        self.inner.env(key, val);
+        self
+    }
+
+    pub fn envs<I, K, V>(&mut self, vars: I) -> &mut Command
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        self.inner.envs(vars);
+        self
+    }
+
+    pub fn env_remove<K: AsRef<OsStr>>(&mut self, key: K) -> &mut Command {
+        self.inner.env_remove(key);
+        self
+    }
+
+    pub fn env_clear(&mut self) -> &mut Command {
+        self.inner.env_clear();
+        self
+    }
+
+    pub fn current_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Command {
+        self.inner.current_dir(dir);
+        self
+    }
+
+    /// Where the child's stdin comes from, `Stdio::piped()` to write it through
+    /// [`Child::stdin`]. Inherited by default.
+    pub fn stdin<T: Into<Stdio>>(&mut self, cfg: T) -> &mut Command {
+        self.inner.stdin(cfg);
+        self
+    }
+
+    /// Where the child's stdout goes, `Stdio::piped()` to read it through
+    /// [`Child::stdout`]. Inherited by default.
+    pub fn stdout<T: Into<Stdio>>(&mut self, cfg: T) -> &mut Command {
+        self.inner.stdout(cfg);
+        self
+    }
+
+    /// Where the child's stderr goes, `Stdio::piped()` to read it through
+    /// [`Child::stderr`]. Inherited by default.
+    pub fn stderr<T: Into<Stdio>>(&mut self, cfg: T) -> &mut Command {
+        self.inner.stderr(cfg);
+        self
+    }
+
+    /// Kills the child run by [`status`](Command::status) or [`output`](Command::output)
+    /// once it ran for `timeout`, which then fail with `ErrorKind::TimedOut`. `None`, the
+    /// default, lets it run for as long as it takes.
+    pub fn timeout(&mut self, timeout: Option<Duration>) -> &mut Command {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Kills the spawned child when its [`Child`] is dropped before it exited, such as
+    /// when the future waiting for it is. Off by default, as with std.
+    pub fn kill_on_drop(&mut self, kill: bool) -> &mut Command {
+        self.kill_on_drop = kill;
+        self
+    }
+
+    pub fn spawn(&mut self) -> io::Result<Child> {
+        let mut child = self.inner.spawn()?;
+        let pidfd = match pidfd_open(child.id()) {
+            Ok(pidfd) => pidfd,
+            Err(e) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(e);
+            }
+        };
+        Ok(Child {
+            stdin: child
+                .stdin
+                .take()
+                .map(|inner| ChildStdin { inner, write: None }),
+            stdout: child.stdout.take().map(|inner| ChildStdout {
+                inner,
+                pipe: Pipe::default(),
+            }),
+            stderr: child.stderr.take().map(|inner| ChildStderr {
+                inner,
+                pipe: Pipe::default(),
+            }),
+            inner: child,
+            pidfd,
+            kill_on_drop: self.kill_on_drop,
+        })
+    }
+
+    /// Spawns the child and waits for it to exit, killing it once the
+    /// [timeout](Command::timeout) elapsed.
+    pub async fn status(&mut self) -> io::Result<ExitStatus> {
+        let mut child = self.spawn()?;
+        let res = within(self.timeout, child.wait()).await;
+        child.or_kill(res).await
+    }
+
+    /// Spawns the child with its stdout and stderr captured, and waits for it to exit,
+    /// killing it once the [timeout](Command::timeout) elapsed. The command keeps both
+    /// piped for later spawns.
+    pub async fn output(&mut self) -> io::Result<Output> {
+        self.stdout(Stdio::piped());
+        self.stderr(Stdio::piped());
+        let mut child = self.spawn()?;
+        let res = within(self.timeout, child.collect()).await;
+        child.or_kill(res).await
+    }
+}
+
+impl From<process::Command> for Command {
+    fn from(inner: process::Command) -> Command {
+        Command {
+            inner,
+            timeout: None,
+            kill_on_drop: false,
+        }
+    }
+}
+
+fn pidfd_open(pid: u32) -> io::Result<RawFd> {
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid as libc::pid_t, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(fd as RawFd)
+}
+
+/// A spawned child process.
+///
+/// Like `std::process::Child`, dropping it neither kills the child nor waits for it,
+/// unless it was spawned to be [killed on drop](Command::kill_on_drop).
+pub struct Child {
+    pub stdin: Option<ChildStdin>,
+    pub stdout: Option<ChildStdout>,
+    pub stderr: Option<ChildStderr>,
+    inner: process::Child,
+    /// Becomes readable once the child exited.
+    pidfd: RawFd,
+    kill_on_drop: bool,
+}
+
+impl Child {
+    pub fn id(&self) -> u32 {
+        self.inner.id()
+    }
+
+    /// Sends `SIGKILL` to the child, it still has to be waited for.
+    pub fn kill(&mut self) -> io::Result<()> {
+        self.inner.kill()
+    }
+
+    /// Returns the exit status if the child has exited, without waiting.
+    pub fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
+        self.inner.try_wait()
+    }
+
+    /// Waits for the child to exit. Stdin is closed first, so a child reading it until
+    /// end of file does not wait for input forever.
+    pub async fn wait(&mut self) -> io::Result<ExitStatus> {
+        drop(self.stdin.take());
+        let waitid = driver::features().is_some_and(|features| features.waitid);
+        loop {
+            if let Some(status) = self.inner.try_wait()? {
+                return Ok(status);
+            }
+            // the exited child is left for `try_wait` to reap.
+            if waitid {
+                Action::waitid(self.id())?.await.output()?;
+            } else {
+                Action::poll_add(self.pidfd, libc::POLLIN as u32)?
+                    .await
+                    .output()?;
+            }
+        }
+    }
+
+    /// Waits for the child to exit while reading its stdout and stderr to the end.
+    pub async fn wait_with_output(mut self) -> io::Result<Output> {
+        self.collect().await
+    }
+
+    async fn collect(&mut self) -> io::Result<Output> {
+        drop(self.stdin.take());
+        let stdout = read_to_end(self.stdout.take());
+        let stderr = read_to_end(self.stderr.take());
+        let (status, stdout, stderr) = try_join3(self.wait(), stdout, stderr).await?;
+        Ok(Output {
+            status,
+            stdout,
+            stderr,
+        })
+    }
+
+    /// Passes the outcome of waiting within the timeout through, or kills the child and
+    /// waits for it to exit before failing with `ErrorKind::TimedOut` if there is none.
+    async fn or_kill<T>(&mut self, res: Option<io::Result<T>>) -> io::Result<T> {
+        if let Some(res) = res {
+            return res;
+        }
+        self.kill()?;
+        self.wait().await?;
+        Err(io::ErrorKind::TimedOut.into())
+    }
+}
+
+/// Runs `future` for at most `timeout`, `None` once it elapsed.
+async fn within<F: Future>(timeout: Option<Duration>, future: F) -> Option<F::Output> {
+    match timeout {
+        Some(timeout) => time::timeout(timeout, future).await.ok(),
+        None => Some(future.await),
+    }
+}
+
+impl Drop for Child {
+    fn drop(&mut self) {
+        let pidfd = unsafe { OwnedFd::from_raw_fd(self.pidfd) };
+        if !self.kill_on_drop || !matches!(self.inner.try_wait(), Ok(None)) {
+            return;
+        }
+        if self.inner.kill().is_err() || driver::features().is_none() {
+            return;
+        }
+        // the killed child is reaped once it exited, without blocking the thread.
+        drop(task::spawn(reap(self.id(), pidfd)));
+    }
+}
+
+/// Waits for the child `pid` to exit and reaps it.
+async fn reap(pid: u32, pidfd: OwnedFd) {
+    let exited = Action::poll_add(pidfd.as_raw_fd(), libc::POLLIN as u32);
+    if let Ok(exited) = exited {
+        let _ = exited.await;
+    }
+    unsafe { libc::waitpid(pid as libc::pid_t, ptr::null_mut(), libc::WNOHANG) };
+}
+
+/// A process watched through a pidfd, which need not be a child of this one, so a
+/// supervisor can wait for and signal processes it did not spawn.
+///
+/// The pidfd refers to the process it was opened for even once its pid is reused, so
+/// signals never reach another process.
+pub struct PidFd {
+    /// Armed by the first wait, stays armed until the process exited.
+    poll: Option<Action<PollMulti>>,
+    /// Set once the process exited, with its status if it was a child of this one.
+    exit: Option<Option<ExitStatus>>,
+    fd: OwnedFd,
+    pid: u32,
+}
+
+impl PidFd {
+    /// Opens a pidfd for the process `pid`, which has to be running.
+    pub fn open(pid: u32) -> io::Result<PidFd> {
+        let fd = pidfd_open(pid)?;
+        Ok(PidFd {
+            poll: None,
+            exit: None,
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+            pid,
+        })
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    /// Sends `SIGKILL` to the process.
+    pub fn kill(&self) -> io::Result<()> {
+        self.signal(libc::SIGKILL)
+    }
+
+    /// Sends `signal` to the process, which fails with `ESRCH` once it exited.
+    pub fn signal(&self, signal: i32) -> io::Result<()> {
+        let res = unsafe {
+            libc::syscall(
+                libc::SYS_pidfd_send_signal,
+                self.fd.as_raw_fd(),
+                signal,
+                ptr::null::<libc::siginfo_t>(),
+                0,
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Waits for the process to exit. A child of this one is reaped and its exit status
+    /// returned, for other processes there is none.
+    pub async fn wait(&mut self) -> io::Result<Option<ExitStatus>> {
+        poll_fn(|cx| self.poll_wait(cx)).await
+    }
+
+    pub fn poll_wait(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<Option<ExitStatus>>> {
+        loop {
+            if let Some(exit) = self.exit {
+                return Poll::Ready(Ok(exit));
+            }
+            let poll = match &mut self.poll {
+                Some(poll) => poll,
+                None => self.poll.insert(Action::poll_multi(
+                    self.fd.as_raw_fd(),
+                    libc::POLLIN as u32,
+                )?),
+            };
+            let events = ready!(poll.poll_ready(cx));
+            if poll.is_finished() {
+                self.poll = None;
+            }
+            // the pidfd becomes readable once the process exited.
+            if events? & libc::POLLIN as u32 != 0 {
+                self.poll = None;
+                self.exit = Some(self.reap()?);
+            }
+        }
+    }
+
+    /// Reaps the exited process if it is a child of this one.
+    fn reap(&self) -> io::Result<Option<ExitStatus>> {
+        let mut info: libc::siginfo_t = unsafe { mem::zeroed() };
+        let res = unsafe {
+            libc::waitid(
+                libc::P_PIDFD,
+                self.fd.as_raw_fd() as libc::id_t,
+                &mut info,
+                libc::WEXITED | libc::WNOHANG,
+            )
+        };
+        if res < 0 {
+            let err = io::Error::last_os_error();
+            return match err.raw_os_error() {
+                Some(libc::ECHILD) => Ok(None),
+                _ => Err(err),
+            };
+        }
+        if unsafe { info.si_pid() } == 0 {
+            return Ok(None);
+        }
+        // rebuilt as the status `waitpid` would have returned.
+        let status = unsafe { info.si_status() };
+        let status = match info.si_code {
+            libc::CLD_EXITED => (status & 0xff) << 8,
+            libc::CLD_DUMPED => status | 0x80,
+            _ => status,
+        };
+        Ok(Some(ExitStatus::from_raw(status)))
+    }
+}
+
+impl AsRawFd for PidFd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+async fn read_to_end<R: AsyncRead + Unpin>(pipe: Option<R>) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    if let Some(mut pipe) = pipe {
+        pipe.read_to_end(&mut buf).await?;
+    }
+    Ok(buf)
+}
+
+/// The write end of the child's stdin, closed when dropped.
+pub struct ChildStdin {
+    inner: process::ChildStdin,
+    write: Option<Write>,
+}
+
+struct Write {
+    action: Action<driver::Write>,
+    /// Address and length of the buffer the write was started for.
+    src: (*const u8, usize),
+}
+
+impl ChildStdin {
+    /// Drives a write left behind by a dropped future to completion.
+    fn poll_flush_write(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        if let Some(write) = &mut self.write {
+            let res = ready!(write.action.poll_write(cx));
+            self.write = None;
+            res?;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsRawFd for ChildStdin {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+impl AsyncWrite for ChildStdin {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let me = self.get_mut();
+        loop {
+            match &mut me.write {
+                None => {
+                    let action = Action::write(me.inner.as_raw_fd(), buf)?;
+                    me.write = Some(Write {
+                        action,
+                        src: (buf.as_ptr(), buf.len()),
+                    });
+                }
+                Some(write) => {
+                    let res = ready!(write.action.poll_write(cx));
+                    let owned = write.src == (buf.as_ptr(), buf.len());
+                    me.write = None;
+                    let n = res?;
+                    if owned {
+                        return Poll::Ready(Ok(n));
+                    }
+                }
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        self.get_mut().poll_flush_write(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        self.get_mut().poll_flush_write(cx)
+    }
+}
+
+impl Drop for ChildStdin {
+    fn drop(&mut self) {
+        // bytes already handed to the kernel are still delivered.
+        if let Some(write) = self.write.take() {
+            drop(write.action.detach());
+        }
+    }
+}
+
+/// The read end of the child's stdout.
+pub struct ChildStdout {
+    inner: process::ChildStdout,
+    pipe: Pipe,
+}
+
+/// The read end of the child's stderr.
+pub struct ChildStderr {
+    inner: process::ChildStderr,
+    pipe: Pipe,
+}
+
+/// The read side of a pipe from the child.
+#[derive(Default)]
+struct Pipe {
+    read: Option<Action<driver::Read>>,
+    /// Bytes a read started by a dropped future took out of the pipe beyond what the
+    /// caller asked for, handed out before reading again.
+    rest: Vec<u8>,
+}
+
+impl Pipe {
+    fn poll_read(
+        &mut self,
+        cx: &mut Context,
+        fd: RawFd,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        if !self.rest.is_empty() {
+            let n = buf.len().min(self.rest.len());
+            buf[..n].copy_from_slice(&self.rest[..n]);
+            self.rest.drain(..n);
+            return Poll::Ready(Ok(n));
+        }
+        let action = match &mut self.read {
+            Some(action) => action,
+            None => self.read.get_or_insert(Action::read(fd, buf.len() as u32)?),
+        };
+        let res = ready!(action.poll_read(cx));
+        self.read = None;
+        let mut src = res?;
+        let n = src.len().min(buf.len());
+        buf[..n].copy_from_slice(&src[..n]);
+        src.drain(..n);
+        self.rest = src;
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl AsRawFd for ChildStdout {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+impl AsyncRead for ChildStdout {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let me = self.get_mut();
+        me.pipe.poll_read(cx, me.inner.as_raw_fd(), buf)
+    }
+}
+
+impl AsRawFd for ChildStderr {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+impl AsyncRead for ChildStderr {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let me = self.get_mut();
+        me.pipe.poll_read(cx, me.inner.as_raw_fd(), buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Runtime;
+    use futures_util::io::AsyncWriteExt;
+
+    #[test]
+    fn output_captures_both_streams_and_the_exit_code() {
+        Runtime::new().unwrap().block_on(async {
+            let output = Command::new("sh")
+                .args(["-c", "echo out; echo err >&2; exit 3"])
+                .output()
+                .await
+                .unwrap();
+            assert_eq!(output.status.code(), Some(3));
+            assert_eq!(output.stdout, b"out\n");
+            assert_eq!(output.stderr, b"err\n");
+        });
+    }
+
+    #[test]
+    fn a_child_reads_what_is_written_to_its_stdin() {
+        Runtime::new().unwrap().block_on(async {
+            let mut child = Command::new("cat")
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn()
+                .unwrap();
+            let mut stdin = child.stdin.take().unwrap();
+            stdin.write_all(b"ping").await.unwrap();
+            // cat exits at the end of its input.
+            drop(stdin);
+            let output = child.wait_with_output().await.unwrap();
+            assert!(output.status.success());
+            assert_eq!(output.stdout, b"ping");
+        });
+    }
+
+    #[test]
+    fn a_child_running_past_the_timeout_is_killed() {
+        Runtime::new().unwrap().block_on(async {
+            let err = Command::new("sleep")
+                .arg("10")
+                .timeout(Some(Duration::from_millis(50)))
+                .status()
+                .await
+                .unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        });
+    }
+}
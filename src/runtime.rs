@@ -1,38 +1,563 @@
+use std::cell::RefCell;
 use std::future::Future;
-use std::io;
+use std::io::{self, Write};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::thread;
+use std::time::Duration;
 
+use futures_util::future::poll_fn;
+
+use crate::blocking;
+use crate::driver::remote::{RemoteWaker, Wake};
 use crate::driver::Driver;
-use crate::local_executor;
+use crate::local_executor::{self, Inbox};
+use crate::signal::{self, SignalKind};
 use crate::waker_fn::waker_fn;
+use crate::workers::{self, Workers};
+
+#[cfg(feature = "metrics")]
+pub use crate::driver::LatencyHistogram;
+pub use crate::driver::{Event, EventKind, Features, RingFlags};
+
+/// The group id of the default buffer ring, the one reads select from unless they
+/// name another group.
+pub const DEFAULT_BUFFER_GROUP: u16 = crate::driver::buffers::GROUP_ID;
+
+/// How long [`Runtime::block_on_with_shutdown`] waits for the kernel to complete the
+/// cancelled operations.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A snapshot of the buffer ring, see [`Runtime::buffer_metrics`].
+#[derive(Debug, Clone, Copy)]
+pub struct BufferMetrics {
+    /// Number of buffers in the ring.
+    pub entries: u16,
+    /// Size of each buffer in bytes.
+    pub size: usize,
+    /// Whether the size follows the observed read sizes.
+    pub adaptive: bool,
+    /// Reads that completed into a ring buffer.
+    pub reads: u64,
+    /// Reads that filled their buffer completely.
+    pub filled: u64,
+    /// Times the ring was resized to adapt to read sizes.
+    pub resizes: u64,
+    /// Reads that found every buffer in use. They are retried with a buffer of the
+    /// stream's own, so no data is lost.
+    pub exhausted: u64,
+    /// Times the ring grew its number of buffers after reads found every buffer in use.
+    pub grows: u64,
+}
+
+/// A snapshot of the loop counters, see [`Runtime::loop_metrics`].
+#[derive(Debug, Clone, Copy)]
+pub struct LoopMetrics {
+    /// Turns of the loop, each running the woken tasks and handing over to the driver.
+    pub iterations: u64,
+    /// `io_uring_enter` calls that only submitted.
+    pub submits: u64,
+    /// `io_uring_enter` calls that parked until a completion arrived.
+    pub waits: u64,
+    /// Completions reaped.
+    pub completions: u64,
+    /// Turns that woke as many tasks of expired timers as allowed and left others for
+    /// later, see [`Runtime::set_timer_batch`].
+    pub timers_held: u64,
+    /// Completions ignored because the operation they belonged to was gone, its slot
+    /// possibly reused by another operation since.
+    pub stale_completions: u64,
+    /// Time spent running tasks and the driver rather than parked.
+    pub busy: Duration,
+    /// Length of the last turn of the loop, parked time included.
+    pub last_iteration: Duration,
+    /// Moving average of `io_uring_enter` calls per completion over the recent turns,
+    /// lower means more work per system call.
+    pub syscalls_per_completion: f64,
+}
+
+/// The activity of the driver, see [`Runtime::metrics`].
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone)]
+pub struct RuntimeMetrics {
+    /// Entries queued for the kernel, those the driver queues for itself included.
+    pub sqes_submitted: u64,
+    /// Completions reaped.
+    pub cqes_processed: u64,
+    /// Operations submitted and not yet completed.
+    pub in_flight: usize,
+    /// Buffers of the buffer rings held by readers.
+    pub buffers_in_use: usize,
+    /// Buffers of all the buffer rings.
+    pub buffers: usize,
+    /// Times the driver parked waiting for a completion.
+    pub parks: u64,
+    /// Parks cut short by a task woken from another thread.
+    pub unparks: u64,
+    /// Time from submission to final completion of the operations, by opcode.
+    pub latencies: Vec<(u8, LatencyHistogram)>,
+}
+
+#[cfg(feature = "metrics")]
+impl RuntimeMetrics {
+    /// The share of the ring buffers held by readers, 0 without any ring.
+    pub fn buffer_utilization(&self) -> f64 {
+        match self.buffers {
+            0 => 0.0,
+            buffers => self.buffers_in_use as f64 / buffers as f64,
+        }
+    }
+}
+
+/// Allocations the driver made for its own bookkeeping, see
+/// [`Runtime::memory_metrics`].
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryMetrics {
+    /// Times the table of in-flight operations grew.
+    pub table_grows: u64,
+    /// Completion queues allocated for multishot operations.
+    pub queues: u64,
+    /// Operations boxed to be kept alive for the kernel after being dropped.
+    pub abandoned: u64,
+}
+
+impl MemoryMetrics {
+    /// All the allocations counted.
+    pub fn total(&self) -> u64 {
+        self.table_grows + self.queues + self.abandoned
+    }
+}
+
+/// How busy the current runtime is, see [`load`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LoadMetrics {
+    /// Spawned tasks that have not finished yet.
+    pub tasks: usize,
+    /// Tasks woken and waiting to be polled.
+    pub queued_tasks: usize,
+    /// io_uring operations submitted and not yet completed.
+    pub in_flight: usize,
+}
+
+/// Load of the runtime running on this thread, all zero outside of one.
+pub fn load() -> LoadMetrics {
+    let in_flight = Driver::try_current(|driver| driver.inner.borrow().in_flight());
+    LoadMetrics {
+        tasks: local_executor::live_tasks(),
+        queued_tasks: local_executor::queued_tasks(),
+        in_flight: in_flight.unwrap_or(0),
+    }
+}
+
+/// The driver events the runtime running on this thread recorded, oldest first. Empty
+/// outside of a runtime, without an [event log](Runtime::set_event_log), or while the
+/// driver itself is running, which leaves it safe to call from a panic hook.
+pub fn events() -> Vec<Event> {
+    let events = Driver::try_current(|driver| {
+        let inner = driver.inner.try_borrow().ok()?;
+        Some(inner.events())
+    });
+    events.flatten().unwrap_or_default()
+}
+
+/// Hands the operations started so far to the kernel right away.
+///
+/// Operations otherwise go to the kernel together at the end of the turn of the loop,
+/// once the woken tasks ran, at the cost of one system call for all of them. A task
+/// that starts a latency-critical operation and then keeps the thread busy calls this
+/// so the kernel starts on it meanwhile. Does nothing outside of a runtime.
+pub fn submit_now() -> io::Result<()> {
+    Driver::try_current(Driver::submit_queued).unwrap_or(Ok(()))
+}
 
 pub struct Runtime {
     driver: Driver,
+    workers: RefCell<Option<Workers>>,
 }
 
 impl Runtime {
     pub fn new() -> io::Result<Runtime> {
+        Runtime::with_ring_flags(RingFlags::default())
+    }
+
+    /// A runtime whose ring is set up with `flags`. The kernel is probed for them, the
+    /// flags it does not support are left out, see [`ring_flags`](Runtime::ring_flags).
+    ///
+    /// `defer_taskrun` and `single_issuer` cut the interrupts and the locking a ring
+    /// driven by a single thread does not need, the runtime's thread being the only one
+    /// to submit anyway. Worker threads set up their rings with the same flags.
+    pub fn with_ring_flags(flags: RingFlags) -> io::Result<Runtime> {
         Ok(Runtime {
-            driver: Driver::new()?,
+            driver: Driver::with_flags(flags)?,
+            workers: RefCell::new(None),
         })
     }
 
+    /// What the kernel supports of the features the runtime falls back from on older
+    /// kernels, probed when the runtime started.
+    pub fn features(&self) -> Features {
+        self.driver.inner.borrow().features()
+    }
+
+    /// The setup flags the ring was created with, those asked for that the kernel does
+    /// not support left out.
+    pub fn ring_flags(&self) -> RingFlags {
+        self.driver.inner.borrow().ring_flags()
+    }
+
+    /// Replaces the ring of buffers socket reads select from with `entries` buffers of
+    /// `size` bytes each, `entries` being a power of two.
+    ///
+    /// The buffer size is otherwise adapted to the observed read sizes, and the number
+    /// of buffers doubled up to 1024 whenever reads find every buffer in use. Setting
+    /// them here turns that off. Buffers still held by streams remain valid until they are
+    /// drained. Needs Linux 5.19, older kernels are provided buffers of a fixed size
+    /// instead and fail with `Unsupported`.
+    pub fn reconfigure_buffers(&self, entries: u16, size: usize) -> io::Result<()> {
+        self.driver.lock().set_buffers(entries, size)
+    }
+
+    /// Registers a ring of `entries` buffers of `size` bytes each as buffer group
+    /// `bgid`, replacing the ring registered under that id before. Reads name the group
+    /// with `read_with_group`, or have it picked by their length with `read_sized`, so
+    /// connections can pick between pools of different buffer sizes, such as small
+    /// buffers for control traffic and large ones for bulk transfers.
+    ///
+    /// [`DEFAULT_BUFFER_GROUP`] is taken by the default ring, see
+    /// [`reconfigure_buffers`](Runtime::reconfigure_buffers). Needs Linux 5.19.
+    pub fn register_buffer_group(&self, bgid: u16, entries: u16, size: usize) -> io::Result<()> {
+        self.driver.lock().register_group(bgid, entries, size)
+    }
+
+    /// Unregisters the ring of buffer group `bgid`. Reads still selecting from it fail
+    /// with `ENOBUFS`, buffers held by streams remain valid until they are drained.
+    pub fn unregister_buffer_group(&self, bgid: u16) -> io::Result<()> {
+        self.driver.lock().unregister_group(bgid)
+    }
+
+    /// The size of the buffers of group `bgid`, `None` if no ring is registered under
+    /// it.
+    pub fn buffer_group_size(&self, bgid: u16) -> Option<usize> {
+        self.driver.inner.borrow().group_size(bgid)
+    }
+
+    /// The buffer group `read_sized` picks for a read of `len` bytes, the one whose
+    /// buffers hold them with the least room to spare or else the one with the largest
+    /// buffers. `None` without any buffer ring.
+    pub fn buffer_group_for(&self, len: usize) -> Option<u16> {
+        self.driver.inner.borrow().group_for(len)
+    }
+
+    /// Wakes the runtime after `max` without a completion, `None` lets it park until
+    /// one arrives.
+    ///
+    /// Work that runs between the turns of the loop, such as idle hooks and the clock of
+    /// [`time::ticks`](crate::time::ticks), then keeps going while no I/O completes,
+    /// without a timer operation in flight. Needs Linux 5.11.
+    pub fn set_max_park(&self, max: Option<Duration>) -> io::Result<()> {
+        self.driver.set_max_park(max)
+    }
+
+    /// Current shape of the buffer ring and counters of the reads that used it, `None`
+    /// if no ring is registered.
+    pub fn buffer_metrics(&self) -> Option<BufferMetrics> {
+        let inner = self.driver.inner.borrow();
+        let (entries, size, sizing) = inner.buffer_metrics()?;
+        Some(BufferMetrics {
+            entries,
+            size,
+            adaptive: sizing.adaptive,
+            reads: sizing.reads,
+            filled: sizing.filled,
+            resizes: sizing.resizes,
+            exhausted: sizing.exhausted,
+            grows: sizing.grows,
+        })
+    }
+
+    /// Counters of the system calls, completions and time of the runtime's loop, to
+    /// check whether submissions are batched as well as expected.
+    pub fn loop_metrics(&self) -> LoopMetrics {
+        let inner = self.driver.inner.borrow();
+        let stats = inner.loop_stats();
+        LoopMetrics {
+            iterations: stats.iterations,
+            submits: stats.submits,
+            waits: stats.waits,
+            completions: stats.completions,
+            timers_held: stats.timers_held,
+            stale_completions: stats.stale,
+            busy: stats.busy,
+            last_iteration: stats.last_iteration,
+            syscalls_per_completion: stats.syscalls_per_completion(),
+        }
+    }
+
+    /// Counters of the submissions, completions and parks of the driver, the use of its
+    /// buffer rings and the latency of its operations by opcode. Only kept with the
+    /// `metrics` feature, which also emits the driver's submissions, completions and
+    /// parks as `tracing` events and spans under the `slings::driver` target.
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> RuntimeMetrics {
+        let inner = self.driver.inner.borrow();
+        let metrics = inner.metrics();
+        let (buffers_in_use, buffers) = inner.buffers_in_use();
+        RuntimeMetrics {
+            sqes_submitted: metrics.sqes,
+            cqes_processed: inner.loop_stats().completions,
+            in_flight: inner.in_flight(),
+            buffers_in_use,
+            buffers,
+            parks: metrics.parks,
+            unparks: metrics.unparks,
+            latencies: metrics.latencies(),
+        }
+    }
+
+    /// Makes room for `operations` in flight at once, so the driver allocates nothing
+    /// for its own bookkeeping while no more are. Latency-sensitive programs reserve up
+    /// front what they expect to need, and check with
+    /// [`memory_metrics`](Runtime::memory_metrics) that it sufficed.
+    ///
+    /// The memory comes from the global allocator, which Rust does not let the
+    /// collections the driver uses be given another one of. It is taken once here and
+    /// reused from then on rather than allocated per operation. Dropping an operation
+    /// the kernel still uses allocates all the same, as it is kept alive until the
+    /// kernel lets go of it.
+    pub fn reserve(&self, operations: usize) {
+        self.driver.inner.borrow_mut().reserve(operations);
+    }
+
+    /// Counters of the allocations the driver made for its own bookkeeping, those of
+    /// operations for their own buffers aside.
+    pub fn memory_metrics(&self) -> MemoryMetrics {
+        let stats = self.driver.inner.borrow_mut().memory().stats();
+        MemoryMetrics {
+            table_grows: stats.table_grows,
+            queues: stats.queues,
+            abandoned: stats.abandoned,
+        }
+    }
+
+    /// Wakes at most `max` tasks of expired timers per turn of the loop, 256 by default.
+    ///
+    /// When many timers expire at once, such as after the machine resumed from suspend,
+    /// the rest are woken over the following turns, and the tasks woken by I/O
+    /// completions meanwhile run in between instead of waiting behind all of them.
+    pub fn set_timer_batch(&self, max: usize) {
+        self.driver.inner.borrow_mut().set_timer_batch(max);
+    }
+
+    /// Calls `hook` with the events that tell how the runtime adapts to the load: buffer
+    /// rings resized, reads that found every buffer in use, and multishot operations
+    /// armed again after the kernel ended them. Lining them up with the application's
+    /// own latency tells whether a spike came from the runtime adapting.
+    ///
+    /// The hook runs at the start of the turn of the loop after the events happened,
+    /// outside of the driver, so it may use the runtime. The events are also kept in
+    /// the [event log](Runtime::set_event_log).
+    pub fn set_adaptation_hook<F>(&self, hook: F)
+    where
+        F: Fn(&Event) + 'static,
+    {
+        self.driver.inner.borrow_mut().set_adaptation_hook(hook);
+    }
+
+    /// Keeps a log of the last `capacity` driver events: submissions, completions with
+    /// their results, cancellations and parks. 0 turns it off, which it is by default.
+    ///
+    /// The log is written to stderr if [`block_on`](Runtime::block_on) panics, and can
+    /// be read with [`events`] at any time, to tell what the driver was waiting for when
+    /// a program hangs.
+    pub fn set_event_log(&self, capacity: usize) {
+        self.driver.inner.borrow_mut().set_event_log(capacity);
+    }
+
+    /// Starts `n` worker threads, each running a runtime with a ring of its own, for
+    /// [`spawn_on_worker`](crate::task::spawn_on_worker) to spread tasks over. The
+    /// tasks of this runtime keep running on the thread calling
+    /// [`block_on`](Runtime::block_on). 0 stops the workers, which is the default.
+    ///
+    /// [`spawn`](crate::spawn) does not route tasks to the workers: the futures it takes
+    /// need not be `Send`, and the sockets and timers they hold belong to the ring of the
+    /// thread that created them. Accepts are spread by binding a listener on every worker
+    /// with [`spawn_on_each_worker`](crate::task::spawn_on_each_worker), the kernel
+    /// handing each new connection to one of the listeners sharing the address through
+    /// [`SO_REUSEPORT`](crate::net::ListenOptions::reuse_port).
+    ///
+    /// Workers started before are stopped, the tasks still running on them are dropped.
+    pub fn set_worker_threads(&self, n: usize) -> io::Result<()> {
+        let workers = match n {
+            0 => None,
+            n => Some(Workers::start(n, self.ring_flags())?),
+        };
+        drop(self.workers.replace(workers));
+        Ok(())
+    }
+
+    /// Limits the pool [`spawn_blocking`](crate::spawn_blocking) runs its work on to `n`
+    /// threads at once, further work waits for one of them. The pool is shared by all
+    /// runtimes of the process, so the last limit set applies to all of them.
+    ///
+    /// The limit is 4 threads per CPU the process may use by default, up to 64. The
+    /// CPUs are counted from the CPU quota of the process's cgroup where it has one, so
+    /// a container limited to 2 CPUs on a large machine runs 8 threads rather than 64.
+    pub fn set_blocking_threads(&self, n: usize) {
+        blocking::set_max_threads(n);
+    }
+
     pub fn block_on<F>(&self, future: F) -> F::Output
     where
         F: Future,
     {
         pin_mut!(future);
-        let waker = waker_fn(|| {});
-        let cx = &mut Context::from_waker(&waker);
+        let _dump = DumpOnPanic(&self.driver);
+        let workers = self.workers.borrow().as_ref().map(Workers::shared);
+
+        workers::enter(workers, || {
+            self.driver.with(|| {
+                // wakes the driver out of its park through its eventfd, for the main future
+                // and the tasks woken on other threads.
+                let mut unpark = RemoteWaker::new();
+                unpark.register(&waker_fn(|| {}));
+                let inbox = Inbox::current();
+                let _inbox = RestoreWake(inbox.clone(), inbox.set_wake(unpark.handle()));
+                let woken = Arc::new(AtomicBool::new(true));
+                let waker = {
+                    let woken = woken.clone();
+                    waker_fn(move || {
+                        woken.store(true, Ordering::Release);
+                        if !inbox.is_local() {
+                            inbox.wake();
+                        }
+                    })
+                };
+                let cx = &mut Context::from_waker(&waker);
+                self.run(future, cx, &woken)
+            })
+        })
+    }
 
-        self.driver.with(|| loop {
-            if let Poll::Ready(output) = future.as_mut().poll(cx) {
-                return output;
+    /// Runs `future` until it completes or one of `signals` is delivered, then shuts
+    /// the runtime down as [`shutdown`](Runtime::shutdown) does, waiting up to 5 seconds
+    /// for the kernel. Returns the output of `future`, `None` if a signal came first.
+    ///
+    /// This is the whole lifecycle of a small daemon, such as one serving until it gets
+    /// `SIGTERM` or ctrl-c. The signals are blocked on the calling thread from then on,
+    /// see [`signal::signal`], so call this before starting
+    /// [worker threads](Runtime::set_worker_threads) for them to be received here. A
+    /// failure to listen for the signals or to shut down in time is returned as an
+    /// error.
+    pub fn block_on_with_shutdown<F>(
+        self,
+        future: F,
+        signals: &[SignalKind],
+    ) -> io::Result<Option<F::Output>>
+    where
+        F: Future,
+    {
+        let mut signals = signals
+            .iter()
+            .map(|&kind| signal::signal(kind))
+            .collect::<io::Result<Vec<_>>>()?;
+        let output = self.block_on(async move {
+            pin_mut!(future);
+            poll_fn(|cx| {
+                if let Poll::Ready(output) = future.as_mut().poll(cx) {
+                    return Poll::Ready(Ok(Some(output)));
+                }
+                for signal in &mut signals {
+                    if let Poll::Ready(res) = signal.poll_recv(cx) {
+                        return Poll::Ready(res.map(|()| None));
+                    }
+                }
+                Poll::Pending
+            })
+            .await
+        });
+        let shutdown = self.shutdown(SHUTDOWN_TIMEOUT);
+        let output = output?;
+        shutdown?;
+        Ok(output)
+    }
+
+    /// Polls `future` whenever woken and runs the spawned tasks in between, until it
+    /// completes.
+    fn run<F: Future>(
+        &self,
+        mut future: Pin<&mut F>,
+        cx: &mut Context,
+        woken: &AtomicBool,
+    ) -> F::Output {
+        loop {
+            if woken.swap(false, Ordering::AcqRel) {
+                if let Poll::Ready(output) = future.as_mut().poll(cx) {
+                    return output;
+                }
             }
             if local_executor::tick() {
+                // keep completions flowing while the run queue stays busy.
+                self.driver.poll().expect("driver poll error");
+                continue;
+            }
+            // a task finishing during the tick may have woken the main future.
+            if woken.load(Ordering::Acquire) {
                 continue;
             }
             self.driver.wait().expect("driver wait error");
+        }
+    }
+
+    /// Shuts the runtime down, cancelling every operation still in flight and waiting up
+    /// to `timeout` for the kernel to complete them before the ring is torn down.
+    ///
+    /// Every task still live is dropped without being polled again, including the ones
+    /// waiting on a timer, a channel or a lock. If the kernel did not complete every
+    /// operation in time, the buffers those operations may still access are leaked
+    /// instead of freed and `ErrorKind::TimedOut` is returned.
+    pub fn shutdown(self, timeout: Duration) -> io::Result<()> {
+        self.driver.with(|| {
+            let drained = self.driver.drain(timeout);
+            local_executor::clear();
+            self.driver.release();
+            match drained? {
+                true => Ok(()),
+                false => Err(io::ErrorKind::TimedOut.into()),
+            }
         })
     }
 }
+
+/// Restores how the thread's runtime is woken once `block_on` returns.
+struct RestoreWake(Arc<Inbox>, Option<Wake>);
+
+impl Drop for RestoreWake {
+    fn drop(&mut self) {
+        self.0.set_wake(self.1.take());
+    }
+}
+
+/// Writes the event log to stderr when the runtime unwinds from a panic.
+struct DumpOnPanic<'a>(&'a Driver);
+
+impl Drop for DumpOnPanic<'_> {
+    fn drop(&mut self) {
+        if !thread::panicking() {
+            return;
+        }
+        let events = match self.0.inner.try_borrow() {
+            Ok(inner) => inner.events(),
+            Err(_) => return,
+        };
+        if events.is_empty() {
+            return;
+        }
+        let mut stderr = io::stderr().lock();
+        let _ = writeln!(stderr, "slings: last {} driver events:", events.len());
+        for event in events {
+            let _ = writeln!(stderr, "{}", event);
+        }
+    }
+}
@@ -0,0 +1,207 @@
+//! Unix signals received as futures, read from a signalfd the driver polls.
+
+use std::io;
+use std::mem::{self, MaybeUninit};
+use std::os::unix::io::RawFd;
+use std::pin::Pin;
+use std::ptr;
+use std::task::{Context, Poll};
+
+use futures_util::future::poll_fn;
+use futures_util::stream::Stream;
+
+use crate::driver::{Action, PollMulti};
+
+/// Most deliveries taken from the signalfd with one read.
+const BATCH: usize = 8;
+
+/// A signal to listen for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SignalKind(libc::c_int);
+
+impl SignalKind {
+    pub fn from_raw(signum: i32) -> SignalKind {
+        SignalKind(signum)
+    }
+
+    pub fn as_raw_value(&self) -> i32 {
+        self.0
+    }
+
+    /// `SIGINT`, sent by the terminal on ctrl-c.
+    pub fn interrupt() -> SignalKind {
+        SignalKind(libc::SIGINT)
+    }
+
+    /// `SIGTERM`, the polite request to shut down.
+    pub fn terminate() -> SignalKind {
+        SignalKind(libc::SIGTERM)
+    }
+
+    /// `SIGHUP`, commonly used to reload configuration.
+    pub fn hangup() -> SignalKind {
+        SignalKind(libc::SIGHUP)
+    }
+
+    /// `SIGQUIT`.
+    pub fn quit() -> SignalKind {
+        SignalKind(libc::SIGQUIT)
+    }
+
+    /// `SIGUSR1`.
+    pub fn user_defined1() -> SignalKind {
+        SignalKind(libc::SIGUSR1)
+    }
+
+    /// `SIGUSR2`.
+    pub fn user_defined2() -> SignalKind {
+        SignalKind(libc::SIGUSR2)
+    }
+}
+
+/// Starts listening for `kind`.
+///
+/// The signal is blocked on the calling thread, so the kernel queues it for the
+/// signalfd instead of running its handler, and stays blocked once the `Signal` is
+/// dropped. A signal sent to the process goes to any thread that does not block it,
+/// so listen before spawning other threads, which inherit the blocked signals.
+/// Deliveries are not broadcast, each one is received by a single `Signal` listening
+/// for its kind.
+pub fn signal(kind: SignalKind) -> io::Result<Signal> {
+    let mut set = MaybeUninit::<libc::sigset_t>::uninit();
+    unsafe { libc::sigemptyset(set.as_mut_ptr()) };
+    syscall!(sigaddset(set.as_mut_ptr(), kind.0))?;
+    let set = unsafe { set.assume_init() };
+    let res = unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, &set, ptr::null_mut()) };
+    if res != 0 {
+        return Err(io::Error::from_raw_os_error(res));
+    }
+    let fd = syscall!(signalfd(-1, &set, libc::SFD_NONBLOCK | libc::SFD_CLOEXEC))?;
+    Ok(Signal {
+        poll: None,
+        pending: 0,
+        fd,
+    })
+}
+
+/// Waits for the next ctrl-c, see [`signal`].
+pub async fn ctrl_c() -> io::Result<()> {
+    signal(SignalKind::interrupt())?.recv().await
+}
+
+/// The deliveries of one kind of signal, also a stream of them.
+pub struct Signal {
+    /// Armed by the first wait, stays armed until the `Signal` is dropped.
+    poll: Option<Action<PollMulti>>,
+    /// Deliveries read from the signalfd and not received yet.
+    pending: usize,
+    fd: RawFd,
+}
+
+impl Signal {
+    /// Waits for the next delivery.
+    pub async fn recv(&mut self) -> io::Result<()> {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        loop {
+            if self.pending > 0 {
+                self.pending -= 1;
+                return Poll::Ready(Ok(()));
+            }
+            let poll = match &mut self.poll {
+                Some(poll) => poll,
+                None => {
+                    // deliveries queued before the poll is armed are taken right away.
+                    self.pending = self.read()?;
+                    if self.pending > 0 {
+                        continue;
+                    }
+                    self.poll
+                        .insert(Action::poll_multi(self.fd, libc::POLLIN as u32)?)
+                }
+            };
+            let res = ready!(poll.poll_ready(cx));
+            if poll.is_finished() {
+                self.poll = None;
+            }
+            res?;
+            self.pending = self.read()?;
+        }
+    }
+
+    /// Takes the deliveries queued on the signalfd, returning how many there were.
+    fn read(&mut self) -> io::Result<usize> {
+        let mut infos = MaybeUninit::<[libc::signalfd_siginfo; BATCH]>::uninit();
+        let mut count = 0;
+        loop {
+            let res = syscall!(read(
+                self.fd,
+                infos.as_mut_ptr() as *mut libc::c_void,
+                mem::size_of_val(&infos)
+            ));
+            match res {
+                Ok(n) => {
+                    let n = n as usize / mem::size_of::<libc::signalfd_siginfo>();
+                    count += n;
+                    if n < BATCH {
+                        return Ok(count);
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(count),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Stream for Signal {
+    type Item = io::Result<()>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Poll::Ready(Some(ready!(self.poll_recv(cx))))
+    }
+}
+
+impl Drop for Signal {
+    fn drop(&mut self) {
+        self.poll.take();
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::delay_for;
+    use crate::Runtime;
+    use std::time::Duration;
+
+    /// Sends `kind` to the calling thread, which blocks it once it listens.
+    fn raise(kind: SignalKind) {
+        let res = unsafe { libc::pthread_kill(libc::pthread_self(), kind.as_raw_value()) };
+        assert_eq!(res, 0);
+    }
+
+    #[test]
+    fn deliveries_before_and_after_the_poll_is_armed_are_received() {
+        Runtime::new().unwrap().block_on(async {
+            let kind = SignalKind::user_defined1();
+            let mut signal = signal(kind).unwrap();
+            // queued before the first wait.
+            raise(kind);
+            signal.recv().await.unwrap();
+            assert!(signal.poll.is_none());
+
+            // sent while the poll is armed.
+            let send = crate::spawn(async move {
+                delay_for(Duration::from_millis(10)).await;
+                raise(kind);
+            });
+            signal.recv().await.unwrap();
+            send.await.unwrap();
+            assert_eq!(signal.pending, 0);
+        });
+    }
+}
@@ -0,0 +1,10 @@
+//! Synchronization primitives for tasks.
+
+pub mod mpsc;
+mod mutex;
+mod semaphore;
+
+pub use mutex::{Mutex, MutexGuard, TryLockError};
+pub use semaphore::{
+    AcquireError, OwnedSemaphorePermit, Semaphore, SemaphorePermit, TryAcquireError,
+};
@@ -0,0 +1,704 @@
+//! Multi-producer, single-consumer channels whose senders may live on other threads.
+//!
+//! A send from a thread that is not running the receiver's runtime wakes the receiving
+//! task through the runtime's eventfd, so work produced by plain threads is picked up
+//! without polling for it.
+use std::collections::VecDeque;
+use std::error;
+use std::fmt;
+use std::mem;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll};
+use std::thread;
+
+use futures_util::future::poll_fn;
+use slab::Slab;
+
+use crate::driver::remote::{RemoteWaker, Wake};
+use crate::driver::Driver;
+use crate::waker_fn::waker_fn;
+
+/// Creates a channel holding at most `buffer` values, sends wait for room once it is
+/// full.
+///
+/// # Panics
+///
+/// Panics if `buffer` is 0.
+pub fn channel<T>(buffer: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(buffer > 0, "mpsc channel buffer must be greater than 0");
+    let chan = Chan::new(Some(buffer));
+    (Sender { chan: chan.clone() }, Receiver { chan })
+}
+
+/// Creates a channel without a limit on the values it holds.
+pub fn unbounded_channel<T>() -> (UnboundedSender<T>, UnboundedReceiver<T>) {
+    let chan = Chan::new(None);
+    (
+        UnboundedSender { chan: chan.clone() },
+        UnboundedReceiver { chan },
+    )
+}
+
+/// Sends values to a bounded channel, see [`channel`].
+pub struct Sender<T> {
+    chan: Arc<Chan<T>>,
+}
+
+/// Receives values from a bounded channel, see [`channel`].
+pub struct Receiver<T> {
+    chan: Arc<Chan<T>>,
+}
+
+/// Sends values to an unbounded channel, see [`unbounded_channel`].
+pub struct UnboundedSender<T> {
+    chan: Arc<Chan<T>>,
+}
+
+/// Receives values from an unbounded channel, see [`unbounded_channel`].
+pub struct UnboundedReceiver<T> {
+    chan: Arc<Chan<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Sends `value`, waiting for room in the channel. Fails with the value if the
+    /// receiver is gone.
+    pub async fn send(&self, value: T) -> Result<(), SendError<T>> {
+        let mut value = Some(value);
+        let mut waiter = Waiter::new(&self.chan);
+        poll_fn(|cx| self.chan.poll_send(cx, &mut value, &mut waiter)).await
+    }
+
+    /// Sends `value` if the channel has room for it.
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        self.chan.try_send(value)
+    }
+
+    /// Sends `value` from a thread outside of any runtime, blocking the thread until
+    /// the channel has room for it.
+    ///
+    /// # Panics
+    ///
+    /// Panics when called on a thread running a runtime, which would never get to
+    /// receive the value.
+    pub fn blocking_send(&self, value: T) -> Result<(), SendError<T>> {
+        assert!(
+            Driver::try_current(|_| ()).is_none(),
+            "blocking_send called from within a runtime"
+        );
+        let thread = thread::current();
+        let waker = waker_fn(move || thread.unpark());
+        let cx = &mut Context::from_waker(&waker);
+        let mut value = Some(value);
+        let mut waiter = Waiter::new(&self.chan);
+        loop {
+            match self.chan.poll_send(cx, &mut value, &mut waiter) {
+                Poll::Ready(result) => return result,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    /// Whether the receiver is gone.
+    pub fn is_closed(&self) -> bool {
+        self.chan.state.lock().unwrap().closed
+    }
+}
+
+impl<T> UnboundedSender<T> {
+    /// Sends `value`, failing with the value if the receiver is gone.
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        self.chan
+            .try_send(value)
+            .map_err(|e| SendError(e.into_inner()))
+    }
+
+    /// Whether the receiver is gone.
+    pub fn is_closed(&self) -> bool {
+        self.chan.state.lock().unwrap().closed
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Receives the next value, `None` once every sender is gone and the channel is
+    /// empty.
+    pub async fn recv(&mut self) -> Option<T> {
+        poll_fn(|cx| self.chan.poll_recv(cx)).await
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.chan.poll_recv(cx)
+    }
+
+    /// Receives the next value if there is one.
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        self.chan.try_recv()
+    }
+
+    /// Fails further sends, values already sent can still be received.
+    pub fn close(&mut self) {
+        self.chan.close();
+    }
+}
+
+impl<T> UnboundedReceiver<T> {
+    /// Receives the next value, `None` once every sender is gone and the channel is
+    /// empty.
+    pub async fn recv(&mut self) -> Option<T> {
+        poll_fn(|cx| self.chan.poll_recv(cx)).await
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.chan.poll_recv(cx)
+    }
+
+    /// Receives the next value if there is one.
+    pub fn try_recv(&mut self) -> Result<T, TryRecvError> {
+        self.chan.try_recv()
+    }
+
+    /// Fails further sends, values already sent can still be received.
+    pub fn close(&mut self) {
+        self.chan.close();
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.chan.state.lock().unwrap().senders += 1;
+        Sender {
+            chan: self.chan.clone(),
+        }
+    }
+}
+
+impl<T> Clone for UnboundedSender<T> {
+    fn clone(&self) -> Self {
+        self.chan.state.lock().unwrap().senders += 1;
+        UnboundedSender {
+            chan: self.chan.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.chan.drop_sender();
+    }
+}
+
+impl<T> Drop for UnboundedSender<T> {
+    fn drop(&mut self) {
+        self.chan.drop_sender();
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.chan.drop_receiver();
+    }
+}
+
+impl<T> Drop for UnboundedReceiver<T> {
+    fn drop(&mut self) {
+        self.chan.drop_receiver();
+    }
+}
+
+struct Chan<T> {
+    state: Mutex<State<T>>,
+}
+
+struct State<T> {
+    queue: VecDeque<T>,
+    /// Most values the queue holds, `None` for an unbounded channel.
+    capacity: Option<usize>,
+    senders: usize,
+    /// Set once the receiver closed the channel or was dropped.
+    closed: bool,
+    receiver: RemoteWaker,
+    /// Keys of the senders waiting for room in the queue, in arrival order.
+    waiting: VecDeque<usize>,
+    senders_waiting: Slab<Slot>,
+}
+
+/// A sender waiting for room in the queue.
+struct Slot {
+    wake: Option<Wake>,
+    /// Cleared once the sender was woken for room, it queues again if it finds none.
+    queued: bool,
+}
+
+impl<T> State<T> {
+    fn full(&self) -> bool {
+        self.capacity.is_some_and(|cap| self.queue.len() >= cap)
+    }
+
+    /// Takes the waker of the sender first in line for the room a receive freed.
+    fn wake_one(&mut self) -> Option<Wake> {
+        let key = self.waiting.pop_front()?;
+        let slot = &mut self.senders_waiting[key];
+        slot.queued = false;
+        slot.wake.take()
+    }
+
+    /// Takes the wakers of every waiting sender.
+    fn wake_all(&mut self) -> Vec<Wake> {
+        self.waiting.clear();
+        self.senders_waiting
+            .iter_mut()
+            .filter_map(|(_, slot)| {
+                slot.queued = false;
+                slot.wake.take()
+            })
+            .collect()
+    }
+}
+
+/// The place of a sender among those waiting for room, given up when dropped.
+struct Waiter<'a, T> {
+    chan: &'a Chan<T>,
+    /// The slot of the sender, `None` until it first found the queue full.
+    key: Option<usize>,
+    waker: RemoteWaker,
+}
+
+impl<T> Waiter<'_, T> {
+    fn new(chan: &Chan<T>) -> Waiter<'_, T> {
+        Waiter {
+            chan,
+            key: None,
+            waker: RemoteWaker::new(),
+        }
+    }
+}
+
+impl<T> Drop for Waiter<'_, T> {
+    fn drop(&mut self) {
+        let key = match self.key.take() {
+            Some(key) => key,
+            None => return,
+        };
+        let mut state = self.chan.state.lock().unwrap();
+        let slot = state.senders_waiting.remove(key);
+        let woken = if slot.queued {
+            state.waiting.retain(|&waiting| waiting != key);
+            None
+        } else {
+            // woken for room it will not take, the next sender gets it.
+            state.wake_one()
+        };
+        drop(state);
+        if let Some(woken) = woken {
+            woken.wake();
+        }
+    }
+}
+
+impl<T> Chan<T> {
+    fn new(capacity: Option<usize>) -> Arc<Chan<T>> {
+        Arc::new(Chan {
+            state: Mutex::new(State {
+                queue: VecDeque::new(),
+                capacity,
+                senders: 1,
+                closed: false,
+                receiver: RemoteWaker::new(),
+                waiting: VecDeque::new(),
+                senders_waiting: Slab::new(),
+            }),
+        })
+    }
+
+    fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        let state = self.state.lock().unwrap();
+        if state.closed {
+            return Err(TrySendError::Closed(value));
+        }
+        if state.full() {
+            return Err(TrySendError::Full(value));
+        }
+        Chan::push(state, value);
+        Ok(())
+    }
+
+    fn poll_send(
+        &self,
+        cx: &mut Context<'_>,
+        value: &mut Option<T>,
+        waiter: &mut Waiter<'_, T>,
+    ) -> Poll<Result<(), SendError<T>>> {
+        let mut state = self.state.lock().unwrap();
+        if !state.closed && state.full() {
+            waiter.waker.register(cx.waker());
+            let wake = waiter.waker.handle();
+            match waiter.key {
+                Some(key) => {
+                    let slot = &mut state.senders_waiting[key];
+                    slot.wake = wake;
+                    if !slot.queued {
+                        // woken for room another sender took, it stays first in line.
+                        slot.queued = true;
+                        state.waiting.push_front(key);
+                    }
+                }
+                None => {
+                    let key = state.senders_waiting.insert(Slot { wake, queued: true });
+                    state.waiting.push_back(key);
+                    waiter.key = Some(key);
+                }
+            }
+            return Poll::Pending;
+        }
+        if let Some(key) = waiter.key.take() {
+            if state.senders_waiting.remove(key).queued {
+                state.waiting.retain(|&waiting| waiting != key);
+            }
+        }
+        let value = value.take().expect("send polled after completion");
+        if state.closed {
+            return Poll::Ready(Err(SendError(value)));
+        }
+        Chan::push(state, value);
+        Poll::Ready(Ok(()))
+    }
+
+    /// Queues `value` and wakes the receiver once the lock is released.
+    fn push(mut state: MutexGuard<'_, State<T>>, value: T) {
+        state.queue.push_back(value);
+        let receiver = state.receiver.handle();
+        drop(state);
+        if let Some(receiver) = receiver {
+            receiver.wake();
+        }
+    }
+
+    fn poll_recv(&self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(value) = state.queue.pop_front() {
+            let woken = state.wake_one();
+            drop(state);
+            if let Some(woken) = woken {
+                woken.wake();
+            }
+            return Poll::Ready(Some(value));
+        }
+        if state.senders == 0 || state.closed {
+            return Poll::Ready(None);
+        }
+        state.receiver.register(cx.waker());
+        Poll::Pending
+    }
+
+    fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut state = self.state.lock().unwrap();
+        match state.queue.pop_front() {
+            Some(value) => {
+                let woken = state.wake_one();
+                drop(state);
+                if let Some(woken) = woken {
+                    woken.wake();
+                }
+                Ok(value)
+            }
+            None if state.senders == 0 || state.closed => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        let woken = state.wake_all();
+        drop(state);
+        woken.into_iter().for_each(Wake::wake);
+    }
+
+    fn drop_sender(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.senders -= 1;
+        if state.senders > 0 {
+            return;
+        }
+        let receiver = state.receiver.handle();
+        drop(state);
+        if let Some(receiver) = receiver {
+            receiver.wake();
+        }
+    }
+
+    fn drop_receiver(&self) {
+        self.close();
+        // values still queued are dropped here rather than with the last sender.
+        let queue = mem::take(&mut self.state.lock().unwrap().queue);
+        drop(queue);
+    }
+}
+
+/// The error returned by a send whose receiver is gone, carrying the value.
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub struct SendError<T>(pub T);
+
+/// The error returned by [`Sender::try_send`].
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum TrySendError<T> {
+    /// The channel has no room for the value.
+    Full(T),
+    /// The receiver is gone.
+    Closed(T),
+}
+
+impl<T> TrySendError<T> {
+    /// Returns the value that could not be sent.
+    pub fn into_inner(self) -> T {
+        match self {
+            TrySendError::Full(value) | TrySendError::Closed(value) => value,
+        }
+    }
+}
+
+/// The error returned by `try_recv`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TryRecvError {
+    /// The channel is empty for now.
+    Empty,
+    /// The channel is empty and every sender is gone, or the receiver closed it.
+    Disconnected,
+}
+
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("SendError").finish_non_exhaustive()
+    }
+}
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        "channel closed".fmt(fmt)
+    }
+}
+
+impl<T> error::Error for SendError<T> {}
+
+impl<T> fmt::Debug for TrySendError<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrySendError::Full(_) => "Full(..)".fmt(fmt),
+            TrySendError::Closed(_) => "Closed(..)".fmt(fmt),
+        }
+    }
+}
+
+impl<T> fmt::Display for TrySendError<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrySendError::Full(_) => "channel full".fmt(fmt),
+            TrySendError::Closed(_) => "channel closed".fmt(fmt),
+        }
+    }
+}
+
+impl<T> error::Error for TrySendError<T> {}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryRecvError::Empty => "channel empty".fmt(fmt),
+            TryRecvError::Disconnected => "channel closed".fmt(fmt),
+        }
+    }
+}
+
+impl error::Error for TryRecvError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::task::Waker;
+
+    use crate::Runtime;
+
+    /// A waker counting how often it was woken.
+    fn counter() -> (Waker, Arc<AtomicUsize>) {
+        let count = Arc::new(AtomicUsize::new(0));
+        let woken = count.clone();
+        let waker = waker_fn(move || {
+            woken.fetch_add(1, Ordering::SeqCst);
+        });
+        (waker, count)
+    }
+
+    fn poll<F: Future>(future: Pin<&mut F>, waker: &Waker) -> Poll<F::Output> {
+        future.poll(&mut Context::from_waker(waker))
+    }
+
+    fn waiting<T>(chan: &Chan<T>) -> (usize, usize) {
+        let state = chan.state.lock().unwrap();
+        (state.waiting.len(), state.senders_waiting.len())
+    }
+
+    #[test]
+    fn a_full_channel_holds_a_send_until_a_value_is_received() {
+        let (tx, mut rx) = channel(2);
+        tx.try_send(1).unwrap();
+        tx.try_send(2).unwrap();
+        assert!(matches!(tx.try_send(3), Err(TrySendError::Full(3))));
+
+        let (waker, woken) = counter();
+        let mut send = Box::pin(tx.send(3));
+        assert!(poll(send.as_mut(), &waker).is_pending());
+        // polling again replaces the waker rather than queueing the sender twice.
+        assert!(poll(send.as_mut(), &waker).is_pending());
+        assert_eq!(waiting(&tx.chan), (1, 1));
+
+        assert_eq!(rx.try_recv(), Ok(1));
+        assert_eq!(woken.load(Ordering::SeqCst), 1);
+        assert!(matches!(poll(send.as_mut(), &waker), Poll::Ready(Ok(()))));
+        assert_eq!(waiting(&tx.chan), (0, 0));
+        assert_eq!(rx.try_recv(), Ok(2));
+        assert_eq!(rx.try_recv(), Ok(3));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+    }
+
+    #[test]
+    fn a_received_value_wakes_one_sender() {
+        let (tx, mut rx) = channel(1);
+        tx.try_send(0).unwrap();
+        let (first_waker, first) = counter();
+        let (second_waker, second) = counter();
+        let mut first_send = Box::pin(tx.send(1));
+        let mut second_send = Box::pin(tx.send(2));
+        assert!(poll(first_send.as_mut(), &first_waker).is_pending());
+        assert!(poll(second_send.as_mut(), &second_waker).is_pending());
+
+        assert_eq!(rx.try_recv(), Ok(0));
+        assert_eq!(first.load(Ordering::SeqCst), 1);
+        assert_eq!(second.load(Ordering::SeqCst), 0);
+
+        // woken for room it does not take, the first sender passes it on.
+        drop(first_send);
+        assert_eq!(second.load(Ordering::SeqCst), 1);
+        assert!(matches!(
+            poll(second_send.as_mut(), &second_waker),
+            Poll::Ready(Ok(()))
+        ));
+        assert_eq!(rx.try_recv(), Ok(2));
+    }
+
+    #[test]
+    fn a_dropped_send_gives_up_its_place() {
+        let (tx, mut rx) = channel(1);
+        tx.try_send(0).unwrap();
+        let (first_waker, first) = counter();
+        let (second_waker, second) = counter();
+        let mut first_send = Box::pin(tx.send(1));
+        let mut second_send = Box::pin(tx.send(2));
+        assert!(poll(first_send.as_mut(), &first_waker).is_pending());
+        assert!(poll(second_send.as_mut(), &second_waker).is_pending());
+
+        drop(first_send);
+        assert_eq!(waiting(&tx.chan), (1, 1));
+        assert_eq!(rx.try_recv(), Ok(0));
+        assert_eq!(first.load(Ordering::SeqCst), 0);
+        assert_eq!(second.load(Ordering::SeqCst), 1);
+        assert!(matches!(
+            poll(second_send.as_mut(), &second_waker),
+            Poll::Ready(Ok(()))
+        ));
+    }
+
+    #[test]
+    fn closing_fails_waiting_and_further_sends() {
+        let (tx, mut rx) = channel(1);
+        tx.try_send(1).unwrap();
+        let (waker, woken) = counter();
+        let mut send = Box::pin(tx.send(2));
+        assert!(poll(send.as_mut(), &waker).is_pending());
+
+        rx.close();
+        assert_eq!(woken.load(Ordering::SeqCst), 1);
+        assert!(matches!(
+            poll(send.as_mut(), &waker),
+            Poll::Ready(Err(SendError(2)))
+        ));
+        assert_eq!(waiting(&tx.chan), (0, 0));
+        assert!(tx.is_closed());
+        assert!(matches!(tx.try_send(3), Err(TrySendError::Closed(3))));
+
+        // values sent before the close are still received.
+        assert_eq!(rx.try_recv(), Ok(1));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn a_channel_disconnects_once_every_sender_is_gone() {
+        let (tx, mut rx) = unbounded_channel();
+        let other = tx.clone();
+        tx.send(1).unwrap();
+        drop(tx);
+        assert_eq!(rx.try_recv(), Ok(1));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+        other.send(2).unwrap();
+        drop(other);
+        assert_eq!(rx.try_recv(), Ok(2));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+
+        let (tx, rx) = channel(1);
+        drop(rx);
+        assert!(tx.is_closed());
+        assert!(matches!(tx.try_send(1), Err(TrySendError::Closed(1))));
+    }
+
+    #[test]
+    fn a_blocking_sender_on_another_thread_wakes_the_receiver() {
+        let (tx, mut rx) = channel(1);
+        let sender = thread::spawn(move || {
+            for i in 0..100 {
+                tx.blocking_send(i).unwrap();
+            }
+        });
+        let received = Runtime::new().unwrap().block_on(async {
+            let mut received = Vec::new();
+            while let Some(i) = rx.recv().await {
+                received.push(i);
+            }
+            received
+        });
+        sender.join().unwrap();
+        assert_eq!(received, (0..100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn a_receiver_on_another_thread_wakes_waiting_senders() {
+        let (tx, mut rx) = channel(1);
+        let receiver = thread::spawn(move || {
+            Runtime::new().unwrap().block_on(async {
+                let mut received = Vec::new();
+                while let Some(i) = rx.recv().await {
+                    received.push(i);
+                }
+                received
+            })
+        });
+        Runtime::new().unwrap().block_on(async {
+            let mut senders = Vec::new();
+            for task in 0..4 {
+                let tx = tx.clone();
+                senders.push(crate::spawn(async move {
+                    for i in 0..25 {
+                        tx.send(task * 25 + i).await.unwrap();
+                    }
+                }));
+            }
+            drop(tx);
+            for sender in senders {
+                sender.await.unwrap();
+            }
+        });
+        let mut received = receiver.join().unwrap();
+        received.sort_unstable();
+        assert_eq!(received, (0..100).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,194 @@
+use std::cell::UnsafeCell;
+use std::error;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+use super::semaphore::{Semaphore, TryAcquireError};
+
+/// A mutual exclusion lock whose `lock` waits without blocking the thread.
+///
+/// The lock is handed to waiting tasks in the order they asked for it, so a task that
+/// keeps locking can not starve the others. Like the [`Semaphore`] it is built on, it
+/// can be shared with other threads.
+pub struct Mutex<T: ?Sized> {
+    semaphore: Semaphore,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub fn new(data: T) -> Mutex<T> {
+        Mutex {
+            semaphore: Semaphore::new(1),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    /// Waits until the lock is free and takes it, it is released when the guard drops.
+    pub async fn lock(&self) -> MutexGuard<'_, T> {
+        let permit = self
+            .semaphore
+            .acquire()
+            .await
+            .expect("mutex semaphore is never closed");
+        permit.forget();
+        MutexGuard { lock: self }
+    }
+
+    /// Takes the lock if it is free and nobody is waiting for it.
+    pub fn try_lock(&self) -> Result<MutexGuard<'_, T>, TryLockError> {
+        match self.semaphore.try_acquire() {
+            Ok(permit) => {
+                permit.forget();
+                Ok(MutexGuard { lock: self })
+            }
+            Err(TryAcquireError::NoPermits | TryAcquireError::Closed) => Err(TryLockError(())),
+        }
+    }
+
+    /// A mutable reference to the data, no locking is needed with exclusive access.
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Mutex<T> {
+        Mutex::new(T::default())
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Mutex<T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = fmt.debug_struct("Mutex");
+        match self.try_lock() {
+            Ok(guard) => d.field("data", &&*guard),
+            Err(_) => d.field("data", &format_args!("<locked>")),
+        };
+        d.finish()
+    }
+}
+
+/// Holds the lock of a [`Mutex`], releasing it when dropped.
+#[must_use]
+pub struct MutexGuard<'a, T: ?Sized> {
+    lock: &'a Mutex<T>,
+}
+
+unsafe impl<T: ?Sized + Sync> Sync for MutexGuard<'_, T> {}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.semaphore.add_permits(1);
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for MutexGuard<'_, T> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, fmt)
+    }
+}
+
+/// The error returned by [`Mutex::try_lock`] when the lock is taken.
+#[derive(Debug, PartialEq, Eq)]
+pub struct TryLockError(());
+
+impl fmt::Display for TryLockError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        "mutex is locked".fmt(fmt)
+    }
+}
+
+impl error::Error for TryLockError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::future::Future;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::task::{Context, Poll};
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::time::delay_for;
+    use crate::waker_fn::waker_fn;
+    use crate::Runtime;
+
+    #[test]
+    fn the_lock_is_handed_to_the_waiting_task_on_release() {
+        let mutex = Mutex::new(0);
+        let mut guard = mutex.try_lock().unwrap();
+        assert!(mutex.try_lock().is_err());
+
+        let woken = Arc::new(AtomicUsize::new(0));
+        let count = woken.clone();
+        let waker = waker_fn(move || {
+            count.fetch_add(1, Ordering::SeqCst);
+        });
+        let cx = &mut Context::from_waker(&waker);
+        let mut lock = Box::pin(mutex.lock());
+        assert!(lock.as_mut().poll(cx).is_pending());
+
+        *guard += 1;
+        drop(guard);
+        assert_eq!(woken.load(Ordering::SeqCst), 1);
+        // the lock went to the waiting task, not to whoever asks next.
+        assert!(mutex.try_lock().is_err());
+        let mut guard = match lock.as_mut().poll(cx) {
+            Poll::Ready(guard) => guard,
+            Poll::Pending => panic!("the lock was not handed on"),
+        };
+        *guard += 1;
+        drop(guard);
+        drop(lock);
+        assert_eq!(mutex.into_inner(), 2);
+    }
+
+    #[test]
+    fn tasks_on_several_threads_take_turns() {
+        let mutex = Arc::new(Mutex::new(0));
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let mutex = mutex.clone();
+                thread::spawn(move || {
+                    Runtime::new().unwrap().block_on(async {
+                        for _ in 0..50 {
+                            let mut guard = mutex.lock().await;
+                            let n = *guard;
+                            // other threads wait for the lock meanwhile.
+                            delay_for(Duration::from_micros(50)).await;
+                            *guard = n + 1;
+                        }
+                    })
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(*mutex.try_lock().unwrap(), 200);
+    }
+}
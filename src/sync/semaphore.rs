@@ -0,0 +1,448 @@
+use std::collections::VecDeque;
+use std::error;
+use std::fmt;
+use std::future::Future;
+use std::mem;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll};
+
+use slab::Slab;
+
+use crate::driver::remote::{RemoteWaker, Wake};
+
+/// Limits how many tasks use a resource at once, for example how many connections an
+/// accept loop serves.
+///
+/// Permits are handed out in the order they were asked for: a task waiting for more
+/// permits than are available holds back the tasks behind it. The semaphore can be
+/// shared with other threads, waiters are woken on the thread of their own runtime.
+pub struct Semaphore {
+    state: Mutex<State>,
+}
+
+struct State {
+    permits: usize,
+    closed: bool,
+    /// Keys of the waiters not granted their permits yet, in arrival order.
+    queue: VecDeque<usize>,
+    waiters: Slab<Waiter>,
+}
+
+struct Waiter {
+    needed: usize,
+    /// Set once the permits were taken on behalf of the waiter.
+    granted: bool,
+    wake: Option<Wake>,
+}
+
+impl State {
+    /// Grants permits to waiters at the front of the queue, returning their wakers.
+    fn grant(&mut self) -> Vec<Wake> {
+        let mut woken = Vec::new();
+        while let Some(&key) = self.queue.front() {
+            let waiter = &mut self.waiters[key];
+            if waiter.needed > self.permits {
+                break;
+            }
+            self.permits -= waiter.needed;
+            waiter.granted = true;
+            woken.extend(waiter.wake.take());
+            self.queue.pop_front();
+        }
+        woken
+    }
+}
+
+impl Semaphore {
+    pub fn new(permits: usize) -> Semaphore {
+        Semaphore {
+            state: Mutex::new(State {
+                permits,
+                closed: false,
+                queue: VecDeque::new(),
+                waiters: Slab::new(),
+            }),
+        }
+    }
+
+    /// Number of permits that can be acquired right away.
+    pub fn available_permits(&self) -> usize {
+        self.lock().permits
+    }
+
+    /// Adds `n` permits, waking the waiters they are enough for.
+    pub fn add_permits(&self, n: usize) {
+        let mut state = self.lock();
+        state.permits += n;
+        let woken = state.grant();
+        drop(state);
+        woken.into_iter().for_each(Wake::wake);
+    }
+
+    /// Waits for a permit, failing if the semaphore is closed meanwhile.
+    pub async fn acquire(&self) -> Result<SemaphorePermit<'_>, AcquireError> {
+        self.acquire_many(1).await
+    }
+
+    /// Waits for `n` permits, which are acquired all at once.
+    pub async fn acquire_many(&self, n: usize) -> Result<SemaphorePermit<'_>, AcquireError> {
+        Acquire::new(self, n).await?;
+        Ok(SemaphorePermit {
+            semaphore: self,
+            permits: n,
+        })
+    }
+
+    /// Like `acquire`, the permit keeps the semaphore alive so it can be moved into a
+    /// spawned task.
+    pub async fn acquire_owned(self: Arc<Self>) -> Result<OwnedSemaphorePermit, AcquireError> {
+        Acquire::new(&self, 1).await?;
+        Ok(OwnedSemaphorePermit {
+            semaphore: self,
+            permits: 1,
+        })
+    }
+
+    /// Acquires a permit if one is available and nobody is waiting for one.
+    pub fn try_acquire(&self) -> Result<SemaphorePermit<'_>, TryAcquireError> {
+        self.try_take(1)?;
+        Ok(SemaphorePermit {
+            semaphore: self,
+            permits: 1,
+        })
+    }
+
+    /// Like `try_acquire`, returning an owned permit.
+    pub fn try_acquire_owned(self: Arc<Self>) -> Result<OwnedSemaphorePermit, TryAcquireError> {
+        self.try_take(1)?;
+        Ok(OwnedSemaphorePermit {
+            semaphore: self,
+            permits: 1,
+        })
+    }
+
+    /// Fails pending and further acquires. Permits already acquired stay valid.
+    pub fn close(&self) {
+        let mut state = self.lock();
+        state.closed = true;
+        let woken: Vec<Wake> = state
+            .waiters
+            .iter_mut()
+            .filter_map(|(_, waiter)| waiter.wake.take())
+            .collect();
+        drop(state);
+        woken.into_iter().for_each(Wake::wake);
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.lock().closed
+    }
+
+    fn try_take(&self, n: usize) -> Result<(), TryAcquireError> {
+        let mut state = self.lock();
+        if state.closed {
+            return Err(TryAcquireError::Closed);
+        }
+        if !state.queue.is_empty() || state.permits < n {
+            return Err(TryAcquireError::NoPermits);
+        }
+        state.permits -= n;
+        Ok(())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+}
+
+impl fmt::Debug for Semaphore {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("Semaphore")
+            .field("permits", &self.available_permits())
+            .finish()
+    }
+}
+
+/// Waits in the queue of a semaphore until `needed` permits were granted.
+struct Acquire<'a> {
+    semaphore: &'a Semaphore,
+    needed: usize,
+    /// The waiter in the queue, `None` before the first poll and once done.
+    key: Option<usize>,
+    waker: RemoteWaker,
+}
+
+impl Acquire<'_> {
+    fn new(semaphore: &Semaphore, needed: usize) -> Acquire<'_> {
+        Acquire {
+            semaphore,
+            needed,
+            key: None,
+            waker: RemoteWaker::new(),
+        }
+    }
+}
+
+impl Future for Acquire<'_> {
+    type Output = Result<(), AcquireError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let me = self.get_mut();
+        let mut state = me.semaphore.lock();
+        let key = match me.key {
+            Some(key) if state.waiters[key].granted => {
+                state.waiters.remove(key);
+                me.key = None;
+                return Poll::Ready(Ok(()));
+            }
+            Some(key) if state.closed => {
+                state.waiters.remove(key);
+                state.queue.retain(|&queued| queued != key);
+                me.key = None;
+                return Poll::Ready(Err(AcquireError(())));
+            }
+            Some(key) => key,
+            None if state.closed => return Poll::Ready(Err(AcquireError(()))),
+            None if state.queue.is_empty() && state.permits >= me.needed => {
+                state.permits -= me.needed;
+                return Poll::Ready(Ok(()));
+            }
+            None => {
+                let key = state.waiters.insert(Waiter {
+                    needed: me.needed,
+                    granted: false,
+                    wake: None,
+                });
+                state.queue.push_back(key);
+                me.key = Some(key);
+                key
+            }
+        };
+        me.waker.register(cx.waker());
+        state.waiters[key].wake = me.waker.handle();
+        Poll::Pending
+    }
+}
+
+impl Drop for Acquire<'_> {
+    fn drop(&mut self) {
+        let key = match self.key.take() {
+            Some(key) => key,
+            None => return,
+        };
+        let mut state = self.semaphore.lock();
+        let waiter = state.waiters.remove(key);
+        if waiter.granted {
+            // granted after the last poll, the permits go to the next waiters.
+            state.permits += waiter.needed;
+        } else {
+            state.queue.retain(|&queued| queued != key);
+        }
+        // the waiters behind may be satisfied by the permits left now.
+        let woken = state.grant();
+        drop(state);
+        woken.into_iter().for_each(Wake::wake);
+    }
+}
+
+/// Permits acquired from a [`Semaphore`], given back when dropped.
+#[must_use]
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+    permits: usize,
+}
+
+impl SemaphorePermit<'_> {
+    /// Keeps the permits from being given back, reducing the semaphore's permits.
+    pub fn forget(mut self) {
+        self.permits = 0;
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        self.semaphore.add_permits(mem::take(&mut self.permits));
+    }
+}
+
+/// A permit that owns a reference to its [`Semaphore`], given back when dropped.
+#[must_use]
+pub struct OwnedSemaphorePermit {
+    semaphore: Arc<Semaphore>,
+    permits: usize,
+}
+
+impl OwnedSemaphorePermit {
+    /// Keeps the permit from being given back, reducing the semaphore's permits.
+    pub fn forget(mut self) {
+        self.permits = 0;
+    }
+}
+
+impl Drop for OwnedSemaphorePermit {
+    fn drop(&mut self) {
+        self.semaphore.add_permits(mem::take(&mut self.permits));
+    }
+}
+
+/// The error returned by an acquire on a closed [`Semaphore`].
+#[derive(Debug, PartialEq, Eq)]
+pub struct AcquireError(());
+
+/// The error returned by [`Semaphore::try_acquire`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum TryAcquireError {
+    Closed,
+    NoPermits,
+}
+
+impl fmt::Display for AcquireError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        "semaphore closed".fmt(fmt)
+    }
+}
+
+impl error::Error for AcquireError {}
+
+impl fmt::Display for TryAcquireError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryAcquireError::Closed => "semaphore closed".fmt(fmt),
+            TryAcquireError::NoPermits => "no permits available".fmt(fmt),
+        }
+    }
+}
+
+impl error::Error for TryAcquireError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::task::Waker;
+
+    use crate::waker_fn::waker_fn;
+
+    /// A waker counting how often it was woken.
+    fn counter() -> (Waker, Arc<AtomicUsize>) {
+        let count = Arc::new(AtomicUsize::new(0));
+        let woken = count.clone();
+        let waker = waker_fn(move || {
+            woken.fetch_add(1, Ordering::SeqCst);
+        });
+        (waker, count)
+    }
+
+    fn poll<F: Future>(future: Pin<&mut F>, waker: &Waker) -> Poll<F::Output> {
+        future.poll(&mut Context::from_waker(waker))
+    }
+
+    #[test]
+    fn permits_go_to_waiters_in_arrival_order() {
+        let semaphore = Semaphore::new(0);
+        let (first_waker, first) = counter();
+        let (second_waker, second) = counter();
+        let mut first_acquire = Box::pin(semaphore.acquire());
+        let mut second_acquire = Box::pin(semaphore.acquire());
+        assert!(poll(first_acquire.as_mut(), &first_waker).is_pending());
+        assert!(poll(second_acquire.as_mut(), &second_waker).is_pending());
+
+        semaphore.add_permits(1);
+        assert_eq!(first.load(Ordering::SeqCst), 1);
+        assert_eq!(second.load(Ordering::SeqCst), 0);
+        // a permit added while others wait goes to the queue, not to `try_acquire`.
+        semaphore.add_permits(1);
+        assert_eq!(second.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            semaphore.try_acquire().err(),
+            Some(TryAcquireError::NoPermits)
+        );
+
+        let permit = match poll(first_acquire.as_mut(), &first_waker) {
+            Poll::Ready(permit) => permit.unwrap(),
+            Poll::Pending => panic!("first waiter was not granted its permit"),
+        };
+        let second_permit = poll(second_acquire.as_mut(), &second_waker);
+        assert!(matches!(second_permit, Poll::Ready(Ok(_))));
+        drop(permit);
+        assert_eq!(semaphore.available_permits(), 1);
+    }
+
+    #[test]
+    fn a_waiter_for_many_permits_holds_back_the_waiters_behind() {
+        let semaphore = Semaphore::new(1);
+        let (many_waker, many) = counter();
+        let (one_waker, one) = counter();
+        let mut acquire_many = Box::pin(semaphore.acquire_many(2));
+        let mut acquire_one = Box::pin(semaphore.acquire());
+        assert!(poll(acquire_many.as_mut(), &many_waker).is_pending());
+        // a permit is available, but the waiter before asked for it first.
+        assert!(poll(acquire_one.as_mut(), &one_waker).is_pending());
+        assert_eq!(semaphore.available_permits(), 1);
+
+        semaphore.add_permits(1);
+        assert_eq!(many.load(Ordering::SeqCst), 1);
+        assert_eq!(one.load(Ordering::SeqCst), 0);
+        assert_eq!(semaphore.available_permits(), 0);
+
+        let permit = match poll(acquire_many.as_mut(), &many_waker) {
+            Poll::Ready(permit) => permit.unwrap(),
+            Poll::Pending => panic!("permits were not granted"),
+        };
+        assert!(poll(acquire_one.as_mut(), &one_waker).is_pending());
+        drop(permit);
+        assert_eq!(one.load(Ordering::SeqCst), 1);
+        let one_permit = poll(acquire_one.as_mut(), &one_waker);
+        assert!(matches!(one_permit, Poll::Ready(Ok(_))));
+        assert_eq!(semaphore.available_permits(), 1);
+        drop(one_permit);
+        assert_eq!(semaphore.available_permits(), 2);
+    }
+
+    #[test]
+    fn closing_fails_waiters_and_keeps_acquired_permits() {
+        let semaphore = Semaphore::new(1);
+        let permit = semaphore.try_acquire().unwrap();
+        let (waker, woken) = counter();
+        let mut acquire = Box::pin(semaphore.acquire());
+        assert!(poll(acquire.as_mut(), &waker).is_pending());
+
+        semaphore.close();
+        assert_eq!(woken.load(Ordering::SeqCst), 1);
+        assert!(matches!(
+            poll(acquire.as_mut(), &waker),
+            Poll::Ready(Err(AcquireError(())))
+        ));
+        assert!(semaphore.is_closed());
+        assert_eq!(semaphore.try_acquire().err(), Some(TryAcquireError::Closed));
+
+        drop(permit);
+        assert_eq!(semaphore.available_permits(), 1);
+    }
+
+    #[test]
+    fn permits_granted_to_a_dropped_waiter_go_to_the_next() {
+        let semaphore = Semaphore::new(0);
+        let (first_waker, _) = counter();
+        let (second_waker, second) = counter();
+        let mut first_acquire = Box::pin(semaphore.acquire());
+        let mut second_acquire = Box::pin(semaphore.acquire());
+        assert!(poll(first_acquire.as_mut(), &first_waker).is_pending());
+        assert!(poll(second_acquire.as_mut(), &second_waker).is_pending());
+
+        // granted after its last poll, the first waiter never takes the permit.
+        semaphore.add_permits(1);
+        assert_eq!(second.load(Ordering::SeqCst), 0);
+        drop(first_acquire);
+        assert_eq!(second.load(Ordering::SeqCst), 1);
+        assert_eq!(semaphore.available_permits(), 0);
+
+        let permit = match poll(second_acquire.as_mut(), &second_waker) {
+            Poll::Ready(permit) => permit.unwrap(),
+            Poll::Pending => panic!("the permit was not handed on"),
+        };
+        drop(permit);
+        assert_eq!(semaphore.available_permits(), 1);
+    }
+}
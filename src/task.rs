@@ -0,0 +1,235 @@
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+
+use async_task::Task;
+use futures_util::future::{poll_fn, FutureExt};
+
+use crate::blocking;
+use crate::local_executor;
+use crate::workers;
+
+/// Spawns a task onto the current runtime and returns a handle to await its output.
+///
+/// The task runs on the spawning thread even if the runtime has
+/// [worker threads](crate::Runtime::set_worker_threads), [`spawn_on_worker`] moves work
+/// to them.
+///
+/// A panic inside the task is caught and surfaces as a [`JoinError`] from the handle.
+/// Dropping the handle detaches the task, it keeps running in the background.
+pub fn spawn<T: 'static>(future: impl Future<Output = T> + 'static) -> JoinHandle<T> {
+    let task = local_executor::spawn_local(AssertUnwindSafe(future).catch_unwind());
+    JoinHandle { task: Some(task) }
+}
+
+/// Spawns a task onto the current runtime, like [`spawn`].
+///
+/// The runtime polls every task on the thread that spawned it, so neither the future
+/// nor its output need to be `Send`.
+pub fn spawn_local<T: 'static>(future: impl Future<Output = T> + 'static) -> JoinHandle<T> {
+    spawn(future)
+}
+
+/// Runs `f` on a pool of threads shared by all runtimes of the process, for work that
+/// would block the runtime's thread, and returns a handle to await its output.
+///
+/// A panic inside `f` surfaces as a [`JoinError`] from the handle. Dropping the handle
+/// does not stop `f`, it runs to completion on the pool.
+pub fn spawn_blocking<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let output = blocking::run(f);
+    spawn(async move {
+        match output.await {
+            Ok(output) => output,
+            Err(panic) => panic::resume_unwind(panic),
+        }
+    })
+}
+
+/// Spawns the future `f` builds onto the next of the current runtime's
+/// [worker threads](crate::Runtime::set_worker_threads) in turn, and returns a handle to
+/// await its output from this thread. Without workers it is spawned like [`spawn`].
+///
+/// `f` runs on the worker, so the future need not be `Send`: the sockets and timers it
+/// creates belong to the worker's ring. A panic inside the task surfaces as a
+/// [`JoinError`] from the handle, and so does the worker stopping before it finished.
+/// Dropping or aborting the handle does not stop the task on the worker.
+pub fn spawn_on_worker<F, Fut>(f: F) -> JoinHandle<Fut::Output>
+where
+    F: FnOnce() -> Fut + Send + 'static,
+    Fut: Future + 'static,
+    Fut::Output: Send + 'static,
+{
+    workers::spawn(f)
+}
+
+/// Spawns the future `f` builds onto every worker thread of the current runtime, or
+/// onto the runtime itself without workers, returning a handle per task.
+///
+/// Like with [`spawn_on_worker`], the future need not be `Send`. This is how a server
+/// accepts on every worker: listeners bound with
+/// [`ListenOptions::reuse_port`](crate::net::ListenOptions::reuse_port) to the same
+/// address by each worker share its connections, the kernel spreading them over the
+/// workers.
+pub fn spawn_on_each_worker<F, Fut>(f: F) -> Vec<JoinHandle<Fut::Output>>
+where
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future + 'static,
+    Fut::Output: Send + 'static,
+{
+    workers::spawn_each(f)
+}
+
+/// A group of tasks whose completion can be awaited together.
+///
+/// Clones refer to the same set, so a task can spawn more tasks onto the set it runs
+/// in. Dropping the set does not cancel its tasks.
+#[derive(Clone, Default)]
+pub struct LocalSet {
+    state: Rc<SetState>,
+}
+
+#[derive(Default)]
+struct SetState {
+    live: Cell<usize>,
+    waker: RefCell<Option<Waker>>,
+}
+
+/// Counts a task of a set as live until its future is dropped.
+struct Member(Rc<SetState>);
+
+impl Drop for Member {
+    fn drop(&mut self) {
+        let live = self.0.live.get() - 1;
+        self.0.live.set(live);
+        if live == 0 {
+            if let Some(waker) = self.0.waker.borrow_mut().take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl LocalSet {
+    pub fn new() -> LocalSet {
+        LocalSet::default()
+    }
+
+    /// Spawns a task that belongs to this set onto the current runtime.
+    pub fn spawn_local<T: 'static>(
+        &self,
+        future: impl Future<Output = T> + 'static,
+    ) -> JoinHandle<T> {
+        self.state.live.set(self.state.live.get() + 1);
+        let member = Member(self.state.clone());
+        spawn(async move {
+            let _member = member;
+            future.await
+        })
+    }
+
+    /// Number of tasks of the set that have not finished or been aborted.
+    pub fn len(&self) -> usize {
+        self.state.live.get()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Waits until every task of the set has finished, including tasks spawned onto it
+    /// while waiting.
+    pub async fn join(&self) {
+        poll_fn(|cx| {
+            if self.is_empty() {
+                return Poll::Ready(());
+            }
+            *self.state.waker.borrow_mut() = Some(cx.waker().clone());
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+/// An owned permission to await the output of a spawned task.
+pub struct JoinHandle<T> {
+    task: Option<Task<Result<T, Box<dyn Any + Send>>>>,
+}
+
+impl<T> JoinHandle<T> {
+    /// Cancels the task, it is dropped the next time it would be polled.
+    pub fn abort(mut self) {
+        if let Some(task) = self.task.take() {
+            drop(task);
+        }
+    }
+
+    /// Whether the task finished, its output being ready to be taken.
+    pub(crate) fn is_finished(&self) -> bool {
+        self.task.as_ref().is_none_or(Task::is_finished)
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let task = self
+            .task
+            .as_mut()
+            .expect("JoinHandle polled after completion");
+        let output = ready!(Pin::new(task).poll(cx));
+        self.task = None;
+        Poll::Ready(output.map_err(|panic| JoinError { panic }))
+    }
+}
+
+impl<T> Drop for JoinHandle<T> {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.detach();
+        }
+    }
+}
+
+/// The error returned by a [`JoinHandle`] whose task panicked.
+pub struct JoinError {
+    panic: Box<dyn Any + Send>,
+}
+
+impl JoinError {
+    /// Consumes the error, returning the value the task panicked with.
+    ///
+    /// Pass it to `std::panic::resume_unwind` to continue the panic in the caller.
+    pub fn into_panic(self) -> Box<dyn Any + Send> {
+        self.panic
+    }
+}
+
+impl fmt::Debug for JoinError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("JoinError").finish()
+    }
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.panic.downcast_ref::<&str>() {
+            Some(msg) => write!(fmt, "task panicked: {}", msg),
+            None => match self.panic.downcast_ref::<String>() {
+                Some(msg) => write!(fmt, "task panicked: {}", msg),
+                None => "task panicked".fmt(fmt),
+            },
+        }
+    }
+}
+
+impl std::error::Error for JoinError {}
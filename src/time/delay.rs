@@ -3,7 +3,7 @@ use std::pin::Pin;
 use std::task::{Context, Poll};
 use std::time::{Duration, Instant};
 
-use super::Timer;
+use super::{SuspendPolicy, Timer};
 
 pub struct Delay {
     inner: Timer,
@@ -31,6 +31,12 @@ impl Delay {
     pub fn reset(&mut self, deadline: Instant) {
         self.inner.reset(deadline);
     }
+
+    /// Sets whether time the system spends suspended counts towards the deadline, see
+    /// [`SuspendPolicy`]. It does not by default.
+    pub fn set_suspend_policy(&mut self, policy: SuspendPolicy) {
+        self.inner.set_suspend_policy(policy);
+    }
 }
 
 impl Future for Delay {
@@ -3,7 +3,7 @@ use std::pin::Pin;
 use std::task::{Context, Poll};
 use std::time::{Duration, Instant};
 
-use super::{delay_until, Delay};
+use super::{delay_until, Delay, SuspendPolicy};
 
 use futures_util::future::poll_fn;
 use futures_util::stream::Stream;
@@ -29,6 +29,12 @@ pub struct Interval {
 }
 
 impl Interval {
+    /// Sets whether time the system spends suspended counts towards the next tick, see
+    /// [`SuspendPolicy`]. It does not by default.
+    pub fn set_suspend_policy(&mut self, policy: SuspendPolicy) {
+        self.delay.set_suspend_policy(policy);
+    }
+
     pub fn poll_tick(&mut self, cx: &mut Context<'_>) -> Poll<Instant> {
         ready!(Pin::new(&mut self.delay).poll(cx));
         let now = self.delay.deadline();
@@ -1,8 +1,8 @@
 use std::io;
-use std::ops::Sub;
+use std::mem::MaybeUninit;
 use std::pin::Pin;
 use std::task::{Context, Poll};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use crate::driver::{self, Action};
 
@@ -14,6 +14,51 @@ pub use delay::{delay_for, delay_until, Delay};
 pub use interval::{interval, interval_at, Interval};
 pub use timeout::{timeout, timeout_at, Timeout};
 
+/// The runtime's coarse clock, in milliseconds since the runtime started.
+///
+/// The clock is read once whenever the runtime wakes up instead of on every call, so
+/// this costs no more than reading a counter and lags behind by however long the tasks
+/// ran since. Meant for coarse timeouts checked on every event of a busy loop, which
+/// compare ticks with integer math instead of calling `Instant::now`. 0 outside of a
+/// runtime.
+pub fn ticks() -> u64 {
+    driver::Driver::try_current(|driver| driver.inner.borrow().ticks()).unwrap_or(0)
+}
+
+/// Ticks elapsed since `tick`, an earlier value of [`ticks`].
+pub fn ticks_since(tick: u64) -> u64 {
+    ticks().saturating_sub(tick)
+}
+
+/// How long the system spent suspended since it booted.
+///
+/// `Instant` stands still while the system is suspended, so comparing this before and
+/// after a wait tells whether the system was suspended meanwhile, and for how long.
+pub fn suspended() -> Duration {
+    clock(libc::CLOCK_BOOTTIME).saturating_sub(clock(libc::CLOCK_MONOTONIC))
+}
+
+fn clock(id: libc::clockid_t) -> Duration {
+    let mut ts = MaybeUninit::<libc::timespec>::uninit();
+    // both clocks exist on every kernel io_uring runs on.
+    unsafe { libc::clock_gettime(id, ts.as_mut_ptr()) };
+    let ts = unsafe { ts.assume_init() };
+    Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+}
+
+/// What a timer makes of the time the system spends suspended, see
+/// [`Delay::set_suspend_policy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SuspendPolicy {
+    /// Time spent suspended does not count, the timer fires that much later by the wall
+    /// clock. This is how `Instant` measures time.
+    #[default]
+    Shift,
+    /// Time spent suspended counts, a timer whose deadline passed while the system was
+    /// suspended fires as soon as it resumes. Needs Linux 5.15.
+    FireOnResume,
+}
+
 enum State {
     Idle,
     Waiting(Action<driver::Timeout>),
@@ -22,6 +67,9 @@ enum State {
 pub struct Timer {
     deadline: Instant,
     state: State,
+    policy: SuspendPolicy,
+    /// The deadline on `CLOCK_BOOTTIME`, for `FireOnResume`.
+    boot_deadline: Duration,
 }
 
 impl Timer {
@@ -29,6 +77,8 @@ impl Timer {
         Timer {
             deadline,
             state: State::Idle,
+            policy: SuspendPolicy::Shift,
+            boot_deadline: Duration::ZERO,
         }
     }
 
@@ -37,20 +87,40 @@ impl Timer {
     }
 
     pub fn is_elapsed(&self) -> bool {
-        self.deadline < Instant::now()
+        match self.policy {
+            SuspendPolicy::Shift => self.deadline < Instant::now(),
+            SuspendPolicy::FireOnResume => self.boot_deadline < clock(libc::CLOCK_BOOTTIME),
+        }
     }
 
     pub fn reset(&mut self, when: Instant) {
         self.state = State::Idle;
         self.deadline = when;
+        self.boot_deadline = boot_deadline(when);
+    }
+
+    pub fn set_suspend_policy(&mut self, policy: SuspendPolicy) {
+        if policy != self.policy {
+            self.policy = policy;
+            self.reset(self.deadline);
+        }
     }
 
     fn poll_timeout(&mut self, cx: &mut Context) -> Poll<io::Result<Instant>> {
         loop {
             match &mut self.state {
                 State::Idle => {
-                    let duration = self.deadline.sub(Instant::now());
-                    let action = Action::timeout(duration.as_secs(), duration.subsec_nanos())?;
+                    let action = match self.policy {
+                        SuspendPolicy::Shift => {
+                            let duration = self.deadline.saturating_duration_since(Instant::now());
+                            Action::timeout(duration.as_secs(), duration.subsec_nanos())?
+                        }
+                        SuspendPolicy::FireOnResume => {
+                            let now = clock(libc::CLOCK_BOOTTIME);
+                            let duration = self.boot_deadline.saturating_sub(now);
+                            Action::timeout_boottime(duration.as_secs(), duration.subsec_nanos())?
+                        }
+                    };
                     self.state = State::Waiting(action);
                 }
                 State::Waiting(action) => {
@@ -61,3 +131,8 @@ impl Timer {
         }
     }
 }
+
+/// Where `deadline` falls on `CLOCK_BOOTTIME`, as of now.
+fn boot_deadline(deadline: Instant) -> Duration {
+    clock(libc::CLOCK_BOOTTIME) + deadline.saturating_duration_since(Instant::now())
+}
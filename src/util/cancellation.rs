@@ -0,0 +1,172 @@
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::task::{Context, Poll};
+
+use slab::Slab;
+
+use crate::driver::remote::{RemoteWaker, Wake};
+use crate::io::Cancellable;
+
+/// Signals tasks to stop what they are doing, for example to shut down accept loops.
+///
+/// Clones share the same state. A [child token](CancellationToken::child_token) is
+/// cancelled along with its parent, cancelling it leaves the parent alone. The token can
+/// be shared with other threads, waiters are woken on the thread of their own runtime.
+///
+/// Futures waiting for I/O are best wrapped with
+/// [`cancellable`](CancellationToken::cancellable): cancelling the token wakes them and
+/// they cancel their io_uring operations, then finish with the cancellation. Racing a
+/// future against [`cancelled`](CancellationToken::cancelled) with `select` instead hands
+/// the losing future back unpolled, its operations keep running until it is dropped.
+#[derive(Clone)]
+pub struct CancellationToken {
+    node: Arc<Node>,
+}
+
+struct Node {
+    state: Mutex<State>,
+}
+
+struct State {
+    cancelled: bool,
+    waiters: Slab<Option<Wake>>,
+    children: Vec<Weak<Node>>,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken {
+            node: Arc::new(Node {
+                state: Mutex::new(State {
+                    cancelled: false,
+                    waiters: Slab::new(),
+                    children: Vec::new(),
+                }),
+            }),
+        }
+    }
+
+    /// A token cancelled along with this one, which can also be cancelled on its own.
+    pub fn child_token(&self) -> CancellationToken {
+        let child = CancellationToken::new();
+        let mut state = self.node.lock();
+        if state.cancelled {
+            child.node.lock().cancelled = true;
+            return child;
+        }
+        // children dropped meanwhile are pruned as new ones are added.
+        state.children.retain(|child| child.strong_count() > 0);
+        state.children.push(Arc::downgrade(&child.node));
+        child
+    }
+
+    /// Cancels the token and its children, waking every task waiting for it.
+    pub fn cancel(&self) {
+        let mut pending = vec![self.node.clone()];
+        while let Some(node) = pending.pop() {
+            let mut state = node.lock();
+            if state.cancelled {
+                continue;
+            }
+            state.cancelled = true;
+            let woken: Vec<Wake> = state
+                .waiters
+                .iter_mut()
+                .filter_map(|(_, wake)| wake.take())
+                .collect();
+            pending.extend(state.children.drain(..).filter_map(|child| child.upgrade()));
+            drop(state);
+            woken.into_iter().for_each(Wake::wake);
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.node.lock().cancelled
+    }
+
+    /// Resolves once the token is cancelled.
+    pub fn cancelled(&self) -> WaitForCancellation {
+        WaitForCancellation {
+            waiter: Waiter::new(self.clone()),
+        }
+    }
+
+    /// Wraps `future` so the io_uring operations it waits for are cancelled once the
+    /// token is, see [`io::cancellable`](crate::io::cancellable).
+    pub fn cancellable<F: Future>(&self, future: F) -> Cancellable<F> {
+        Cancellable::new(future, Waiter::new(self.clone()))
+    }
+}
+
+impl Default for CancellationToken {
+    fn default() -> CancellationToken {
+        CancellationToken::new()
+    }
+}
+
+impl fmt::Debug for CancellationToken {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("CancellationToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+impl Node {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+}
+
+/// Future returned by [`CancellationToken::cancelled`].
+pub struct WaitForCancellation {
+    waiter: Waiter,
+}
+
+impl Future for WaitForCancellation {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        self.get_mut().waiter.poll_cancelled(cx)
+    }
+}
+
+/// A task waiting for a token to be cancelled.
+pub(crate) struct Waiter {
+    token: CancellationToken,
+    /// The waiter registered with the token, `None` before the first poll.
+    key: Option<usize>,
+    waker: RemoteWaker,
+}
+
+impl Waiter {
+    pub(crate) fn new(token: CancellationToken) -> Waiter {
+        Waiter {
+            token,
+            key: None,
+            waker: RemoteWaker::new(),
+        }
+    }
+
+    /// Ready once the token is cancelled, until then the task is woken when it is.
+    pub(crate) fn poll_cancelled(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = self.token.node.lock();
+        if state.cancelled {
+            return Poll::Ready(());
+        }
+        let key = *self.key.get_or_insert_with(|| state.waiters.insert(None));
+        self.waker.register(cx.waker());
+        state.waiters[key] = self.waker.handle();
+        Poll::Pending
+    }
+}
+
+impl Drop for Waiter {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.token.node.lock().waiters.remove(key);
+        }
+    }
+}
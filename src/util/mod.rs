@@ -0,0 +1,5 @@
+//! Utilities for structuring tasks.
+
+pub(crate) mod cancellation;
+
+pub use cancellation::{CancellationToken, WaitForCancellation};
@@ -0,0 +1,277 @@
+//! Worker threads running a runtime of their own each, see
+//! [`Runtime::set_worker_threads`](crate::Runtime::set_worker_threads).
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::task::{Context, Poll};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use futures_util::future::{poll_fn, FutureExt};
+
+use crate::blocking::{self, Blocking};
+use crate::driver::remote::{RemoteWaker, Wake};
+use crate::runtime::{RingFlags, Runtime};
+use crate::task;
+
+/// How long a stopping worker waits for the kernel to complete its cancelled operations.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
+type Job = Box<dyn FnOnce() + Send>;
+
+thread_local! {
+    /// The workers tasks on this thread hand work to.
+    static CURRENT: RefCell<Option<Arc<Shared>>> = const { RefCell::new(None) };
+
+    /// The tasks spawned onto the worker running on this thread, cancelled once it
+    /// stops so the handles awaiting them fail.
+    static SPAWNED: RefCell<Vec<task::JoinHandle<()>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// The worker threads of a runtime, stopped and joined once dropped.
+pub(crate) struct Workers {
+    shared: Arc<Shared>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+pub(crate) struct Shared {
+    queues: Vec<Arc<Queue>>,
+    next: AtomicUsize,
+}
+
+/// The jobs handed to a worker, run on its thread.
+struct Queue {
+    state: Mutex<QueueState>,
+}
+
+struct QueueState {
+    jobs: VecDeque<Job>,
+    closed: bool,
+    waiter: Option<Wake>,
+}
+
+impl Workers {
+    /// Starts `n` threads, each running a runtime of its own set up with `flags`, and
+    /// fails if any of the runtimes could not be created.
+    pub(crate) fn start(n: usize, flags: RingFlags) -> io::Result<Workers> {
+        let queues: Vec<Arc<Queue>> = (0..n).map(|_| Arc::new(Queue::new())).collect();
+        let shared = Arc::new(Shared {
+            queues,
+            next: AtomicUsize::new(0),
+        });
+        let mut workers = Workers {
+            shared: shared.clone(),
+            threads: Vec::with_capacity(n),
+        };
+        let (started, ready) = mpsc::channel();
+        for (i, queue) in shared.queues.iter().enumerate() {
+            let queue = queue.clone();
+            let shared = shared.clone();
+            let started = started.clone();
+            let thread = thread::Builder::new()
+                .name(format!("slings-worker-{}", i))
+                .spawn(move || work(shared, queue, flags, started))?;
+            workers.threads.push(thread);
+        }
+        drop(started);
+        for res in ready.iter().take(n) {
+            res?;
+        }
+        Ok(workers)
+    }
+
+    pub(crate) fn shared(&self) -> Arc<Shared> {
+        self.shared.clone()
+    }
+}
+
+impl Drop for Workers {
+    fn drop(&mut self) {
+        for queue in &self.shared.queues {
+            queue.close();
+        }
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Runs the worker's runtime until its queue is closed.
+fn work(
+    shared: Arc<Shared>,
+    queue: Arc<Queue>,
+    flags: RingFlags,
+    started: mpsc::Sender<io::Result<()>>,
+) {
+    let runtime = match Runtime::with_ring_flags(flags) {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            let _ = started.send(Err(e));
+            return;
+        }
+    };
+    let _ = started.send(Ok(()));
+    enter(Some(shared), || {
+        let mut waiter = RemoteWaker::new();
+        runtime.block_on(poll_fn(|cx| queue.poll_run(cx, &mut waiter)));
+    });
+    // a task waiting without having registered a waker is only reachable from here.
+    for spawned in SPAWNED.with(|spawned| spawned.take()) {
+        spawned.abort();
+    }
+    // the tasks still running are dropped along with their operations.
+    let _ = runtime.shutdown(SHUTDOWN_TIMEOUT);
+}
+
+/// Makes the tasks of the current thread hand work to `workers` while `f` runs.
+pub(crate) fn enter<T>(workers: Option<Arc<Shared>>, f: impl FnOnce() -> T) -> T {
+    struct Reset(Option<Arc<Shared>>);
+
+    impl Drop for Reset {
+        fn drop(&mut self) {
+            CURRENT.with(|current| *current.borrow_mut() = self.0.take());
+        }
+    }
+
+    let previous = CURRENT.with(|current| mem::replace(&mut *current.borrow_mut(), workers));
+    let _reset = Reset(previous);
+    f()
+}
+
+impl Queue {
+    fn new() -> Queue {
+        Queue {
+            state: Mutex::new(QueueState {
+                jobs: VecDeque::new(),
+                closed: false,
+                waiter: None,
+            }),
+        }
+    }
+
+    /// Queues `job`, which is dropped right away once the queue is closed.
+    fn push(&self, job: Job) {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            drop(state);
+            return drop(job);
+        }
+        state.jobs.push_back(job);
+        let waiter = state.waiter.take();
+        drop(state);
+        if let Some(waiter) = waiter {
+            waiter.wake();
+        }
+    }
+
+    /// Closes the queue, dropping the jobs that did not run yet so the handles of their
+    /// tasks fail instead of waiting forever.
+    fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        let jobs = mem::take(&mut state.jobs);
+        let waiter = state.waiter.take();
+        drop(state);
+        drop(jobs);
+        if let Some(waiter) = waiter {
+            waiter.wake();
+        }
+    }
+
+    /// Runs the queued jobs, ready once the queue is closed.
+    fn poll_run(&self, cx: &mut Context<'_>, waiter: &mut RemoteWaker) -> Poll<()> {
+        loop {
+            let mut state = self.state.lock().unwrap();
+            if state.closed {
+                return Poll::Ready(());
+            }
+            if state.jobs.is_empty() {
+                waiter.register(cx.waker());
+                state.waiter = waiter.handle();
+                return Poll::Pending;
+            }
+            let jobs = mem::take(&mut state.jobs);
+            drop(state);
+            SPAWNED.with(|spawned| spawned.borrow_mut().retain(|task| !task.is_finished()));
+            // jobs only spawn their task, which runs once this returns.
+            jobs.into_iter().for_each(|job| job());
+        }
+    }
+}
+
+/// Spawns the future `f` builds onto the next worker in turn, or onto the current
+/// runtime if it has no workers. The future is built on the worker, so it need not be
+/// `Send`.
+pub(crate) fn spawn<F, Fut>(f: F) -> task::JoinHandle<Fut::Output>
+where
+    F: FnOnce() -> Fut + Send + 'static,
+    Fut: Future + 'static,
+    Fut::Output: Send + 'static,
+{
+    let shared = match current() {
+        Some(shared) => shared,
+        None => return task::spawn(f()),
+    };
+    let i = shared.next.fetch_add(1, Ordering::Relaxed) % shared.queues.len();
+    join(run_on(&shared.queues[i], f))
+}
+
+/// Spawns the future `f` builds onto every worker, or onto the current runtime if it
+/// has no workers.
+pub(crate) fn spawn_each<F, Fut>(f: F) -> Vec<task::JoinHandle<Fut::Output>>
+where
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future + 'static,
+    Fut::Output: Send + 'static,
+{
+    let shared = match current() {
+        Some(shared) => shared,
+        None => return vec![task::spawn(f())],
+    };
+    let f = Arc::new(f);
+    let spawned = shared.queues.iter().map(|queue| {
+        let f = f.clone();
+        join(run_on(queue, move || f()))
+    });
+    spawned.collect()
+}
+
+fn current() -> Option<Arc<Shared>> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// Spawns the future `f` builds on the worker of `queue`, resolving to its output once
+/// it finished there.
+fn run_on<F, Fut>(queue: &Queue, f: F) -> Blocking<Fut::Output>
+where
+    F: FnOnce() -> Fut + Send + 'static,
+    Fut: Future + 'static,
+    Fut::Output: Send + 'static,
+{
+    let (done, output) = blocking::oneshot();
+    queue.push(Box::new(move || {
+        let future = match panic::catch_unwind(AssertUnwindSafe(f)) {
+            Ok(future) => AssertUnwindSafe(future).catch_unwind(),
+            Err(panic) => return done.complete(Err(panic)),
+        };
+        let spawned = task::spawn(async move { done.complete(future.await) });
+        SPAWNED.with(|tasks| tasks.borrow_mut().push(spawned));
+    }));
+    output
+}
+
+/// A handle on the current runtime to await the output of a task on a worker.
+fn join<T: Send + 'static>(output: Blocking<T>) -> task::JoinHandle<T> {
+    task::spawn(async move {
+        match output.await {
+            Ok(output) => output,
+            Err(panic) => panic::resume_unwind(panic),
+        }
+    })
+}